@@ -0,0 +1,570 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const MAGIC: &[u8] = b"age-encryption.org/v1\n";
+/// Plaintext bytes per payload chunk.
+const CHUNK: usize = 64 * 1024;
+const TAG: usize = 16;
+/// Largest scrypt work factor accepted; age itself writes 18. Each step up
+/// doubles the memory a passphrase check needs (1 GiB at 20).
+const MAX_SCRYPT_LOG_N: u8 = 20;
+
+#[derive(Debug)]
+pub enum AgeError {
+    WrongPassphrase,
+    /// The file is encrypted to keys, and none of the identities fit.
+    NoMatchingIdentity,
+    /// The file is encrypted with a passphrase, but identities were given.
+    NeedsPassphrase,
+    /// The file is encrypted to keys, but a passphrase was given.
+    NeedsIdentity,
+    Unsupported(String),
+    Corrupt(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for AgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeError::WrongPassphrase => f.write_str("wrong passphrase"),
+            AgeError::NoMatchingIdentity => {
+                f.write_str("none of the identities can decrypt this file")
+            }
+            AgeError::NeedsPassphrase => {
+                f.write_str("the file is encrypted with a passphrase, not to an identity")
+            }
+            AgeError::NeedsIdentity => {
+                f.write_str("the file is encrypted to a key; pass its identity file")
+            }
+            AgeError::Unsupported(what) => write!(f, "unsupported age file: {what}"),
+            AgeError::Corrupt(what) => write!(f, "age file is damaged: {what}"),
+            AgeError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AgeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AgeError {
+    fn from(e: io::Error) -> Self {
+        AgeError::Io(e)
+    }
+}
+
+fn corrupt(what: impl fmt::Display) -> AgeError {
+    AgeError::Corrupt(what.to_string())
+}
+
+/// Whether `head` starts like an age file, binary or armored.
+pub fn is_age(head: &[u8]) -> bool {
+    head.starts_with(MAGIC) || head.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+}
+
+/// Whether the age file starting with `head` is unlocked by a passphrase
+/// rather than an identity.
+pub fn uses_passphrase(head: &[u8]) -> bool {
+    head.windows(10).any(|w| w == b"\n-> scrypt")
+}
+
+/// An X25519 secret key, as written by `age-keygen`.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn from_secret(secret: [u8; 32]) -> Identity {
+        Identity(StaticSecret::from(secret))
+    }
+
+    fn public(&self) -> PublicKey {
+        PublicKey::from(&self.0)
+    }
+}
+
+/// Read the `AGE-SECRET-KEY-1...` lines of an identity file; comments and
+/// blank lines are skipped.
+pub fn parse_identities(text: &str) -> Result<Vec<Identity>, AgeError> {
+    let identities = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|line| {
+            let data = bech32_decode(line, "age-secret-key-").ok_or_else(|| {
+                AgeError::Unsupported(format!("not an age identity: {line:.20}…"))
+            })?;
+            let secret: [u8; 32] = data
+                .try_into()
+                .map_err(|_| corrupt("identity is not 32 bytes"))?;
+            Ok(Identity::from_secret(secret))
+        })
+        .collect::<Result<Vec<_>, AgeError>>()?;
+    if identities.is_empty() {
+        return Err(AgeError::Unsupported("no identities in the file".into()));
+    }
+    Ok(identities)
+}
+
+/// Decode a Bech32 string with the given (lowercase) human-readable part.
+fn bech32_decode(s: &str, hrp: &str) -> Option<Vec<u8>> {
+    const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+    let lower = s.to_ascii_lowercase();
+    if lower != s && s.to_ascii_uppercase() != s {
+        return None;
+    }
+    let (prefix, data) = lower.rsplit_once('1')?;
+    if prefix != hrp.trim_end_matches('1') && format!("{prefix}1") != hrp {
+        return None;
+    }
+    let values: Vec<u8> = data
+        .bytes()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|&x| x == c)
+                .and_then(|p| u8::try_from(p).ok())
+        })
+        .collect::<Option<_>>()?;
+    if values.len() < 6 {
+        return None;
+    }
+
+    let mut checked: Vec<u8> = prefix.bytes().map(|b| b >> 5).collect();
+    checked.push(0);
+    checked.extend(prefix.bytes().map(|b| b & 31));
+    checked.extend(&values);
+    let polymod = checked.iter().fold(1u32, |chk, &v| {
+        let top = chk >> 25;
+        let mut chk = ((chk & 0x1ff_ffff) << 5) ^ u32::from(v);
+        for (i, g) in [
+            0x3b6a_57b2,
+            0x2650_8e6d,
+            0x1ea1_19fa,
+            0x3d42_33dd,
+            0x2a14_62b3,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+        chk
+    });
+    if polymod != 1 {
+        return None;
+    }
+
+    // Regroup the 5-bit values into bytes
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for &v in &values[..values.len() - 6] {
+        acc = (acc << 5) | u32::from(v);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits).to_le_bytes()[0]);
+        }
+    }
+    (bits < 5 && acc & ((1 << bits) - 1) == 0).then_some(out)
+}
+
+/// How to unlock an age file.
+pub enum Unlock<'a> {
+    Passphrase(&'a str),
+    Identities(&'a [Identity]),
+}
+
+struct Stanza {
+    kind: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+/// Read an age header from `input` and unlock it. The returned reader
+/// yields the plaintext, checking each 64 KiB chunk before releasing it.
+pub fn decrypt<R: BufRead>(mut input: R, unlock: &Unlock) -> Result<Decryptor<R>, AgeError> {
+    let mut header = Vec::new();
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line)?;
+    if line.starts_with(b"-----BEGIN AGE") {
+        return Err(AgeError::Unsupported(
+            "ASCII-armored files; dearmor with `age -d` first".into(),
+        ));
+    }
+    if line != MAGIC {
+        return Err(corrupt("not an age file"));
+    }
+    header.extend(&line);
+
+    let mut stanzas = Vec::new();
+    let mac = loop {
+        line.clear();
+        input.read_until(b'\n', &mut line)?;
+        let text = std::str::from_utf8(&line)
+            .ok()
+            .and_then(|l| l.strip_suffix('\n'))
+            .ok_or_else(|| corrupt("header ends early"))?;
+        if let Some(mac) = text.strip_prefix("--- ") {
+            header.extend(b"---");
+            break b64(mac)?;
+        }
+        header.extend(&line);
+        let mut args = text
+            .strip_prefix("-> ")
+            .ok_or_else(|| corrupt("bad header line"))?
+            .split(' ')
+            .map(str::to_string);
+        let kind = args.next().unwrap_or_default();
+        stanzas.push(Stanza {
+            kind,
+            args: args.collect(),
+            body: read_body(&mut input, &mut header)?,
+        });
+    };
+
+    let file_key = unwrap_file_key(&stanzas, unlock)?;
+    let mut check = <Hmac<Sha256> as Mac>::new_from_slice(&hkdf(&[], &file_key, b"header"))
+        .expect("HMAC takes any key length");
+    check.update(&header);
+    check
+        .verify_slice(&mac)
+        .map_err(|_| corrupt("header MAC does not match"))?;
+
+    let mut nonce = [0u8; 16];
+    input.read_exact(&mut nonce)?;
+    Ok(Decryptor {
+        input,
+        key: hkdf(&nonce, &file_key, b"payload"),
+        counter: 0,
+        sealed: Vec::with_capacity(CHUNK + TAG + 1),
+        plain: Vec::new(),
+        pos: 0,
+        done: false,
+    })
+}
+
+/// A stanza body: base64 lines of 64 columns, ended by a shorter line.
+fn read_body(input: &mut impl BufRead, header: &mut Vec<u8>) -> Result<Vec<u8>, AgeError> {
+    let mut encoded = String::new();
+    loop {
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        header.extend(line.as_bytes());
+        let text = line
+            .strip_suffix('\n')
+            .ok_or_else(|| corrupt("header ends early"))?;
+        if text.len() > 64 {
+            return Err(corrupt("stanza line too long"));
+        }
+        encoded.push_str(text);
+        if text.len() < 64 {
+            return b64(&encoded);
+        }
+    }
+}
+
+fn b64(s: &str) -> Result<Vec<u8>, AgeError> {
+    STANDARD_NO_PAD
+        .decode(s)
+        .map_err(|_| corrupt("bad base64 in header"))
+}
+
+fn unwrap_file_key(stanzas: &[Stanza], unlock: &Unlock) -> Result<[u8; 16], AgeError> {
+    let scrypt = stanzas.iter().find(|s| s.kind == "scrypt");
+    if scrypt.is_some() && stanzas.len() > 1 {
+        return Err(corrupt("scrypt stanza alongside other recipients"));
+    }
+    match (unlock, scrypt) {
+        (Unlock::Passphrase(passphrase), Some(stanza)) => {
+            let [salt, log_n] = &stanza.args[..] else {
+                return Err(corrupt("bad scrypt stanza"));
+            };
+            let salt = b64(salt)?;
+            let log_n: u8 = log_n
+                .parse()
+                .ok()
+                .filter(|_| !log_n.starts_with('0'))
+                .ok_or_else(|| corrupt("bad scrypt work factor"))?;
+            if salt.len() != 16 {
+                return Err(corrupt("bad scrypt salt"));
+            }
+            if log_n > MAX_SCRYPT_LOG_N {
+                return Err(AgeError::Unsupported(format!(
+                    "scrypt work factor {log_n} is above {MAX_SCRYPT_LOG_N}"
+                )));
+            }
+            let params = scrypt::Params::new(log_n, 8, 1, 32).map_err(corrupt)?;
+            let mut key = [0u8; 32];
+            let salt = [b"age-encryption.org/v1/scrypt".as_slice(), &salt].concat();
+            scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key).map_err(corrupt)?;
+            open_file_key(&key, &stanza.body).ok_or(AgeError::WrongPassphrase)
+        }
+        (Unlock::Identities(_), Some(_)) => Err(AgeError::NeedsPassphrase),
+        (Unlock::Passphrase(_), None) => Err(AgeError::NeedsIdentity),
+        (Unlock::Identities(identities), None) => {
+            for stanza in stanzas.iter().filter(|s| s.kind == "X25519") {
+                let [share] = &stanza.args[..] else {
+                    return Err(corrupt("bad X25519 stanza"));
+                };
+                let share: [u8; 32] = b64(share)?
+                    .try_into()
+                    .map_err(|_| corrupt("bad X25519 share"))?;
+                for identity in *identities {
+                    let shared = identity.0.diffie_hellman(&PublicKey::from(share));
+                    if shared.as_bytes() == &[0; 32] {
+                        continue;
+                    }
+                    let salt = [share, identity.public().to_bytes()].concat();
+                    let key = hkdf(&salt, shared.as_bytes(), b"age-encryption.org/v1/X25519");
+                    if let Some(file_key) = open_file_key(&key, &stanza.body) {
+                        return Ok(file_key);
+                    }
+                }
+            }
+            Err(AgeError::NoMatchingIdentity)
+        }
+    }
+}
+
+fn open_file_key(key: &[u8; 32], body: &[u8]) -> Option<[u8; 16]> {
+    if body.len() != 16 + TAG {
+        return None;
+    }
+    open(key, &[0; 12], body)?.try_into().ok()
+}
+
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, &mut key)
+        .expect("32 bytes is a valid HKDF length");
+    key
+}
+
+/// ChaCha20-Poly1305 (RFC 8439) decryption with no associated data.
+fn open(key: &[u8; 32], nonce: &[u8; 12], sealed: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(nonce.into(), sealed)
+        .ok()
+}
+
+/// The plaintext of an age file, decrypted as it is read.
+pub struct Decryptor<R> {
+    input: R,
+    key: [u8; 32],
+    counter: u64,
+    sealed: Vec<u8>,
+    plain: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Decryptor<R> {
+    fn next_chunk(&mut self) -> io::Result<()> {
+        // One byte past a full chunk tells whether this chunk is the last
+        let want = CHUNK + TAG + 1;
+        let missing = want - self.sealed.len();
+        (&mut self.input)
+            .take(missing as u64)
+            .read_to_end(&mut self.sealed)?;
+        let last = self.sealed.len() < want;
+        let take = if last { self.sealed.len() } else { CHUNK + TAG };
+
+        let mut nonce = [0u8; 12];
+        nonce[3..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = u8::from(last);
+        let damaged =
+            |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("age payload {what}"));
+        let plain = open(&self.key, &nonce, &self.sealed[..take])
+            .ok_or_else(|| damaged("is damaged or truncated"))?;
+        if last && plain.is_empty() && self.counter > 0 {
+            return Err(damaged("ends with an empty chunk"));
+        }
+        self.sealed.drain(..take);
+        self.counter += 1;
+        self.plain = plain;
+        self.pos = 0;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    fn seal(key: &[u8; 32], nonce: &[u8; 12], plain: &[u8]) -> Vec<u8> {
+        ChaCha20Poly1305::new(key.into())
+            .encrypt(nonce.into(), plain)
+            .unwrap()
+    }
+
+    fn stanza_text(kind: &str, args: &[String], body: &[u8]) -> String {
+        let mut text = format!("-> {kind}");
+        for arg in args {
+            text.push(' ');
+            text.push_str(arg);
+        }
+        text.push('\n');
+        let encoded = STANDARD_NO_PAD.encode(body);
+        let mut rest = encoded.as_str();
+        loop {
+            let (line, tail) = rest.split_at(rest.len().min(64));
+            text.push_str(line);
+            text.push('\n');
+            rest = tail;
+            if line.len() < 64 {
+                return text;
+            }
+        }
+    }
+
+    fn encrypt(file_key: [u8; 16], stanza: &str, plain: &[u8]) -> Vec<u8> {
+        let mut header = format!("age-encryption.org/v1\n{stanza}---").into_bytes();
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(&hkdf(&[], &file_key, b"header")).unwrap();
+        mac.update(&header);
+        let tag = STANDARD_NO_PAD.encode(mac.finalize().into_bytes());
+        header.extend(format!(" {tag}\n").as_bytes());
+
+        let nonce = [7u8; 16];
+        header.extend(nonce);
+        let key = hkdf(&nonce, &file_key, b"payload");
+        let chunks: Vec<&[u8]> = if plain.is_empty() {
+            vec![plain]
+        } else {
+            plain.chunks(CHUNK).collect()
+        };
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut chunk_nonce = [0u8; 12];
+            chunk_nonce[3..11].copy_from_slice(&(i as u64).to_be_bytes());
+            chunk_nonce[11] = u8::from(i + 1 == chunks.len());
+            header.extend(seal(&key, &chunk_nonce, chunk));
+        }
+        header
+    }
+
+    pub(crate) fn with_passphrase(passphrase: &str, plain: &[u8]) -> Vec<u8> {
+        let file_key = [3u8; 16];
+        let salt = [9u8; 16];
+        let params = scrypt::Params::new(10, 8, 1, 32).unwrap();
+        let mut key = [0u8; 32];
+        let full_salt = [b"age-encryption.org/v1/scrypt".as_slice(), &salt].concat();
+        scrypt::scrypt(passphrase.as_bytes(), &full_salt, &params, &mut key).unwrap();
+        let body = seal(&key, &[0; 12], &file_key);
+        let args = [STANDARD_NO_PAD.encode(salt), "10".to_string()];
+        encrypt(file_key, &stanza_text("scrypt", &args, &body), plain)
+    }
+
+    fn to_recipient(recipient: &PublicKey, plain: &[u8]) -> Vec<u8> {
+        let file_key = [5u8; 16];
+        let ephemeral = StaticSecret::from([11u8; 32]);
+        let share = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(recipient);
+        let salt = [share.to_bytes(), recipient.to_bytes()].concat();
+        let key = hkdf(&salt, shared.as_bytes(), b"age-encryption.org/v1/X25519");
+        let body = seal(&key, &[0; 12], &file_key);
+        let args = [STANDARD_NO_PAD.encode(share.as_bytes())];
+        encrypt(file_key, &stanza_text("X25519", &args, &body), plain)
+    }
+
+    fn read_all(file: Vec<u8>, unlock: &Unlock) -> Result<Vec<u8>, AgeError> {
+        let mut out = Vec::new();
+        decrypt(BufReader::new(Cursor::new(file)), unlock)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn decrypts_passphrase_files_across_chunks() {
+        let plain: Vec<u8> = (0..=255u8).cycle().take(CHUNK * 2 + 100).collect();
+        let file = with_passphrase("hunter2", &plain);
+        assert_eq!(
+            read_all(file.clone(), &Unlock::Passphrase("hunter2")).unwrap(),
+            plain
+        );
+        assert!(matches!(
+            read_all(file, &Unlock::Passphrase("hunter3")),
+            Err(AgeError::WrongPassphrase)
+        ));
+
+        // A file that is an exact number of chunks long
+        let exact = vec![1u8; CHUNK];
+        let file = with_passphrase("pw", &exact);
+        assert_eq!(read_all(file, &Unlock::Passphrase("pw")).unwrap(), exact);
+        let empty = with_passphrase("pw", b"");
+        assert!(read_all(empty, &Unlock::Passphrase("pw"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn decrypts_to_identities_and_rejects_damage() {
+        let identity = Identity::from_secret([42u8; 32]);
+        let other = Identity::from_secret([43u8; 32]);
+        let file = to_recipient(&identity.public(), b"home sweet home");
+        let both = [other.clone(), identity];
+        assert_eq!(
+            read_all(file.clone(), &Unlock::Identities(&both)).unwrap(),
+            b"home sweet home"
+        );
+        assert!(matches!(
+            read_all(file.clone(), &Unlock::Identities(&[other])),
+            Err(AgeError::NoMatchingIdentity)
+        ));
+        assert!(matches!(
+            read_all(file.clone(), &Unlock::Passphrase("pw")),
+            Err(AgeError::NeedsIdentity)
+        ));
+
+        let mut damaged = file.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        let err = read_all(damaged, &Unlock::Identities(&both)).unwrap_err();
+        assert!(err.to_string().contains("damaged"), "{err}");
+        let truncated = file[..file.len() - 1].to_vec();
+        assert!(read_all(truncated, &Unlock::Identities(&both)).is_err());
+    }
+
+    #[test]
+    fn parses_identity_files() {
+        // The key 0x42 repeated, as age-keygen would print it
+        let text = "# created: 2021-01-01\n\
+            AGE-SECRET-KEY-1GFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPQ4EGAEX\n";
+        let identities = parse_identities(text).unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].0.to_bytes(), [0x42; 32]);
+        assert!(parse_identities("# nothing\n").is_err());
+        assert!(parse_identities("AGE-SECRET-KEY-1GFPYYSJZGFPYYSJZ\n").is_err());
+    }
+}
@@ -0,0 +1,1101 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+use crate::age::{self, AgeError, Identity, Unlock};
+use crate::copy::{self, progress_bar, Owner, Ownership};
+use crate::types::{CopyError, CopyOp, CopyPlan, CopyResult, DetectedMapping, DirOp, XdgDir};
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Age(AgeError),
+    /// `gpg` could not be started.
+    Gpg(io::Error),
+    /// The archive is encrypted, but the key given is of the wrong kind.
+    WrongKey(&'static str),
+    /// A member name would land outside the restored folder.
+    UnsafePath(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Age(e) => e.fmt(f),
+            ArchiveError::Gpg(e) => write!(f, "could not run gpg: {e}"),
+            ArchiveError::WrongKey(what) => f.write_str(what),
+            ArchiveError::UnsafePath(p) => {
+                write!(f, "refusing to extract {} outside the target", p.display())
+            }
+            ArchiveError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<AgeError> for ArchiveError {
+    fn from(e: AgeError) -> Self {
+        ArchiveError::Age(e)
+    }
+}
+
+/// How a tar archive is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    None,
+    Age,
+    /// GPG, as written by `gpg --symmetric`.
+    Gpg,
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encryption::None => "tar",
+            Encryption::Age => "age-encrypted tar",
+            Encryption::Gpg => "GPG-encrypted tar",
+        })
+    }
+}
+
+/// What unlocks an encrypted archive.
+pub enum Key {
+    Passphrase(String),
+    /// age identities, from an identity file.
+    Identities(Vec<Identity>),
+}
+
+/// Recognize a (possibly compressed) tar archive, encrypted or not.
+///
+/// Encrypted files are told apart by their headers. Unencrypted ones need a
+/// tar header, or a compressed file named like `home.tar.zst` or `.tgz`.
+pub fn detect_archive(path: &Path) -> Option<Encryption> {
+    let file = File::open(path).ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+    let mut head = Vec::new();
+    file.take(512).read_to_end(&mut head).ok()?;
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+    if age::is_age(&head) {
+        Some(Encryption::Age)
+    } else if is_openpgp(&head, path) {
+        Some(Encryption::Gpg)
+    } else if head.get(257..262) == Some(b"ustar")
+        || (Compression::sniff(&head) != Compression::None
+            && (name.contains(".tar.") || has_extension(path, "tgz")))
+    {
+        Some(Encryption::None)
+    } else {
+        None
+    }
+}
+
+/// GPG messages start with a session key packet; symmetric ones use tag 3,
+/// in the old or new packet format. Armored ones start with a text line.
+fn is_openpgp(head: &[u8], path: &Path) -> bool {
+    let packet = head.first().copied().unwrap_or(0);
+    let binary = matches!(packet, 0x8c..=0x8f | 0xc3) && has_extension(path, "gpg");
+    binary || head.starts_with(b"-----BEGIN PGP MESSAGE-----")
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    fn sniff(head: &[u8]) -> Compression {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Open the tar stream inside `path`: decrypted with `key` and then
+/// decompressed as it is read. Nothing is written to disk.
+pub fn open_stream(
+    path: &Path,
+    encryption: Encryption,
+    key: Option<&Key>,
+) -> Result<Box<dyn Read + Send>, ArchiveError> {
+    let plain: Box<dyn Read + Send> = match (encryption, key) {
+        (Encryption::None, _) => Box::new(File::open(path)?),
+        (Encryption::Age, Some(Key::Passphrase(passphrase))) => {
+            let input = BufReader::new(File::open(path)?);
+            Box::new(age::decrypt(input, &Unlock::Passphrase(passphrase))?)
+        }
+        (Encryption::Age, Some(Key::Identities(identities))) => {
+            let input = BufReader::new(File::open(path)?);
+            Box::new(age::decrypt(input, &Unlock::Identities(identities))?)
+        }
+        (Encryption::Gpg, Some(Key::Passphrase(passphrase))) => {
+            Box::new(Gpg::decrypt(path, passphrase).map_err(ArchiveError::Gpg)?)
+        }
+        (Encryption::Gpg, Some(Key::Identities(_))) => {
+            return Err(ArchiveError::WrongKey(
+                "GPG archives take a passphrase, not an age identity",
+            ))
+        }
+        (_, None) => return Err(ArchiveError::WrongKey("the archive is encrypted")),
+    };
+    decompress(plain)
+}
+
+fn decompress(mut input: Box<dyn Read + Send>) -> Result<Box<dyn Read + Send>, ArchiveError> {
+    let mut head = Vec::new();
+    (&mut input).take(6).read_to_end(&mut head)?;
+    let compression = Compression::sniff(&head);
+    let input = Cursor::new(head).chain(input);
+    Ok(match compression {
+        Compression::None => Box::new(input),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(input)),
+        Compression::Zstd => Box::new(Zstd::new(BufReader::new(input))?),
+        Compression::Xz => Box::new(Xz::new(BufReader::new(input))),
+    })
+}
+
+/// `gpg --decrypt`, with the passphrase fed over stdin. The exit status is
+/// checked at the end of the output, so a failed integrity check surfaces
+/// as a read error. Diagnostics go to an unnamed temp file rather than a
+/// pipe, so a chatty gpg can't stall waiting for us to drain them.
+struct Gpg {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: File,
+}
+
+impl Gpg {
+    fn decrypt(path: &Path, passphrase: &str) -> io::Result<Gpg> {
+        let stderr = tempfile::tempfile()?;
+        let mut child = Command::new("gpg")
+            .args([
+                "--batch",
+                "--quiet",
+                "--no-tty",
+                "--pinentry-mode",
+                "loopback",
+            ])
+            .args(["--passphrase-fd", "0", "--decrypt"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr.try_clone()?)
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        writeln!(stdin, "{passphrase}")?;
+        drop(stdin);
+        let stdout = child.stdout.take().unwrap();
+        Ok(Gpg {
+            child,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+impl Read for Gpg {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            let mut stderr = String::new();
+            self.stderr.rewind()?;
+            self.stderr.read_to_string(&mut stderr)?;
+            let message = stderr.lines().last().unwrap_or("decryption failed");
+            return Err(io::Error::other(message.trim_start_matches("gpg: ")));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Gpg {
+    fn drop(&mut self) {
+        // Stopped early, such as on a damaged entry
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A zstd stream of one or more frames, as written by `zstd` and
+/// `tar --zstd`.
+struct Zstd<R: BufRead> {
+    decoder: Option<StreamingDecoder<R, ruzstd::decoding::FrameDecoder>>,
+}
+
+impl<R: BufRead> Zstd<R> {
+    fn new(input: R) -> io::Result<Zstd<R>> {
+        let decoder = StreamingDecoder::new(input).map_err(io::Error::other)?;
+        Ok(Zstd {
+            decoder: Some(decoder),
+        })
+    }
+}
+
+impl<R: BufRead> Read for Zstd<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(decoder) = &mut self.decoder else {
+                return Ok(0);
+            };
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            let mut input = self.decoder.take().unwrap().into_inner();
+            if input.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            self.decoder = Some(StreamingDecoder::new(input).map_err(io::Error::other)?);
+        }
+    }
+}
+
+/// An xz stream, decompressed on a thread feeding a pipe.
+struct Xz {
+    output: io::PipeReader,
+    worker: Option<JoinHandle<Result<(), lzma_rs::error::Error>>>,
+}
+
+impl Xz {
+    fn new<R: BufRead + Send + 'static>(mut input: R) -> Xz {
+        let (output, mut writer) = io::pipe().expect("pipes can be created");
+        let worker = thread::spawn(move || lzma_rs::xz_decompress(&mut input, &mut writer));
+        Xz {
+            output,
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Read for Xz {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.output.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            // The writer closes on errors too; only the thread knows which
+            if let Some(worker) = self.worker.take() {
+                let result = worker.join().expect("xz thread panicked");
+                result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// A regular file or folder in the archive.
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Position among the archive's entries, or what [`Files`] reads the
+    /// member by.
+    pub index: usize,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: SystemTime,
+    /// `None` when the header leaves the ids blank.
+    pub owner: Option<Owner>,
+}
+
+/// The members of an archive, read from its headers.
+#[derive(Debug, Default)]
+pub struct Listing {
+    /// Later copies of a path replace earlier ones, as when extracting.
+    pub members: Vec<Member>,
+    /// Symlinks, hard links and special files, which are not restored.
+    pub skipped: usize,
+    /// The contents of every `etc/passwd` and `etc/group`, by member path,
+    /// for mapping owners by name.
+    pub account_files: BTreeMap<PathBuf, String>,
+}
+
+impl Listing {
+    /// The `etc/passwd` and `etc/group` nearest to the members at `sources`,
+    /// as [`crate::users::find_account_files`] finds them in a folder.
+    pub fn account_files(&self, sources: &[&Path]) -> Option<(PathBuf, String, String)> {
+        sources.iter().find_map(|source| {
+            source.ancestors().skip(1).find_map(|dir| {
+                let etc = dir.join("etc");
+                let passwd = self.account_files.get(&etc.join("passwd"))?;
+                let group = self.account_files.get(&etc.join("group"));
+                Some((etc, passwd.clone(), group.cloned().unwrap_or_default()))
+            })
+        })
+    }
+}
+
+/// Whether the member at `path` is an account file worth keeping.
+pub(crate) fn is_account_file(path: &Path, size: u64) -> bool {
+    let name = path.file_name().unwrap_or_default();
+    let in_etc = path.parent().and_then(Path::file_name) == Some(OsStr::new("etc"));
+    in_etc && (name == "passwd" || name == "group") && size <= MAX_ACCOUNT_FILE
+}
+
+/// Account files are a few kilobytes; anything far larger is something else.
+const MAX_ACCOUNT_FILE: u64 = 1 << 20;
+
+/// Read the archive's headers, skipping over file contents.
+pub fn list_members(stream: impl Read) -> Result<Listing, ArchiveError> {
+    let mut archive = tar::Archive::new(stream);
+    let mut listing = Listing::default();
+    let mut by_path: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, entry) in archive.entries()?.enumerate() {
+        let mut entry = entry?;
+        let header = entry.header();
+        let is_dir = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => false,
+            tar::EntryType::Directory => true,
+            tar::EntryType::Symlink | tar::EntryType::Link | tar::EntryType::Fifo => {
+                listing.skipped += 1;
+                continue;
+            }
+            // Long names and extended headers, which the tar crate applies
+            _ => continue,
+        };
+        let Some(path) = member_path(&entry.path()?)? else {
+            continue;
+        };
+        let member = Member {
+            index,
+            path: path.clone(),
+            is_dir,
+            size: header.size()?,
+            mode: header.mode()? & 0o7777,
+            mtime: UNIX_EPOCH + Duration::from_secs(header.mtime()?),
+            owner: header_owner(header),
+        };
+        if !is_dir && is_account_file(&path, member.size) {
+            let mut text = String::new();
+            if entry.read_to_string(&mut text).is_ok() {
+                listing.account_files.insert(path.clone(), text);
+            }
+        }
+        if let Some(&i) = by_path.get(&path) {
+            listing.members[i] = member;
+        } else {
+            by_path.insert(path, listing.members.len());
+            listing.members.push(member);
+        }
+    }
+    Ok(listing)
+}
+
+fn header_owner(header: &tar::Header) -> Option<Owner> {
+    Some(Owner {
+        uid: u32::try_from(header.uid().ok()?).ok()?,
+        gid: u32::try_from(header.gid().ok()?).ok()?,
+    })
+}
+
+/// The member's path relative to the archive root, as tar extracts it:
+/// leading `/` and `./` are dropped. `None` for the root itself.
+fn member_path(raw: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+    let mut path = PathBuf::new();
+    for component in raw.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => return Err(ArchiveError::UnsafePath(raw.to_path_buf())),
+        }
+    }
+    Ok((!path.as_os_str().is_empty()).then_some(path))
+}
+
+/// Find XDG folders in the archive, like a scan of a backup directory:
+/// folders inside a matched folder are not matched again. Returns one
+/// mapping per folder found, with the source under `archive`.
+pub fn find_xdg_folders(
+    archive: &Path,
+    listing: &Listing,
+    home_dir: &Path,
+) -> Vec<DetectedMapping> {
+    // Folders can be implied by the files in them
+    let mut dirs = BTreeSet::new();
+    for member in &listing.members {
+        let start = if member.is_dir {
+            Some(member.path.as_path())
+        } else {
+            member.path.parent()
+        };
+        dirs.extend(start.into_iter().flat_map(Path::ancestors));
+    }
+
+    let mut found: Vec<PathBuf> = Vec::new();
+    let mut mappings = Vec::new();
+    for dir in dirs {
+        if found.iter().any(|f| dir.starts_with(f)) {
+            continue;
+        }
+        let Some(xdg_dir) = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(XdgDir::from_dir_name)
+        else {
+            continue;
+        };
+        found.push(dir.to_path_buf());
+        mappings.push(DetectedMapping {
+            xdg_dir,
+            source_path: archive.join(dir),
+            dest_path: home_dir.join(xdg_dir.dir_name()),
+        });
+    }
+    mappings
+}
+
+/// Bytes of the files in the archive under `folder`.
+pub fn folder_bytes(archive: &Path, listing: &Listing, folder: &Path) -> u64 {
+    let folder = folder.strip_prefix(archive).unwrap_or(folder);
+    listing
+        .members
+        .iter()
+        .filter(|m| !m.is_dir && m.path.starts_with(folder))
+        .map(|m| m.size)
+        .sum()
+}
+
+/// Plan restoring `mappings` from the archive. Sources are the members'
+/// paths under `archive`, which [`extract_plan`] reads from the stream.
+pub fn build_plan(archive: &Path, listing: &Listing, mappings: &[DetectedMapping]) -> CopyPlan {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for mapping in mappings {
+        dirs.push(DirOp {
+            dest: mapping.dest_path.clone(),
+            source: Some(mapping.source_path.clone()),
+        });
+        let folder = mapping.source_path.strip_prefix(archive).unwrap();
+        for member in &listing.members {
+            let Ok(relative) = member.path.strip_prefix(folder) else {
+                continue;
+            };
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+            let dest = mapping.dest_path.join(relative);
+            if member.is_dir {
+                dirs.push(DirOp {
+                    dest,
+                    source: Some(archive.join(&member.path)),
+                });
+            } else {
+                // Folders needn't have entries of their own
+                if let Some(parent) = dest.parent() {
+                    dirs.push(DirOp {
+                        dest: parent.to_path_buf(),
+                        source: None,
+                    });
+                }
+                files.push(CopyOp {
+                    source: archive.join(&member.path),
+                    dest,
+                    size: member.size,
+                    mtime: Some(member.mtime),
+                    xdg_dir: mapping.xdg_dir,
+                    snapshot: None,
+                });
+            }
+        }
+    }
+    // Parents before children, as a folder walk would list them, keeping
+    // the copy of each folder that has a member of its own
+    dirs.sort_by(|a, b| (&a.dest, a.source.is_none()).cmp(&(&b.dest, b.source.is_none())));
+    dirs.dedup_by(|a, b| a.dest == b.dest);
+
+    let total_bytes = files.iter().map(|f| f.size).sum();
+    CopyPlan {
+        dirs,
+        files,
+        total_bytes,
+    }
+}
+
+/// Restore the planned files from a fresh `stream` of the archive listed
+/// as `listing`. Files are written straight to their destinations, or
+/// beside them as `.restore` conflicts, with the mode and mtime from the
+/// archive.
+///
+/// A stream that fails part way (a wrong key or a damaged archive) records
+/// every planned file as an error, and the files this run already wrote are
+/// removed again: gpg checks a message's integrity only at its end, so
+/// nothing that came before can be trusted.
+pub fn extract_plan(
+    stream: impl Read,
+    archive_path: &Path,
+    listing: &Listing,
+    plan: &CopyPlan,
+    ownership: &Ownership,
+) -> io::Result<CopyResult> {
+    let members: HashMap<PathBuf, &Member> = listing
+        .members
+        .iter()
+        .map(|m| (archive_path.join(&m.path), m))
+        .collect();
+    // New folders stay private until the files are in them
+    let mut created_dirs = Vec::new();
+    for dir_op in &plan.dirs {
+        let new_dirs = copy::create_private_dirs(&dir_op.dest)?;
+        let member = || members.get(dir_op.source.as_ref()?);
+        let created = new_dirs.last() == Some(&dir_op.dest);
+        if let Some(owner) = ownership.owner_for(|| member()?.owner).filter(|_| created) {
+            for dir in &new_dirs {
+                let _ = owner.apply(dir);
+            }
+        }
+        let count = new_dirs.len();
+        for (i, dir) in new_dirs.into_iter().enumerate() {
+            let mode = member().filter(|_| i + 1 == count).map(|m| m.mode | 0o700);
+            created_dirs.push((dir, mode.unwrap_or_else(copy::default_dir_mode)));
+        }
+    }
+    let progress = progress_bar(plan.total_bytes);
+
+    // Entry index → planned copy, for the last copy of each member
+    let mut wanted: HashMap<usize, (&CopyOp, &Member)> = plan
+        .files
+        .iter()
+        .filter_map(|op| members.get(&op.source).map(|m| (m.index, (op, *m))))
+        .collect();
+
+    let mut result = CopyResult {
+        copied: Vec::new(),
+        conflicts: Vec::new(),
+        errors: Vec::new(),
+        bytes_copied: 0,
+    };
+    let mut written = Vec::new();
+    let mut archive = tar::Archive::new(stream);
+    let mut failure = None;
+    match archive.entries() {
+        Ok(entries) => {
+            for (index, entry) in entries.enumerate() {
+                let mut entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
+                    }
+                };
+                let Some((op, member)) = wanted.remove(&index) else {
+                    continue;
+                };
+                match copy::place_file(&mut entry, &op.dest) {
+                    Ok(placed) => {
+                        let path = placed.path(&op.dest);
+                        let _ = fs::set_permissions(path, fs::Permissions::from_mode(member.mode));
+                        if let Ok(file) = File::options().write(true).open(path) {
+                            let _ = file.set_modified(member.mtime);
+                        }
+                        if let Some(owner) = ownership.owner_for(|| member.owner) {
+                            let _ = owner.apply(path);
+                        }
+                        progress.inc(placed.bytes());
+                        written.push((op, path.to_path_buf()));
+                        result.record(op, placed);
+                    }
+                    Err(error) => {
+                        progress.inc(op.size);
+                        result.errors.push(copy_error(op, error));
+                    }
+                }
+            }
+        }
+        Err(e) => failure = Some(e),
+    }
+    // The end of the tar data isn't the end of the stream: read the rest,
+    // which is when gpg reports a failed integrity check
+    if failure.is_none() {
+        if let Err(e) = io::copy(&mut archive.into_inner(), &mut io::sink()) {
+            failure = Some(e);
+        }
+    }
+    progress.finish_and_clear();
+
+    if let Some(e) = &failure {
+        undo(&mut result, written, &created_dirs, e);
+    }
+    copy::open_dirs(&created_dirs);
+
+    // Whatever wasn't reached: the stream broke, or the archive changed
+    // since it was listed
+    let mut missed: Vec<&CopyOp> = wanted.into_values().map(|(op, _)| op).collect();
+    missed.extend(
+        plan.files
+            .iter()
+            .filter(|op| !members.contains_key(&op.source)),
+    );
+    missed.sort_by(|a, b| a.dest.cmp(&b.dest));
+    for op in missed {
+        let error = match &failure {
+            Some(e) => io::Error::new(e.kind(), format!("archive unreadable: {e}")),
+            None => io::Error::new(io::ErrorKind::NotFound, "no longer in the archive"),
+        };
+        result.errors.push(copy_error(op, error));
+    }
+    Ok(result)
+}
+
+/// A backup whose files can be read in any order and from several threads
+/// at once, such as a restic snapshot or a disk image. Listed like an
+/// archive, it is restored with [`copy_plan`] without being unpacked.
+pub trait Files: Sync {
+    /// Write the contents of the file `member`, returning the bytes written.
+    fn write_member(&self, member: &Member, out: &mut dyn Write) -> io::Result<u64>;
+}
+
+/// Restore the planned files from `files`, listed as `listing` with its
+/// members under `archive_path`, across `jobs` threads. Files and new
+/// folders get the mode, mtime and owner from the listing.
+pub fn copy_plan(
+    files: &dyn Files,
+    archive_path: &Path,
+    listing: &Listing,
+    plan: &CopyPlan,
+    jobs: usize,
+    ownership: &Ownership,
+) -> io::Result<CopyResult> {
+    let members: HashMap<PathBuf, &Member> = listing
+        .members
+        .iter()
+        .map(|m| (archive_path.join(&m.path), m))
+        .collect();
+    let member = |path: Option<&PathBuf>| members.get(path?).copied();
+
+    let mut new_dirs = Vec::new();
+    let dir_owner = |dir_op: &DirOp| {
+        if let Some(m) = member(dir_op.source.as_ref()) {
+            new_dirs.push((dir_op.dest.clone(), m));
+        }
+        ownership.owner_for(|| member(dir_op.source.as_ref())?.owner)
+    };
+    let result = copy::execute_with(plan, jobs, None, dir_owner, |op| {
+        let member = member(Some(&op.source))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no longer in the backup"))?;
+        let placed = copy::place_with(&mut |out| files.write_member(member, out), &op.dest)?;
+        let path = placed.path(&op.dest);
+        let file = File::options().write(true).open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(member.mode))?;
+        file.set_modified(member.mtime)?;
+        if let Some(owner) = ownership.owner_for(|| member.owner) {
+            let _ = owner.apply(path);
+        }
+        Ok(placed)
+    })?;
+
+    // Deepest first, so an mtime isn't undone by writing into the folder
+    // afterwards; the owner keeps full access to them
+    for (dest, member) in new_dirs.iter().rev() {
+        let _ = fs::set_permissions(dest, fs::Permissions::from_mode(member.mode | 0o700));
+        if let Ok(dir) = File::open(dest) {
+            let _ = dir.set_modified(member.mtime);
+        }
+    }
+    Ok(result)
+}
+
+/// Take back what a stream that then failed wrote: its files are removed
+/// and become errors, and the folders it created go too if left empty.
+fn undo(
+    result: &mut CopyResult,
+    written: Vec<(&CopyOp, PathBuf)>,
+    created_dirs: &[(PathBuf, u32)],
+    failure: &io::Error,
+) {
+    for (op, path) in written {
+        let _ = fs::remove_file(path);
+        let error = io::Error::new(
+            failure.kind(),
+            format!("archive failed after this file: {failure}"),
+        );
+        result.errors.push(copy_error(op, error));
+    }
+    result.copied.clear();
+    result.conflicts.clear();
+    result.bytes_copied = 0;
+    for (dir, _) in created_dirs.iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+fn copy_error(op: &CopyOp, error: io::Error) -> CopyError {
+    CopyError {
+        source: op.source.clone(),
+        dest: op.dest.clone(),
+        error,
+        xdg_dir: op.xdg_dir,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::age::tests::with_passphrase;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::tempdir;
+
+    const MTIME: u64 = 1_600_000_000;
+
+    fn file(builder: &mut tar::Builder<Vec<u8>>, path: &str, contents: &str) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o640);
+        header.set_mtime(MTIME);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+
+    fn home_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut dir = tar::Header::new_gnu();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_mode(0o755);
+        dir.set_mtime(MTIME);
+        dir.set_size(0);
+        builder
+            .append_data(&mut dir, "./home/joe/Documents/", io::empty())
+            .unwrap();
+        file(&mut builder, "./home/joe/Documents/a.txt", "old a");
+        file(&mut builder, "./home/joe/Documents/Pictures/b.txt", "b");
+        file(&mut builder, "./home/joe/Music/c.mp3", "music");
+        file(&mut builder, "./home/joe/.bashrc", "not restored");
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        builder
+            .append_link(&mut link, "./home/joe/Documents/link", "a.txt")
+            .unwrap();
+        // Appended again, as `tar --append` does; the later copy wins
+        file(&mut builder, "./home/joe/Documents/a.txt", "new a");
+        builder.into_inner().unwrap()
+    }
+
+    /// Restore the XDG folders of a backup read by `files` into `home`.
+    pub(crate) fn restore_files(files: &dyn Files, listing: &Listing, home: &Path) -> CopyResult {
+        let root = Path::new("/backup");
+        let mappings = find_xdg_folders(root, listing, home);
+        let plan = build_plan(root, listing, &mappings);
+        copy_plan(files, root, listing, &plan, 2, &Ownership::Unchanged).unwrap()
+    }
+
+    fn listing_of(data: Vec<u8>) -> Listing {
+        list_members(decompress(Box::new(Cursor::new(data))).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn lists_members_through_any_compression() {
+        let tar = home_tar();
+        let gzip = {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        };
+        // Two zstd frames, as parallel compressors write
+        let (front, back) = tar.split_at(1000);
+        let level = ruzstd::encoding::CompressionLevel::Fastest;
+        let mut zstd = ruzstd::encoding::compress_to_vec(front, level);
+        zstd.extend(ruzstd::encoding::compress_to_vec(back, level));
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &tar[..], &mut xz).unwrap();
+
+        for data in [tar.clone(), gzip, zstd, xz] {
+            let listing = listing_of(data);
+            assert_eq!(listing.skipped, 1);
+            let paths: Vec<&Path> = listing.members.iter().map(|m| m.path.as_path()).collect();
+            assert_eq!(
+                paths,
+                [
+                    "home/joe/Documents",
+                    "home/joe/Documents/a.txt",
+                    "home/joe/Documents/Pictures/b.txt",
+                    "home/joe/Music/c.mp3",
+                    "home/joe/.bashrc",
+                ]
+                .map(Path::new)
+            );
+            assert_eq!(listing.members[1].index, 6);
+        }
+
+        let archive = Path::new("/backups/home.tar");
+        let listing = listing_of(tar);
+        let mappings = find_xdg_folders(archive, &listing, Path::new("/h"));
+        let found: Vec<(&Path, &Path)> = mappings
+            .iter()
+            .map(|m| (m.source_path.as_path(), m.dest_path.as_path()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    Path::new("/backups/home.tar/home/joe/Documents"),
+                    Path::new("/h/Documents")
+                ),
+                (
+                    Path::new("/backups/home.tar/home/joe/Music"),
+                    Path::new("/h/Music")
+                ),
+            ]
+        );
+        assert_eq!(folder_bytes(archive, &listing, &mappings[0].source_path), 6);
+    }
+
+    #[test]
+    fn restores_from_an_encrypted_stream_without_staging() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("home.tar.age");
+        fs::write(&archive, with_passphrase("pw", &home_tar())).unwrap();
+        assert_eq!(detect_archive(&archive), Some(Encryption::Age));
+
+        let key = Key::Passphrase("pw".into());
+        let open = || open_stream(&archive, Encryption::Age, Some(&key)).unwrap();
+        let listing = list_members(open()).unwrap();
+        let home = dir.path().join("home");
+        let mappings = find_xdg_folders(&archive, &listing, &home);
+        let plan = build_plan(&archive, &listing, &mappings);
+        assert_eq!(plan.files.len(), 3);
+        assert_eq!(plan.total_bytes, 11);
+
+        fs::create_dir_all(home.join("Documents")).unwrap();
+        fs::write(home.join("Documents/a.txt"), "mine").unwrap();
+        let result =
+            extract_plan(open(), &archive, &listing, &plan, &Ownership::Unchanged).unwrap();
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert_eq!(result.copied.len(), 2);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(
+            fs::read_to_string(home.join("Documents/a.txt")).unwrap(),
+            "mine"
+        );
+        assert_eq!(
+            fs::read_to_string(&result.conflicts[0].restore_path).unwrap(),
+            "new a"
+        );
+        let meta = fs::metadata(home.join("Documents/Pictures/b.txt")).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o640);
+        assert_eq!(meta.mtime(), MTIME.cast_signed());
+        assert!(!home.join(".bashrc").exists());
+        assert!(!home.join("Documents/link").exists());
+
+        let wrong = Key::Passphrase("nope".into());
+        assert!(matches!(
+            open_stream(&archive, Encryption::Age, Some(&wrong)),
+            Err(ArchiveError::Age(AgeError::WrongPassphrase))
+        ));
+    }
+
+    #[test]
+    fn reports_files_cut_off_by_a_damaged_stream() {
+        let dir = tempdir().unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        // Big enough to span several age chunks
+        let big = "x".repeat(200_000);
+        file(&mut builder, "Documents/big.txt", &big);
+        file(&mut builder, "Documents/after.txt", "after");
+        let tar = builder.into_inner().unwrap();
+
+        let archive = dir.path().join("home.tar.age");
+        let key = Key::Passphrase("pw".into());
+        fs::write(&archive, with_passphrase("pw", &tar)).unwrap();
+        let listing =
+            list_members(open_stream(&archive, Encryption::Age, Some(&key)).unwrap()).unwrap();
+        let home = dir.path().join("home");
+        let plan = build_plan(
+            &archive,
+            &listing,
+            &find_xdg_folders(&archive, &listing, &home),
+        );
+
+        // Flip a byte in the second chunk
+        let mut damaged = fs::read(&archive).unwrap();
+        let at = damaged.len() - 100_000;
+        damaged[at] ^= 1;
+        fs::write(&archive, damaged).unwrap();
+        let stream = open_stream(&archive, Encryption::Age, Some(&key)).unwrap();
+        let result =
+            extract_plan(stream, &archive, &listing, &plan, &Ownership::Unchanged).unwrap();
+        assert!(result.copied.is_empty());
+        assert_eq!(result.errors.len(), 2);
+        // No partly written plaintext is left behind
+        assert!(!home.join("Documents/big.txt").exists());
+    }
+
+    #[test]
+    fn removes_what_a_tampered_gpg_archive_wrote() {
+        let dir = tempdir().unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        // Large enough for gpg to pass it on before the integrity check
+        let first: String = (0..1_000_000u32)
+            .map(|i| char::from(b'a' + (i * 7 % 26) as u8))
+            .collect();
+        file(&mut builder, "Documents/first.txt", &first);
+        file(&mut builder, "Documents/last.txt", "last");
+        let tar = dir.path().join("home.tar");
+        fs::write(&tar, builder.into_inner().unwrap()).unwrap();
+        let archive = dir.path().join("home.tar.gpg");
+        let encrypted = Command::new("gpg")
+            .env("GNUPGHOME", dir.path())
+            .args(["--batch", "--quiet", "--pinentry-mode", "loopback"])
+            .args([
+                "--compress-algo",
+                "none",
+                "--passphrase",
+                "pw",
+                "--symmetric",
+            ])
+            .arg("--output")
+            .args([&archive, &tar])
+            .stderr(Stdio::null())
+            .status();
+        if !encrypted.is_ok_and(|s| s.success()) {
+            eprintln!("gpg not available; skipping");
+            return;
+        }
+        let key = Key::Passphrase("pw".into());
+        let listing =
+            list_members(open_stream(&archive, Encryption::Gpg, Some(&key)).unwrap()).unwrap();
+        let home = dir.path().join("home");
+        let plan = build_plan(
+            &archive,
+            &listing,
+            &find_xdg_folders(&archive, &listing, &home),
+        );
+
+        // Change a byte of the first file; only the check at the end sees it
+        let mut tampered = fs::read(&archive).unwrap();
+        tampered[500_000] ^= 1;
+        fs::write(&archive, tampered).unwrap();
+        let stream = open_stream(&archive, Encryption::Gpg, Some(&key)).unwrap();
+        let result =
+            extract_plan(stream, &archive, &listing, &plan, &Ownership::Unchanged).unwrap();
+
+        assert!(result.copied.is_empty());
+        assert_eq!(result.errors.len(), 2);
+        assert!(!home.join("Documents/first.txt").exists());
+        assert!(!home.join("Documents/last.txt").exists());
+    }
+
+    #[test]
+    fn rejects_members_that_climb_out() {
+        assert!(matches!(
+            member_path(Path::new("home/../../etc/passwd")),
+            Err(ArchiveError::UnsafePath(_))
+        ));
+        assert_eq!(
+            member_path(Path::new("/home/./joe")).unwrap(),
+            Some(PathBuf::from("home/joe"))
+        );
+        assert_eq!(member_path(Path::new("./")).unwrap(), None);
+    }
+
+    #[test]
+    fn decrypts_gpg_archives_when_gpg_is_installed() {
+        let dir = tempdir().unwrap();
+        let plain = dir.path().join("home.tar");
+        fs::write(&plain, home_tar()).unwrap();
+        let archive = dir.path().join("home.tar.gpg");
+        let encrypted = Command::new("gpg")
+            .env("GNUPGHOME", dir.path())
+            .args(["--batch", "--quiet", "--pinentry-mode", "loopback"])
+            .args(["--passphrase", "pw", "--symmetric", "--output"])
+            .args([&archive, &plain])
+            .stderr(Stdio::null())
+            .status();
+        if !encrypted.is_ok_and(|s| s.success()) {
+            eprintln!("gpg not available; skipping");
+            return;
+        }
+        assert_eq!(detect_archive(&archive), Some(Encryption::Gpg));
+
+        let key = Key::Passphrase("pw".into());
+        let listing =
+            list_members(open_stream(&archive, Encryption::Gpg, Some(&key)).unwrap()).unwrap();
+        assert_eq!(listing.members.len(), 5);
+
+        let wrong = Key::Passphrase("nope".into());
+        let stream = open_stream(&archive, Encryption::Gpg, Some(&wrong));
+        assert!(stream.and_then(list_members).is_err());
+    }
+
+    #[test]
+    fn keeps_owners_mapped_through_the_backups_accounts() {
+        let mut builder = tar::Builder::new(Vec::new());
+        file(
+            &mut builder,
+            "laptop/etc/passwd",
+            "joe:x:1000:1000::/home/joe:/bin/sh\n",
+        );
+        file(&mut builder, "laptop/etc/group", "joe:x:1000:\n");
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_uid(1000);
+        header.set_gid(1000);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, "laptop/home/joe/Documents/a.txt", &b"mine"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let listing = listing_of(tar.clone());
+        let owner = listing.members[2].owner.unwrap();
+        assert_eq!((owner.uid, owner.gid), (1000, 1000));
+        let docs = Path::new("laptop/home/joe/Documents");
+        let (etc, passwd, group) = listing.account_files(&[docs]).unwrap();
+        assert_eq!(etc, Path::new("laptop/etc"));
+        assert!(passwd.starts_with("joe:") && group.starts_with("joe:"));
+
+        if !crate::users::is_root() {
+            return;
+        }
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("laptop.tar");
+        let mappings = find_xdg_folders(&archive, &listing, &dir.path().join("home"));
+        let plan = build_plan(&archive, &listing, &mappings);
+        let mut map = crate::users::IdMap::default();
+        map.users.insert(1000, 4242);
+        let ownership = Ownership::Preserve(map);
+        extract_plan(Cursor::new(tar), &archive, &listing, &plan, &ownership).unwrap();
+        let restored = fs::metadata(dir.path().join("home/Documents/a.txt")).unwrap();
+        assert_eq!((restored.uid(), restored.gid()), (4242, 1000));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::plan::walk_source;
+use crate::preview::contents_identical;
+use crate::types::DetectedMapping;
+
+/// What a candidate folder holds, for judging which copy is current.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CandidateStats {
+    pub file_count: usize,
+    pub total_bytes: u64,
+    pub newest: Option<SystemTime>,
+    pub oldest: Option<SystemTime>,
+    /// Files whose relative path also exists in another candidate.
+    pub shared: usize,
+}
+
+impl CandidateStats {
+    /// Share of this candidate's files also present in another candidate.
+    pub fn overlap(&self) -> f64 {
+        if self.file_count == 0 {
+            return 0.0;
+        }
+        self.shared as f64 / self.file_count as f64
+    }
+}
+
+/// One of several backup folders mapping to the same XDG directory.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub mapping: DetectedMapping,
+    pub stats: CandidateStats,
+    /// Likelihood of being the copy to restore, from 0 to 1.
+    pub score: f64,
+}
+
+/// Score candidates for the same XDG directory, most likely first.
+///
+/// The score favours the candidate with the most recent file (half the
+/// weight), then the one with the most files, then the one with the most
+/// files no other candidate has. Ties go to the shallower path.
+pub fn rank_candidates(mappings: Vec<DetectedMapping>) -> std::io::Result<Vec<Candidate>> {
+    let mut listings = Vec::new();
+    for m in &mappings {
+        let files: Vec<(PathBuf, u64, Option<SystemTime>)> = walk_source(&m.source_path)?
+            .into_iter()
+            .filter(|e| !e.is_dir)
+            .map(|e| (e.relative, e.size, e.mtime))
+            .collect();
+        listings.push(files);
+    }
+
+    // How many candidates hold each relative path
+    let mut holders: HashMap<&Path, usize> = HashMap::new();
+    for files in &listings {
+        let unique: HashSet<&Path> = files.iter().map(|(p, _, _)| p.as_path()).collect();
+        for p in unique {
+            *holders.entry(p).or_default() += 1;
+        }
+    }
+
+    let stats: Vec<CandidateStats> = listings
+        .iter()
+        .map(|files| CandidateStats {
+            file_count: files.len(),
+            total_bytes: files.iter().map(|f| f.1).sum(),
+            newest: files.iter().filter_map(|f| f.2).max(),
+            oldest: files.iter().filter_map(|f| f.2).min(),
+            shared: files.iter().filter(|f| holders[f.0.as_path()] > 1).count(),
+        })
+        .collect();
+
+    let scores = score(&stats);
+    let mut candidates: Vec<Candidate> = mappings
+        .into_iter()
+        .zip(stats)
+        .zip(scores)
+        .map(|((mapping, stats), score)| Candidate {
+            mapping,
+            stats,
+            score,
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        b.score.total_cmp(&a.score).then_with(|| {
+            let depth = |c: &Candidate| c.mapping.source_path.components().count();
+            depth(a)
+                .cmp(&depth(b))
+                .then_with(|| a.mapping.source_path.cmp(&b.mapping.source_path))
+        })
+    });
+    Ok(candidates)
+}
+
+fn score(stats: &[CandidateStats]) -> Vec<f64> {
+    let secs = |t: Option<SystemTime>| {
+        t.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0.0, |d| d.as_secs_f64())
+    };
+    let newest: Vec<f64> = stats.iter().map(|s| secs(s.newest)).collect();
+    let min_newest = newest.iter().copied().fold(f64::INFINITY, f64::min);
+    let max_newest = newest.iter().copied().fold(0.0, f64::max);
+    let max_count = stats.iter().map(|s| s.file_count).max().unwrap_or(0);
+
+    stats
+        .iter()
+        .zip(newest)
+        .map(|(s, newest)| {
+            if s.file_count == 0 {
+                return 0.0;
+            }
+            let recency = if max_newest > min_newest {
+                (newest - min_newest) / (max_newest - min_newest)
+            } else {
+                1.0
+            };
+            let size = s.file_count as f64 / max_count as f64;
+            let unique = 1.0 - s.overlap();
+            0.5 * recency + 0.3 * size + 0.2 * unique
+        })
+        .collect()
+}
+
+/// What differs between two folders, by path relative to each.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Comparison {
+    pub only_a: Vec<PathBuf>,
+    pub only_b: Vec<PathBuf>,
+    /// In both, with different contents.
+    pub differing: Vec<PathBuf>,
+    /// In both, byte-identical.
+    pub identical: usize,
+    /// Total size of the files only in A.
+    pub only_a_bytes: u64,
+    /// Total size of the files only in B.
+    pub only_b_bytes: u64,
+}
+
+/// Compare two folders file by file. Paths in the result are sorted.
+pub fn compare_folders(a: &Path, b: &Path) -> std::io::Result<Comparison> {
+    let files = |root: &Path| -> std::io::Result<HashMap<PathBuf, u64>> {
+        Ok(walk_source(root)?
+            .into_iter()
+            .filter(|e| !e.is_dir)
+            .map(|e| (e.relative, e.size))
+            .collect())
+    };
+    let a_files = files(a)?;
+    let mut b_files = files(b)?;
+
+    let mut cmp = Comparison::default();
+    for (path, size) in a_files {
+        match b_files.remove(&path) {
+            None => {
+                cmp.only_a_bytes += size;
+                cmp.only_a.push(path);
+            }
+            Some(b_size) => {
+                if size == b_size && contents_identical(&a.join(&path), &b.join(&path))? {
+                    cmp.identical += 1;
+                } else {
+                    cmp.differing.push(path);
+                }
+            }
+        }
+    }
+    for (path, size) in b_files {
+        cmp.only_b_bytes += size;
+        cmp.only_b.push(path);
+    }
+
+    cmp.only_a.sort();
+    cmp.only_b.sort();
+    cmp.differing.sort();
+    Ok(cmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::XdgDir;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, contents: &str, age_days: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(age_days * 86_400);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn mapping(source: PathBuf) -> DetectedMapping {
+        DetectedMapping {
+            xdg_dir: XdgDir::Documents,
+            source_path: source,
+            dest_path: PathBuf::from("/home/u/Documents"),
+        }
+    }
+
+    #[test]
+    fn ranks_current_copy_first_and_reports_stats() {
+        let backup = tempdir().unwrap();
+        let old = backup.path().join("old-backup/Documents");
+        let current = backup.path().join("Documents");
+        write(&old.join("a.txt"), "aaaa", 400);
+        write(&old.join("b.txt"), "bb", 300);
+        write(&current.join("a.txt"), "aaaa", 400);
+        write(&current.join("b.txt"), "bbb", 2);
+        write(&current.join("c.txt"), "c", 1);
+
+        let ranked = rank_candidates(vec![mapping(old.clone()), mapping(current.clone())]).unwrap();
+
+        assert_eq!(ranked[0].mapping.source_path, current);
+        assert_eq!(ranked[0].stats.file_count, 3);
+        assert_eq!(ranked[0].stats.total_bytes, 8);
+        assert_eq!(ranked[0].stats.shared, 2);
+        assert!(ranked[0].stats.newest > ranked[1].stats.newest);
+        assert!(ranked[0].stats.oldest < ranked[0].stats.newest);
+
+        assert_eq!(ranked[1].mapping.source_path, old);
+        assert!((ranked[1].stats.overlap() - 1.0).abs() < f64::EPSILON);
+        assert!(ranked[0].score > ranked[1].score);
+    }
+
+    #[test]
+    fn empty_candidates_rank_last() {
+        let backup = tempdir().unwrap();
+        let empty = backup.path().join("Documents");
+        let full = backup.path().join("laptop/home/Documents");
+        fs::create_dir_all(&empty).unwrap();
+        write(&full.join("x.txt"), "x", 10);
+
+        let ranked = rank_candidates(vec![mapping(empty.clone()), mapping(full.clone())]).unwrap();
+
+        assert_eq!(ranked[0].mapping.source_path, full);
+        assert_eq!(ranked[1].stats, CandidateStats::default());
+        assert!(ranked[1].score.abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn ties_prefer_shallower_path() {
+        let backup = tempdir().unwrap();
+        let deep = backup.path().join("a/b/Documents");
+        let shallow = backup.path().join("z/Documents");
+        write(&deep.join("x.txt"), "x", 5);
+        write(&shallow.join("x.txt"), "x", 5);
+        let mtime = fs::metadata(deep.join("x.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        fs::File::options()
+            .write(true)
+            .open(shallow.join("x.txt"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let ranked = rank_candidates(vec![mapping(deep), mapping(shallow.clone())]).unwrap();
+
+        assert_eq!(ranked[0].mapping.source_path, shallow);
+    }
+
+    #[test]
+    fn compares_two_folders() {
+        let backup = tempdir().unwrap();
+        let a = backup.path().join("Documents");
+        let b = backup.path().join("old/Documents");
+        write(&a.join("same.txt"), "same", 1);
+        write(&b.join("same.txt"), "same", 9);
+        write(&a.join("sub/changed.txt"), "new", 1);
+        write(&b.join("sub/changed.txt"), "old", 9);
+        write(&a.join("longer.txt"), "long", 1);
+        write(&b.join("longer.txt"), "longer", 9);
+        write(&a.join("mine.txt"), "m", 1);
+        write(&b.join("z/theirs.txt"), "tt", 9);
+        write(&b.join("a/theirs.txt"), "t", 9);
+
+        let cmp = compare_folders(&a, &b).unwrap();
+
+        assert_eq!(cmp.only_a, [PathBuf::from("mine.txt")]);
+        assert_eq!(
+            cmp.only_b,
+            [PathBuf::from("a/theirs.txt"), PathBuf::from("z/theirs.txt")]
+        );
+        assert_eq!(
+            cmp.differing,
+            [
+                PathBuf::from("longer.txt"),
+                PathBuf::from("sub/changed.txt")
+            ]
+        );
+        assert_eq!(cmp.identical, 1);
+        assert_eq!((cmp.only_a_bytes, cmp.only_b_bytes), (1, 3));
+    }
+}
@@ -1,5 +1,18 @@
-use crate::types::Conflict;
+use crate::copy::original_names;
+use crate::dotfiles::Category;
+use crate::merge::merge_texts;
+use crate::trash::Trash;
+use crate::types::{Conflict, XdgDir};
+use std::ffi::CString;
+use std::fmt;
 use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
 
 /// What to do with a conflict.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -10,22 +23,328 @@ pub enum Resolution {
     KeepOriginal,
     /// Leave both files in place.
     LeaveAsIs,
+    /// Merge the two text versions into the original, marking divergent
+    /// regions with conflict markers.
+    Merge,
 }
 
-/// Apply a resolution to a single conflict.
-pub fn apply_resolution(conflict: &Conflict, resolution: Resolution) -> std::io::Result<()> {
-    match resolution {
-        Resolution::Overwrite => {
-            fs::rename(&conflict.restore_path, &conflict.original_path)?;
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Resolution::Overwrite => "overwrite",
+            Resolution::KeepOriginal => "keep original",
+            Resolution::LeaveAsIs => "keep both",
+            Resolution::Merge => "merge",
+        })
+    }
+}
+
+/// What happens to the file a resolution displaces.
+#[derive(Debug, Clone, Copy)]
+pub enum Disposal<'a> {
+    /// Delete it (or let the rename replace it) permanently.
+    Delete,
+    /// Move it to the trash so it can be recovered.
+    Trash(&'a Trash),
+}
+
+/// Why a resolution could not be applied.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The `.restore` file is gone, so there is nothing to resolve.
+    RestoreMissing(PathBuf),
+    /// The original changed after the conflict was recorded; overwriting
+    /// it would lose those changes.
+    OriginalModified(PathBuf),
+    /// Only text files can be merged.
+    NotText(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::RestoreMissing(p) => {
+                write!(f, "restore file {} no longer exists", p.display())
+            }
+            ResolveError::OriginalModified(p) => {
+                write!(f, "{} was modified since the copy", p.display())
+            }
+            ResolveError::NotText(p) => {
+                write!(f, "{} is not a text file and cannot be merged", p.display())
+            }
+            ResolveError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResolveError::Io(e) => Some(e),
+            _ => None,
         }
+    }
+}
+
+impl From<io::Error> for ResolveError {
+    fn from(e: io::Error) -> Self {
+        ResolveError::Io(e)
+    }
+}
+
+/// Apply a resolution to a single conflict, permanently discarding the
+/// losing file.
+pub fn apply_resolution(conflict: &Conflict, resolution: Resolution) -> Result<(), ResolveError> {
+    apply_resolution_with(conflict, resolution, Disposal::Delete)
+}
+
+/// Apply a resolution to a single conflict, disposing of the losing file
+/// as requested.
+///
+/// Overwrites atomically swap the two files with `renameat2(RENAME_EXCHANGE)`,
+/// so the original path always holds one complete version; the displaced
+/// original then sits on the `.restore` path until it is deleted or trashed.
+pub fn apply_resolution_with(
+    conflict: &Conflict,
+    resolution: Resolution,
+    disposal: Disposal,
+) -> Result<(), ResolveError> {
+    if resolution == Resolution::LeaveAsIs {
+        return Ok(());
+    }
+    if let Err(e) = fs::symlink_metadata(&conflict.restore_path) {
+        return Err(match e.kind() {
+            io::ErrorKind::NotFound => ResolveError::RestoreMissing(conflict.restore_path.clone()),
+            _ => e.into(),
+        });
+    }
+
+    match resolution {
+        Resolution::Overwrite => replace_original(conflict, &conflict.restore_path, disposal),
         Resolution::KeepOriginal => {
-            fs::remove_file(&conflict.restore_path)?;
+            dispose(&conflict.restore_path, &conflict.restore_path, disposal)?;
+            Ok(())
+        }
+        Resolution::Merge => merge_into_original(conflict, None, disposal).map(|_| ()),
+        Resolution::LeaveAsIs => Ok(()),
+    }
+}
+
+/// Merge the `.restore` version into the original and discard the
+/// `.restore` file. With an `ancestor` (the same file from an older
+/// backup) the merge is three-way. Returns the number of regions left
+/// with conflict markers.
+pub fn merge_into_original(
+    conflict: &Conflict,
+    ancestor: Option<&Path>,
+    disposal: Disposal,
+) -> Result<usize, ResolveError> {
+    let ours = read_text(&conflict.original_path)?;
+    let theirs = read_text(&conflict.restore_path)?;
+    let base = ancestor.map(read_text).transpose()?;
+    let merged = merge_texts(base.as_deref(), &ours, &theirs);
+
+    // Stage the result next to the original so the swap stays on one filesystem
+    let mut staged_name = std::ffi::OsString::from(".");
+    staged_name.push(conflict.original_path.file_name().unwrap_or_default());
+    staged_name.push(format!(".merge-{}", std::process::id()));
+    let staged = conflict.original_path.with_file_name(staged_name);
+    fs::File::create_new(&staged)?.write_all(merged.text.as_bytes())?;
+    if let Ok(meta) = fs::metadata(&conflict.original_path) {
+        let _ = fs::set_permissions(&staged, meta.permissions());
+        // Merging as root in another user's home must not hand them a
+        // root-owned file
+        let _ = std::os::unix::fs::chown(&staged, Some(meta.uid()), Some(meta.gid()));
+    }
+
+    if let Err(e) = replace_original(conflict, &staged, disposal) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    dispose(&conflict.restore_path, &conflict.restore_path, disposal)?;
+    Ok(merged.conflicts)
+}
+
+fn read_text(path: &Path) -> Result<String, ResolveError> {
+    let bytes = fs::read(path)?;
+    if bytes.contains(&0) {
+        return Err(ResolveError::NotText(path.to_path_buf()));
+    }
+    String::from_utf8(bytes).map_err(|_| ResolveError::NotText(path.to_path_buf()))
+}
+
+/// Put `replacement` (a file in the original's directory) in place of the
+/// original and dispose of the original's old contents.
+fn replace_original(
+    conflict: &Conflict,
+    replacement: &Path,
+    disposal: Disposal,
+) -> Result<(), ResolveError> {
+    let (original, restore) = (&conflict.original_path, replacement);
+
+    if !original_unchanged(conflict)? {
+        return Err(ResolveError::OriginalModified(original.clone()));
+    }
+    if !original.exists() {
+        // Nothing to displace
+        fs::rename(restore, original)?;
+        return Ok(());
+    }
+
+    match exchange(restore, original) {
+        Ok(()) => {
+            // The original now lives at the .restore path. If it changed
+            // between the check and the swap, swap back.
+            if !mtime_matches(restore, conflict.original_mtime)? {
+                exchange(restore, original)?;
+                return Err(ResolveError::OriginalModified(original.clone()));
+            }
+            dispose(restore, original, disposal)?;
         }
-        Resolution::LeaveAsIs => {}
+        Err(e) if exchange_unsupported(&e) => {
+            // Filesystems like some FUSE mounts lack RENAME_EXCHANGE
+            if let Disposal::Trash(trash) = disposal {
+                trash.trash(original)?;
+            }
+            fs::rename(restore, original)?;
+        }
+        Err(e) => return Err(e.into()),
     }
     Ok(())
 }
 
+/// Whether the original still has the mtime recorded with the conflict.
+/// A missing original counts as unchanged: there is nothing to lose.
+fn original_unchanged(conflict: &Conflict) -> io::Result<bool> {
+    match mtime_matches(&conflict.original_path, conflict.original_mtime) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+        other => other,
+    }
+}
+
+fn mtime_matches(path: &Path, expected: Option<SystemTime>) -> io::Result<bool> {
+    let actual = fs::symlink_metadata(path)?.modified().ok();
+    Ok(match (expected, actual) {
+        (Some(expected), Some(actual)) => expected == actual,
+        _ => true,
+    })
+}
+
+/// Delete or trash `path`, recording it in the trash as `recorded_as`.
+fn dispose(path: &Path, recorded_as: &Path, disposal: Disposal) -> io::Result<()> {
+    match disposal {
+        Disposal::Delete => fs::remove_file(path),
+        Disposal::Trash(trash) => trash.trash_as(path, recorded_as).map(|_| ()),
+    }
+}
+
+/// Atomically swap two paths.
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both pointers are valid NUL-terminated strings that outlive
+    // the call; AT_FDCWD makes them relative to the working directory.
+    let rc = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn exchange_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP)
+    )
+}
+
+/// Conflicts left behind by earlier restores.
+pub struct LeftoverScan {
+    pub conflicts: Vec<Conflict>,
+    pub warnings: Vec<walkdir::Error>,
+}
+
+/// Rebuild conflict records from `.restore` files under the home directory's
+/// XDG folders and the hidden folders of the `include` categories. Trash
+/// folders are never searched: what was thrown away stays thrown away.
+///
+/// A `.restore` file only counts as a conflict if its original still exists
+/// next to it; when a name is ambiguous (`notes.restore.2`) the first
+/// existing original wins.
+pub fn find_leftover_conflicts(home_dir: &Path, include: &[Category]) -> LeftoverScan {
+    let mut conflicts = Vec::new();
+    let mut warnings = Vec::new();
+
+    let hidden = include
+        .iter()
+        .flat_map(|c| c.dirs())
+        .map(|&d| XdgDir::Hidden(d));
+    for xdg_dir in XdgDir::ALL.into_iter().chain(hidden) {
+        let root = home_dir.join(xdg_dir.dir_name());
+        if !root.is_dir() {
+            continue;
+        }
+        let home_trash = root.join("Trash");
+        let walk = WalkDir::new(&root).into_iter().filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            !(e.path() == home_trash || name == ".Trash" || name.starts_with(".Trash-"))
+        });
+        for entry in walk {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warnings.push(e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str() else {
+                continue;
+            };
+            let parent = entry.path().parent().unwrap_or(&root);
+            let Some(original_path) = original_names(name)
+                .into_iter()
+                .map(|n| parent.join(n))
+                .find(|p| p.is_file())
+            else {
+                continue;
+            };
+            let size = match entry.metadata() {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    warnings.push(e);
+                    continue;
+                }
+            };
+            let original_mtime = fs::metadata(&original_path).and_then(|m| m.modified()).ok();
+            conflicts.push(Conflict {
+                restore_path: entry.path().to_path_buf(),
+                original_path,
+                size,
+                original_mtime,
+                xdg_dir,
+            });
+        }
+    }
+
+    LeftoverScan {
+        conflicts,
+        warnings,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,6 +360,7 @@ mod tests {
             restore_path: dir.join("photo.restore.jpg"),
             original_path: dir.join("photo.jpg"),
             size: 8,
+            original_mtime: None,
             xdg_dir: XdgDir::Pictures,
         }
     }
@@ -91,4 +411,305 @@ mod tests {
             "restored"
         );
     }
+
+    #[test]
+    fn overwrite_can_trash_the_original() {
+        let dir = tempdir().unwrap();
+        let conflict = make_conflict(dir.path());
+        let trash = Trash::new(dir.path().join("Trash"));
+
+        apply_resolution_with(&conflict, Resolution::Overwrite, Disposal::Trash(&trash)).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "restored"
+        );
+        assert!(!dir.path().join("photo.restore.jpg").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("Trash/files/photo.jpg")).unwrap(),
+            "original"
+        );
+        assert!(dir.path().join("Trash/info/photo.jpg.trashinfo").exists());
+    }
+
+    #[test]
+    fn keep_original_can_trash_the_restore_copy() {
+        let dir = tempdir().unwrap();
+        let conflict = make_conflict(dir.path());
+        let trash = Trash::new(dir.path().join("Trash"));
+
+        apply_resolution_with(&conflict, Resolution::KeepOriginal, Disposal::Trash(&trash))
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "original"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("Trash/files/photo.restore.jpg")).unwrap(),
+            "restored"
+        );
+    }
+
+    #[test]
+    fn overwrite_refuses_when_original_changed_since_copy() {
+        let dir = tempdir().unwrap();
+        let mut conflict = make_conflict(dir.path());
+        conflict.original_mtime = Some(SystemTime::UNIX_EPOCH);
+
+        let err = apply_resolution(&conflict, Resolution::Overwrite).unwrap_err();
+
+        assert!(matches!(err, ResolveError::OriginalModified(_)));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "original"
+        );
+        assert!(dir.path().join("photo.restore.jpg").exists());
+    }
+
+    #[test]
+    fn overwrite_proceeds_when_original_unchanged() {
+        let dir = tempdir().unwrap();
+        let mut conflict = make_conflict(dir.path());
+        conflict.original_mtime = fs::metadata(dir.path().join("photo.jpg"))
+            .unwrap()
+            .modified()
+            .ok();
+
+        apply_resolution(&conflict, Resolution::Overwrite).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "restored"
+        );
+        assert!(!dir.path().join("photo.restore.jpg").exists());
+    }
+
+    #[test]
+    fn reports_vanished_restore_file() {
+        let dir = tempdir().unwrap();
+        let conflict = make_conflict(dir.path());
+        fs::remove_file(dir.path().join("photo.restore.jpg")).unwrap();
+
+        for resolution in [Resolution::Overwrite, Resolution::KeepOriginal] {
+            let err = apply_resolution(&conflict, resolution).unwrap_err();
+            assert!(matches!(err, ResolveError::RestoreMissing(_)));
+        }
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "original"
+        );
+    }
+
+    #[test]
+    fn merge_writes_conflict_markers_and_drops_restore() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("todo.txt"), "milk\nbread\n").unwrap();
+        fs::write(dir.path().join("todo.restore.txt"), "milk\nbutter\n").unwrap();
+        let conflict = Conflict {
+            restore_path: dir.path().join("todo.restore.txt"),
+            original_path: dir.path().join("todo.txt"),
+            size: 12,
+            original_mtime: None,
+            xdg_dir: XdgDir::Documents,
+        };
+
+        apply_resolution(&conflict, Resolution::Merge).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("todo.txt")).unwrap(),
+            "milk\n<<<<<<< original\nbread\n=======\nbutter\n>>>>>>> restore\n"
+        );
+        assert!(!dir.path().join("todo.restore.txt").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn merge_uses_ancestor_for_three_way() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("refs.bib"), "a\nb\nc\nADDED\n").unwrap();
+        fs::write(dir.path().join("refs.restore.bib"), "a\nB\nc\n").unwrap();
+        fs::write(dir.path().join("ancestor.bib"), "a\nb\nc\n").unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let conflict = Conflict {
+            restore_path: dir.path().join("refs.restore.bib"),
+            original_path: dir.path().join("refs.bib"),
+            size: 6,
+            original_mtime: None,
+            xdg_dir: XdgDir::Documents,
+        };
+
+        let conflicts = merge_into_original(
+            &conflict,
+            Some(&dir.path().join("ancestor.bib")),
+            Disposal::Trash(&trash),
+        )
+        .unwrap();
+
+        assert_eq!(conflicts, 0);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("refs.bib")).unwrap(),
+            "a\nB\nc\nADDED\n"
+        );
+        // Both pre-merge versions are recoverable
+        assert_eq!(
+            fs::read_to_string(dir.path().join("Trash/files/refs.bib")).unwrap(),
+            "a\nb\nc\nADDED\n"
+        );
+        assert!(dir.path().join("Trash/files/refs.restore.bib").exists());
+    }
+
+    #[test]
+    fn merge_refuses_binary_files() {
+        let dir = tempdir().unwrap();
+        let conflict = make_conflict(dir.path());
+        fs::write(&conflict.restore_path, b"\xFF\xD8\0binary").unwrap();
+
+        let err = apply_resolution(&conflict, Resolution::Merge).unwrap_err();
+
+        assert!(matches!(err, ResolveError::NotText(_)));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("photo.jpg")).unwrap(),
+            "original"
+        );
+        assert!(conflict.restore_path.exists());
+    }
+
+    #[test]
+    fn exchange_swaps_contents() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a"), "A").unwrap();
+        fs::write(dir.path().join("b"), "B").unwrap();
+
+        exchange(&dir.path().join("a"), &dir.path().join("b")).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.path().join("a")).unwrap(), "B");
+        assert_eq!(fs::read_to_string(dir.path().join("b")).unwrap(), "A");
+    }
+
+    #[test]
+    fn finds_leftover_restore_files_in_xdg_dirs() {
+        let home = tempdir().unwrap();
+        let pics = home.path().join("Pictures/2019");
+        let docs = home.path().join("Documents");
+        fs::create_dir_all(&pics).unwrap();
+        fs::create_dir_all(&docs).unwrap();
+        make_conflict(&pics);
+        fs::write(pics.join("photo.restore.2.jpg"), "again").unwrap();
+        fs::write(docs.join("Makefile"), "old").unwrap();
+        fs::write(docs.join("Makefile.restore"), "new").unwrap();
+        // Orphan: no original next to it
+        fs::write(docs.join("gone.restore.txt"), "x").unwrap();
+        // Outside any XDG dir
+        fs::write(home.path().join("stray.restore.txt"), "x").unwrap();
+        fs::write(home.path().join("stray.txt"), "x").unwrap();
+
+        let mut scan = find_leftover_conflicts(home.path(), &[]);
+        scan.conflicts
+            .sort_by(|a, b| a.restore_path.cmp(&b.restore_path));
+
+        let found: Vec<_> = scan
+            .conflicts
+            .iter()
+            .map(|c| (c.restore_path.clone(), c.original_path.clone(), c.xdg_dir))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    docs.join("Makefile.restore"),
+                    docs.join("Makefile"),
+                    XdgDir::Documents
+                ),
+                (
+                    pics.join("photo.restore.2.jpg"),
+                    pics.join("photo.jpg"),
+                    XdgDir::Pictures
+                ),
+                (
+                    pics.join("photo.restore.jpg"),
+                    pics.join("photo.jpg"),
+                    XdgDir::Pictures
+                ),
+            ]
+        );
+        assert_eq!(scan.conflicts[1].size, 5);
+    }
+
+    #[test]
+    fn multi_user_resolution_writes_to_each_users_own_trash() {
+        use crate::copy::{Owner, WriteAs};
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        if !crate::users::is_root() {
+            eprintln!("not root; skipping");
+            return;
+        }
+        let base = tempdir().unwrap();
+        fs::set_permissions(base.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        for uid in [4321, 4322] {
+            let home = base.path().join(uid.to_string());
+            let pics = home.join("Pictures");
+            fs::create_dir_all(&pics).unwrap();
+            let conflict = make_conflict(&pics);
+            for path in [
+                &home,
+                &pics,
+                &conflict.original_path,
+                &conflict.restore_path,
+            ] {
+                chown(path, Some(uid), Some(uid)).unwrap();
+            }
+
+            let trash = Trash::for_user(&home, uid);
+            let _writing = WriteAs::new(Some(Owner { uid, gid: uid }));
+            apply_resolution_with(&conflict, Resolution::Overwrite, Disposal::Trash(&trash))
+                .unwrap();
+        }
+
+        for uid in [4321, 4322] {
+            let trash = base.path().join(format!("{uid}/.local/share/Trash"));
+            let trashed = trash.join("files/photo.jpg");
+            assert_eq!(fs::read_to_string(&trashed).unwrap(), "original");
+            for path in [&trash, &trashed, &trash.join("info/photo.jpg.trashinfo")] {
+                let meta = fs::symlink_metadata(path).unwrap();
+                assert_eq!((meta.uid(), meta.gid()), (uid, uid), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn searches_included_hidden_folders_but_not_the_trash() {
+        let home = tempdir().unwrap();
+        let app = home.path().join(".local/share/app");
+        let trashed = home.path().join(".local/share/Trash/files");
+        let media_trash = home.path().join("Documents/.Trash-1000/files");
+        for dir in [&app, &trashed, &media_trash] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("db"), "old").unwrap();
+            fs::write(dir.join("db.restore"), "new").unwrap();
+        }
+
+        assert!(find_leftover_conflicts(home.path(), &[])
+            .conflicts
+            .is_empty());
+        let scan = find_leftover_conflicts(home.path(), &[Category::AppData, Category::Keys]);
+
+        let found: Vec<_> = scan.conflicts.iter().map(|c| &c.restore_path).collect();
+        assert_eq!(found, [&app.join("db.restore")]);
+    }
+
+    #[test]
+    fn picks_existing_original_for_ambiguous_numbered_name() {
+        let home = tempdir().unwrap();
+        let docs = home.path().join("Documents");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("notes.2"), "old").unwrap();
+        fs::write(docs.join("notes.restore.2"), "new").unwrap();
+
+        let scan = find_leftover_conflicts(home.path(), &[]);
+
+        assert_eq!(scan.conflicts.len(), 1);
+        assert_eq!(scan.conflicts[0].original_path, docs.join("notes.2"));
+    }
 }
@@ -1,31 +1,152 @@
+use std::collections::HashSet;
 use std::ffi::OsString;
 use std::fs::{self, File};
-use std::io;
+use std::io::{self, Read};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::time::SystemTime;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 
-use crate::types::{Conflict, CopiedFile, CopyError, CopyOp, CopyPlan, CopyResult};
+use crate::types::{Conflict, CopiedFile, CopyError, CopyOp, CopyPlan, CopyResult, DirOp};
+use crate::users::IdMap;
 
 /// Execute the copy plan, returning results with conflicts and errors.
 ///
 /// Creates all directories first, then copies files in parallel across
-/// `jobs` threads. If a destination file exists, writes to a `.restore`
-/// suffixed path instead and records a conflict.
+/// `jobs` threads. New folders and files are private to their owner until
+/// written, so keys are never readable by others on the way, and then take
+/// the backup's modes. If a destination file exists, writes to a `.restore`
+/// suffixed path instead and records a conflict. When several operations
+/// share a destination, the first in the plan is copied before the others,
+/// which become conflicts.
 pub fn execute_plan(plan: &CopyPlan, jobs: usize) -> io::Result<CopyResult> {
-    // Create all directories first
-    for dir_op in &plan.dirs {
-        fs::create_dir_all(&dir_op.dest)?;
+    execute_plan_as(plan, jobs, &Ownership::Unchanged)
+}
+
+/// A uid and gid, such as the owner of a restored file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    /// The owner of `path`, following symlinks.
+    pub fn of(path: &Path) -> Option<Owner> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Owner {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
     }
 
-    let progress = ProgressBar::new(plan.total_bytes);
-    progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{bar:40} {bytes}/{total_bytes} ({eta})")
-            .unwrap(),
-    );
+    /// Give `path` to this owner, not following symlinks.
+    pub(crate) fn apply(self, path: &Path) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, Some(self.uid), Some(self.gid))
+    }
+}
+
+/// Who restored files and folders belong to.
+#[derive(Debug, Clone, Default)]
+pub enum Ownership {
+    /// Whoever runs the restore, as with any new file.
+    #[default]
+    Unchanged,
+    /// One account, when restoring into another user's home as root.
+    User(Owner),
+    /// The owner each file or folder has in the backup, translated to this
+    /// system's ids. Needs root.
+    Preserve(IdMap),
+}
+
+impl Ownership {
+    /// The owner for something restored from a source owned by `source`,
+    /// or `None` to leave it be. `source` is only asked when needed.
+    pub(crate) fn owner_for(&self, source: impl FnOnce() -> Option<Owner>) -> Option<Owner> {
+        match self {
+            Ownership::Unchanged => None,
+            Ownership::User(owner) => Some(*owner),
+            Ownership::Preserve(map) => source().map(|owner| map.map(owner)),
+        }
+    }
+}
+
+/// [`execute_plan`], setting the owner of everything it creates as
+/// `ownership` says: the files written, `.restore` files included, and the
+/// folders that did not exist yet. Existing folders keep their owner.
+///
+/// With [`Ownership::User`], root writes with that user's file access, so
+/// a symlink in their home (`Documents -> /etc`) can't send the restore
+/// anywhere they could not write themselves.
+pub fn execute_plan_as(
+    plan: &CopyPlan,
+    jobs: usize,
+    ownership: &Ownership,
+) -> io::Result<CopyResult> {
+    let writer = match ownership {
+        Ownership::User(owner) => Some(*owner),
+        _ => None,
+    };
+    let dir_owner =
+        |dir_op: &DirOp| ownership.owner_for(|| dir_op.source.as_deref().and_then(Owner::of));
+    execute_with(plan, jobs, writer, dir_owner, |op| {
+        // Read as ourselves, write as the user
+        let mut file = File::open(&op.source)?;
+        let metadata = file.metadata()?;
+        let _writing = WriteAs::new(writer);
+        let placed = place_file(&mut file, &op.dest)?;
+        preserve_metadata(&metadata, placed.path(&op.dest));
+        if let Some(owner) = ownership.owner_for(|| Owner::of(&op.source)) {
+            let _ = owner.apply(placed.path(&op.dest));
+        }
+        Ok(placed)
+    })
+}
+
+/// Run `plan`: create its folders, giving those that are new the owner
+/// `dir_owner` picks, then write each file with `place` across `jobs`
+/// threads, as [`execute_plan`] describes. The folders are created with
+/// `writer`'s file access when given; `place` switches for itself.
+pub(crate) fn execute_with(
+    plan: &CopyPlan,
+    jobs: usize,
+    writer: Option<Owner>,
+    mut dir_owner: impl FnMut(&DirOp) -> Option<Owner>,
+    place: impl Fn(&CopyOp) -> io::Result<Placed> + Sync,
+) -> io::Result<CopyResult> {
+    // The backup's folder modes, read before switching to `writer`
+    let modes: Vec<Option<u32>> = plan
+        .dirs
+        .iter()
+        .map(|dir_op| Some(fs::metadata(dir_op.source.as_ref()?).ok()?.mode()))
+        .collect();
+
+    // Create all directories first, private until the files are in them
+    let mut created = Vec::new();
+    {
+        let _writing = WriteAs::new(writer);
+        for (dir_op, mode) in plan.dirs.iter().zip(modes) {
+            let new_dirs = create_private_dirs(&dir_op.dest)?;
+            // Parents made on the way belong to the same owner as the folder
+            if new_dirs.last() == Some(&dir_op.dest) {
+                if let Some(owner) = dir_owner(dir_op) {
+                    for dir in &new_dirs {
+                        let _ = owner.apply(dir);
+                    }
+                }
+            }
+            let count = new_dirs.len();
+            for (i, dir) in new_dirs.into_iter().enumerate() {
+                let mode = mode.filter(|_| i + 1 == count);
+                created.push((dir, mode.map_or_else(default_dir_mode, |m| m | 0o700)));
+            }
+        }
+    }
+
+    let progress = progress_bar(plan.total_bytes);
 
     let result = Mutex::new(CopyResult {
         copied: Vec::new(),
@@ -39,102 +160,215 @@ pub fn execute_plan(plan: &CopyPlan, jobs: usize) -> io::Result<CopyResult> {
         .build()
         .unwrap();
 
-    pool.install(|| {
-        plan.files.par_iter().for_each(|op| {
-            copy_file(op, &result, &progress);
-        });
-    });
-
-    progress.finish_and_clear();
-    Ok(result.into_inner().unwrap())
-}
+    // Later operations for an already planned destination (versions kept
+    // when merging folders) wait, so the first one takes the real name
+    let mut planned = HashSet::new();
+    let (first, repeats): (Vec<&CopyOp>, Vec<&CopyOp>) =
+        plan.files.iter().partition(|op| planned.insert(&op.dest));
 
-fn copy_file(op: &CopyOp, result: &Mutex<CopyResult>, progress: &ProgressBar) {
-    match try_copy_atomic(&op.source, &op.dest) {
-        Ok(bytes) => {
-            preserve_permissions(&op.source, &op.dest);
-            let mut r = result.lock().unwrap();
-            r.bytes_copied += bytes;
-            progress.inc(bytes);
-            r.copied.push(CopiedFile {
-                source: op.source.clone(),
-                dest: op.dest.clone(),
-                size: bytes,
-                xdg_dir: op.xdg_dir,
-            });
-        }
-        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-            match write_to_restore_path(&op.source, &op.dest) {
-                Ok((restore_path, bytes)) => {
-                    let mut r = result.lock().unwrap();
-                    r.bytes_copied += bytes;
-                    progress.inc(bytes);
-                    r.conflicts.push(Conflict {
-                        restore_path,
-                        original_path: op.dest.clone(),
-                        size: bytes,
-                        xdg_dir: op.xdg_dir,
-                    });
+    pool.install(|| {
+        for ops in [first, repeats] {
+            ops.par_iter().for_each(|op| match place(op) {
+                Ok(placed) => {
+                    progress.inc(placed.bytes());
+                    result.lock().unwrap().record(op, placed);
                 }
                 Err(error) => {
                     progress.inc(op.size);
-                    let mut r = result.lock().unwrap();
-                    r.errors.push(CopyError {
+                    result.lock().unwrap().errors.push(CopyError {
                         source: op.source.clone(),
                         dest: op.dest.clone(),
                         error,
                         xdg_dir: op.xdg_dir,
                     });
                 }
+            });
+        }
+    });
+
+    progress.finish_and_clear();
+    let _writing = WriteAs::new(writer);
+    open_dirs(&created);
+    Ok(result.into_inner().unwrap())
+}
+
+/// Create `dir` and whichever of its parents are missing, readable only by
+/// their owner, so no file written into them is seen by others before its
+/// mode is set. Returns the folders created, outermost first, to widen
+/// with [`open_dirs`] once they are filled.
+pub(crate) fn create_private_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && fs::symlink_metadata(d).is_err())
+        .collect();
+    let mut created = Vec::new();
+    for dir in missing.into_iter().rev() {
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => created.push(dir.to_path_buf()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    // Something other than a folder in the way
+    if !dir.is_dir() {
+        fs::create_dir(dir)?;
+    }
+    Ok(created)
+}
+
+/// Give folders from [`create_private_dirs`] their modes, deepest first.
+pub(crate) fn open_dirs(dirs: &[(PathBuf, u32)]) {
+    for (dir, mode) in dirs.iter().rev() {
+        let _ = fs::set_permissions(dir, fs::Permissions::from_mode(*mode));
+    }
+}
+
+/// The mode a new folder gets with the process's umask.
+pub(crate) fn default_dir_mode() -> u32 {
+    let umask = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let value = status.lines().find_map(|l| l.strip_prefix("Umask:"))?;
+            u32::from_str_radix(value.trim(), 8).ok()
+        })
+        .unwrap_or(0o022);
+    0o777 & !umask
+}
+
+/// While alive, the calling thread opens and creates files with `owner`'s
+/// uid and gid, as if that user did. Only root can switch; for anyone else,
+/// or without an owner, it does nothing.
+pub struct WriteAs {
+    switched: bool,
+}
+
+impl WriteAs {
+    pub fn new(owner: Option<Owner>) -> WriteAs {
+        let Some(owner) = owner.filter(|_| crate::users::is_root()) else {
+            return WriteAs { switched: false };
+        };
+        // SAFETY: setfsgid and setfsuid only change the filesystem ids of
+        // the calling thread; root may set them to any value. The group goes
+        // first, while we still have the uid allowed to change it.
+        unsafe {
+            libc::setfsgid(owner.gid);
+            libc::setfsuid(owner.uid);
+        }
+        WriteAs { switched: true }
+    }
+}
+
+impl Drop for WriteAs {
+    fn drop(&mut self) {
+        if self.switched {
+            // SAFETY: as in `new`; our real uid is still 0, so the switch
+            // back is allowed.
+            unsafe {
+                libc::setfsuid(0);
+                libc::setfsgid(0);
             }
         }
-        Err(error) => {
-            progress.inc(op.size);
-            let mut r = result.lock().unwrap();
-            r.errors.push(CopyError {
+    }
+}
+
+/// The byte progress bar shown while restoring.
+pub(crate) fn progress_bar(total_bytes: u64) -> ProgressBar {
+    let progress = ProgressBar::new(total_bytes);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40} {bytes}/{total_bytes} ({eta})")
+            .unwrap(),
+    );
+    progress
+}
+
+/// Where [`place_file`] wrote a file.
+pub(crate) enum Placed {
+    /// At its destination, which did not exist yet.
+    Dest { bytes: u64 },
+    /// Beside an existing destination, under a `.restore` name.
+    Restore {
+        path: PathBuf,
+        bytes: u64,
+        original_mtime: Option<SystemTime>,
+    },
+}
+
+impl Placed {
+    pub(crate) fn path<'a>(&'a self, dest: &'a Path) -> &'a Path {
+        match self {
+            Placed::Dest { .. } => dest,
+            Placed::Restore { path, .. } => path,
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        match self {
+            Placed::Dest { bytes } | Placed::Restore { bytes, .. } => *bytes,
+        }
+    }
+}
+
+impl CopyResult {
+    /// Record a file written for `op`, as copied or as a conflict.
+    pub(crate) fn record(&mut self, op: &CopyOp, placed: Placed) {
+        self.bytes_copied += placed.bytes();
+        match placed {
+            Placed::Dest { bytes } => self.copied.push(CopiedFile {
                 source: op.source.clone(),
                 dest: op.dest.clone(),
-                error,
+                size: bytes,
                 xdg_dir: op.xdg_dir,
-            });
+            }),
+            Placed::Restore {
+                path,
+                bytes,
+                original_mtime,
+            } => self.conflicts.push(Conflict {
+                restore_path: path,
+                original_path: op.dest.clone(),
+                size: bytes,
+                original_mtime,
+                xdg_dir: op.xdg_dir,
+            }),
         }
     }
 }
 
-/// Atomically create dest and copy source into it.
-/// Returns `AlreadyExists` if dest already exists.
-fn try_copy_atomic(source: &Path, dest: &Path) -> io::Result<u64> {
-    let mut src_file = File::open(source)?;
-    let mut dst_file = File::create_new(dest)?;
-    let bytes = io::copy(&mut src_file, &mut dst_file)?;
-    Ok(bytes)
+/// Write `contents` to `dest`, which must not exist yet. If it does, write
+/// to a `.restore` path beside it instead, retrying with incrementing
+/// suffixes if those also already exist. `contents` is only read once a
+/// file has been created.
+pub(crate) fn place_file(contents: &mut dyn Read, dest: &Path) -> io::Result<Placed> {
+    place_with(&mut |file| io::copy(contents, file), dest)
 }
 
-/// Copy source to a .restore path, retrying with incrementing suffixes
-/// if those also already exist.
-fn write_to_restore_path(source: &Path, original_dest: &Path) -> io::Result<(PathBuf, u64)> {
-    let stem = original_dest.file_stem().unwrap_or_default();
-    let ext = original_dest.extension();
-    let parent = original_dest.parent().unwrap_or(Path::new(""));
-
-    // First try: name.restore.ext
-    let candidate = parent.join(make_restore_name(stem, ext, None));
-    match try_copy_atomic(source, &candidate) {
-        Ok(bytes) => {
-            preserve_permissions(source, &candidate);
-            return Ok((candidate, bytes));
-        }
+/// [`place_file`], with the contents written by `fill` into the new file.
+pub(crate) fn place_with(
+    fill: &mut dyn FnMut(&mut File) -> io::Result<u64>,
+    dest: &Path,
+) -> io::Result<Placed> {
+    match try_copy_atomic(fill, dest) {
+        Ok(bytes) => return Ok(Placed::Dest { bytes }),
         Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
         Err(e) => return Err(e),
     }
+    let original_mtime = fs::metadata(dest).and_then(|m| m.modified()).ok();
 
-    // Subsequent tries: name.restore.N.ext
-    for n in 2u32.. {
-        let candidate = parent.join(make_restore_name(stem, ext, Some(n)));
-        match try_copy_atomic(source, &candidate) {
+    let stem = dest.file_stem().unwrap_or_default();
+    let ext = dest.extension();
+    let parent = dest.parent().unwrap_or(Path::new(""));
+
+    // First try name.restore.ext, then name.restore.N.ext
+    for n in std::iter::once(None).chain((2u32..).map(Some)) {
+        let candidate = parent.join(make_restore_name(stem, ext, n));
+        match try_copy_atomic(fill, &candidate) {
             Ok(bytes) => {
-                preserve_permissions(source, &candidate);
-                return Ok((candidate, bytes));
+                return Ok(Placed::Restore {
+                    path: candidate,
+                    bytes,
+                    original_mtime,
+                })
             }
             Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
             Err(e) => return Err(e),
@@ -144,9 +378,35 @@ fn write_to_restore_path(source: &Path, original_dest: &Path) -> io::Result<(Pat
     unreachable!()
 }
 
-fn preserve_permissions(source: &Path, dest: &Path) {
-    if let Ok(metadata) = fs::metadata(source) {
-        let _ = fs::set_permissions(dest, metadata.permissions());
+/// Atomically create dest and copy contents into it.
+/// Returns `AlreadyExists` if dest already exists.
+fn try_copy_atomic(
+    fill: &mut dyn FnMut(&mut File) -> io::Result<u64>,
+    dest: &Path,
+) -> io::Result<u64> {
+    // Private until the caller gives it its mode
+    let mut dst_file = File::options()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(dest)?;
+    match fill(&mut dst_file) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => {
+            // Don't leave a truncated file behind
+            drop(dst_file);
+            let _ = fs::remove_file(dest);
+            Err(e)
+        }
+    }
+}
+
+/// Carry permissions and modification time over, so later comparisons
+/// (e.g. "newer wins" conflict rules) see the backup's mtime, not the copy time.
+fn preserve_metadata(source: &fs::Metadata, dest: &Path) {
+    let _ = fs::set_permissions(dest, source.permissions());
+    if let (Ok(mtime), Ok(file)) = (source.modified(), File::options().write(true).open(dest)) {
+        let _ = file.set_modified(mtime);
     }
 }
 
@@ -170,9 +430,40 @@ fn make_restore_name(
     name
 }
 
+/// Invert [`make_restore_name`]: list the original file names a `.restore`
+/// name could have come from, most likely first.
+///
+/// `notes.restore.2` is ambiguous — it may be the second restore of an
+/// extensionless `notes` or the first of `notes.2` — so both are returned.
+pub(crate) fn original_names(restore_name: &str) -> Vec<String> {
+    let parts: Vec<&str> = restore_name.split('.').collect();
+    let is_counter = |s: &str| s.parse::<u32>().is_ok_and(|n| n >= 2);
+
+    for i in (1..parts.len()).rev() {
+        if parts[i] != "restore" {
+            continue;
+        }
+        let stem = parts[..i].join(".");
+        if stem.is_empty() {
+            continue;
+        }
+        match parts[i + 1..] {
+            [] => return vec![stem],
+            [n] if is_counter(n) => return vec![stem.clone(), format!("{stem}.{n}")],
+            [ext] if !ext.is_empty() => return vec![format!("{stem}.{ext}")],
+            [n, ext] if is_counter(n) && !ext.is_empty() => {
+                return vec![format!("{stem}.{ext}")];
+            }
+            _ => {}
+        }
+    }
+    Vec::new()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dotfiles::HiddenDir;
     use crate::types::{CopyOp, DirOp, XdgDir};
     use std::fs;
     use tempfile::tempdir;
@@ -186,12 +477,15 @@ mod tests {
         let plan = CopyPlan {
             dirs: vec![DirOp {
                 dest: dest.path().join("Documents"),
+                source: None,
             }],
             files: vec![CopyOp {
                 source: src.path().join("hello.txt"),
                 dest: dest.path().join("Documents/hello.txt"),
                 size: 5,
+                mtime: None,
                 xdg_dir: XdgDir::Documents,
+                snapshot: None,
             }],
             total_bytes: 5,
         };
@@ -224,7 +518,9 @@ mod tests {
                 source: src.path().join("notes.txt"),
                 dest: dest.path().join("Documents/notes.txt"),
                 size: 11,
+                mtime: None,
                 xdg_dir: XdgDir::Documents,
+                snapshot: None,
             }],
             total_bytes: 11,
         };
@@ -269,7 +565,9 @@ mod tests {
                 source: src.path().join("photo.jpg"),
                 dest: dest.path().join("Pictures/photo.jpg"),
                 size: 5,
+                mtime: None,
                 xdg_dir: XdgDir::Pictures,
+                snapshot: None,
             }],
             total_bytes: 5,
         };
@@ -301,7 +599,9 @@ mod tests {
                 source: src.path().join("Makefile"),
                 dest: dest.path().join("Documents/Makefile"),
                 size: 3,
+                mtime: None,
                 xdg_dir: XdgDir::Documents,
+                snapshot: None,
             }],
             total_bytes: 3,
         };
@@ -328,12 +628,15 @@ mod tests {
         let plan = CopyPlan {
             dirs: vec![DirOp {
                 dest: dest.path().join("Documents"),
+                source: None,
             }],
             files: vec![CopyOp {
                 source: src_file,
                 dest: dest.path().join("Documents/script.sh"),
                 size: 9,
+                mtime: None,
                 xdg_dir: XdgDir::Documents,
+                snapshot: None,
             }],
             total_bytes: 9,
         };
@@ -347,6 +650,94 @@ mod tests {
         assert_eq!(perms.mode() & 0o777, 0o755);
     }
 
+    #[test]
+    fn keeps_keys_private_while_writing_them() {
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::create_dir(src.path().join(".ssh")).unwrap();
+        fs::write(src.path().join(".ssh/id_ed25519"), "secret").unwrap();
+        fs::set_permissions(src.path().join(".ssh"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::create_dir(src.path().join("Music")).unwrap();
+        fs::set_permissions(src.path().join("Music"), fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = |name: &str| DirOp {
+            dest: dest.path().join(name),
+            source: Some(src.path().join(name)),
+        };
+        let plan = CopyPlan {
+            dirs: vec![dir(".ssh"), dir("Music")],
+            files: vec![CopyOp {
+                source: src.path().join(".ssh/id_ed25519"),
+                dest: dest.path().join(".ssh/id_ed25519"),
+                size: 6,
+                mtime: None,
+                xdg_dir: XdgDir::Hidden(HiddenDir::Ssh),
+                snapshot: None,
+            }],
+            total_bytes: 6,
+        };
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o777;
+        let seen = Mutex::new(Vec::new());
+        execute_with(
+            &plan,
+            1,
+            None,
+            |_| None,
+            |op| {
+                let mut source = File::open(&op.source)?;
+                let fill = &mut |out: &mut File| {
+                    let folder = mode(op.dest.parent().unwrap());
+                    seen.lock()
+                        .unwrap()
+                        .push((folder, out.metadata()?.mode() & 0o777));
+                    io::copy(&mut source, out)
+                };
+                place_with(fill, &op.dest)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(seen.into_inner().unwrap(), [(0o700, 0o600)]);
+        // Then the folders get the backup's modes
+        assert_eq!(mode(&dest.path().join(".ssh")), 0o700);
+        assert_eq!(mode(&dest.path().join("Music")), 0o755);
+    }
+
+    #[test]
+    fn preserves_modification_time() {
+        use std::time::{Duration, SystemTime};
+
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        let src_file = src.path().join("old.txt");
+        fs::write(&src_file, "old").unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_007);
+        File::options()
+            .write(true)
+            .open(&src_file)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![CopyOp {
+                source: src_file,
+                dest: dest.path().join("old.txt"),
+                size: 3,
+                mtime: None,
+                xdg_dir: XdgDir::Documents,
+                snapshot: None,
+            }],
+            total_bytes: 3,
+        };
+
+        execute_plan(&plan, 1).unwrap();
+
+        let copied = fs::metadata(dest.path().join("old.txt")).unwrap();
+        assert_eq!(copied.modified().unwrap(), mtime);
+    }
+
     #[test]
     fn collects_errors_without_aborting() {
         let src = tempdir().unwrap();
@@ -362,13 +753,17 @@ mod tests {
                     source: src.path().join("nonexistent.txt"),
                     dest: dest.path().join("Documents/nonexistent.txt"),
                     size: 10,
+                    mtime: None,
                     xdg_dir: XdgDir::Documents,
+                    snapshot: None,
                 },
                 CopyOp {
                     source: src.path().join("good.txt"),
                     dest: dest.path().join("Documents/good.txt"),
                     size: 4,
+                    mtime: None,
                     xdg_dir: XdgDir::Documents,
+                    snapshot: None,
                 },
             ],
             total_bytes: 14,
@@ -400,7 +795,9 @@ mod tests {
                 source: src.path().join(&name),
                 dest: dest.path().join(format!("Downloads/{name}")),
                 size,
+                mtime: None,
                 xdg_dir: XdgDir::Downloads,
+                snapshot: None,
             });
         }
 
@@ -417,6 +814,223 @@ mod tests {
         assert_eq!(result.bytes_copied, total);
     }
 
+    #[test]
+    fn gives_new_files_and_folders_to_the_owner() {
+        if !crate::users::is_root() {
+            eprintln!("not root; skipping");
+            return;
+        }
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::write(src.path().join("a.txt"), "new").unwrap();
+        fs::create_dir(dest.path().join("Documents")).unwrap();
+        fs::write(dest.path().join("Documents/a.txt"), "old").unwrap();
+        // The home and its folder are the user's, as they would be
+        std::os::unix::fs::chown(dest.path(), Some(4321), Some(4322)).unwrap();
+        std::os::unix::fs::chown(dest.path().join("Documents"), Some(4321), Some(0)).unwrap();
+        let op = |name: &str| CopyOp {
+            source: src.path().join("a.txt"),
+            dest: dest.path().join(name),
+            size: 3,
+            mtime: None,
+            xdg_dir: XdgDir::Documents,
+            snapshot: None,
+        };
+        let plan = CopyPlan {
+            dirs: vec![
+                DirOp {
+                    dest: dest.path().join("Documents"),
+                    source: None,
+                },
+                DirOp {
+                    dest: dest.path().join("Documents/sub"),
+                    source: None,
+                },
+            ],
+            files: vec![op("Documents/a.txt"), op("Documents/sub/b.txt")],
+            total_bytes: 6,
+        };
+
+        let owner = Owner {
+            uid: 4321,
+            gid: 4322,
+        };
+        let result = execute_plan_as(&plan, 1, &Ownership::User(owner)).unwrap();
+        assert_eq!(result.conflicts.len(), 1);
+
+        let ids = |path: &Path| {
+            let meta = fs::symlink_metadata(path).unwrap();
+            (meta.uid(), meta.gid())
+        };
+        assert_eq!(ids(&dest.path().join("Documents/sub")), (4321, 4322));
+        assert_eq!(ids(&dest.path().join("Documents/sub/b.txt")), (4321, 4322));
+        assert_eq!(ids(&result.conflicts[0].restore_path), (4321, 4322));
+        // Folders and files that were already there are left alone
+        assert_eq!(ids(&dest.path().join("Documents")), (4321, 0));
+        assert_eq!(ids(&dest.path().join("Documents/a.txt")), (0, 0));
+    }
+
+    #[test]
+    fn does_not_follow_a_users_links_where_they_cannot_write() {
+        use std::os::unix::fs::PermissionsExt;
+
+        if !crate::users::is_root() {
+            eprintln!("not root; skipping");
+            return;
+        }
+        let src = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        fs::set_permissions(outside.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(src.path().join("a.txt"), "new").unwrap();
+        std::os::unix::fs::chown(home.path(), Some(4321), Some(4322)).unwrap();
+        std::os::unix::fs::symlink(outside.path(), home.path().join("Documents")).unwrap();
+        let plan = CopyPlan {
+            dirs: vec![
+                DirOp {
+                    dest: home.path().join("Documents"),
+                    source: None,
+                },
+                DirOp {
+                    dest: home.path().join("Documents/sub"),
+                    source: None,
+                },
+            ],
+            files: vec![],
+            total_bytes: 0,
+        };
+        let owner = Owner {
+            uid: 4321,
+            gid: 4322,
+        };
+        assert!(execute_plan_as(&plan, 1, &Ownership::User(owner)).is_err());
+        assert!(!outside.path().join("sub").exists());
+
+        let plan = CopyPlan {
+            dirs: vec![DirOp {
+                dest: home.path().join("Documents"),
+                source: None,
+            }],
+            files: vec![CopyOp {
+                source: src.path().join("a.txt"),
+                dest: home.path().join("Documents/a.txt"),
+                size: 3,
+                mtime: None,
+                xdg_dir: XdgDir::Documents,
+                snapshot: None,
+            }],
+            total_bytes: 3,
+        };
+        let result = execute_plan_as(&plan, 1, &Ownership::User(owner)).unwrap();
+        assert_eq!(result.errors.len(), 1);
+        assert!(!outside.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn keeps_backup_owners_through_the_id_map() {
+        if !crate::users::is_root() {
+            eprintln!("not root; skipping");
+            return;
+        }
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::create_dir(src.path().join("sub")).unwrap();
+        fs::write(src.path().join("sub/a.txt"), "a").unwrap();
+        std::os::unix::fs::chown(src.path().join("sub"), Some(1000), Some(1000)).unwrap();
+        std::os::unix::fs::chown(src.path().join("sub/a.txt"), Some(1000), Some(100)).unwrap();
+        let plan = CopyPlan {
+            dirs: vec![DirOp {
+                dest: dest.path().join("sub"),
+                source: Some(src.path().join("sub")),
+            }],
+            files: vec![CopyOp {
+                source: src.path().join("sub/a.txt"),
+                dest: dest.path().join("sub/a.txt"),
+                size: 1,
+                mtime: None,
+                xdg_dir: XdgDir::Documents,
+                snapshot: None,
+            }],
+            total_bytes: 1,
+        };
+
+        let mut map = IdMap::default();
+        map.users.insert(1000, 1001);
+        map.groups.insert(1000, 1001);
+        execute_plan_as(&plan, 1, &Ownership::Preserve(map)).unwrap();
+
+        let ids = |path: &Path| {
+            let meta = fs::symlink_metadata(path).unwrap();
+            (meta.uid(), meta.gid())
+        };
+        assert_eq!(ids(&dest.path().join("sub")), (1001, 1001));
+        // Ids the map doesn't mention are kept
+        assert_eq!(ids(&dest.path().join("sub/a.txt")), (1001, 100));
+    }
+
+    #[test]
+    fn gives_missing_parents_the_folders_owner() {
+        if !crate::users::is_root() {
+            eprintln!("not root; skipping");
+            return;
+        }
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::create_dir(src.path().join("sub")).unwrap();
+        std::os::unix::fs::chown(src.path().join("sub"), Some(1000), Some(1000)).unwrap();
+        let plan = CopyPlan {
+            dirs: vec![DirOp {
+                dest: dest.path().join("Projects/old/sub"),
+                source: Some(src.path().join("sub")),
+            }],
+            files: vec![],
+            total_bytes: 0,
+        };
+
+        let mut map = IdMap::default();
+        map.users.insert(1000, 1001);
+        map.groups.insert(1000, 1001);
+        execute_plan_as(&plan, 1, &Ownership::Preserve(map)).unwrap();
+
+        for dir in ["Projects", "Projects/old", "Projects/old/sub"] {
+            let meta = fs::symlink_metadata(dest.path().join(dir)).unwrap();
+            assert_eq!((meta.uid(), meta.gid()), (1001, 1001), "{dir}");
+        }
+    }
+
+    #[test]
+    fn first_op_for_a_shared_destination_wins() {
+        let src = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        let mut files = Vec::new();
+        for i in 0..8 {
+            let name = format!("v{i}.txt");
+            fs::write(src.path().join(&name), format!("version {i}")).unwrap();
+            files.push(CopyOp {
+                source: src.path().join(name),
+                dest: dest.path().join("notes.txt"),
+                size: 9,
+                mtime: None,
+                xdg_dir: XdgDir::Documents,
+                snapshot: None,
+            });
+        }
+        let plan = CopyPlan {
+            dirs: vec![],
+            files,
+            total_bytes: 72,
+        };
+
+        let result = execute_plan(&plan, 4).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.path().join("notes.txt")).unwrap(),
+            "version 0"
+        );
+        assert_eq!(result.copied.len(), 1);
+        assert_eq!(result.conflicts.len(), 7);
+    }
+
     #[test]
     fn returns_error_when_dir_creation_fails() {
         let src = tempdir().unwrap();
@@ -429,16 +1043,59 @@ mod tests {
         let plan = CopyPlan {
             dirs: vec![DirOp {
                 dest: dest.path().join("Documents"),
+                source: None,
             }],
             files: vec![CopyOp {
                 source: src.path().join("hello.txt"),
                 dest: dest.path().join("Documents/hello.txt"),
                 size: 5,
+                mtime: None,
                 xdg_dir: XdgDir::Documents,
+                snapshot: None,
             }],
             total_bytes: 5,
         };
 
         assert!(execute_plan(&plan, 1).is_err());
     }
+
+    #[test]
+    fn original_names_inverts_restore_names() {
+        let cases: &[(&str, &str, Option<u32>)] = &[
+            ("notes", "txt", None),
+            ("photo", "jpg", Some(3)),
+            ("archive.tar", "gz", Some(2)),
+            (".bashrc", "", None),
+            ("Makefile", "", None),
+        ];
+        for &(stem, ext, n) in cases {
+            let ext = (!ext.is_empty()).then(|| std::ffi::OsStr::new(ext));
+            let restore = make_restore_name(std::ffi::OsStr::new(stem), ext, n);
+            let original = match ext {
+                Some(ext) => format!("{stem}.{}", ext.to_str().unwrap()),
+                None => stem.to_string(),
+            };
+            assert_eq!(
+                original_names(restore.to_str().unwrap()),
+                vec![original],
+                "{restore:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn original_names_reports_both_readings_of_numbered_names() {
+        assert_eq!(
+            original_names("Makefile.restore.2"),
+            vec!["Makefile".to_string(), "Makefile.2".to_string()]
+        );
+    }
+
+    #[test]
+    fn original_names_ignores_unrelated_files() {
+        assert!(original_names("notes.txt").is_empty());
+        assert!(original_names("restore.txt").is_empty());
+        assert!(original_names(".restore").is_empty());
+        assert!(original_names("a.restore.b.c").is_empty());
+    }
 }
@@ -0,0 +1,457 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::conflict::Resolution;
+use crate::rules::{Action, FileFacts, RuleSet};
+use crate::types::{Conflict, DetectedMapping, XdgDir};
+
+/// A hidden folder in a home that a [`Category`] restores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum HiddenDir {
+    /// `~/.config`
+    Config,
+    /// `~/.local/share`
+    AppData,
+    Ssh,
+    Gnupg,
+    Mozilla,
+    Thunderbird,
+}
+
+impl HiddenDir {
+    /// The folder's path relative to the home directory.
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            HiddenDir::Config => ".config",
+            HiddenDir::AppData => ".local/share",
+            HiddenDir::Ssh => ".ssh",
+            HiddenDir::Gnupg => ".gnupg",
+            HiddenDir::Mozilla => ".mozilla",
+            HiddenDir::Thunderbird => ".thunderbird",
+        }
+    }
+}
+
+/// A group of hidden folders in a home, restored alongside the XDG folders
+/// only when opted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    Config,
+    AppData,
+    Keys,
+    BrowserProfiles,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Config,
+        Category::AppData,
+        Category::Keys,
+        Category::BrowserProfiles,
+    ];
+
+    /// The name used on the command line, such as `app-data`.
+    pub fn flag_name(self) -> &'static str {
+        match self {
+            Category::Config => "config",
+            Category::AppData => "app-data",
+            Category::Keys => "keys",
+            Category::BrowserProfiles => "browser-profiles",
+        }
+    }
+
+    /// The folders it covers.
+    pub fn dirs(self) -> &'static [HiddenDir] {
+        match self {
+            Category::Config => &[HiddenDir::Config],
+            Category::AppData => &[HiddenDir::AppData],
+            Category::Keys => &[HiddenDir::Ssh, HiddenDir::Gnupg],
+            Category::BrowserProfiles => &[HiddenDir::Mozilla, HiddenDir::Thunderbird],
+        }
+    }
+
+    /// The category a folder belongs to; `None` for the XDG folders.
+    pub fn of(xdg_dir: XdgDir) -> Option<Category> {
+        let XdgDir::Hidden(dir) = xdg_dir else {
+            return None;
+        };
+        Category::ALL.into_iter().find(|c| c.dirs().contains(&dir))
+    }
+
+    /// What becomes of a conflict no rule decides; `None` asks.
+    pub fn default_action(self) -> Option<Action> {
+        match self {
+            // An application's databases are only consistent as one whole
+            // copy, so taking the newer of each file could mix the two;
+            // which copy to keep is the user's call
+            Category::Config | Category::Keys | Category::AppData => None,
+            // The backup's profiles.ini is what points at the restored
+            // profiles; a new install's only knows its empty one
+            Category::BrowserProfiles => Some(Action::Overwrite),
+        }
+    }
+
+    /// Whether a rule or the default may replace an existing file without
+    /// asking. Settings and keys written on the new system are never lost
+    /// unseen.
+    pub fn may_overwrite(self) -> bool {
+        matches!(self, Category::AppData | Category::BrowserProfiles)
+    }
+
+    /// One line on how its conflicts are handled.
+    pub fn policy(self) -> &'static str {
+        match self {
+            Category::Config | Category::Keys => "never overwritten without asking",
+            Category::AppData => "asked about, unless a rule decides",
+            Category::BrowserProfiles => "the backup's copy wins",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Category::Config => "config",
+            Category::AppData => "app data",
+            Category::Keys => "keys",
+            Category::BrowserProfiles => "browser profiles",
+        })
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .into_iter()
+            .find(|c| c.flag_name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown category '{s}' (expected config, app-data, keys or browser-profiles)"
+                )
+            })
+    }
+}
+
+/// The home folders in a backup, going by the restored XDG folders: the
+/// folders holding them, those holding the most first.
+pub fn home_roots(mappings: &[DetectedMapping]) -> Vec<&Path> {
+    folder_roots(mappings.iter().map(|m| m.source_path.as_path()))
+}
+
+/// [`home_roots`], for XDG folders given by their paths.
+pub fn folder_roots<'a>(folders: impl IntoIterator<Item = &'a Path>) -> Vec<&'a Path> {
+    let mut roots: Vec<(&Path, usize)> = Vec::new();
+    for folder in folders {
+        let Some(root) = folder.parent() else {
+            continue;
+        };
+        match roots.iter_mut().find(|(r, _)| *r == root) {
+            Some((_, n)) => *n += 1,
+            None => roots.push((root, 1)),
+        }
+    }
+    roots.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    roots.into_iter().map(|(root, _)| root).collect()
+}
+
+/// Find the folders of `categories` in the backup's home folders `roots`,
+/// taking each from the first root that has it, mapped into `home_dir`.
+/// `exists` says whether a folder is in the backup, which needn't be on
+/// disk.
+pub fn find_folders(
+    roots: &[&Path],
+    home_dir: &Path,
+    categories: &[Category],
+    mut exists: impl FnMut(&Path) -> bool,
+) -> Vec<DetectedMapping> {
+    categories
+        .iter()
+        .flat_map(|c| c.dirs())
+        .filter_map(|&dir| {
+            let xdg_dir = XdgDir::Hidden(dir);
+            let source_path = roots
+                .iter()
+                .map(|root| root.join(xdg_dir.dir_name()))
+                .find(|path| exists(path))?;
+            Some(DetectedMapping {
+                xdg_dir,
+                source_path,
+                dest_path: home_dir.join(xdg_dir.dir_name()),
+            })
+        })
+        .collect()
+}
+
+/// The categories with folders among `found`, in [`Category::ALL`] order.
+pub fn categories_found(found: &[DetectedMapping]) -> Vec<Category> {
+    Category::ALL
+        .into_iter()
+        .filter(|&c| found.iter().any(|m| Category::of(m.xdg_dir) == Some(c)))
+        .collect()
+}
+
+/// Which categories of hidden folders to restore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CategoryChoice {
+    /// Those given with `--include`; `missing` are the ones the backup has
+    /// no folders for.
+    Given {
+        include: Vec<Category>,
+        missing: Vec<Category>,
+    },
+    /// Offer the categories found in a checklist.
+    Ask(Vec<Category>),
+    /// Restore none, naming those found so they can be included.
+    LeaveOut(Vec<Category>),
+}
+
+/// Choose between the `include` categories and asking about the
+/// `available` ones, which needs `can_ask`: a terminal, and a restore that
+/// asks at all.
+pub fn choose_categories(
+    include: &[Category],
+    available: Vec<Category>,
+    can_ask: bool,
+) -> CategoryChoice {
+    if !include.is_empty() {
+        let missing = include
+            .iter()
+            .copied()
+            .filter(|c| !available.contains(c))
+            .collect();
+        return CategoryChoice::Given {
+            include: include.to_vec(),
+            missing,
+        };
+    }
+    if can_ask && !available.is_empty() {
+        CategoryChoice::Ask(available)
+    } else {
+        CategoryChoice::LeaveOut(available)
+    }
+}
+
+/// The folders of the `include` categories beside the XDG `folders` found
+/// in a backup read remotely, relative to its root like them. `exists` asks
+/// the server whether a folder is there.
+pub fn included_folders(
+    folders: &[PathBuf],
+    include: &[Category],
+    exists: impl FnMut(&Path) -> bool,
+) -> Vec<PathBuf> {
+    let roots = folder_roots(folders.iter().map(PathBuf::as_path));
+    find_folders(&roots, Path::new(""), include, exists)
+        .into_iter()
+        .map(|m| m.source_path)
+        .collect()
+}
+
+/// How a conflict was settled without asking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settled {
+    /// By one of the user's rules.
+    Rule(Resolution),
+    /// By the default of the category the file is in.
+    Default(Resolution),
+}
+
+/// Settle a conflict by the rules, then by the default of its category.
+/// `None` leaves it to the user, as are overwrites in categories that may
+/// not overwrite without asking.
+pub fn settle(rules: &RuleSet, conflict: &Conflict, home_dir: &Path) -> Option<Settled> {
+    let category = Category::of(conflict.xdg_dir);
+    let settled = if let Some(decision) = rules.decide_conflict(conflict, home_dir) {
+        Settled::Rule(decision.resolution)
+    } else {
+        let action = category?.default_action()?;
+        let original = FileFacts::of(&conflict.original_path).ok()?;
+        let restore = FileFacts::of(&conflict.restore_path).ok()?;
+        Settled::Default(action.resolve(original, restore)?)
+    };
+    let (Settled::Rule(resolution) | Settled::Default(resolution)) = settled;
+    if resolution == Resolution::Overwrite && category.is_some_and(|c| !c.may_overwrite()) {
+        return None;
+    }
+    Some(settled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    fn conflict(home: &Path, name: &str, xdg_dir: XdgDir, restore_is_newer: bool) -> Conflict {
+        let original_path = home.join(name);
+        let restore_path = home.join(format!("{name}.restore"));
+        fs::create_dir_all(original_path.parent().unwrap()).unwrap();
+        fs::write(&original_path, "original").unwrap();
+        fs::write(&restore_path, "restored").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let new = old + Duration::from_mins(1);
+        let (original_mtime, restore_mtime) = if restore_is_newer {
+            (old, new)
+        } else {
+            (new, old)
+        };
+        let set = |path: &Path, mtime| {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(mtime).unwrap();
+        };
+        set(&original_path, original_mtime);
+        set(&restore_path, restore_mtime);
+        Conflict {
+            restore_path,
+            original_path,
+            size: 8,
+            original_mtime: Some(original_mtime),
+            xdg_dir,
+        }
+    }
+
+    #[test]
+    fn finds_category_folders_in_the_fullest_home() {
+        let mapping = |source: &str| DetectedMapping {
+            xdg_dir: XdgDir::Documents,
+            source_path: PathBuf::from(source),
+            dest_path: PathBuf::from("/unused"),
+        };
+        let mappings = [
+            mapping("/b/old/joe/Documents"),
+            mapping("/b/joe/Music"),
+            mapping("/b/joe/Pictures"),
+        ];
+        let roots = home_roots(&mappings);
+        assert_eq!(roots, [Path::new("/b/joe"), Path::new("/b/old/joe")]);
+        // Paths relative to a server's root, which can hold a home itself
+        let listed = ["Music", "home/joe/Documents", "home/joe/Videos"].map(Path::new);
+        assert_eq!(folder_roots(listed), [Path::new("home/joe"), Path::new("")]);
+
+        let in_backup = [
+            "/b/joe/.config",
+            "/b/old/joe/.config",
+            "/b/old/joe/.gnupg",
+            "/b/joe/.mozilla",
+        ];
+        let found = find_folders(
+            &roots,
+            Path::new("/h"),
+            &[Category::Config, Category::Keys],
+            |path| in_backup.iter().any(|p| Path::new(p) == path),
+        );
+        let found: Vec<(XdgDir, &Path, &Path)> = found
+            .iter()
+            .map(|m| (m.xdg_dir, m.source_path.as_path(), m.dest_path.as_path()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    XdgDir::Hidden(HiddenDir::Config),
+                    Path::new("/b/joe/.config"),
+                    Path::new("/h/.config")
+                ),
+                (
+                    XdgDir::Hidden(HiddenDir::Gnupg),
+                    Path::new("/b/old/joe/.gnupg"),
+                    Path::new("/h/.gnupg")
+                ),
+            ]
+        );
+        assert_eq!("app-data".parse(), Ok(Category::AppData));
+        assert!("dotfiles".parse::<Category>().is_err());
+    }
+
+    #[test]
+    fn asks_about_categories_only_without_include() {
+        let found = [
+            DetectedMapping {
+                xdg_dir: XdgDir::Hidden(HiddenDir::Gnupg),
+                source_path: PathBuf::from("/b/joe/.gnupg"),
+                dest_path: PathBuf::from("/h/.gnupg"),
+            },
+            DetectedMapping {
+                xdg_dir: XdgDir::Hidden(HiddenDir::Config),
+                source_path: PathBuf::from("/b/joe/.config"),
+                dest_path: PathBuf::from("/h/.config"),
+            },
+        ];
+        let available = categories_found(&found);
+        assert_eq!(available, [Category::Config, Category::Keys]);
+
+        assert_eq!(
+            choose_categories(&[], available.clone(), true),
+            CategoryChoice::Ask(available.clone())
+        );
+        // No terminal, --dry-run, --all-users and the like
+        assert_eq!(
+            choose_categories(&[], available.clone(), false),
+            CategoryChoice::LeaveOut(available.clone())
+        );
+        assert_eq!(
+            choose_categories(&[], Vec::new(), true),
+            CategoryChoice::LeaveOut(Vec::new())
+        );
+        let include = [Category::Keys, Category::AppData];
+        let given = CategoryChoice::Given {
+            include: include.to_vec(),
+            missing: vec![Category::AppData],
+        };
+        assert_eq!(choose_categories(&include, available.clone(), true), given);
+        assert_eq!(choose_categories(&include, available, false), given);
+    }
+
+    #[test]
+    fn settles_conflicts_by_category_policy() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        let none = RuleSet::default();
+        let overwrite = RuleSet::new(vec!["overwrite".parse().unwrap()]);
+        let keep = RuleSet::new(vec!["keep-original".parse().unwrap()]);
+
+        // App data is asked about, even with one side newer, unless a rule
+        // decides
+        let data = conflict(
+            home,
+            ".local/share/app/db",
+            XdgDir::Hidden(HiddenDir::AppData),
+            true,
+        );
+        assert_eq!(settle(&none, &data, home), None);
+        assert_eq!(
+            settle(&keep, &data, home),
+            Some(Settled::Rule(Resolution::KeepOriginal))
+        );
+        assert_eq!(
+            settle(&overwrite, &data, home),
+            Some(Settled::Rule(Resolution::Overwrite))
+        );
+
+        // Config is asked about unless a rule keeps the original
+        let config = conflict(
+            home,
+            ".config/app/settings.ini",
+            XdgDir::Hidden(HiddenDir::Config),
+            true,
+        );
+        assert_eq!(settle(&none, &config, home), None);
+        assert_eq!(settle(&overwrite, &config, home), None);
+        assert_eq!(
+            settle(&keep, &config, home),
+            Some(Settled::Rule(Resolution::KeepOriginal))
+        );
+
+        // XDG folders follow the rules alone
+        let doc = conflict(home, "Documents/a.txt", XdgDir::Documents, true);
+        assert_eq!(settle(&none, &doc, home), None);
+        assert_eq!(
+            settle(&overwrite, &doc, home),
+            Some(Settled::Rule(Resolution::Overwrite))
+        );
+    }
+}
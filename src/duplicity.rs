@@ -0,0 +1,718 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use flate2::read::GzDecoder;
+
+use crate::snapshot::parse_stamp;
+
+/// Whether a backup set holds everything or only changes since the set
+/// before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetKind {
+    Full,
+    Incremental,
+}
+
+/// One duplicity backup run: a manifest plus numbered `difftar` volumes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupSet {
+    pub kind: SetKind,
+    /// For incrementals, the time of the set this one builds on.
+    pub start: Option<SystemTime>,
+    pub time: SystemTime,
+    /// Volumes in order.
+    pub volumes: Vec<PathBuf>,
+    /// Volumes are GnuPG-encrypted (`.difftar.gpg`).
+    pub encrypted: bool,
+}
+
+#[derive(Debug)]
+pub enum DuplicityError {
+    /// No complete chain of backup sets covers the requested time.
+    NoChain,
+    /// The volumes are encrypted; only plain `.difftar.gz` is supported.
+    Encrypted(PathBuf),
+    /// A volume entry would land outside the restore directory.
+    UnsafePath(PathBuf),
+    /// A `diff/` entry is not a valid librsync delta, or refers to a file
+    /// earlier sets never created.
+    BadDelta(PathBuf, String),
+    Io(io::Error),
+}
+
+impl fmt::Display for DuplicityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DuplicityError::NoChain => {
+                f.write_str("no complete duplicity backup covers the requested time")
+            }
+            DuplicityError::Encrypted(p) => {
+                write!(f, "{} is encrypted; decrypt the backup first", p.display())
+            }
+            DuplicityError::UnsafePath(p) => {
+                write!(f, "refusing to restore {} outside the target", p.display())
+            }
+            DuplicityError::BadDelta(p, why) => {
+                write!(f, "cannot apply changes to {}: {why}", p.display())
+            }
+            DuplicityError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DuplicityError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DuplicityError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DuplicityError {
+    fn from(e: io::Error) -> Self {
+        DuplicityError::Io(e)
+    }
+}
+
+/// Whether `dir` holds duplicity backup files (as written by Déjà Dup).
+pub fn is_duplicity_backup(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        entries.flatten().any(|e| {
+            e.file_name()
+                .to_str()
+                .is_some_and(|n| n.starts_with("duplicity-") && n.contains(".manifest"))
+        })
+    })
+}
+
+/// The complete backup sets in `dir`, oldest first. Sets without a
+/// manifest were interrupted and are left out.
+pub fn find_backup_sets(dir: &Path) -> io::Result<Vec<BackupSet>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        if let Ok(name) = entry?.file_name().into_string() {
+            names.push(name);
+        }
+    }
+
+    let mut sets = Vec::new();
+    for name in &names {
+        let Some(stem) = name
+            .strip_suffix(".manifest")
+            .or_else(|| name.strip_suffix(".manifest.gpg"))
+        else {
+            continue;
+        };
+        let Some((kind, start, time)) = parse_set_name(stem) else {
+            continue;
+        };
+
+        let prefix = format!("{stem}.vol");
+        let mut volumes: Vec<(u32, &String)> = names
+            .iter()
+            .filter_map(|n| {
+                let rest = n.strip_prefix(&prefix)?;
+                let (number, _) = rest.split_once(".difftar")?;
+                Some((number.parse().ok()?, n))
+            })
+            .collect();
+        if volumes.is_empty() {
+            continue;
+        }
+        volumes.sort();
+        sets.push(BackupSet {
+            kind,
+            start,
+            time,
+            encrypted: volumes.iter().any(|(_, n)| n.contains(".difftar.gpg")),
+            volumes: volumes.into_iter().map(|(_, n)| dir.join(n)).collect(),
+        });
+    }
+    sets.sort_by_key(|s| s.time);
+    Ok(sets)
+}
+
+/// `duplicity-full.<time>` or `duplicity-inc.<start>.to.<time>`.
+fn parse_set_name(stem: &str) -> Option<(SetKind, Option<SystemTime>, SystemTime)> {
+    let time = |s: &str| parse_stamp(s).map(|(t, _)| t);
+    if let Some(t) = stem.strip_prefix("duplicity-full.") {
+        return Some((SetKind::Full, None, time(t)?));
+    }
+    let (start, end) = stem.strip_prefix("duplicity-inc.")?.split_once(".to.")?;
+    Some((SetKind::Incremental, Some(time(start)?), time(end)?))
+}
+
+/// The sets to replay for the state at `until` (latest when `None`): the
+/// newest full backup at or before it and the incrementals that follow it.
+pub fn select_chain(sets: &[BackupSet], until: Option<SystemTime>) -> Option<Vec<&BackupSet>> {
+    let in_range = |s: &&BackupSet| until.is_none_or(|u| s.time <= u);
+    let full = sets
+        .iter()
+        .filter(in_range)
+        .filter(|s| s.kind == SetKind::Full)
+        .max_by_key(|s| s.time)?;
+
+    let mut chain = vec![full];
+    while let Some(next) = sets
+        .iter()
+        .filter(in_range)
+        .find(|s| s.start == Some(chain.last().unwrap().time))
+    {
+        chain.push(next);
+    }
+    Some(chain)
+}
+
+/// Replay a chain of backup sets into `dest`, reconstructing the backed-up
+/// tree as of the last set. Returns the number of files written.
+pub fn restore_chain(chain: &[&BackupSet], dest: &Path) -> Result<usize, DuplicityError> {
+    let mut restorer = Restorer {
+        dest,
+        written: 0,
+        pending_delta: None,
+        settle: Vec::new(),
+    };
+    for set in chain {
+        if set.encrypted {
+            return Err(DuplicityError::Encrypted(set.volumes[0].clone()));
+        }
+        for volume in &set.volumes {
+            let mut archive =
+                tar::Archive::new(GzDecoder::new(BufReader::new(File::open(volume)?)));
+            for entry in archive.entries()? {
+                restorer.apply(entry?)?;
+            }
+        }
+        restorer.finish_delta()?;
+    }
+    restorer.settle()?;
+    Ok(restorer.written)
+}
+
+struct Restorer<'a> {
+    dest: &'a Path,
+    written: usize,
+    /// A delta split across `multivol_diff` chunks: the file it applies to,
+    /// the unnamed temporary file collecting the chunks, and the file's mode.
+    pending_delta: Option<(PathBuf, File, u32)>,
+    /// Modes and mtimes to set once everything is written: folders change
+    /// as they are filled, and read-only files can't take more chunks.
+    settle: Vec<(PathBuf, u32, Option<SystemTime>)>,
+}
+
+impl Restorer<'_> {
+    fn apply<R: Read>(&mut self, mut entry: tar::Entry<R>) -> Result<(), DuplicityError> {
+        let name = entry.path()?.into_owned();
+        let mut parts = name.components();
+        let Some(Component::Normal(kind)) = parts.next() else {
+            return Ok(());
+        };
+        let kind = kind.to_string_lossy().into_owned();
+        let relative = parts.as_path().to_path_buf();
+
+        let (relative, chunk) = if kind.starts_with("multivol_") {
+            let chunk: u32 = relative
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(1);
+            (
+                relative.parent().unwrap_or(Path::new("")).to_path_buf(),
+                chunk,
+            )
+        } else {
+            (relative, 1)
+        };
+        if relative.as_os_str().is_empty() {
+            // The backup root itself
+            return Ok(());
+        }
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(DuplicityError::UnsafePath(relative));
+        }
+        // Links restored earlier must not lead writes out of `dest`
+        refuse_symlinks(self.dest, &relative)?;
+        let target = self.dest.join(&relative);
+        let mode = entry.header().mode().unwrap_or(0o644) & 0o7777;
+
+        if self
+            .pending_delta
+            .as_ref()
+            .is_some_and(|(t, _, _)| *t != target || kind != "multivol_diff")
+        {
+            self.finish_delta()?;
+        }
+
+        match kind.as_str() {
+            "deleted" => remove(&target)?,
+            "diff" if entry.header().entry_type().is_file() => {
+                let mut delta = Vec::new();
+                entry.read_to_end(&mut delta)?;
+                patch_file(&target, &mut delta.as_slice(), mode)?;
+                self.written += 1;
+            }
+            // Directories and links are always stored whole
+            "snapshot" | "diff" => self.write_entry(&mut entry, &target, mode)?,
+            "multivol_snapshot" => {
+                let mut out = if chunk == 1 {
+                    remove(&target)?;
+                    create_parent(&target)?;
+                    self.written += 1;
+                    let mtime = header_mtime(entry.header());
+                    self.settle.push((target.clone(), mode, mtime));
+                    create_file(&target, mode | 0o200)?
+                } else {
+                    File::options()
+                        .append(true)
+                        .custom_flags(libc::O_NOFOLLOW)
+                        .open(&target)?
+                };
+                io::copy(&mut entry, &mut out)?;
+            }
+            "multivol_diff" => {
+                if chunk == 1 {
+                    // Unnamed, so it can't meet a restored file or another run
+                    let collected = tempfile::tempfile_in(self.dest)?;
+                    self.pending_delta = Some((target, collected, mode));
+                }
+                if let Some((_, collected, _)) = &mut self.pending_delta {
+                    io::copy(&mut entry, collected)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Restore a complete `snapshot/` (or non-file `diff/`) entry.
+    fn write_entry<R: Read>(
+        &mut self,
+        entry: &mut tar::Entry<R>,
+        target: &Path,
+        mode: u32,
+    ) -> Result<(), DuplicityError> {
+        let header = entry.header();
+        let kind = header.entry_type();
+        let mtime = header_mtime(header);
+
+        if kind.is_dir() {
+            if !fs::symlink_metadata(target).is_ok_and(|m| m.is_dir()) {
+                remove(target)?;
+                fs::create_dir_all(target)?;
+            }
+            self.settle.push((target.to_path_buf(), mode, mtime));
+        } else if kind.is_symlink() {
+            let Some(link) = entry.link_name()? else {
+                return Ok(());
+            };
+            remove(target)?;
+            create_parent(target)?;
+            std::os::unix::fs::symlink(link, target)?;
+        } else if kind.is_file() {
+            remove(target)?;
+            create_parent(target)?;
+            let mut out = create_file(target, mode)?;
+            io::copy(entry, &mut out)?;
+            out.set_permissions(fs::Permissions::from_mode(mode))?;
+            if let Some(mtime) = mtime {
+                out.set_modified(mtime)?;
+            }
+            self.written += 1;
+        }
+        Ok(())
+    }
+
+    fn finish_delta(&mut self) -> Result<(), DuplicityError> {
+        let Some((target, mut collected, mode)) = self.pending_delta.take() else {
+            return Ok(());
+        };
+        collected.seek(SeekFrom::Start(0))?;
+        self.written += 1;
+        patch_file(&target, &mut BufReader::new(collected), mode)
+    }
+
+    /// Set the modes and mtimes held back while writing, innermost first.
+    fn settle(&mut self) -> io::Result<()> {
+        self.settle.sort_by(|a, b| b.0.cmp(&a.0));
+        for (path, mode, mtime) in self.settle.drain(..) {
+            // Deleted or replaced by a later set
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if metadata.is_symlink() {
+                continue;
+            }
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            if let Some(mtime) = mtime {
+                let _ = File::open(&path).and_then(|f| f.set_modified(mtime));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn header_mtime(header: &tar::Header) -> Option<SystemTime> {
+    let secs = header.mtime().ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(std::time::Duration::from_secs(secs))
+}
+
+/// Create a new file at `path` with no more than `mode`'s permissions, so a
+/// private file is never readable by others on the way. Fails if anything,
+/// a symlink included, is already there.
+fn create_file(path: &Path, mode: u32) -> io::Result<File> {
+    File::options()
+        .write(true)
+        .create_new(true)
+        .mode(mode & 0o777)
+        .open(path)
+}
+
+/// Refuse `relative` when any folder on the way to it inside `dest`, or the
+/// entry itself, is a symlink.
+fn refuse_symlinks(dest: &Path, relative: &Path) -> Result<(), DuplicityError> {
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.is_symlink() && path != dest.join(relative) => {
+                return Err(DuplicityError::UnsafePath(relative.to_path_buf()));
+            }
+            Ok(_) => {}
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Remove whatever is at `path`, if anything.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace `target` with the result of applying a librsync delta to it.
+fn patch_file(target: &Path, delta: &mut impl Read, mode: u32) -> Result<(), DuplicityError> {
+    let bad = |why: &str| DuplicityError::BadDelta(target.to_path_buf(), why.to_string());
+    let mut basis = File::open(target).map_err(|_| bad("the earlier version is missing"))?;
+    let patched = target.with_file_name(format!(
+        ".{}.duplicity-patch",
+        target.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    if fs::symlink_metadata(target)?.is_symlink() {
+        return Err(bad("the earlier version is a link"));
+    }
+    let _ = fs::remove_file(&patched);
+    let mut out = BufWriter::new(create_file(&patched, mode)?);
+    let applied = apply_delta(&mut basis, delta, &mut out)
+        .and_then(|()| out.flush())
+        .and_then(|()| {
+            out.get_ref()
+                .set_permissions(fs::Permissions::from_mode(mode))
+        });
+    drop(out);
+    match applied {
+        Ok(()) => Ok(fs::rename(&patched, target)?),
+        Err(e) => {
+            let _ = fs::remove_file(&patched);
+            Err(if e.kind() == io::ErrorKind::InvalidData {
+                bad(&e.to_string())
+            } else {
+                e.into()
+            })
+        }
+    }
+}
+
+const DELTA_MAGIC: u32 = 0x7273_0236;
+
+/// Apply a librsync (rdiff) delta: literal runs and copies from `basis`.
+fn apply_delta(
+    basis: &mut (impl Read + Seek),
+    delta: &mut impl Read,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    // Parameter widths for the literal and copy commands, in bytes
+    const WIDTHS: [usize; 4] = [1, 2, 4, 8];
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+    if read_be(delta, 4)? != u64::from(DELTA_MAGIC) {
+        return Err(invalid("not a librsync delta"));
+    }
+
+    loop {
+        let mut op = [0u8; 1];
+        delta.read_exact(&mut op)?;
+        match op[0] {
+            0x00 => return Ok(()),
+            n @ 0x01..=0x40 => copy_exact(delta, out, u64::from(n))?,
+            n @ 0x41..=0x44 => {
+                let len = read_be(delta, WIDTHS[usize::from(n - 0x41)])?;
+                copy_exact(delta, out, len)?;
+            }
+            n @ 0x45..=0x54 => {
+                let n = usize::from(n - 0x45);
+                let offset = read_be(delta, WIDTHS[n / 4])?;
+                let len = read_be(delta, WIDTHS[n % 4])?;
+                basis.seek(SeekFrom::Start(offset))?;
+                copy_exact(basis, out, len)?;
+            }
+            _ => return Err(invalid("unknown delta command")),
+        }
+    }
+}
+
+fn read_be(r: &mut impl Read, width: usize) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf[8 - width..])?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn copy_exact(from: &mut impl Read, to: &mut impl Write, len: u64) -> io::Result<()> {
+    let copied = io::copy(&mut from.take(len), to)?;
+    if copied < len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "delta refers past the end of its data",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::tempdir;
+
+    enum Item<'a> {
+        File(&'a str, &'a [u8]),
+        Private(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Link(&'a str, &'a str),
+        Deleted(&'a str),
+    }
+
+    fn write_volume(path: &Path, items: &[Item]) {
+        let gz = GzEncoder::new(File::create(path).unwrap(), Compression::fast());
+        let mut tar = tar::Builder::new(gz);
+        for item in items {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_mtime(1_600_000_000);
+            let (name, data): (&str, &[u8]) = match item {
+                Item::File(name, data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (name, data)
+                }
+                Item::Private(name, data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(0o600);
+                    (name, data)
+                }
+                Item::Dir(name) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    (name, b"")
+                }
+                Item::Link(name, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    tar.append_link(&mut header, name, target).unwrap();
+                    continue;
+                }
+                Item::Deleted(name) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (name, b"")
+                }
+            };
+            header.set_size(data.len() as u64);
+            tar.append_data(&mut header, name, data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn delta(commands: &[&[u8]]) -> Vec<u8> {
+        let mut out = DELTA_MAGIC.to_be_bytes().to_vec();
+        for c in commands {
+            out.extend_from_slice(c);
+        }
+        out.push(0);
+        out
+    }
+
+    fn at(rfc: &str) -> SystemTime {
+        humantime::parse_rfc3339(rfc).unwrap()
+    }
+
+    #[test]
+    fn applies_literal_and_copy_commands() {
+        let mut basis = io::Cursor::new(b"hello world".to_vec());
+        // copy "hello" (offset 0, len 5), literal ", rust", copy "!" is new
+        let d = delta(&[&[0x45, 0, 5], &[0x06], b", rust", &[0x41, 1], b"!"]);
+        let mut out = Vec::new();
+
+        apply_delta(&mut basis, &mut d.as_slice(), &mut out).unwrap();
+
+        assert_eq!(out, b"hello, rust!");
+        let mut out = Vec::new();
+        assert!(apply_delta(&mut basis, &mut &b"nope"[..], &mut out).is_err());
+    }
+
+    #[test]
+    fn restores_full_plus_incrementals() {
+        let backup = tempdir().unwrap();
+        let b = backup.path();
+        let full = "duplicity-full.20210304T102231Z";
+        let inc1 = "duplicity-inc.20210304T102231Z.to.20210305T090000Z";
+        let inc2 = "duplicity-inc.20210305T090000Z.to.20210306T090000Z";
+        for stem in [full, inc1, inc2] {
+            fs::write(b.join(format!("{stem}.manifest")), "").unwrap();
+        }
+        fs::write(
+            b.join("duplicity-full-signatures.20210304T102231Z.sigtar.gz"),
+            "",
+        )
+        .unwrap();
+        write_volume(
+            &b.join(format!("{full}.vol1.difftar.gz")),
+            &[
+                Item::Dir("snapshot"),
+                // A file of the backup's own that happens to have this name
+                Item::File("snapshot/.duplicity-delta", b"mine"),
+                Item::Dir("snapshot/home/joe/Documents"),
+                Item::File("snapshot/home/joe/Documents/notes.txt", b"first draft"),
+                Item::Private("snapshot/home/joe/Documents/key.pem", b"secret"),
+                Item::File("multivol_snapshot/home/joe/Documents/big.bin/1", b"abc"),
+            ],
+        );
+        write_volume(
+            &b.join(format!("{full}.vol2.difftar.gz")),
+            &[
+                Item::File("multivol_snapshot/home/joe/Documents/big.bin/2", b"def"),
+                Item::File("snapshot/home/joe/Documents/old.txt", b"old"),
+            ],
+        );
+        let big_delta = delta(&[&[0x45, 0, 3], &[0x03], b"XYZ"]);
+        let (first, second) = big_delta.split_at(big_delta.len() / 2);
+        write_volume(
+            &b.join(format!("{inc1}.vol1.difftar.gz")),
+            &[
+                Item::File(
+                    "diff/home/joe/Documents/notes.txt",
+                    &delta(&[&[0x45, 0, 5], &[0x06], b" final"]),
+                ),
+                Item::Deleted("deleted/home/joe/Documents/old.txt"),
+                Item::File("multivol_diff/home/joe/Documents/big.bin/1", first),
+                Item::File("multivol_diff/home/joe/Documents/big.bin/2", second),
+            ],
+        );
+        write_volume(
+            &b.join(format!("{inc2}.vol1.difftar.gz")),
+            &[Item::File("snapshot/home/joe/Documents/new.txt", b"new")],
+        );
+        // Interrupted run: volumes but no manifest
+        write_volume(
+            &b.join("duplicity-inc.20210306T090000Z.to.20210307T090000Z.vol1.difftar.gz"),
+            &[Item::Deleted("deleted/home/joe/Documents/notes.txt")],
+        );
+
+        assert!(is_duplicity_backup(b));
+        let sets = find_backup_sets(b).unwrap();
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[0].volumes.len(), 2);
+
+        let out = tempdir().unwrap();
+        let docs = out.path().join("home/joe/Documents");
+        let chain = select_chain(&sets, Some(at("2021-03-05T12:00:00Z"))).unwrap();
+        assert_eq!(chain.len(), 2);
+        let written = restore_chain(&chain, out.path()).unwrap();
+
+        assert_eq!(written, 7);
+        assert_eq!(
+            fs::read_to_string(docs.join("notes.txt")).unwrap(),
+            "first final"
+        );
+        assert_eq!(fs::read_to_string(docs.join("big.bin")).unwrap(), "abcXYZ");
+        assert!(!docs.join("old.txt").exists());
+        assert!(!docs.join("new.txt").exists());
+        assert_eq!(
+            fs::read_to_string(out.path().join(".duplicity-delta")).unwrap(),
+            "mine"
+        );
+        let mode = |name: &str| fs::metadata(docs.join(name)).unwrap().permissions().mode();
+        assert_eq!(mode("key.pem") & 0o777, 0o600);
+        assert_eq!(mode("notes.txt") & 0o777, 0o644);
+        assert_eq!(
+            fs::metadata(&docs).unwrap().modified().unwrap(),
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000)
+        );
+
+        let latest = select_chain(&sets, None).unwrap();
+        assert_eq!(latest.len(), 3);
+        assert!(select_chain(&sets, Some(at("2021-01-01T00:00:00Z"))).is_none());
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_target() {
+        let backup = tempdir().unwrap();
+        let stem = "duplicity-full.20210304T102231Z";
+        fs::write(backup.path().join(format!("{stem}.manifest")), "").unwrap();
+        let volume = backup.path().join(format!("{stem}.vol1.difftar.gz"));
+        // tar::Builder refuses `..`, so write the name into the header directly
+        let gz = GzEncoder::new(File::create(&volume).unwrap(), Compression::fast());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..17].copy_from_slice(b"snapshot/../evil\0");
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(1);
+        header.set_cksum();
+        tar.append(&header, &b"x"[..]).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let sets = find_backup_sets(backup.path()).unwrap();
+        let out = tempdir().unwrap();
+        let err = restore_chain(&select_chain(&sets, None).unwrap(), out.path()).unwrap_err();
+
+        assert!(matches!(err, DuplicityError::UnsafePath(_)));
+    }
+
+    #[test]
+    fn refuses_writes_through_restored_links() {
+        let backup = tempdir().unwrap();
+        let elsewhere = tempdir().unwrap();
+        let stem = "duplicity-full.20210304T102231Z";
+        fs::write(backup.path().join(format!("{stem}.manifest")), "").unwrap();
+        write_volume(
+            &backup.path().join(format!("{stem}.vol1.difftar.gz")),
+            &[
+                Item::Link("snapshot/a", elsewhere.path().to_str().unwrap()),
+                Item::File("snapshot/a/x", b"escaped"),
+            ],
+        );
+
+        let sets = find_backup_sets(backup.path()).unwrap();
+        let out = tempdir().unwrap();
+        let err = restore_chain(&select_chain(&sets, None).unwrap(), out.path()).unwrap_err();
+
+        assert!(matches!(err, DuplicityError::UnsafePath(_)));
+        assert!(!elsewhere.path().join("x").exists());
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::snapshot::parse_stamp;
+use crate::types::{CopyOp, CopyPlan};
+
+/// Split a Windows File History name such as
+/// `report (2021_03_04 10_22_31 UTC).docx` into the original name
+/// (`report.docx`) and the time the version was saved.
+pub fn parse_versioned_name(name: &str) -> Option<(String, SystemTime)> {
+    // ` (` + `YYYY_MM_DD HH_MM_SS` + ` UTC)`
+    const STAMP_LEN: usize = 2 + 19 + 5;
+
+    let end = name.rfind(" UTC)")? + 5;
+    let start = end.checked_sub(STAMP_LEN)?;
+    let suffix = name.get(start..end)?;
+    if !suffix.starts_with(" (") {
+        return None;
+    }
+    let stamp = &suffix[2..21];
+    let shape_ok = stamp.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 | 13 | 16 => b == b'_',
+        10 => b == b' ',
+        _ => b.is_ascii_digit(),
+    });
+    if !shape_ok || start == 0 {
+        return None;
+    }
+    let (time, _) = parse_stamp(stamp)?;
+    Some((format!("{}{}", &name[..start], &name[end..]), time))
+}
+
+/// Whether any of `paths` lies inside a File History backup.
+pub fn is_file_history(paths: &[&Path]) -> bool {
+    paths
+        .iter()
+        .any(|p| p.components().any(|c| c.as_os_str() == "FileHistory"))
+}
+
+/// Collapse File History versions: each file keeps only its newest saved
+/// version, restored under its original name.
+///
+/// Only files whose backup name carries a version suffix are collapsed.
+/// Other files are kept as planned, repeats of a destination from merged
+/// copies included, unless a versioned copy of the same name exists.
+/// Returns the new plan and the number of older versions dropped.
+pub fn collapse_versions(plan: &CopyPlan) -> (CopyPlan, usize) {
+    // A plain op, or the place of a versioned file's newest version
+    enum Slot {
+        Op(CopyOp),
+        Versions(PathBuf),
+    }
+    let version_of = |op: &CopyOp| {
+        let name = op.source.file_name()?.to_str()?;
+        let (name, saved) = parse_versioned_name(name)?;
+        Some((op.dest.with_file_name(name), saved))
+    };
+    let versioned: HashSet<PathBuf> = plan
+        .files
+        .iter()
+        .filter_map(|op| Some(version_of(op)?.0))
+        .collect();
+
+    let mut slots = Vec::new();
+    let mut newest: HashMap<PathBuf, (SystemTime, CopyOp)> = HashMap::new();
+    let mut dropped = 0;
+    for op in &plan.files {
+        let Some((dest, saved)) = version_of(op) else {
+            if versioned.contains(&op.dest) {
+                dropped += 1;
+            } else {
+                slots.push(Slot::Op(op.clone()));
+            }
+            continue;
+        };
+        let version = CopyOp {
+            dest: dest.clone(),
+            ..op.clone()
+        };
+        match newest.get_mut(&dest) {
+            None => {
+                slots.push(Slot::Versions(dest.clone()));
+                newest.insert(dest, (saved, version));
+            }
+            Some(kept) => {
+                dropped += 1;
+                if saved > kept.0 {
+                    *kept = (saved, version);
+                }
+            }
+        }
+    }
+
+    let files: Vec<CopyOp> = slots
+        .into_iter()
+        .map(|slot| match slot {
+            Slot::Op(op) => op,
+            Slot::Versions(dest) => newest.remove(&dest).unwrap().1,
+        })
+        .collect();
+    (
+        CopyPlan {
+            dirs: plan.dirs.clone(),
+            total_bytes: files.iter().map(|f| f.size).sum(),
+            files,
+        },
+        dropped,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::XdgDir;
+
+    fn at(rfc: &str) -> SystemTime {
+        humantime::parse_rfc3339(rfc).unwrap()
+    }
+
+    #[test]
+    fn parses_versioned_names() {
+        assert_eq!(
+            parse_versioned_name("report (2021_03_04 10_22_31 UTC).docx"),
+            Some(("report.docx".to_string(), at("2021-03-04T10:22:31Z")))
+        );
+        assert_eq!(
+            parse_versioned_name("Makefile (2020_01_02 03_04_05 UTC)"),
+            Some(("Makefile".to_string(), at("2020-01-02T03:04:05Z")))
+        );
+        assert_eq!(
+            parse_versioned_name("a (1) (2021_03_04 10_22_31 UTC).tar.gz"),
+            Some(("a (1).tar.gz".to_string(), at("2021-03-04T10:22:31Z")))
+        );
+        assert_eq!(parse_versioned_name("report.docx"), None);
+        assert_eq!(
+            parse_versioned_name("x (2021-03-04 10:22:31 UTC).txt"),
+            None
+        );
+        assert_eq!(parse_versioned_name(" (2021_03_04 10_22_31 UTC).txt"), None);
+    }
+
+    #[test]
+    fn keeps_newest_version_under_original_name() {
+        let op = |name: &str, size: u64| CopyOp {
+            source: Path::new("/b/FileHistory/joe/PC/Data/C/Users/joe/Documents").join(name),
+            dest: Path::new("/home/joe/Documents").join(name),
+            size,
+            mtime: None,
+            xdg_dir: XdgDir::Documents,
+            snapshot: None,
+        };
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![
+                op("report (2021_03_04 10_22_31 UTC).docx", 1),
+                op("report (2021_05_01 08_00_00 UTC).docx", 2),
+                op("report (2020_12_24 18_30_00 UTC).docx", 4),
+                op("notes.txt", 8),
+            ],
+            total_bytes: 15,
+        };
+
+        let (collapsed, dropped) = collapse_versions(&plan);
+
+        assert_eq!(dropped, 2);
+        assert_eq!(collapsed.total_bytes, 10);
+        let files: Vec<(&Path, &Path)> = collapsed
+            .files
+            .iter()
+            .map(|f| (f.dest.as_path(), f.source.file_name().unwrap().as_ref()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    Path::new("/home/joe/Documents/report.docx"),
+                    Path::new("report (2021_05_01 08_00_00 UTC).docx")
+                ),
+                (
+                    Path::new("/home/joe/Documents/notes.txt"),
+                    Path::new("notes.txt")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_repeats_of_merged_copies() {
+        let op = |copy: &str, name: &str, size: u64| CopyOp {
+            source: Path::new("/b").join(copy).join(name),
+            dest: Path::new("/home/joe/Documents").join(name),
+            size,
+            mtime: None,
+            xdg_dir: XdgDir::Documents,
+            snapshot: None,
+        };
+        // Two merged copies of Documents, one of them a File History backup
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![
+                op("laptop", "notes.txt", 1),
+                op("FileHistory", "report (2021_03_04 10_22_31 UTC).docx", 2),
+                op("desktop", "notes.txt", 4),
+                op("FileHistory", "report (2021_05_01 08_00_00 UTC).docx", 8),
+            ],
+            total_bytes: 15,
+        };
+
+        let (collapsed, dropped) = collapse_versions(&plan);
+
+        assert_eq!(dropped, 1);
+        let files: Vec<(&Path, u64)> = collapsed
+            .files
+            .iter()
+            .map(|f| (f.dest.as_path(), f.size))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (Path::new("/home/joe/Documents/notes.txt"), 1),
+                (Path::new("/home/joe/Documents/report.docx"), 8),
+                (Path::new("/home/joe/Documents/notes.txt"), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_file_history_paths() {
+        assert!(is_file_history(&[Path::new(
+            "/mnt/FileHistory/joe/PC/Data/C/Users/joe/Documents"
+        )]));
+        assert!(!is_file_history(&[Path::new("/mnt/Users/joe/Documents")]));
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::archive::{self, Files, Listing, Member};
+use crate::iso9660::Iso9660;
+use crate::squashfs::SquashFs;
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// A valid image using a feature this reader doesn't handle.
+    Unsupported(String),
+    /// The image is truncated or its structures don't parse.
+    Corrupt(String),
+    /// An entry name would land outside the extraction directory.
+    UnsafePath(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Unsupported(what) => write!(f, "unsupported image: {what}"),
+            ImageError::Corrupt(what) => write!(f, "image is damaged: {what}"),
+            ImageError::UnsafePath(p) => {
+                write!(f, "refusing to extract {} outside the target", p.display())
+            }
+            ImageError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+pub(crate) fn corrupt(what: impl fmt::Display) -> ImageError {
+    ImageError::Corrupt(what.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    SquashFs,
+    Iso9660,
+}
+
+impl fmt::Display for ImageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageKind::SquashFs => "SquashFS",
+            ImageKind::Iso9660 => "ISO 9660",
+        })
+    }
+}
+
+/// Recognize an image file by its magic bytes.
+pub fn detect_image(path: &Path) -> Option<ImageKind> {
+    let file = File::open(path).ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+    // ISO 9660 puts its first volume descriptor after 16 reserved sectors
+    let mut head = Vec::new();
+    file.take(16 * 2048 + 6).read_to_end(&mut head).ok()?;
+    if head.starts_with(b"hsqs") {
+        Some(ImageKind::SquashFs)
+    } else if head.get(16 * 2048 + 1..) == Some(b"CD001") {
+        Some(ImageKind::Iso9660)
+    } else {
+        None
+    }
+}
+
+/// One entry of an image's directory tree.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Permission bits as stored in the image.
+    pub mode: u32,
+    pub mtime: SystemTime,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EntryKind {
+    Dir(Vec<Entry>),
+    /// `id` is passed back to [`Image::read_file`].
+    File {
+        size: u64,
+        id: usize,
+    },
+    Symlink(String),
+    /// Devices, FIFOs and sockets, which are never restored.
+    Other,
+}
+
+/// A filesystem image read in userspace. Reads go through positioned I/O,
+/// so files can be extracted from several threads at once.
+pub trait Image: Sync {
+    /// The entries at the top of the image.
+    fn root(&self) -> &[Entry];
+
+    /// Write the contents of file `id` to `out`.
+    fn read_file(&self, id: usize, out: &mut dyn Write) -> Result<(), ImageError>;
+}
+
+/// Open an image file of the given kind and read its directory tree.
+pub fn open_image(path: &Path, kind: ImageKind) -> Result<Box<dyn Image>, ImageError> {
+    let file = File::open(path)?;
+    Ok(match kind {
+        ImageKind::SquashFs => Box::new(SquashFs::open(file)?),
+        ImageKind::Iso9660 => Box::new(Iso9660::open(file)?),
+    })
+}
+
+/// List the files and folders of `image` at their paths in it. Of the
+/// contents only `etc/passwd` and `etc/group` are read; the returned
+/// [`ImageFiles`] reads the rest as they are restored.
+pub fn list_image(image: Box<dyn Image>) -> Result<(Listing, ImageFiles), ImageError> {
+    let mut listing = Listing::default();
+    collect(image.as_ref(), image.root(), Path::new(""), &mut listing)?;
+    Ok((listing, ImageFiles(image)))
+}
+
+fn collect(
+    image: &dyn Image,
+    entries: &[Entry],
+    dir: &Path,
+    listing: &mut Listing,
+) -> Result<(), ImageError> {
+    for entry in entries {
+        if entry.name.is_empty()
+            || entry.name == "."
+            || entry.name == ".."
+            || entry.name.contains('/')
+        {
+            return Err(ImageError::UnsafePath(dir.join(&entry.name)));
+        }
+        let path = dir.join(&entry.name);
+        let (is_dir, size, index) = match &entry.kind {
+            EntryKind::Dir(_) => (true, 0, 0),
+            EntryKind::File { size, id } => (false, *size, *id),
+            EntryKind::Symlink(_) | EntryKind::Other => {
+                listing.skipped += 1;
+                continue;
+            }
+        };
+        if !is_dir && archive::is_account_file(&path, size) {
+            let mut text = Vec::new();
+            image.read_file(index, &mut text)?;
+            if let Ok(text) = String::from_utf8(text) {
+                listing.account_files.insert(path.clone(), text);
+            }
+        }
+        listing.members.push(Member {
+            index,
+            path: path.clone(),
+            is_dir,
+            size,
+            mode: entry.mode & 0o7777,
+            mtime: entry.mtime,
+            owner: None,
+        });
+        if let EntryKind::Dir(children) = &entry.kind {
+            collect(image, children, &path, listing)?;
+        }
+    }
+    Ok(())
+}
+
+/// The files of an image, read in place through positioned I/O.
+pub struct ImageFiles(Box<dyn Image>);
+
+impl Files for ImageFiles {
+    fn write_member(&self, member: &Member, out: &mut dyn Write) -> io::Result<u64> {
+        let mut out = Counted { out, bytes: 0 };
+        self.0
+            .read_file(member.index, &mut out)
+            .map_err(|e| match e {
+                ImageError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?;
+        Ok(out.bytes)
+    }
+}
+
+/// A writer that counts what passes through it.
+struct Counted<'a> {
+    out: &'a mut dyn Write,
+    bytes: u64,
+}
+
+impl Write for Counted<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::restore_files;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::tempdir;
+
+    struct Fake {
+        root: Vec<Entry>,
+        contents: Vec<&'static str>,
+    }
+
+    impl Image for Fake {
+        fn root(&self) -> &[Entry] {
+            &self.root
+        }
+
+        fn read_file(&self, id: usize, out: &mut dyn Write) -> Result<(), ImageError> {
+            Ok(out.write_all(self.contents[id].as_bytes())?)
+        }
+    }
+
+    fn entry(name: &str, mode: u32, kind: EntryKind) -> Entry {
+        Entry {
+            name: name.to_string(),
+            mode,
+            mtime: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            kind,
+        }
+    }
+
+    #[test]
+    fn restores_only_xdg_folders_with_modes_and_mtimes() {
+        let image = Fake {
+            root: vec![entry(
+                "joe",
+                0o755,
+                EntryKind::Dir(vec![
+                    entry(
+                        "Documents",
+                        0o555,
+                        EntryKind::Dir(vec![
+                            entry("run.sh", 0o750, EntryKind::File { size: 3, id: 0 }),
+                            entry("link", 0o777, EntryKind::Symlink("run.sh".into())),
+                        ]),
+                    ),
+                    entry(".bashrc", 0o644, EntryKind::File { size: 3, id: 1 }),
+                    entry("src", 0o755, EntryKind::Dir(vec![])),
+                ]),
+            )],
+            contents: vec!["abc", "def"],
+        };
+        let out = tempdir().unwrap();
+
+        let (listing, files) = list_image(Box::new(image)).unwrap();
+        let result = restore_files(&files, &listing, out.path());
+
+        let docs = out.path().join("Documents");
+        assert_eq!(result.copied.len(), 1);
+        assert_eq!(listing.skipped, 1);
+        assert_eq!(fs::read_to_string(docs.join("run.sh")).unwrap(), "abc");
+        let meta = fs::metadata(docs.join("run.sh")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(
+            meta.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        let meta = fs::metadata(&docs).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o755);
+        assert_eq!(
+            meta.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        assert!(!out.path().join(".bashrc").exists());
+        assert!(!out.path().join("src").exists());
+    }
+
+    #[test]
+    fn rejects_names_that_escape() {
+        let image = Fake {
+            root: vec![entry(
+                "Documents",
+                0o755,
+                EntryKind::Dir(vec![entry("..", 0o644, EntryKind::File { size: 0, id: 0 })]),
+            )],
+            contents: vec![""],
+        };
+        let err = list_image(Box::new(image)).err().unwrap();
+
+        assert!(matches!(err, ImageError::UnsafePath(_)));
+    }
+}
@@ -0,0 +1,608 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, SystemTime};
+
+use crate::image::{corrupt, Entry, EntryKind, Image, ImageError};
+
+const SECTOR: u64 = 2048;
+const SECTOR_LEN: usize = 2048;
+const FLAG_DIRECTORY: u8 = 0x02;
+/// Set on every record of a file stored in several extents but the last.
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// An ISO 9660 image, as burned to CD/DVD or written by `genisoimage` or
+/// `xorriso`. Rock Ridge names, modes, mtimes and symlinks are used when
+/// present, otherwise Joliet names, otherwise the plain 8.3-style names.
+pub struct Iso9660 {
+    file: File,
+    root: Vec<Entry>,
+    /// Byte ranges making up each file.
+    files: Vec<Vec<(u64, u64)>>,
+}
+
+/// Which of the image's name sets the tree is read with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Names {
+    RockRidge,
+    Joliet,
+    Plain,
+}
+
+/// A directory record, after Rock Ridge fields are applied.
+struct Record {
+    name: String,
+    extent: u64,
+    size: u64,
+    flags: u8,
+    mode: Option<u32>,
+    mtime: SystemTime,
+    symlink: Option<String>,
+    /// Rock Ridge relocated-directory markers; such entries are skipped in
+    /// favour of the child link (`CL`).
+    relocated: bool,
+    child_link: Option<u64>,
+}
+
+impl Iso9660 {
+    /// Read the volume descriptors and the whole directory tree.
+    pub fn open(file: File) -> Result<Iso9660, ImageError> {
+        let mut primary = None;
+        let mut joliet = None;
+        for sector in 16..64 {
+            let mut vd = vec![0u8; SECTOR_LEN];
+            file.read_exact_at(&mut vd, sector * SECTOR)?;
+            if &vd[1..6] != b"CD001" {
+                return Err(corrupt("bad volume descriptor"));
+            }
+            match vd[0] {
+                1 if primary.is_none() => primary = Some(vd),
+                // Joliet is a supplementary descriptor with a UCS-2 escape
+                2 if matches!(&vd[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(vd),
+                255 => break,
+                _ => {}
+            }
+        }
+        let primary = primary.ok_or_else(|| corrupt("no primary volume descriptor"))?;
+        if le16(&primary, 128) != 2048 {
+            return Err(ImageError::Unsupported(format!(
+                "{}-byte logical blocks",
+                le16(&primary, 128)
+            )));
+        }
+
+        let mut image = Iso9660 {
+            file,
+            root: Vec::new(),
+            files: Vec::new(),
+        };
+        let root = parse_record(&primary[156..190], Names::Plain)?;
+        let (root, names) = match joliet {
+            _ if image.has_rock_ridge(&root)? => (root, Names::RockRidge),
+            Some(vd) => (parse_record(&vd[156..190], Names::Joliet)?, Names::Joliet),
+            None => (root, Names::Plain),
+        };
+        let mut files = Vec::new();
+        image.root = image.read_dir(&root, names, 0, &mut files)?;
+        image.files = files;
+        Ok(image)
+    }
+
+    /// Rock Ridge images announce themselves with a SUSP `SP` entry in the
+    /// root's `.` record.
+    fn has_rock_ridge(&self, root: &Record) -> Result<bool, ImageError> {
+        let mut sector = vec![0u8; SECTOR_LEN];
+        self.file.read_exact_at(&mut sector, root.extent * SECTOR)?;
+        let len = usize::from(sector[0]);
+        let name_len = usize::from(sector[32]);
+        let system_use = 33 + name_len + (1 - name_len % 2);
+        Ok(sector.get(system_use..system_use + 2) == Some(b"SP") && len > system_use)
+    }
+
+    fn read_dir(
+        &self,
+        dir: &Record,
+        names: Names,
+        depth: usize,
+        files: &mut Vec<Vec<(u64, u64)>>,
+    ) -> Result<Vec<Entry>, ImageError> {
+        // Guards against directory loops in a damaged image
+        if depth > 256 {
+            return Err(corrupt("directories nested too deeply"));
+        }
+        let mut data = vec![0u8; usize::try_from(dir.size).map_err(corrupt)?];
+        self.file.read_exact_at(&mut data, dir.extent * SECTOR)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = usize::from(data[pos]);
+            if len == 0 {
+                // Records never cross a sector; the rest is padding
+                pos = (pos / SECTOR_LEN + 1) * SECTOR_LEN;
+                continue;
+            }
+            let raw = data
+                .get(pos..pos + len)
+                .ok_or_else(|| corrupt("directory record runs past its directory"))?;
+            pos += len;
+            let mut record = parse_record(raw, names)?;
+            if record.name.is_empty() {
+                // `.` and `..`
+                continue;
+            }
+            if names == Names::RockRidge {
+                self.apply_rock_ridge(raw, &mut record)?;
+            }
+            records.push(record);
+        }
+
+        let mut entries = Vec::new();
+        let mut extents = Vec::new();
+        for record in records {
+            if record.relocated {
+                continue;
+            }
+            let mode = record
+                .mode
+                .unwrap_or(if record.flags & FLAG_DIRECTORY != 0 {
+                    0o755
+                } else {
+                    0o644
+                });
+            let kind = if let Some(target) = record.symlink {
+                EntryKind::Symlink(target)
+            } else if let Some(extent) = record.child_link {
+                let moved = self.relocated_dir(extent)?;
+                EntryKind::Dir(self.read_dir(&moved, names, depth + 1, files)?)
+            } else if record.flags & FLAG_DIRECTORY != 0 {
+                EntryKind::Dir(self.read_dir(&record, names, depth + 1, files)?)
+            } else {
+                extents.push((record.extent * SECTOR, record.size));
+                if record.flags & FLAG_MULTI_EXTENT != 0 {
+                    continue;
+                }
+                let size = extents.iter().map(|e| e.1).sum();
+                files.push(std::mem::take(&mut extents));
+                EntryKind::File {
+                    size,
+                    id: files.len() - 1,
+                }
+            };
+            entries.push(Entry {
+                name: record.name,
+                mode,
+                mtime: record.mtime,
+                kind,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// The `.` record of a directory Rock Ridge moved out of a deep tree.
+    fn relocated_dir(&self, extent: u64) -> Result<Record, ImageError> {
+        let mut sector = vec![0u8; SECTOR_LEN];
+        self.file.read_exact_at(&mut sector, extent * SECTOR)?;
+        let len = usize::from(sector[0]);
+        parse_record(&sector[..len], Names::RockRidge)
+    }
+
+    /// Apply the Rock Ridge entries in a record's system use area, following
+    /// continuation areas (`CE`).
+    fn apply_rock_ridge(&self, raw: &[u8], record: &mut Record) -> Result<(), ImageError> {
+        let name_len = usize::from(raw[32]);
+        let start = 33 + name_len + (1 - name_len % 2);
+        let mut area = raw.get(start..).unwrap_or_default().to_vec();
+        let mut name = String::new();
+        let mut link = String::new();
+        // Bounds the number of continuation areas followed
+        for _ in 0..16 {
+            let mut continuation = None;
+            let mut pos = 0;
+            while pos + 4 <= area.len() {
+                let (sig, len) = (&area[pos..pos + 2], usize::from(area[pos + 2]));
+                if len < 4 || pos + len > area.len() {
+                    break;
+                }
+                let body = &area[pos + 4..pos + len];
+                match sig {
+                    b"PX" if body.len() >= 8 => record.mode = Some(le32(body, 0) & 0o7777),
+                    b"NM" if !body.is_empty() => {
+                        name.push_str(&String::from_utf8_lossy(&body[1..]));
+                    }
+                    b"SL" if !body.is_empty() => append_symlink(&body[1..], &mut link),
+                    b"TF" if !body.is_empty() => {
+                        if let Some(mtime) = rock_ridge_mtime(body) {
+                            record.mtime = mtime;
+                        }
+                    }
+                    b"CL" if body.len() >= 8 => record.child_link = Some(u64::from(le32(body, 0))),
+                    b"RE" => record.relocated = true,
+                    b"CE" if body.len() >= 24 => {
+                        continuation = Some((le32(body, 0), le32(body, 8), le32(body, 16)));
+                    }
+                    b"ST" => break,
+                    _ => {}
+                }
+                pos += len;
+            }
+            let Some((block, offset, len)) = continuation else {
+                break;
+            };
+            // A continuation area never spans past its own sector
+            let (offset, len) = (offset as usize, len as usize);
+            if offset.saturating_add(len) > SECTOR_LEN {
+                return Err(corrupt("continuation area runs past its sector"));
+            }
+            area = vec![0; len];
+            self.file
+                .read_exact_at(&mut area, u64::from(block) * SECTOR + offset as u64)?;
+        }
+        if !name.is_empty() {
+            record.name = name;
+        }
+        if !link.is_empty() {
+            record.symlink = Some(link);
+        }
+        Ok(())
+    }
+}
+
+impl Image for Iso9660 {
+    fn root(&self) -> &[Entry] {
+        &self.root
+    }
+
+    fn read_file(&self, id: usize, out: &mut dyn Write) -> Result<(), ImageError> {
+        let mut buf = vec![0u8; 1 << 16];
+        for &(start, len) in &self.files[id] {
+            let mut done = 0;
+            while done < len {
+                let n = usize::try_from(len - done).map_or(buf.len(), |left| left.min(buf.len()));
+                self.file.read_exact_at(&mut buf[..n], start + done)?;
+                out.write_all(&buf[..n])?;
+                done += n as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_record(raw: &[u8], names: Names) -> Result<Record, ImageError> {
+    if raw.len() < 34 {
+        return Err(corrupt("short directory record"));
+    }
+    let name_len = usize::from(raw[32]);
+    let id = raw
+        .get(33..33 + name_len)
+        .ok_or_else(|| corrupt("directory record name runs past the record"))?;
+    let name = if id == [0] || id == [1] {
+        String::new()
+    } else if names == Names::Joliet {
+        let units: Vec<u16> = id
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        strip_version(&String::from_utf16_lossy(&units))
+    } else {
+        strip_version(&String::from_utf8_lossy(id))
+    };
+    Ok(Record {
+        name,
+        extent: u64::from(le32(raw, 2)),
+        size: u64::from(le32(raw, 10)),
+        flags: raw[25],
+        mode: None,
+        mtime: short_time(&raw[18..25]).unwrap_or(SystemTime::UNIX_EPOCH),
+        symlink: None,
+        relocated: false,
+        child_link: None,
+    })
+}
+
+/// Drop the `;1` version suffix, and the `.` left on names without an
+/// extension.
+fn strip_version(name: &str) -> String {
+    let name = name.split_once(';').map_or(name, |(n, _)| n);
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+/// Rock Ridge symlink components: flags, length, content.
+fn append_symlink(mut body: &[u8], link: &mut String) {
+    while body.len() >= 2 {
+        let (flags, len) = (body[0], usize::from(body[1]));
+        let Some(content) = body.get(2..2 + len) else {
+            return;
+        };
+        let part = match flags & 0x0e {
+            0x02 => ".".into(),
+            0x04 => "..".into(),
+            0x08 => String::new(),
+            _ => String::from_utf8_lossy(content).into_owned(),
+        };
+        if flags & 0x08 != 0 {
+            link.push('/');
+        } else {
+            if !link.is_empty() && !link.ends_with('/') {
+                link.push('/');
+            }
+            link.push_str(&part);
+        }
+        body = &body[2 + len..];
+    }
+}
+
+/// The modification time from a Rock Ridge `TF` entry.
+fn rock_ridge_mtime(body: &[u8]) -> Option<SystemTime> {
+    const CREATION: u8 = 0x01;
+    const MODIFY: u8 = 0x02;
+    const LONG_FORM: u8 = 0x80;
+
+    let flags = body[0];
+    if flags & MODIFY == 0 {
+        return None;
+    }
+    let width = if flags & LONG_FORM != 0 { 17 } else { 7 };
+    let at = 1 + if flags & CREATION != 0 { width } else { 0 };
+    let stamp = body.get(at..at + width)?;
+    if width == 7 {
+        short_time(stamp)
+    } else {
+        long_time(stamp)
+    }
+}
+
+/// The 7-byte directory record time: years since 1900, month, day, hour,
+/// minute, second, and the UTC offset in 15-minute steps.
+fn short_time(b: &[u8]) -> Option<SystemTime> {
+    let stamp = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        1900 + u32::from(b[0]),
+        b[1],
+        b[2],
+        b[3],
+        b[4],
+        b[5]
+    );
+    apply_offset(humantime::parse_rfc3339(&stamp).ok()?, b[6])
+}
+
+/// The 17-byte volume descriptor time: `YYYYMMDDHHMMSScc` in ASCII plus
+/// the UTC offset.
+fn long_time(b: &[u8]) -> Option<SystemTime> {
+    let digits = std::str::from_utf8(&b[..14]).ok()?;
+    let stamp = format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &digits[..4],
+        &digits[4..6],
+        &digits[6..8],
+        &digits[8..10],
+        &digits[10..12],
+        &digits[12..14]
+    );
+    apply_offset(humantime::parse_rfc3339(&stamp).ok()?, b[16])
+}
+
+fn apply_offset(local: SystemTime, quarter_hours: u8) -> Option<SystemTime> {
+    #[allow(clippy::cast_possible_wrap)]
+    let offset = i64::from(quarter_hours as i8) * 15 * 60;
+    let shift = Duration::from_secs(offset.unsigned_abs());
+    if offset >= 0 {
+        local.checked_sub(shift)
+    } else {
+        local.checked_add(shift)
+    }
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+// Fixture builders write lengths into the format's narrow fields
+#[allow(clippy::cast_possible_truncation)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+
+    fn both32(v: u32) -> [u8; 8] {
+        let mut b = [0; 8];
+        b[..4].copy_from_slice(&v.to_le_bytes());
+        b[4..].copy_from_slice(&v.to_be_bytes());
+        b
+    }
+
+    /// 2021-03-04 10:22:31 at UTC+1
+    const DATE: [u8; 7] = [121, 3, 4, 10, 22, 31, 4];
+
+    fn record(name: &[u8], extent: u32, size: u32, flags: u8, system_use: &[u8]) -> Vec<u8> {
+        let mut r = vec![0u8; 33];
+        r[2..10].copy_from_slice(&both32(extent));
+        r[10..18].copy_from_slice(&both32(size));
+        r[18..25].copy_from_slice(&DATE);
+        r[25] = flags;
+        r[28..32].copy_from_slice(&[1, 0, 0, 1]);
+        r[32] = name.len() as u8;
+        r.extend(name);
+        if name.len().is_multiple_of(2) {
+            r.push(0);
+        }
+        r.extend(system_use);
+        if r.len() % 2 == 1 {
+            r.push(0);
+        }
+        r[0] = r.len() as u8;
+        r
+    }
+
+    fn susp(sig: [u8; 2], body: &[u8]) -> Vec<u8> {
+        [&sig[..], &[4 + body.len() as u8, 1], body].concat()
+    }
+
+    fn rock_ridge(name: &str, mode: u32) -> Vec<u8> {
+        [
+            susp(
+                *b"PX",
+                &[&both32(mode)[..], &both32(1), &both32(0), &both32(0)].concat(),
+            ),
+            susp(*b"NM", &[&[0u8][..], name.as_bytes()].concat()),
+            susp(*b"TF", &[&[0x02u8][..], &[120, 1, 2, 3, 4, 5, 0]].concat()),
+        ]
+        .concat()
+    }
+
+    /// Root at sector 18, `Documents` at 19, file data from 20.
+    fn build_image(with_rock_ridge: bool) -> Vec<u8> {
+        let mut img = vec![0u8; 24 * SECTOR_LEN];
+        let su = |name: &str, mode: u32| {
+            if with_rock_ridge {
+                rock_ridge(name, mode)
+            } else {
+                Vec::new()
+            }
+        };
+        let sector = |img: &mut Vec<u8>, n: usize, data: &[u8]| {
+            img[n * SECTOR_LEN..n * SECTOR_LEN + data.len()].copy_from_slice(data);
+        };
+
+        let sp = if with_rock_ridge {
+            susp(*b"SP", &[0xbe, 0xef, 0])
+        } else {
+            Vec::new()
+        };
+        let root = [
+            record(&[0], 18, 2048, FLAG_DIRECTORY, &sp),
+            record(&[1], 18, 2048, FLAG_DIRECTORY, &[]),
+            record(
+                b"DOCUMENTS",
+                19,
+                2048,
+                FLAG_DIRECTORY,
+                &su("Documents", 0o40_755),
+            ),
+            record(b"README.;1", 23, 3, 0, &su("README", 0o100_644)),
+        ]
+        .concat();
+        sector(&mut img, 18, &root);
+
+        let link = if with_rock_ridge {
+            susp(*b"SL", &[&[0u8, 0, 9][..], b"notes.txt"].concat())
+        } else {
+            Vec::new()
+        };
+        let docs = [
+            record(&[0], 19, 2048, FLAG_DIRECTORY, &[]),
+            record(&[1], 18, 2048, FLAG_DIRECTORY, &[]),
+            record(
+                b"BIG.BIN;1",
+                20,
+                2048,
+                FLAG_MULTI_EXTENT,
+                &su("big.bin", 0o100_600),
+            ),
+            record(b"BIG.BIN;1", 21, 5, 0, &su("big.bin", 0o100_600)),
+            record(b"LINK.;1", 0, 0, 0, &[su("link", 0o120_777), link].concat()),
+            record(b"NOTES.TXT;1", 22, 5, 0, &su("notes.txt", 0o100_640)),
+        ]
+        .concat();
+        sector(&mut img, 19, &docs);
+        sector(&mut img, 20, &[b'a'; 2048]);
+        sector(&mut img, 21, b"bbbbb");
+        sector(&mut img, 22, b"hello");
+        sector(&mut img, 23, b"top");
+
+        let mut pvd = vec![0u8; 190];
+        pvd[0] = 1;
+        pvd[1..6].copy_from_slice(b"CD001");
+        pvd[6] = 1;
+        pvd[128..130].copy_from_slice(&2048u16.to_le_bytes());
+        pvd[156..190].copy_from_slice(&record(&[0], 18, 2048, FLAG_DIRECTORY, &[]));
+        sector(&mut img, 16, &pvd);
+        sector(&mut img, 17, &[255, b'C', b'D', b'0', b'0', b'1', 1]);
+        img
+    }
+
+    fn open(img: &[u8]) -> Iso9660 {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(img).unwrap();
+        file.rewind().unwrap();
+        Iso9660::open(file).unwrap()
+    }
+
+    fn contents(image: &Iso9660, entry: &Entry) -> Vec<u8> {
+        let EntryKind::File { id, .. } = entry.kind else {
+            panic!("{} is not a file", entry.name);
+        };
+        let mut out = Vec::new();
+        image.read_file(id, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn reads_rock_ridge_names_modes_and_times() {
+        let image = open(&build_image(true));
+
+        let names: Vec<&str> = image.root().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Documents", "README"]);
+        let EntryKind::Dir(docs) = &image.root()[0].kind else {
+            panic!("Documents is not a directory");
+        };
+        let names: Vec<&str> = docs.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["big.bin", "link", "notes.txt"]);
+
+        let big = contents(&image, &docs[0]);
+        assert_eq!(big.len(), 2053);
+        assert!(big.ends_with(b"abbbbb"));
+        assert!(matches!(&docs[1].kind, EntryKind::Symlink(t) if t == "notes.txt"));
+        assert_eq!(contents(&image, &docs[2]), b"hello");
+        assert_eq!(docs[2].mode, 0o640);
+        assert_eq!(
+            docs[2].mtime,
+            humantime::parse_rfc3339("2020-01-02T03:04:05Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_continuation_areas() {
+        let mut img = build_image(true);
+        let ce = susp(*b"CE", &[both32(23), both32(16), both32(u32::MAX)].concat());
+        let root = [
+            record(
+                &[0],
+                18,
+                2048,
+                FLAG_DIRECTORY,
+                &susp(*b"SP", &[0xbe, 0xef, 0]),
+            ),
+            record(&[1], 18, 2048, FLAG_DIRECTORY, &[]),
+            record(b"README.;1", 23, 3, 0, &ce),
+        ]
+        .concat();
+        img[18 * SECTOR_LEN..19 * SECTOR_LEN].fill(0);
+        img[18 * SECTOR_LEN..18 * SECTOR_LEN + root.len()].copy_from_slice(&root);
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&img).unwrap();
+
+        let err = Iso9660::open(file).err().unwrap();
+
+        assert!(matches!(err, ImageError::Corrupt(_)), "{err}");
+    }
+
+    #[test]
+    fn falls_back_to_plain_names() {
+        let image = open(&build_image(false));
+
+        let names: Vec<&str> = image.root().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["DOCUMENTS", "README"]);
+        assert_eq!(contents(&image, &image.root()[1]), b"top");
+        assert_eq!(image.root()[1].mode, 0o644);
+        assert_eq!(
+            image.root()[1].mtime,
+            humantime::parse_rfc3339("2021-03-04T09:22:31Z").unwrap()
+        );
+    }
+}
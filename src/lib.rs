@@ -1,6 +1,28 @@
+pub mod age;
+pub mod archive;
+pub mod candidates;
 pub mod conflict;
 pub mod copy;
+pub mod dotfiles;
+pub mod duplicity;
+pub mod file_history;
+pub mod image;
+pub mod iso9660;
+pub mod merge;
+pub mod picker;
 pub mod plan;
+pub mod plan_file;
+pub mod preview;
 pub mod report;
+pub mod restic;
+pub mod rules;
+pub mod s3;
 pub mod scan;
+pub mod sftp;
+pub mod snapshot;
+pub mod squashfs;
+pub mod staging;
+pub mod trash;
+pub mod tui;
 pub mod types;
+pub mod users;
@@ -1,116 +1,1874 @@
-use std::collections::BTreeMap;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
 
-use anyhow::bail;
-use clap::Parser;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use console::style;
-use dialoguer::{Confirm, Select};
+use dialoguer::{Confirm, MultiSelect, Password, Select};
 
-use backup_restore::conflict::{self, Resolution};
-use backup_restore::copy;
-use backup_restore::types::{Conflict, DetectedMapping, XdgDir};
-use backup_restore::{plan, report, scan};
+use backup_restore::age;
+use backup_restore::archive::{self, Encryption, Files, Key, Listing};
+use backup_restore::conflict::{self, Disposal, Resolution, ResolveError};
+use backup_restore::copy::{self, Owner, Ownership, WriteAs};
+use backup_restore::dotfiles::{self, Category, CategoryChoice, Settled};
+use backup_restore::duplicity::{self, DuplicityError};
+use backup_restore::file_history;
+use backup_restore::image;
+use backup_restore::plan::MergeNote;
+use backup_restore::restic::{self, RepoSnapshot, Repository};
+use backup_restore::rules::{Rule, RuleSet};
+use backup_restore::s3::{self, Credentials, Location};
+use backup_restore::sftp::{self, Remote, Session};
+use backup_restore::snapshot::{self, Snapshot, SnapshotSet};
+use backup_restore::staging::{self, StagingDir};
+use backup_restore::trash::Trash;
+use backup_restore::types::{Conflict, CopyPlan, CopyResult, DetectedMapping, XdgDir};
+use backup_restore::users::{self, Account, IdMap, Skipped};
+use backup_restore::{candidates, picker, plan, plan_file, preview, report, scan, tui};
 
 #[derive(Parser)]
 #[command(
     name = "backup-restore",
-    about = "Restore files from a backup into your home directory"
+    about = "Restore files from a backup into your home directory",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    /// Path to the backup directory to restore from
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    restore: RestoreArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Restore files from a backup (the default when no subcommand is given)
+    Restore(RestoreArgs),
+    /// Scan a backup and save the copy plan to a file for review
+    Plan(PlanArgs),
+    /// Resolve `.restore` files left over from earlier restores
+    Resolve(ResolveArgs),
+    /// Show which files differ between two copies of a folder
+    Compare(CompareArgs),
+}
+
+#[derive(Args)]
+#[allow(clippy::struct_excessive_bools)] // Independent command-line switches
+struct RestoreArgs {
+    /// Path to the backup directory, SquashFS/ISO image, tar archive
+    /// (optionally age- or GPG-encrypted), `[user@]host:path` on an SSH
+    /// server, or `s3://bucket/prefix`, to restore from
+    #[arg(required_unless_present = "plan")]
+    backup_dir: Option<PathBuf>,
+
+    /// Execute a plan saved with `plan --out` instead of scanning a backup
+    #[arg(long, conflicts_with_all = ["backup_dir", "home"])]
+    plan: Option<PathBuf>,
+
+    /// Home directory to restore into (defaults to $HOME)
+    #[arg(long)]
+    home: Option<PathBuf>,
+
+    /// Preview what would happen without copying anything
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Restore only this folder or file, relative to the home directory
+    /// (repeatable); skips the interactive picker
+    #[arg(long, value_name = "PATH")]
+    only: Vec<PathBuf>,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Move replaced originals, discarded .restore files and cleaned-up
+    /// sources to the trash instead of deleting them
+    #[arg(long)]
+    trash: bool,
+
+    /// Older copy of the home directory to use as the common ancestor when
+    /// merging text conflicts (enables three-way merges)
+    #[arg(long, value_name = "DIR")]
+    merge_base: Option<PathBuf>,
+
+    /// Go straight to the full-screen conflict browser
+    #[arg(long)]
+    tui: bool,
+
+    /// Restore every user's home found in the backup (a copy of /home)
+    /// into the local account of the same name, owned by that user. Needs
+    /// root
+    #[arg(long, conflicts_with_all = ["plan", "home", "only", "merge_base"])]
+    all_users: bool,
+
+    /// With --all-users, restore the backup's home NAME into local account
+    /// USER instead (repeatable)
+    #[arg(long = "user", value_name = "NAME=USER", value_parser = parse_user_mapping, requires = "all_users")]
+    user_map: Vec<(String, String)>,
+
+    /// As root, give files owned by uid OLD in the backup to local uid NEW
+    /// (repeatable). Accounts in the backup's /etc/passwd are mapped to
+    /// local ones of the same name without asking
+    #[arg(long, value_name = "OLD:NEW", value_parser = parse_id_mapping, conflicts_with = "all_users")]
+    map_owner: Vec<(u32, u32)>,
+
+    /// As root, give files of gid OLD in the backup to local gid NEW
+    /// (repeatable); groups in its /etc/group are mapped by name too
+    #[arg(long, value_name = "OLD:NEW", value_parser = parse_id_mapping, conflicts_with = "all_users")]
+    map_group: Vec<(u32, u32)>,
+
+    #[command(flatten)]
+    rules: RuleArgs,
+}
+
+#[derive(Args)]
+struct SourceArgs {
+    /// What to do when an XDG folder appears more than once in the backup
+    #[arg(long, value_enum, default_value_t = DuplicateStrategy::Ask)]
+    duplicates: DuplicateStrategy,
+
+    /// Snapshot to restore from when the backup holds several (rsnapshot,
+    /// dated folders or restic): a name like `daily.1`, a restic snapshot
+    /// ID, or a date/time to take the latest snapshot at or before
+    #[arg(long, value_name = "NAME|DATE")]
+    snapshot: Option<String>,
+
+    /// Restore each file as it was at this date/time, overlaying all
+    /// snapshots up to then (brings back files deleted since)
+    #[arg(long, value_name = "DATE", value_parser = parse_point_in_time, conflicts_with = "snapshot")]
+    at: Option<SystemTime>,
+
+    /// Where to unpack backups stored as archives, such as duplicity or
+    /// restic (defaults to ~/.cache/backup-restore)
+    #[arg(long, value_name = "DIR")]
+    staging: Option<PathBuf>,
+
+    /// File holding the restic repository password or the passphrase of an
+    /// encrypted archive; `RESTIC_PASSWORD_FILE`, `RESTIC_PASSWORD` and
+    /// `BACKUP_RESTORE_PASSPHRASE` are honored too, otherwise it is asked for
+    #[arg(long, value_name = "FILE")]
+    password_file: Option<PathBuf>,
+
+    /// SSH private key for SFTP backups (otherwise ssh uses its agent and
+    /// default keys), or age identity file for age-encrypted archives
+    #[arg(long, value_name = "FILE")]
+    identity: Option<PathBuf>,
+
+    /// S3 endpoint for `s3://` backups kept outside AWS, such as on a
+    /// self-hosted server (defaults to `AWS_ENDPOINT_URL`, then AWS itself)
+    #[arg(long, value_name = "URL")]
+    endpoint: Option<String>,
+
+    /// S3 region (defaults to `AWS_REGION`, then us-east-1)
+    #[arg(long)]
+    region: Option<String>,
+
+    /// Number of parallel copy threads, and of connections used to
+    /// download SFTP and S3 backups
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
+
+    /// Treat the backup as Windows File History: restore the newest
+    /// version of each file under its original name. Automatic when the
+    /// backup path contains a `FileHistory` folder
+    #[arg(long)]
+    file_history: bool,
+
+    /// Also restore a category of hidden folders from the backup's home
+    /// (repeatable): config (~/.config), app-data (~/.local/share), keys
+    /// (~/.ssh, ~/.gnupg) or browser-profiles (~/.mozilla,
+    /// ~/.thunderbird). Without it, the ones found are offered
+    #[arg(long, value_name = "CATEGORY")]
+    include: Vec<Category>,
+}
+
+fn parse_point_in_time(spec: &str) -> Result<SystemTime, String> {
+    snapshot::parse_time_spec(spec)
+        .ok_or_else(|| "expected a date like 2024-03-05 or \"2024-03-05 14:30\"".to_string())
+}
+
+fn parse_user_mapping(spec: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((name, user)) if !name.is_empty() && !user.is_empty() => {
+            Ok((name.to_string(), user.to_string()))
+        }
+        _ => Err("expected NAME=USER, such as alice=alice.smith".to_string()),
+    }
+}
+
+fn parse_id_mapping(spec: &str) -> Result<(u32, u32), String> {
+    spec.split_once(':')
+        .and_then(|(old, new)| Some((old.parse().ok()?, new.parse().ok()?)))
+        .ok_or_else(|| "expected OLD:NEW numeric ids, such as 1000:1001".to_string())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DuplicateStrategy {
+    /// Ask which copy to restore, suggesting the most likely one
+    Ask,
+    /// Take the highest-ranked copy without asking
+    Best,
+    /// Merge all copies, keeping the newest version of each file
+    Merge,
+}
+
+#[derive(Args)]
+struct RuleArgs {
+    /// Conflict rule `[GLOB=]ACTION`, tried in order (repeatable).
+    /// Actions: overwrite, keep-original, keep-both, newer, larger, merge
+    #[arg(long = "rule", value_name = "RULE")]
+    rules: Vec<Rule>,
+
+    /// File with one conflict rule per line, tried after any --rule flags
+    #[arg(long, value_name = "FILE")]
+    rules_file: Option<PathBuf>,
+}
+
+impl RuleArgs {
+    fn load(&self) -> anyhow::Result<RuleSet> {
+        let mut rules = RuleSet::new(self.rules.clone());
+        if let Some(path) = &self.rules_file {
+            rules.extend(
+                RuleSet::from_file(path)
+                    .with_context(|| format!("Failed to read rules {}", path.display()))?,
+            );
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Args)]
+struct PlanArgs {
+    /// Path to the backup directory, SquashFS/ISO image, `[user@]host:path`
+    /// on an SSH server, or `s3://bucket/prefix`, to restore from (tar
+    /// archives are restored directly, without a plan)
     backup_dir: PathBuf,
 
-    /// Number of parallel copy threads
-    #[arg(short, long, default_value_t = 4)]
-    jobs: usize,
+    /// File to write the plan to
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Plan only this folder or file, relative to the home directory
+    /// (repeatable)
+    #[arg(long, value_name = "PATH")]
+    only: Vec<PathBuf>,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Home directory to restore into (defaults to $HOME)
+    #[arg(long)]
+    home: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// First folder
+    a: PathBuf,
+
+    /// Second folder
+    b: PathBuf,
+
+    /// List every differing file instead of a sample
+    #[arg(short, long)]
+    detailed: bool,
+}
+
+#[derive(Args)]
+struct ResolveArgs {
+    /// Home directory to search for leftover conflicts (defaults to $HOME)
+    #[arg(long)]
+    home: Option<PathBuf>,
+
+    /// Also search a category of hidden folders (repeatable), as restored
+    /// with `--include`
+    #[arg(long, value_name = "CATEGORY")]
+    include: Vec<Category>,
+
+    /// Move replaced originals and discarded .restore files to the trash
+    /// instead of deleting them
+    #[arg(long)]
+    trash: bool,
+
+    /// Older copy of the home directory to use as the common ancestor when
+    /// merging text conflicts (enables three-way merges)
+    #[arg(long, value_name = "DIR")]
+    merge_base: Option<PathBuf>,
+
+    /// Go straight to the full-screen conflict browser
+    #[arg(long)]
+    tui: bool,
+
+    #[command(flatten)]
+    rules: RuleArgs,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{} {:#}", style("Error:").red().bold(), e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Plan(args)) => run_plan(args),
+        Some(Command::Restore(args)) => run_restore(&args),
+        Some(Command::Resolve(args)) => run_resolve(args),
+        Some(Command::Compare(args)) => run_compare(&args),
+        None => run_restore(&cli.restore),
+    }
+}
+
+fn home_or_default(home: Option<PathBuf>) -> PathBuf {
+    home.unwrap_or_else(|| {
+        PathBuf::from(std::env::var("HOME").expect("HOME environment variable not set"))
+    })
+}
+
+fn run_restore(args: &RestoreArgs) -> anyhow::Result<()> {
+    let rules = args.rules.load()?;
+    let trash = args.trash.then(Trash::from_env);
+
+    if let Some(plan_path) = &args.plan {
+        return run_saved_plan(plan_path, args, &rules, trash);
+    }
+    if args.all_users {
+        return run_all_users(args, &rules);
+    }
+
+    let backup_dir = args
+        .backup_dir
+        .as_deref()
+        .expect("clap requires backup_dir without --plan");
+    let home_dir = home_or_default(args.home.clone());
+    let resolver = Resolver {
+        rules: &rules,
+        home_dir: &home_dir,
+        trash,
+        merge_base: args.merge_base.as_deref(),
+        tui: args.tui,
+    };
+
+    let Some(chosen) = scan_and_choose(backup_dir, &home_dir, &args.source, false, !args.dry_run)?
+    else {
+        return Ok(());
+    };
+
+    // Step 2: Plan, narrowed to what the user wants
+    let (full_plan, merge_notes) = chosen.build_plan()?;
+    let total_files = full_plan.files.len();
+    let Some(copy_plan) = choose_scope(full_plan, &home_dir, &args.only)? else {
+        println!("Aborted.");
+        return Ok(());
+    };
+    let partial = copy_plan.files.len() < total_files;
+
+    let ownership = choose_ownership(args, chosen.account_files(backup_dir), &home_dir)?;
+    if args.dry_run {
+        print!("{}", chosen.format_report(&copy_plan, &merge_notes));
+        print_dry_run(&copy_plan, &rules, &home_dir);
+    }
+    if let Ownership::Preserve(map) = &ownership {
+        report_owners(chosen.source_owners(&copy_plan), map, args.dry_run);
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let result = copy_and_resolve(
+        &copy_plan,
+        args.source.jobs,
+        chosen.archive.as_ref(),
+        &ownership,
+        &resolver,
+    )?;
+
+    // Step 6: Optional source cleanup, only when everything was restored
+    // from a single copy of the backup, read in place
+    if partial || !chosen.overlay.is_empty() || chosen.staging.is_some() || chosen.archive.is_some()
+    {
+        return Ok(());
+    }
+    if !result.copied.is_empty() || !result.conflicts.is_empty() {
+        println!();
+        if Confirm::new()
+            .with_prompt("Delete source files from backup?")
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+        {
+            delete_sources(&chosen.mappings, resolver.trash.as_ref());
+        }
+    }
+
+    Ok(())
+}
+
+/// Restore each user's home found in the backup into their own account,
+/// for rebuilding a shared machine as root. With `--trash`, each user's
+/// displaced files go to their own trash.
+fn run_all_users(args: &RestoreArgs, rules: &RuleSet) -> anyhow::Result<()> {
+    if !users::is_root() {
+        bail!("--all-users restores into other users' homes, so it must run as root");
+    }
+    let backup_dir = args
+        .backup_dir
+        .as_deref()
+        .expect("clap requires backup_dir without --plan");
+    let (homes, _staging) = scan_user_homes(backup_dir, args)?;
+    if homes.is_empty() {
+        println!(
+            "{} No user homes to restore were found in the backup.",
+            style("!").yellow().bold()
+        );
+        return Ok(());
+    }
+
+    let mut plans = Vec::new();
+    for home in &homes {
+        plans.push(home.chosen.build_plan()?);
+    }
+    if args.dry_run {
+        for (UserRestore { account, chosen }, (copy_plan, merge_notes)) in homes.iter().zip(&plans)
+        {
+            println!(
+                "\n{} {}",
+                style("→").cyan().bold(),
+                style(&account.name).bold()
+            );
+            print!("{}", chosen.format_report(copy_plan, merge_notes));
+            print_dry_run(copy_plan, rules, &account.home);
+        }
+        return Ok(());
+    }
+    let (files, bytes) = plans.iter().fold((0, 0), |(files, bytes), (p, _)| {
+        (files + p.files.len(), bytes + p.total_bytes)
+    });
+    if !Confirm::new()
+        .with_prompt(format!(
+            "Restore {files} files ({}) into {} homes?",
+            report::format_bytes(bytes),
+            homes.len()
+        ))
+        .default(true)
+        .interact()
+        .unwrap_or(false)
+    {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let mut summary = Vec::new();
+    for (UserRestore { account, .. }, (copy_plan, _)) in homes.iter().zip(&plans) {
+        println!(
+            "\n{} Restoring {} into {}",
+            style("→").cyan().bold(),
+            style(&account.name).bold(),
+            account.home.display()
+        );
+        let resolver = Resolver {
+            rules,
+            home_dir: &account.home,
+            trash: args
+                .trash
+                .then(|| Trash::for_user(&account.home, account.uid)),
+            merge_base: None,
+            tui: args.tui,
+        };
+        let owner = Owner {
+            uid: account.uid,
+            gid: account.gid,
+        };
+        let ownership = Ownership::User(owner);
+        let result = copy_and_resolve(copy_plan, args.source.jobs, None, &ownership, &resolver)?;
+        summary.push((&account.name, result));
+    }
+
+    println!("\n--- Per-user summary ---");
+    for (name, result) in &summary {
+        println!(
+            "  {name}: {} copied, {} conflicts, {} errors",
+            result.copied.len(),
+            result.conflicts.len(),
+            result.errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// A user home from the backup and the local account it goes to.
+struct UserRestore {
+    account: Account,
+    chosen: Chosen,
+}
+
+/// Scan the backup for user homes and pair each with its local account.
+/// Also returns the staging folder the backup was unpacked into, if any,
+/// which must live until the restore is done.
+fn scan_user_homes(
+    backup_dir: &Path,
+    args: &RestoreArgs,
+) -> anyhow::Result<(Vec<UserRestore>, Option<StagingDir>)> {
+    let source = &args.source;
+    if archive::detect_archive(backup_dir).is_some() {
+        bail!("--all-users can't restore from a tar archive");
+    }
+    let staging = stage_backup(backup_dir, &home_or_default(None), source)?;
+    if staging.is_none() && !backup_dir.is_dir() {
+        bail!(
+            "Not a backup directory or a SquashFS/ISO image: {}",
+            backup_dir.display()
+        );
+    }
+    let mut root = staging
+        .as_ref()
+        .map_or(backup_dir, StagingDir::path)
+        .to_path_buf();
+    if let Some(set) = staging
+        .is_none()
+        .then(|| snapshot::detect_snapshots(&root))
+        .flatten()
+    {
+        if source.at.is_some() {
+            bail!("--at can't be combined with --all-users; pick a snapshot with --snapshot");
+        }
+        root = choose_snapshot(&set, source)?;
+    }
+
+    println!(
+        "{} Scanning {}...",
+        style("→").cyan().bold(),
+        root.display()
+    );
+    let scan_result = scan::scan_backup(&root, Path::new("/"));
+    for warning in &scan_result.warnings {
+        eprintln!("{} Scan warning: {}", style("!").yellow().bold(), warning);
+    }
+
+    let matched = users::match_accounts(&root, scan_result.mappings, &args.user_map);
+    for skipped in &matched.skipped {
+        report_skipped(skipped);
+    }
+
+    let mut homes = Vec::new();
+    for (account, mappings) in matched.accounts.into_values() {
+        println!(
+            "\n{} {} (uid {}):",
+            style("✓").green().bold(),
+            style(&account.name).bold(),
+            account.uid
+        );
+        let mut mappings = resolve_duplicate_mappings(mappings, source.duplicates)?;
+        let roots = dotfiles::home_roots(&mappings);
+        let hidden = dotfiles::find_folders(&roots, &account.home, &source.include, Path::is_dir);
+        mappings.extend(hidden);
+        for m in &mappings {
+            println!(
+                "  {} → {}",
+                style(m.source_path.display()).dim(),
+                m.dest_path.display()
+            );
+        }
+
+        let sources: Vec<&Path> = mappings.iter().map(|m| m.source_path.as_path()).collect();
+        let file_history = source.file_history || file_history::is_file_history(&sources);
+        let chosen = Chosen {
+            mappings,
+            overlay: Vec::new(),
+            file_history,
+            staging: None,
+            archive: None,
+        };
+        homes.push(UserRestore { account, chosen });
+    }
+    Ok((homes, staging))
+}
+
+fn report_skipped(skipped: &Skipped) {
+    let warn = style("!").yellow().bold();
+    match skipped {
+        Skipped::NotInHome(source) => {
+            eprintln!("{warn} Skipping {}: not in a home folder", source.display());
+        }
+        Skipped::NoAccount { root, name, local } => eprintln!(
+            "{warn} Skipping {}: no local account {local}; map it with --user {name}=USER",
+            root.display()
+        ),
+        Skipped::SystemAccount {
+            root,
+            name,
+            account,
+        } => eprintln!(
+            "{warn} Skipping {}: {} is a system account (uid {}); restore it with --user {name}={}",
+            root.display(),
+            account.name,
+            account.uid,
+            account.name
+        ),
+    }
+}
+
+fn run_plan(args: PlanArgs) -> anyhow::Result<()> {
+    let home_dir = home_or_default(args.home);
+    if archive::detect_archive(&args.backup_dir).is_some() {
+        bail!(
+            "Plans read their files from disk, which a tar archive is not; \
+             restore from it directly, with --dry-run to preview"
+        );
+    }
+
+    // Plans are run later, from files on disk, so everything is unpacked
+    let Some(mut chosen) = scan_and_choose(&args.backup_dir, &home_dir, &args.source, true, false)?
+    else {
+        return Ok(());
+    };
+
+    let (copy_plan, merge_notes) = chosen.build_plan()?;
+    let copy_plan = apply_only(copy_plan, &home_dir, &args.only)?;
+    plan_file::save_plan(&copy_plan, &home_dir, &args.out)
+        .with_context(|| format!("Failed to write plan to {}", args.out.display()))?;
+
+    print!("{}", chosen.format_report(&copy_plan, &merge_notes));
+    print!("{}", report::format_dry_run_report(&copy_plan));
+    println!(
+        "\n{} Plan saved to {}",
+        style("✓").green().bold(),
+        args.out.display()
+    );
+    if let Some(staging) = &mut chosen.staging {
+        staging.keep();
+        println!(
+            "  The plan reads from {}; delete it once the plan has run",
+            staging.path().display()
+        );
+    }
+    Ok(())
+}
+
+fn run_saved_plan(
+    plan_path: &Path,
+    args: &RestoreArgs,
+    rules: &RuleSet,
+    trash: Option<Trash>,
+) -> anyhow::Result<()> {
+    let (copy_plan, home_dir) = plan_file::load_plan(plan_path)
+        .with_context(|| format!("Failed to read plan {}", plan_path.display()))?;
+    let resolver = &Resolver {
+        rules,
+        home_dir: &home_dir,
+        trash,
+        merge_base: args.merge_base.as_deref(),
+        tui: args.tui,
+    };
+    let copy_plan = apply_only(copy_plan, resolver.home_dir, &args.only)?;
+
+    let stale = plan_file::verify_sources(&copy_plan);
+    if !stale.is_empty() {
+        eprintln!(
+            "{} {} source file{} changed since the plan was saved:",
+            style("!").yellow().bold(),
+            stale.len(),
+            if stale.len() == 1 { "" } else { "s" }
+        );
+        for s in &stale {
+            eprintln!("  {s}");
+        }
+        bail!("Plan is out of date; re-run `plan` or edit the plan file");
+    }
+
+    // Plans don't say where the backup's own accounts are, so only the
+    // mappings given on the command line apply
+    let ownership = choose_ownership(args, None, resolver.home_dir)?;
+    if args.dry_run {
+        print_dry_run(&copy_plan, resolver.rules, resolver.home_dir);
+    }
+    if let Ownership::Preserve(map) = &ownership {
+        let owners = copy_plan
+            .files
+            .iter()
+            .filter_map(|op| Owner::of(&op.source));
+        report_owners(owners, map, args.dry_run);
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    if !Confirm::new()
+        .with_prompt(format!(
+            "Restore {} files ({}) from {}?",
+            copy_plan.files.len(),
+            report::format_bytes(copy_plan.total_bytes),
+            plan_path.display()
+        ))
+        .default(true)
+        .interact()
+        .unwrap_or(false)
+    {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    copy_and_resolve(&copy_plan, args.source.jobs, None, &ownership, resolver)?;
+    Ok(())
+}
+
+fn run_compare(args: &CompareArgs) -> anyhow::Result<()> {
+    for dir in [&args.a, &args.b] {
+        if !dir.is_dir() {
+            bail!("Not a directory: {}", dir.display());
+        }
+    }
+    let cmp = candidates::compare_folders(&args.a, &args.b)?;
+    print!(
+        "{}",
+        report::format_comparison(&cmp, &args.a, &args.b, args.detailed)
+    );
+    Ok(())
+}
+
+fn run_resolve(args: ResolveArgs) -> anyhow::Result<()> {
+    let rules = args.rules.load()?;
+    let home_dir = home_or_default(args.home);
+
+    println!(
+        "{} Searching {} for leftover conflicts...",
+        style("→").cyan().bold(),
+        home_dir.display()
+    );
+    let leftovers = conflict::find_leftover_conflicts(&home_dir, &args.include);
+
+    for warning in &leftovers.warnings {
+        eprintln!("{} Scan warning: {}", style("!").yellow().bold(), warning);
+    }
+
+    if leftovers.conflicts.is_empty() {
+        println!("{} No leftover conflicts found.", style("✓").green().bold());
+        return Ok(());
+    }
+
+    println!(
+        "{} Found {} leftover conflict{}\n",
+        style("!").yellow().bold(),
+        leftovers.conflicts.len(),
+        if leftovers.conflicts.len() == 1 {
+            ""
+        } else {
+            "s"
+        }
+    );
+    let resolver = Resolver {
+        rules: &rules,
+        home_dir: &home_dir,
+        trash: args.trash.then(Trash::from_env),
+        merge_base: args.merge_base.as_deref(),
+        tui: args.tui,
+    };
+    resolver.resolve_conflicts(&leftovers.conflicts)
+}
+
+/// Folders picked for restoring.
+struct Chosen {
+    mappings: Vec<DetectedMapping>,
+    /// Snapshots to overlay each folder from, oldest first, for `--at`;
+    /// empty for an ordinary restore.
+    overlay: Vec<Snapshot>,
+    /// Collapse Windows File History versions.
+    file_history: bool,
+    /// Where an archive-format backup was unpacked, removed when done.
+    staging: Option<StagingDir>,
+    /// A backup restored straight from its own format instead.
+    archive: Option<ArchiveSource>,
+}
+
+/// A backup being restored without unpacking it first: a tar archive, a
+/// restic snapshot or a disk image.
+struct ArchiveSource {
+    /// The backup, under which its members' paths are placed.
+    path: PathBuf,
+    listing: Listing,
+    reader: Reader,
+}
+
+enum Reader {
+    /// A tar archive, read as a stream once to list and once to restore.
+    Stream {
+        encryption: Encryption,
+        key: Option<Key>,
+    },
+    /// A restic snapshot or an image, read file by file.
+    Files(Box<dyn Files>),
+}
+
+impl ArchiveSource {
+    /// Whether anything in the backup is at or under `path`.
+    fn has(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.path).is_ok_and(|folder| {
+            self.listing
+                .members
+                .iter()
+                .any(|m| m.path.starts_with(folder))
+        })
+    }
+
+    /// Restore the planned files, as `ownership` says.
+    fn restore(
+        &self,
+        copy_plan: &CopyPlan,
+        jobs: usize,
+        ownership: &Ownership,
+    ) -> anyhow::Result<CopyResult> {
+        let (path, listing) = (&self.path, &self.listing);
+        Ok(match &self.reader {
+            Reader::Stream { encryption, key } => {
+                let stream = open_archive(path, *encryption, key.as_ref())?;
+                archive::extract_plan(stream, path, listing, copy_plan, ownership)?
+            }
+            Reader::Files(files) => {
+                archive::copy_plan(files.as_ref(), path, listing, copy_plan, jobs, ownership)?
+            }
+        })
+    }
+}
+
+fn open_archive(
+    path: &Path,
+    encryption: Encryption,
+    key: Option<&Key>,
+) -> anyhow::Result<Box<dyn Read + Send>> {
+    archive::open_stream(path, encryption, key)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+impl Chosen {
+    fn build_plan(&self) -> anyhow::Result<(CopyPlan, Vec<MergeNote>)> {
+        let (copy_plan, merge_notes) = if let Some(archive) = &self.archive {
+            let copy_plan = archive::build_plan(&archive.path, &archive.listing, &self.mappings);
+            (copy_plan, Vec::new())
+        } else if self.overlay.is_empty() {
+            plan::build_merged_plan(&self.mappings)?
+        } else {
+            let copy_plan = snapshot::build_overlay_plan(&self.overlay, &self.mappings)?;
+            (copy_plan, Vec::new())
+        };
+        if !self.file_history {
+            return Ok((copy_plan, merge_notes));
+        }
+
+        let (copy_plan, dropped) = file_history::collapse_versions(&copy_plan);
+        println!(
+            "{} File History: restoring the newest of {} versions for {} files",
+            style("→").cyan().bold(),
+            copy_plan.files.len() + dropped,
+            copy_plan.files.len()
+        );
+        Ok((copy_plan, merge_notes))
+    }
+
+    /// The backup's `etc` folder with its passwd and group files, nearest
+    /// the restored folders, for mapping owners by name.
+    fn account_files(&self, backup_dir: &Path) -> Option<(PathBuf, String, String)> {
+        if let Some(archive) = &self.archive {
+            let sources: Vec<&Path> = self
+                .mappings
+                .iter()
+                .filter_map(|m| m.source_path.strip_prefix(&archive.path).ok())
+                .collect();
+            let (etc, passwd, group) = archive.listing.account_files(&sources)?;
+            return Some((archive.path.join(etc), passwd, group));
+        }
+        // Unpacked copies have lost their owners along with the way back
+        // to the backup's root
+        if self.staging.is_some() {
+            return None;
+        }
+        let sources: Vec<&Path> = self
+            .mappings
+            .iter()
+            .map(|m| m.source_path.as_path())
+            .collect();
+        users::find_account_files(backup_dir, &sources)
+    }
+
+    /// The backup's owner of each planned file, for the dry run.
+    fn source_owners(&self, copy_plan: &CopyPlan) -> Vec<Owner> {
+        let Some(archive) = &self.archive else {
+            return copy_plan
+                .files
+                .iter()
+                .filter_map(|op| Owner::of(&op.source))
+                .collect();
+        };
+        let members: HashMap<PathBuf, Owner> = archive
+            .listing
+            .members
+            .iter()
+            .filter_map(|m| Some((archive.path.join(&m.path), m.owner?)))
+            .collect();
+        copy_plan
+            .files
+            .iter()
+            .filter_map(|op| members.get(&op.source).copied())
+            .collect()
+    }
+
+    /// Where the planned files come from, for dry runs and saved plans.
+    fn format_report(&self, copy_plan: &CopyPlan, merge_notes: &[MergeNote]) -> String {
+        if self.overlay.is_empty() {
+            report::format_merge_report(copy_plan, &self.mappings, merge_notes)
+        } else {
+            report::format_snapshot_report(copy_plan, &self.overlay)
+        }
+    }
+}
+
+/// Scan the backup, let the user pick among duplicates, and show the result.
+///
+/// Returns `None` when nothing restorable was found.
+///
+/// Restic repositories and images are read in place, unless `unpack` asks
+/// for every file to be on disk. Hidden folders are only offered when `ask`
+/// allows prompting for them.
+fn scan_and_choose(
+    backup_dir: &Path,
+    home_dir: &Path,
+    source: &SourceArgs,
+    unpack: bool,
+    ask: bool,
+) -> anyhow::Result<Option<Chosen>> {
+    // Tar archives are read as a stream, so they are never unpacked
+    let archive = if let Some(encryption) = archive::detect_archive(backup_dir) {
+        Some(scan_archive(backup_dir, encryption, source)?)
+    } else if unpack {
+        None
+    } else {
+        open_files(backup_dir, source)?
+    };
+    if let Some(archive) = archive {
+        let Some(mut chosen) = choose_in_archive(archive, home_dir, source) else {
+            return Ok(None);
+        };
+        choose_categories(&mut chosen, home_dir, source, ask)?;
+        return Ok(Some(chosen));
+    }
+
+    // Archive formats and remote backups are unpacked first, as of the
+    // --at time if given
+    let staging = stage_backup(backup_dir, home_dir, source)?;
+    if staging.is_none() && !backup_dir.is_dir() {
+        bail!(
+            "Not a backup directory, a SquashFS/ISO image or a tar archive: {}",
+            backup_dir.display()
+        );
+    }
+    let backup_dir = staging.as_ref().map_or(backup_dir, StagingDir::path);
+
+    // Snapshot sets hold the same folders many times over; scan just one,
+    // or every one up to the --at time
+    let set = if staging.is_some() {
+        None
+    } else {
+        snapshot::detect_snapshots(backup_dir)
+    };
+    let overlay = match (&set, source.at) {
+        (Some(set), Some(at)) => {
+            let overlay = set.until(at).to_vec();
+            if overlay.is_empty() {
+                bail!(
+                    "No snapshot was taken by {}; the oldest is from {}",
+                    preview::format_mtime(at),
+                    preview::format_mtime(set.snapshots[0].time)
+                );
+            }
+            overlay
+        }
+        _ => Vec::new(),
+    };
+    let roots: Vec<PathBuf> = match set {
+        Some(_) if !overlay.is_empty() => {
+            println!(
+                "{} Overlaying {} snapshots up to {}",
+                style("✓").green().bold(),
+                overlay.len(),
+                overlay.last().unwrap().name
+            );
+            overlay.iter().rev().map(|s| s.root.clone()).collect()
+        }
+        Some(set) => vec![choose_snapshot(&set, source)?],
+        None if staging.is_none() && (source.snapshot.is_some() || source.at.is_some()) => bail!(
+            "--snapshot and --at need a backup holding a set of snapshots, \
+             which {} does not",
+            backup_dir.display()
+        ),
+        None => vec![backup_dir.to_path_buf()],
+    };
+
+    // Step 1: Scan, newest snapshot first so its copy of a folder is kept
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    for root in &roots {
+        println!(
+            "{} Scanning {}...",
+            style("→").cyan().bold(),
+            root.display()
+        );
+        let scan_result = scan::scan_backup(root, home_dir);
+
+        for warning in &scan_result.warnings {
+            eprintln!("{} Scan warning: {}", style("!").yellow().bold(), warning);
+        }
+        for m in scan_result.mappings {
+            let relative = m.source_path.strip_prefix(root).unwrap().to_path_buf();
+            if seen.insert(relative) {
+                found.push(m);
+            }
+        }
+    }
+
+    if found.is_empty() {
+        println!(
+            "{} No XDG directories found in backup.",
+            style("!").yellow().bold()
+        );
+        return Ok(None);
+    }
+
+    // Handle duplicates: group by XdgDir, let user choose if ambiguous
+    let mappings = resolve_duplicate_mappings(found, source.duplicates)?;
+    print_mappings(&mappings);
+
+    let sources: Vec<&Path> = mappings.iter().map(|m| m.source_path.as_path()).collect();
+    let file_history = source.file_history || file_history::is_file_history(&sources);
+
+    let mut chosen = Chosen {
+        mappings,
+        overlay,
+        file_history,
+        staging,
+        archive: None,
+    };
+    choose_categories(&mut chosen, home_dir, source, ask)?;
+    Ok(Some(chosen))
+}
+
+/// Add the hidden folders in the backup's home that the user opts into,
+/// by category: those given with `--include`, or else picked from the
+/// categories found. None are restored unasked: without `ask`, a terminal,
+/// or when `--duplicates best` says not to ask, only `--include` adds them.
+fn choose_categories(
+    chosen: &mut Chosen,
+    home_dir: &Path,
+    source: &SourceArgs,
+    ask: bool,
+) -> anyhow::Result<()> {
+    let roots = dotfiles::home_roots(&chosen.mappings);
+    let found = match &chosen.archive {
+        Some(archive) => {
+            dotfiles::find_folders(&roots, home_dir, &Category::ALL, |path| archive.has(path))
+        }
+        None => dotfiles::find_folders(&roots, home_dir, &Category::ALL, Path::is_dir),
+    };
+    let available = dotfiles::categories_found(&found);
+    let can_ask =
+        ask && io::stdin().is_terminal() && !matches!(source.duplicates, DuplicateStrategy::Best);
+    let picked = match dotfiles::choose_categories(&source.include, available, can_ask) {
+        CategoryChoice::LeaveOut(available) => {
+            if !available.is_empty() {
+                let names: Vec<&str> = available.iter().map(|c| c.flag_name()).collect();
+                println!(
+                    "{} Leaving out the backup's hidden folders ({}); add them with --include",
+                    style("→").cyan().bold(),
+                    names.join(", ")
+                );
+            }
+            return Ok(());
+        }
+        CategoryChoice::Ask(available) => {
+            let items: Vec<String> = available
+                .iter()
+                .map(|&c| {
+                    let dirs: Vec<&str> = c.dirs().iter().map(|d| d.dir_name()).collect();
+                    format!("{c} ({}): {}", dirs.join(", "), c.policy())
+                })
+                .collect();
+            MultiSelect::new()
+                .with_prompt("Also restore hidden folders from the backup's home? (space to pick)")
+                .items(&items)
+                .interact()?
+                .into_iter()
+                .map(|i| available[i])
+                .collect()
+        }
+        CategoryChoice::Given { include, missing } => {
+            for c in missing {
+                println!(
+                    "{} No {c} folders in the backup's home",
+                    style("!").yellow().bold()
+                );
+            }
+            include
+        }
+    };
+
+    let added: Vec<DetectedMapping> = found
+        .into_iter()
+        .filter(|m| Category::of(m.xdg_dir).is_some_and(|c| picked.contains(&c)))
+        .collect();
+    if added.is_empty() {
+        return Ok(());
+    }
+    println!("{} Hidden folders to restore:", style("✓").green().bold());
+    for m in &added {
+        println!(
+            "  {} → {}",
+            style(m.source_path.display()).dim(),
+            m.dest_path.display()
+        );
+    }
+    println!();
+    chosen.mappings.extend(added);
+    Ok(())
+}
+
+fn print_mappings(mappings: &[DetectedMapping]) {
+    println!(
+        "\n{} Detected {} directories:",
+        style("✓").green().bold(),
+        mappings.len()
+    );
+    for m in mappings {
+        println!(
+            "  {} → {}",
+            style(m.source_path.display()).dim(),
+            m.dest_path.display()
+        );
+    }
+    println!();
+}
+
+/// Read the headers of a tar archive, decrypting it as it streams by. The
+/// archive is read again when restoring, so no plaintext is written
+/// anywhere but the restored files.
+fn scan_archive(
+    path: &Path,
+    encryption: Encryption,
+    source: &SourceArgs,
+) -> anyhow::Result<ArchiveSource> {
+    if source.snapshot.is_some() || source.at.is_some() {
+        bail!("--snapshot and --at need a backup holding a set of snapshots, not an archive");
+    }
+    println!(
+        "{} Found {encryption} archive {}",
+        style("✓").green().bold(),
+        path.display()
+    );
+    let key = archive_key(path, encryption, source)?;
+
+    println!("{} Reading the archive...", style("→").cyan().bold());
+    let listing = archive::list_members(open_archive(path, encryption, key.as_ref())?)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(ArchiveSource {
+        path: path.to_path_buf(),
+        listing,
+        reader: Reader::Stream { encryption, key },
+    })
+}
+
+/// Open a restic repository or an image and list its files, which are
+/// read as they are restored. `None` for other backups.
+fn open_files(backup_dir: &Path, source: &SourceArgs) -> anyhow::Result<Option<ArchiveSource>> {
+    let (listing, files): (Listing, Box<dyn Files>) =
+        if let Some(kind) = image::detect_image(backup_dir) {
+            println!(
+                "{} Found {kind} image {}",
+                style("✓").green().bold(),
+                backup_dir.display()
+            );
+            let (listing, files) = image::open_image(backup_dir, kind)
+                .and_then(image::list_image)
+                .with_context(|| format!("Failed to read {}", backup_dir.display()))?;
+            (listing, Box::new(files))
+        } else if restic::is_restic_repo(backup_dir) {
+            let repo = open_restic(backup_dir, source)?;
+            let snapshots = repo.snapshots()?;
+            if snapshots.is_empty() {
+                bail!(
+                    "The restic repository {} has no snapshots",
+                    backup_dir.display()
+                );
+            }
+            let chosen = choose_restic_snapshot(&snapshots, source)?;
+            println!(
+                "{} Reading snapshot {}...",
+                style("→").cyan().bold(),
+                chosen.short_id()
+            );
+            let (listing, files) = repo.list_snapshot(chosen)?;
+            (listing, Box::new(files))
+        } else {
+            return Ok(None);
+        };
+    Ok(Some(ArchiveSource {
+        path: backup_dir.to_path_buf(),
+        listing,
+        reader: Reader::Files(files),
+    }))
+}
+
+/// Find the XDG folders in a backup restored without unpacking it.
+fn choose_in_archive(
+    archive: ArchiveSource,
+    home_dir: &Path,
+    source: &SourceArgs,
+) -> Option<Chosen> {
+    let path = archive.path.as_path();
+    if archive.listing.skipped > 0 {
+        println!(
+            "  {} links and special files in the backup are not restored",
+            archive.listing.skipped
+        );
+    }
+
+    let found = archive::find_xdg_folders(path, &archive.listing, home_dir);
+    if found.is_empty() {
+        println!(
+            "{} No XDG directories found in backup.",
+            style("!").yellow().bold()
+        );
+        return None;
+    }
+
+    // Comparing copies needs them on disk; take the fullest one instead
+    let mut by_dir: BTreeMap<XdgDir, Vec<DetectedMapping>> = BTreeMap::new();
+    for m in found {
+        by_dir.entry(m.xdg_dir).or_default().push(m);
+    }
+    let mut mappings = Vec::new();
+    for (xdg_dir, mut copies) in by_dir {
+        copies.sort_by_key(|m| {
+            std::cmp::Reverse(archive::folder_bytes(
+                path,
+                &archive.listing,
+                &m.source_path,
+            ))
+        });
+        if copies.len() > 1 {
+            println!(
+                "{} The backup holds {} '{}' directories; using the largest, {}",
+                style("?").yellow().bold(),
+                copies.len(),
+                xdg_dir,
+                copies[0].source_path.display()
+            );
+        }
+        mappings.push(copies.swap_remove(0));
+    }
+    print_mappings(&mappings);
+
+    let sources: Vec<&Path> = mappings.iter().map(|m| m.source_path.as_path()).collect();
+    let file_history = source.file_history || file_history::is_file_history(&sources);
+
+    Some(Chosen {
+        mappings,
+        overlay: Vec::new(),
+        file_history,
+        staging: None,
+        archive: Some(archive),
+    })
+}
+
+/// What unlocks an encrypted archive: an age identity file given with
+/// `--identity`, or else a passphrase from `--password-file`,
+/// `BACKUP_RESTORE_PASSPHRASE` or a prompt, in that order.
+fn archive_key(
+    path: &Path,
+    encryption: Encryption,
+    source: &SourceArgs,
+) -> anyhow::Result<Option<Key>> {
+    match encryption {
+        Encryption::None => return Ok(None),
+        Encryption::Age => {
+            if let Some(file) = &source.identity {
+                let text = std::fs::read_to_string(file)
+                    .with_context(|| format!("Failed to read identity file {}", file.display()))?;
+                let identities = age::parse_identities(&text)
+                    .with_context(|| format!("Failed to read identity file {}", file.display()))?;
+                return Ok(Some(Key::Identities(identities)));
+            }
+            let mut head = Vec::new();
+            File::open(path)?.take(4096).read_to_end(&mut head)?;
+            if !age::uses_passphrase(&head) {
+                bail!(
+                    "{} is encrypted to an age key; pass its identity file with --identity",
+                    path.display()
+                );
+            }
+        }
+        Encryption::Gpg => {}
+    }
+
+    if let Some(file) = &source.password_file {
+        let passphrase = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read password file {}", file.display()))?;
+        let passphrase = passphrase.trim_end_matches(['\r', '\n']).to_string();
+        return Ok(Some(Key::Passphrase(passphrase)));
+    }
+    if let Ok(passphrase) = std::env::var("BACKUP_RESTORE_PASSPHRASE") {
+        return Ok(Some(Key::Passphrase(passphrase)));
+    }
+    let passphrase = Password::new()
+        .with_prompt(format!(
+            "Passphrase for {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+        .interact()?;
+    Ok(Some(Key::Passphrase(passphrase)))
+}
+
+/// Unpack a backup stored in an archive format, or on an SSH server, into
+/// a staging directory. Returns `None` for plain folder backups, which are
+/// read in place.
+fn stage_backup(
+    backup_dir: &Path,
+    home_dir: &Path,
+    source: &SourceArgs,
+) -> anyhow::Result<Option<StagingDir>> {
+    if !backup_dir.exists() {
+        // `host:path` means a remote backup, unless a local path is named so
+        let spec = backup_dir.to_str().unwrap_or_default();
+        let remote = Location::parse(spec).is_some() || Remote::parse(spec).is_some();
+        if remote && (source.snapshot.is_some() || source.at.is_some()) {
+            bail!("--snapshot and --at don't apply to backups on SSH servers or S3");
+        }
+        if let Some(location) = Location::parse(spec) {
+            return stage_s3(&location, home_dir, source).map(Some);
+        }
+        if let Some(remote) = Remote::parse(spec) {
+            return stage_sftp(&remote, home_dir, source).map(Some);
+        }
+        bail!("Backup directory does not exist: {}", backup_dir.display());
+    }
+    if duplicity::is_duplicity_backup(backup_dir) {
+        stage_duplicity(backup_dir, home_dir, source).map(Some)
+    } else if let Some(archive) = open_files(backup_dir, source)? {
+        unpack_files(&archive, home_dir, source).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn create_staging(home_dir: &Path, source: &SourceArgs, label: &str) -> anyhow::Result<StagingDir> {
+    let parent = source
+        .staging
+        .clone()
+        .unwrap_or_else(|| staging::default_parent(home_dir));
+    StagingDir::create(&parent, label)
+        .with_context(|| format!("Failed to create staging directory in {}", parent.display()))
+}
 
-    /// Home directory to restore into (defaults to $HOME)
-    #[arg(long)]
-    home: Option<PathBuf>,
+fn stage_duplicity(
+    backup_dir: &Path,
+    home_dir: &Path,
+    source: &SourceArgs,
+) -> anyhow::Result<StagingDir> {
+    let sets = duplicity::find_backup_sets(backup_dir)
+        .with_context(|| format!("Failed to read {}", backup_dir.display()))?;
+    // Duplicity sets are told apart by time alone
+    let until = match &source.snapshot {
+        Some(spec) => Some(snapshot::parse_time_spec(spec).with_context(|| {
+            format!("--snapshot {spec}: duplicity backups are picked by date, like 2024-01-31")
+        })?),
+        None => source.at,
+    };
+    let chain = duplicity::select_chain(&sets, until).ok_or(DuplicityError::NoChain)?;
+    let last = chain.last().unwrap();
+    println!(
+        "{} Found duplicity backup: {} sets, {} to {}",
+        style("✓").green().bold(),
+        sets.len(),
+        preview::format_mtime(sets[0].time),
+        preview::format_mtime(sets.last().unwrap().time)
+    );
 
-    /// Preview what would happen without copying anything
-    #[arg(short = 'n', long)]
-    dry_run: bool,
+    let staging = create_staging(home_dir, source, "duplicity")?;
+    println!(
+        "{} Unpacking the full backup plus {} incrementals, as of {}...",
+        style("→").cyan().bold(),
+        chain.len() - 1,
+        preview::format_mtime(last.time)
+    );
+    let files = duplicity::restore_chain(&chain, staging.path())?;
+    println!("  {files} files unpacked into {}", staging.path().display());
+    Ok(staging)
 }
 
-fn main() {
-    if let Err(e) = run() {
-        eprintln!("{} {:#}", style("Error:").red().bold(), e);
-        std::process::exit(1);
+/// Unpack the XDG folders of a restic snapshot or an image, and the hidden
+/// folders asked for with `--include`, each at its path in the backup.
+fn unpack_files(
+    archive: &ArchiveSource,
+    home_dir: &Path,
+    source: &SourceArgs,
+) -> anyhow::Result<StagingDir> {
+    let staging = create_staging(home_dir, source, "unpacked")?;
+    let mut mappings = archive::find_xdg_folders(&archive.path, &archive.listing, home_dir);
+    let roots = dotfiles::home_roots(&mappings);
+    let hidden =
+        dotfiles::find_folders(&roots, home_dir, &source.include, |path| archive.has(path));
+    mappings.extend(hidden);
+    for m in &mut mappings {
+        m.dest_path = staging
+            .path()
+            .join(m.source_path.strip_prefix(&archive.path).unwrap());
+    }
+
+    println!(
+        "{} Extracting {} folders from the backup...",
+        style("→").cyan().bold(),
+        mappings.len()
+    );
+    let copy_plan = archive::build_plan(&archive.path, &archive.listing, &mappings);
+    let result = archive.restore(&copy_plan, source.jobs, &Ownership::Unchanged)?;
+    if let Some(e) = result.errors.first() {
+        bail!(
+            "Failed to unpack {} files, such as {}: {}",
+            result.errors.len(),
+            e.source.display(),
+            e.error
+        );
     }
+    println!(
+        "  {} files unpacked into {}",
+        result.copied.len(),
+        staging.path().display()
+    );
+    Ok(staging)
 }
 
-fn run() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+/// Find the XDG folders on an SSH server from its listings, then download
+/// just those over `--jobs` connections.
+fn stage_sftp(remote: &Remote, home_dir: &Path, source: &SourceArgs) -> anyhow::Result<StagingDir> {
+    let identity = source.identity.as_deref();
+    let mut session = Session::connect(remote, identity)
+        .with_context(|| format!("Failed to connect to {}", remote.host))?;
+    println!("{} Connected to {}", style("✓").green().bold(), remote.host);
+    if !session.is_dir(&remote.path)? {
+        bail!("Not a folder on the server: {remote}");
+    }
 
-    let home_dir = cli.home.unwrap_or_else(|| {
-        PathBuf::from(std::env::var("HOME").expect("HOME environment variable not set"))
-    });
+    println!("{} Scanning {remote}...", style("→").cyan().bold());
+    let (mut folders, warnings) = sftp::find_xdg_folders(&mut session, &remote.path)?;
+    for warning in &warnings {
+        eprintln!("{} Scan warning: {}", style("!").yellow().bold(), warning);
+    }
+    let hidden = sftp::find_included_folders(&mut session, &remote.path, &folders, &source.include);
+    folders.extend(hidden);
+    let files = sftp::list_folders(&mut session, &remote.path, &folders)?;
+
+    let staging = create_staging(home_dir, source, "sftp")?;
+    let downloads: Vec<u64> = files
+        .iter()
+        .filter(|f| f.entry.kind == sftp::RemoteKind::File)
+        .map(|f| f.entry.size)
+        .collect();
+    let connections = source.jobs.clamp(1, downloads.len().max(1));
+    println!(
+        "{} Downloading {} files ({}) over {} connection{}...",
+        style("→").cyan().bold(),
+        downloads.len(),
+        report::format_bytes(downloads.iter().sum()),
+        connections,
+        if connections == 1 { "" } else { "s" }
+    );
+    let mut sessions = vec![session];
+    for _ in 1..connections {
+        sessions.push(Session::connect(remote, identity)?);
+    }
+    let count = sftp::mirror(sessions, &remote.path, &folders, &files, staging.path())?;
+    println!(
+        "  {count} files downloaded into {}",
+        staging.path().display()
+    );
+    Ok(staging)
+}
 
-    if !cli.backup_dir.is_dir() {
+/// List the bucket prefix, find the XDG folders among the keys, then
+/// download just those, `--jobs` requests at a time.
+fn stage_s3(
+    location: &Location,
+    home_dir: &Path,
+    source: &SourceArgs,
+) -> anyhow::Result<StagingDir> {
+    let Some(credentials) = Credentials::from_env(home_dir) else {
         bail!(
-            "Backup directory does not exist: {}",
-            cli.backup_dir.display()
+            "No S3 credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, \
+             or add them to ~/.aws/credentials"
         );
-    }
+    };
+    let region = source
+        .region
+        .clone()
+        .or_else(|| std::env::var("AWS_REGION").ok())
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+        .unwrap_or_else(|| "us-east-1".to_string());
+    let endpoint = source
+        .endpoint
+        .clone()
+        .or_else(|| std::env::var("AWS_ENDPOINT_URL_S3").ok())
+        .or_else(|| std::env::var("AWS_ENDPOINT_URL").ok())
+        .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com"));
+    let client = s3::Client::new(&endpoint, &region, credentials)?;
 
-    // Step 1: Scan
+    println!("{} Listing {location}...", style("→").cyan().bold());
+    let objects = client
+        .list(location)
+        .with_context(|| format!("Failed to list {location} at {endpoint}"))?;
+    let mut folders = s3::find_xdg_prefixes(&objects, &location.prefix);
+    let hidden = s3::find_included_prefixes(&objects, &location.prefix, &folders, &source.include);
+    folders.extend(hidden);
+    let size = s3::selected_bytes(&objects, &location.prefix, &folders);
+
+    let staging = create_staging(home_dir, source, "s3")?;
     println!(
-        "{} Scanning {}...",
+        "{} Downloading {} folders ({}) with {} parallel requests...",
         style("→").cyan().bold(),
-        cli.backup_dir.display()
+        folders.len(),
+        report::format_bytes(size),
+        source.jobs
     );
-    let scan_result = scan::scan_backup(&cli.backup_dir, &home_dir);
+    let files = s3::download(
+        &client,
+        location,
+        &objects,
+        &folders,
+        staging.path(),
+        source.jobs,
+    )?;
+    println!(
+        "  {files} files downloaded into {}",
+        staging.path().display()
+    );
+    Ok(staging)
+}
 
-    for warning in &scan_result.warnings {
-        eprintln!("{} Scan warning: {}", style("!").yellow().bold(), warning);
+fn open_restic(backup_dir: &Path, source: &SourceArgs) -> anyhow::Result<Repository> {
+    let password = restic_password(source)?;
+    Repository::open(backup_dir, password.as_bytes())
+        .with_context(|| format!("Failed to open restic repository {}", backup_dir.display()))
+}
+
+/// The restic password from `--password-file`, restic's own environment
+/// variables, or a prompt, in that order.
+fn restic_password(source: &SourceArgs) -> anyhow::Result<String> {
+    let file = source
+        .password_file
+        .clone()
+        .or_else(|| std::env::var_os("RESTIC_PASSWORD_FILE").map(PathBuf::from));
+    if let Some(file) = file {
+        let password = std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read password file {}", file.display()))?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+    if let Ok(password) = std::env::var("RESTIC_PASSWORD") {
+        return Ok(password);
     }
+    Ok(Password::new()
+        .with_prompt("Password for the restic repository")
+        .interact()?)
+}
+
+/// Pick the restic snapshot to restore: by `--snapshot` ID or date, the
+/// latest at the `--at` time, the latest when not asking, or whichever the
+/// user chooses.
+fn choose_restic_snapshot<'a>(
+    snapshots: &'a [RepoSnapshot],
+    source: &SourceArgs,
+) -> anyhow::Result<&'a RepoSnapshot> {
+    let count = snapshots.len();
+    let latest = &snapshots[count - 1];
+    println!(
+        "{} Found restic repository with {} snapshot{} ({} to {})",
+        style("✓").green().bold(),
+        count,
+        if count == 1 { "" } else { "s" },
+        preview::format_mtime(snapshots[0].time),
+        preview::format_mtime(latest.time)
+    );
+
+    let at_or_before = |time: SystemTime| snapshots.iter().rev().find(|s| s.time <= time);
+    let chosen = if let Some(spec) = &source.snapshot {
+        let by_id: Vec<&RepoSnapshot> = snapshots
+            .iter()
+            .filter(|s| s.id.starts_with(spec.as_str()))
+            .collect();
+        match by_id.as_slice() {
+            [one] => *one,
+            [] => snapshot::parse_time_spec(spec)
+                .and_then(at_or_before)
+                .with_context(|| {
+                    format!("No snapshot matches {spec}: give a snapshot ID or a date on or after the oldest")
+                })?,
+            _ => bail!("{spec} matches {} snapshots; give more of the ID", by_id.len()),
+        }
+    } else if let Some(at) = source.at {
+        at_or_before(at).with_context(|| {
+            format!(
+                "No snapshot was taken by {}; the oldest is from {}",
+                preview::format_mtime(at),
+                preview::format_mtime(snapshots[0].time)
+            )
+        })?
+    } else if count > 1 && matches!(source.duplicates, DuplicateStrategy::Ask) {
+        // Newest first, so the default is at the top
+        let labels: Vec<String> = snapshots
+            .iter()
+            .rev()
+            .map(|s| {
+                format!(
+                    "{}  ({})  {}  {}",
+                    s.short_id(),
+                    preview::format_mtime(s.time),
+                    s.hostname,
+                    s.paths.join(", ")
+                )
+            })
+            .collect();
+        let choice = Select::new()
+            .with_prompt("Which snapshot do you want to restore from?")
+            .items(&labels)
+            .default(0)
+            .interact()?;
+        &snapshots[count - 1 - choice]
+    } else {
+        latest
+    };
+
+    println!(
+        "  Using {} from {}",
+        style(chosen.short_id()).bold(),
+        preview::format_mtime(chosen.time)
+    );
+    Ok(chosen)
+}
 
-    if scan_result.mappings.is_empty() {
+/// Pick the snapshot to restore from: the one named by `--snapshot`, the
+/// latest when not asking, or whichever the user chooses.
+fn choose_snapshot(set: &SnapshotSet, source: &SourceArgs) -> anyhow::Result<PathBuf> {
+    let count = set.snapshots.len();
+    if count == 1 {
         println!(
-            "{} No XDG directories found in backup.",
-            style("!").yellow().bold()
+            "{} Found 1 {} snapshot",
+            style("✓").green().bold(),
+            set.layout
+        );
+    } else {
+        println!(
+            "{} Found {} {} snapshots ({} to {})",
+            style("✓").green().bold(),
+            count,
+            set.layout,
+            preview::format_mtime(set.snapshots[0].time),
+            preview::format_mtime(set.latest().time)
         );
-        return Ok(());
     }
 
-    // Handle duplicates: group by XdgDir, let user choose if ambiguous
-    let mappings = resolve_duplicate_mappings(scan_result.mappings)?;
+    let chosen = if let Some(spec) = &source.snapshot {
+        let Some(found) = set.find(spec) else {
+            bail!(
+                "No snapshot matches {spec}: give a snapshot name or a date on or after the oldest"
+            );
+        };
+        found
+    } else if count > 1 && matches!(source.duplicates, DuplicateStrategy::Ask) {
+        // Newest first, so the default is at the top
+        let labels: Vec<String> = set
+            .snapshots
+            .iter()
+            .rev()
+            .map(|s| format!("{}  ({})", s.name, preview::format_mtime(s.time)))
+            .collect();
+        let choice = Select::new()
+            .with_prompt("Which snapshot do you want to restore from?")
+            .items(&labels)
+            .default(0)
+            .interact()?;
+        &set.snapshots[count - 1 - choice]
+    } else {
+        set.latest()
+    };
 
-    // Show detected mappings and confirm
     println!(
-        "\n{} Detected {} directories:",
-        style("✓").green().bold(),
-        mappings.len()
+        "  Using {} from {}",
+        style(&chosen.name).bold(),
+        preview::format_mtime(chosen.time)
     );
-    for m in &mappings {
-        println!(
-            "  {} → {}",
-            style(m.source_path.display()).dim(),
-            m.dest_path.display()
-        );
+    Ok(chosen.root.clone())
+}
+
+/// Narrow the plan to the `--only` paths, failing on any that match nothing.
+fn apply_only(copy_plan: CopyPlan, home_dir: &Path, only: &[PathBuf]) -> anyhow::Result<CopyPlan> {
+    if only.is_empty() {
+        return Ok(copy_plan);
     }
-    println!();
+    let unmatched = plan::unmatched_paths(&copy_plan, home_dir, only);
+    if !unmatched.is_empty() {
+        let list: Vec<String> = unmatched.iter().map(|p| p.display().to_string()).collect();
+        bail!("--only matches nothing in the backup: {}", list.join(", "));
+    }
+    Ok(plan::filter_plan(&copy_plan, home_dir, only))
+}
 
-    if !Confirm::new()
+/// Confirm the restore, optionally narrowing it with `--only` or the
+/// interactive picker. Returns `None` if the user aborted.
+fn choose_scope(
+    copy_plan: CopyPlan,
+    home_dir: &Path,
+    only: &[PathBuf],
+) -> anyhow::Result<Option<CopyPlan>> {
+    if !only.is_empty() {
+        let total = copy_plan.files.len();
+        let copy_plan = apply_only(copy_plan, home_dir, only)?;
+        print_scope(&copy_plan, total);
+        let proceed = Confirm::new()
+            .with_prompt("Proceed with restore?")
+            .default(true)
+            .interact()
+            .unwrap_or(false);
+        return Ok(proceed.then_some(copy_plan));
+    }
+
+    let selection = Select::new()
         .with_prompt("Proceed with restore?")
-        .default(true)
+        .items(&["Restore everything", "Choose folders and files...", "Abort"])
+        .default(0)
         .interact()
-        .unwrap_or(false)
-    {
-        println!("Aborted.");
-        return Ok(());
+        .unwrap_or(2);
+    match selection {
+        0 => Ok(Some(copy_plan)),
+        1 => {
+            let Some(paths) = picker::pick(&copy_plan, home_dir)? else {
+                return Ok(None);
+            };
+            if paths.is_empty() {
+                println!("{} Nothing selected.", style("!").yellow().bold());
+                return Ok(None);
+            }
+            let filtered = plan::filter_plan(&copy_plan, home_dir, &paths);
+            if filtered.files.len() == copy_plan.files.len() {
+                return Ok(Some(copy_plan));
+            }
+            print_scope(&filtered, copy_plan.files.len());
+            let flags: Vec<String> = paths
+                .iter()
+                .map(|p| format!("--only '{}'", p.display()))
+                .collect();
+            println!(
+                "  {}",
+                style(format!("To repeat this selection: {}", flags.join(" "))).dim()
+            );
+            Ok(Some(filtered))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn print_scope(copy_plan: &CopyPlan, total_files: usize) {
+    println!(
+        "{} Restoring {} of {} files ({})",
+        style("→").cyan().bold(),
+        copy_plan.files.len(),
+        total_files,
+        report::format_bytes(copy_plan.total_bytes)
+    );
+}
+
+fn print_dry_run(copy_plan: &CopyPlan, rules: &RuleSet, home_dir: &Path) {
+    print!("{}", report::format_dry_run_report(copy_plan));
+    if !rules.is_empty() {
+        let decisions = rules.preview_plan(copy_plan, home_dir);
+        print!("{}", report::format_rule_preview(rules, &decisions));
     }
+}
 
-    // Step 2: Plan
-    let copy_plan = plan::build_plan(&mappings)?;
+/// Who restored files belong to. As root they keep their owners from the
+/// backup, mapped to local accounts by name through the backup's own
+/// `account_files` (`etc` folder, passwd, group) and by `--map-owner` and
+/// `--map-group`; ids neither names go to the owner of `home_dir`.
+/// Otherwise they belong to whoever runs the restore.
+fn choose_ownership(
+    args: &RestoreArgs,
+    account_files: Option<(PathBuf, String, String)>,
+    home_dir: &Path,
+) -> anyhow::Result<Ownership> {
+    let explicit = !args.map_owner.is_empty() || !args.map_group.is_empty();
+    if !users::is_root() {
+        if explicit {
+            bail!("--map-owner and --map-group need root, to give files to other users");
+        }
+        return Ok(Ownership::Unchanged);
+    }
 
-    if cli.dry_run {
-        print!("{}", report::format_dry_run_report(&copy_plan));
-        return Ok(());
+    let mut map = IdMap::default();
+    if let Some((etc, passwd, group)) = account_files {
+        map = IdMap::by_name(&passwd, &group);
+        println!(
+            "{} Matching owners to local accounts by name, using {}",
+            style("→").cyan().bold(),
+            etc.display()
+        );
+    }
+    map.users.extend(args.map_owner.iter().copied());
+    map.groups.extend(args.map_group.iter().copied());
+    for (kind, ids) in [("uid", &map.users), ("gid", &map.groups)] {
+        for (old, new) in ids.iter().filter(|(old, new)| old != new) {
+            println!("  {kind} {old} → {new}");
+        }
+    }
+    let home_owner = map.fall_back_to_home(home_dir);
+    println!(
+        "  other ids → {}:{}, the owner of {}",
+        home_owner.uid,
+        home_owner.gid,
+        home_dir.display()
+    );
+    Ok(Ownership::Preserve(map))
+}
+
+/// Count the files going to each owner: listed for a dry run, otherwise
+/// only a warning about the ids no mapping names.
+fn report_owners(owners: impl IntoIterator<Item = Owner>, map: &IdMap, dry_run: bool) {
+    let counts = users::count_owners(owners, map);
+    if dry_run {
+        print!("{}", report::format_ownership_report(&counts, map));
+        return;
     }
+    let unmapped: usize = counts
+        .iter()
+        .filter(|&&(owner, _, _)| !map.is_mapped(owner))
+        .map(|&(_, _, files)| files)
+        .sum();
+    if unmapped > 0 {
+        eprintln!(
+            "{} {unmapped} files have owners the backup's accounts don't name; they go to the home's owner (see --dry-run, or map them with --map-owner and --map-group)",
+            style("!").yellow().bold()
+        );
+    }
+}
 
+/// Copy (or extract, from an archive), report, and resolve any conflicts by
+/// rule or interactively, setting owners as `ownership` says.
+fn copy_and_resolve(
+    copy_plan: &CopyPlan,
+    jobs: usize,
+    archive: Option<&ArchiveSource>,
+    ownership: &Ownership,
+    resolver: &Resolver,
+) -> anyhow::Result<CopyResult> {
     println!(
         "\n{} {} files to copy ({} total)",
         style("→").cyan().bold(),
@@ -120,36 +1878,31 @@ fn run() -> anyhow::Result<()> {
 
     // Step 3: Copy
     let start = Instant::now();
-    let result = copy::execute_plan(&copy_plan, cli.jobs)?;
+    let result = match archive {
+        Some(archive) => archive.restore(copy_plan, jobs, ownership)?,
+        None => copy::execute_plan_as(copy_plan, jobs, ownership)?,
+    };
     let elapsed = start.elapsed();
 
     // Step 4: Report
     print!("{}", report::format_report(&result, elapsed));
 
-    // Step 5: Conflict resolution
+    // Step 5: Conflict resolution, with the same file access as the copy
     if !result.conflicts.is_empty() {
         println!();
-        resolve_conflicts(&result.conflicts)?;
-    }
-
-    // Step 6: Optional source cleanup
-    if !result.copied.is_empty() || !result.conflicts.is_empty() {
-        println!();
-        if Confirm::new()
-            .with_prompt("Delete source files from backup?")
-            .default(false)
-            .interact()
-            .unwrap_or(false)
-        {
-            delete_sources(&mappings);
-        }
+        let _writing = WriteAs::new(match ownership {
+            Ownership::User(owner) => Some(*owner),
+            _ => None,
+        });
+        resolver.resolve_conflicts(&result.conflicts)?;
     }
 
-    Ok(())
+    Ok(result)
 }
 
 fn resolve_duplicate_mappings(
     all_mappings: Vec<DetectedMapping>,
+    strategy: DuplicateStrategy,
 ) -> anyhow::Result<Vec<DetectedMapping>> {
     let mut by_dir: BTreeMap<XdgDir, Vec<DetectedMapping>> = BTreeMap::new();
     for m in all_mappings {
@@ -160,151 +1913,351 @@ fn resolve_duplicate_mappings(
     for (xdg_dir, candidates) in by_dir {
         if candidates.len() == 1 {
             chosen.push(candidates.into_iter().next().unwrap());
-        } else {
-            println!(
-                "\n{} Multiple '{}' directories found:",
-                style("?").yellow().bold(),
-                xdg_dir
-            );
-            let labels: Vec<String> = candidates
-                .iter()
-                .map(|c| c.source_path.display().to_string())
-                .collect();
+            continue;
+        }
 
-            let selection = Select::new()
-                .with_prompt(format!("Which {xdg_dir} to restore?"))
-                .items(&labels)
-                .default(0)
-                .interact()?;
+        let ranked = candidates::rank_candidates(candidates)
+            .with_context(|| format!("Failed to inspect {xdg_dir} candidates"))?;
+        let merge_all = ranked.len();
+        let selection = match strategy {
+            DuplicateStrategy::Merge => {
+                println!(
+                    "\n{} Multiple '{}' directories found; merging all {}",
+                    style("?").yellow().bold(),
+                    xdg_dir,
+                    ranked.len()
+                );
+                merge_all
+            }
+            DuplicateStrategy::Best => {
+                println!(
+                    "\n{} Multiple '{}' directories found; using {} ({})",
+                    style("?").yellow().bold(),
+                    xdg_dir,
+                    ranked[0].mapping.source_path.display(),
+                    report::format_candidate(&ranked[0])
+                );
+                0
+            }
+            DuplicateStrategy::Ask => {
+                println!(
+                    "\n{} Multiple '{}' directories found:",
+                    style("?").yellow().bold(),
+                    xdg_dir
+                );
+                let mut labels: Vec<String> = ranked
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        format!(
+                            "{}  {}{}",
+                            c.mapping.source_path.display(),
+                            style(report::format_candidate(c)).dim(),
+                            if i == 0 { " (suggested)" } else { "" }
+                        )
+                    })
+                    .collect();
+                labels.push(format!(
+                    "Merge all {} (newest version of each file wins)",
+                    ranked.len()
+                ));
+                labels.push("Compare copies...".to_string());
+
+                loop {
+                    let selection = Select::new()
+                        .with_prompt(format!("Which {xdg_dir} to restore?"))
+                        .items(&labels)
+                        .default(0)
+                        .interact()?;
+                    if selection <= merge_all {
+                        break selection;
+                    }
+                    // Compare the suggested copy against each of the others
+                    for other in &ranked[1..] {
+                        let (a, b) = (&ranked[0].mapping.source_path, &other.mapping.source_path);
+                        let cmp = candidates::compare_folders(a, b)
+                            .with_context(|| format!("Failed to compare {}", b.display()))?;
+                        println!("\n{}", report::format_comparison(&cmp, a, b, false));
+                    }
+                }
+            }
+        };
 
-            chosen.push(candidates.into_iter().nth(selection).unwrap());
+        if selection == merge_all {
+            // Ranked order: the likeliest copy supplies tied versions first
+            chosen.extend(ranked.into_iter().map(|c| c.mapping));
+        } else {
+            chosen.push(ranked.into_iter().nth(selection).unwrap().mapping);
         }
     }
 
     Ok(chosen)
 }
 
-fn resolve_conflicts(conflicts: &[Conflict]) -> anyhow::Result<()> {
-    let options = &[
-        "Overwrite all originals with restored versions",
-        "Keep all originals (delete .restore files)",
-        "Decide per folder",
-        "Decide individually",
-        "Leave as-is (keep both)",
-    ];
-
-    let selection = Select::new()
-        .with_prompt("How to handle conflicts?")
-        .items(options)
-        .default(4)
-        .interact()?;
-
-    match selection {
-        0 => apply_to_all(conflicts, Resolution::Overwrite),
-        1 => apply_to_all(conflicts, Resolution::KeepOriginal),
-        2 => resolve_per_folder(conflicts)?,
-        3 => resolve_individually(conflicts)?,
-        4 => apply_to_all(conflicts, Resolution::LeaveAsIs),
-        _ => unreachable!(),
-    }
-
-    Ok(())
+/// Conflict resolution settings shared by the restore and resolve flows.
+struct Resolver<'a> {
+    rules: &'a RuleSet,
+    home_dir: &'a Path,
+    /// Trash displaced files instead of deleting them.
+    trash: Option<Trash>,
+    /// Home-shaped tree holding common ancestors for text merges.
+    merge_base: Option<&'a Path>,
+    /// Skip the menu and open the full-screen browser.
+    tui: bool,
 }
 
-fn apply_to_all(conflicts: &[Conflict], resolution: Resolution) {
-    for c in conflicts {
-        if let Err(e) = conflict::apply_resolution(c, resolution) {
-            eprintln!(
-                "{} Failed to resolve {}: {}",
-                style("Error:").red().bold(),
-                c.original_path.display(),
-                e
+impl Resolver<'_> {
+    fn resolve_conflicts(&self, conflicts: &[Conflict]) -> anyhow::Result<()> {
+        // Byte-identical copies need no decision: drop the .restore file
+        let (identical, conflicts): (Vec<Conflict>, Vec<Conflict>) =
+            conflicts.iter().cloned().partition(preview::is_identical);
+        if !identical.is_empty() {
+            for c in &identical {
+                self.apply_one(c, Resolution::KeepOriginal);
+            }
+            println!(
+                "{} {} identical conflict{} resolved automatically",
+                style("✓").green().bold(),
+                identical.len(),
+                if identical.len() == 1 { "" } else { "s" }
             );
         }
-    }
-}
 
-fn resolve_per_folder(conflicts: &[Conflict]) -> anyhow::Result<()> {
-    let mut by_dir: BTreeMap<XdgDir, Vec<&Conflict>> = BTreeMap::new();
-    for c in conflicts {
-        by_dir.entry(c.xdg_dir).or_default().push(c);
-    }
+        // Rules, then the defaults of hidden folder categories, decide what
+        // they can; the rest goes to the interactive flow
+        let mut by_rule = 0;
+        let mut by_default = 0;
+        let mut undecided = Vec::new();
+        for c in conflicts {
+            match dotfiles::settle(self.rules, &c, self.home_dir) {
+                Some(Settled::Rule(resolution)) => {
+                    self.apply_one(&c, resolution);
+                    by_rule += 1;
+                }
+                Some(Settled::Default(resolution)) => {
+                    self.apply_one(&c, resolution);
+                    by_default += 1;
+                }
+                None => undecided.push(c),
+            }
+        }
+        if by_rule > 0 {
+            println!(
+                "{} {} conflict{} resolved by rules",
+                style("✓").green().bold(),
+                by_rule,
+                if by_rule == 1 { "" } else { "s" }
+            );
+        }
+        if by_default > 0 {
+            println!(
+                "{} {} conflict{} in hidden folders resolved by their category's default",
+                style("✓").green().bold(),
+                by_default,
+                if by_default == 1 { "" } else { "s" }
+            );
+        }
 
-    let options = &["Overwrite", "Keep originals", "Leave as-is"];
+        if undecided.is_empty() {
+            return Ok(());
+        }
+        let conflicts = &undecided[..];
+        if self.tui {
+            return self.resolve_in_browser(conflicts);
+        }
+
+        let discard = if self.trash.is_some() {
+            "trash"
+        } else {
+            "delete"
+        };
+        let keep_all = format!("Keep all originals ({discard} .restore files)");
+        let options = &[
+            "Overwrite all originals with restored versions",
+            keep_all.as_str(),
+            "Decide per folder",
+            "Decide individually",
+            "Browse in full-screen view",
+            "Leave as-is (keep both)",
+        ];
 
-    for (xdg_dir, folder_conflicts) in &by_dir {
         let selection = Select::new()
-            .with_prompt(format!(
-                "{} ({} conflicts)",
-                xdg_dir,
-                folder_conflicts.len()
-            ))
+            .with_prompt("How to handle conflicts?")
             .items(options)
-            .default(2)
+            .default(5)
             .interact()?;
 
-        let resolution = match selection {
-            0 => Resolution::Overwrite,
-            1 => Resolution::KeepOriginal,
-            _ => Resolution::LeaveAsIs,
+        match selection {
+            0 => self.apply_to_all(conflicts, Resolution::Overwrite),
+            1 => self.apply_to_all(conflicts, Resolution::KeepOriginal),
+            2 => self.resolve_per_folder(conflicts)?,
+            3 => self.resolve_individually(conflicts)?,
+            4 => self.resolve_in_browser(conflicts)?,
+            5 => self.apply_to_all(conflicts, Resolution::LeaveAsIs),
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn resolve_in_browser(&self, conflicts: &[Conflict]) -> anyhow::Result<()> {
+        let Some(decisions) = tui::review(conflicts, self.home_dir)? else {
+            println!(
+                "{} Cancelled; {} conflict{} left as-is",
+                style("!").yellow().bold(),
+                conflicts.len(),
+                if conflicts.len() == 1 { "" } else { "s" }
+            );
+            return Ok(());
         };
 
-        for c in folder_conflicts {
-            if let Err(e) = conflict::apply_resolution(c, resolution) {
-                eprintln!(
-                    "{} Failed to resolve {}: {}",
-                    style("Error:").red().bold(),
-                    c.original_path.display(),
-                    e
-                );
+        let mut resolved = 0;
+        for &(i, resolution) in &decisions {
+            if resolution != Resolution::LeaveAsIs {
+                self.apply_one(&conflicts[i], resolution);
+                resolved += 1;
             }
         }
+        println!(
+            "{} {} conflict{} resolved, {} left as-is",
+            style("✓").green().bold(),
+            resolved,
+            if resolved == 1 { "" } else { "s" },
+            conflicts.len() - resolved
+        );
+        Ok(())
     }
 
-    Ok(())
-}
+    fn disposal(&self) -> Disposal<'_> {
+        match &self.trash {
+            Some(trash) => Disposal::Trash(trash),
+            None => Disposal::Delete,
+        }
+    }
 
-fn resolve_individually(conflicts: &[Conflict]) -> anyhow::Result<()> {
-    let options = &["Overwrite", "Keep original", "Leave as-is"];
+    fn apply_one(&self, c: &Conflict, resolution: Resolution) {
+        if resolution != Resolution::Merge {
+            report_failure(
+                c,
+                conflict::apply_resolution_with(c, resolution, self.disposal()),
+            );
+            return;
+        }
 
-    for c in conflicts {
-        let prompt = format!("{} (restore: {} bytes)", c.original_path.display(), c.size);
-        let selection = Select::new()
-            .with_prompt(prompt)
-            .items(options)
-            .default(2)
-            .interact()?;
+        let ancestor = self
+            .merge_base
+            .zip(c.original_path.strip_prefix(self.home_dir).ok())
+            .map(|(base, relative)| base.join(relative))
+            .filter(|p| p.is_file());
+        match conflict::merge_into_original(c, ancestor.as_deref(), self.disposal()) {
+            Ok(0) => {}
+            Ok(regions) => println!(
+                "{} {} merged with {} conflicting region{}",
+                style("!").yellow().bold(),
+                c.original_path.display(),
+                regions,
+                if regions == 1 { "" } else { "s" }
+            ),
+            Err(e) => report_failure(c, Err(e)),
+        }
+    }
 
-        let resolution = match selection {
-            0 => Resolution::Overwrite,
-            1 => Resolution::KeepOriginal,
-            _ => Resolution::LeaveAsIs,
-        };
+    fn apply_to_all(&self, conflicts: &[Conflict], resolution: Resolution) {
+        for c in conflicts {
+            self.apply_one(c, resolution);
+        }
+    }
 
-        if let Err(e) = conflict::apply_resolution(c, resolution) {
-            eprintln!(
-                "{} Failed to resolve {}: {}",
-                style("Error:").red().bold(),
-                c.original_path.display(),
-                e
-            );
+    fn resolve_per_folder(&self, conflicts: &[Conflict]) -> anyhow::Result<()> {
+        let mut by_dir: BTreeMap<XdgDir, Vec<&Conflict>> = BTreeMap::new();
+        for c in conflicts {
+            by_dir.entry(c.xdg_dir).or_default().push(c);
+        }
+
+        let options = &[
+            "Overwrite",
+            "Keep originals",
+            "Merge (text files)",
+            "Leave as-is",
+        ];
+
+        for (xdg_dir, folder_conflicts) in &by_dir {
+            let selection = Select::new()
+                .with_prompt(format!(
+                    "{} ({} conflicts)",
+                    xdg_dir,
+                    folder_conflicts.len()
+                ))
+                .items(options)
+                .default(3)
+                .interact()?;
+
+            let resolution = match selection {
+                0 => Resolution::Overwrite,
+                1 => Resolution::KeepOriginal,
+                2 => Resolution::Merge,
+                _ => Resolution::LeaveAsIs,
+            };
+
+            for c in folder_conflicts {
+                self.apply_one(c, resolution);
+            }
         }
+
+        Ok(())
     }
 
-    Ok(())
+    fn resolve_individually(&self, conflicts: &[Conflict]) -> anyhow::Result<()> {
+        let options = &["Overwrite", "Keep original", "Merge", "Leave as-is"];
+
+        for c in conflicts {
+            println!("\n{}", preview::format_conflict_preview(c).trim_end());
+            let selection = Select::new()
+                .with_prompt("Resolve")
+                .items(options)
+                .default(3)
+                .interact()?;
+
+            let resolution = match selection {
+                0 => Resolution::Overwrite,
+                1 => Resolution::KeepOriginal,
+                2 => Resolution::Merge,
+                _ => Resolution::LeaveAsIs,
+            };
+
+            self.apply_one(c, resolution);
+        }
+
+        Ok(())
+    }
+}
+
+fn report_failure(c: &Conflict, result: Result<(), ResolveError>) {
+    if let Err(e) = result {
+        eprintln!(
+            "{} Failed to resolve {}: {}",
+            style("Error:").red().bold(),
+            c.original_path.display(),
+            e
+        );
+    }
 }
 
-fn delete_sources(mappings: &[DetectedMapping]) {
+fn delete_sources(mappings: &[DetectedMapping], trash: Option<&Trash>) {
     for mapping in mappings {
-        if let Err(e) = std::fs::remove_dir_all(&mapping.source_path) {
+        let outcome = match trash {
+            Some(trash) => trash.trash(&mapping.source_path).map(|_| "Trashed"),
+            None => std::fs::remove_dir_all(&mapping.source_path).map(|()| "Deleted"),
+        };
+        if let Err(e) = &outcome {
             eprintln!(
                 "{} Failed to delete {}: {}",
                 style("Error:").red().bold(),
                 mapping.source_path.display(),
                 e
             );
-        } else {
-            println!("  Deleted {}", mapping.source_path.display());
+        } else if let Ok(verb) = outcome {
+            println!("  {verb} {}", mapping.source_path.display());
         }
     }
 }
@@ -0,0 +1,202 @@
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+/// The result of merging two or three versions of a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    pub text: String,
+    /// Number of regions wrapped in conflict markers.
+    pub conflicts: usize,
+}
+
+/// Merge the original (`ours`) and restored (`theirs`) versions of a text.
+///
+/// With a common ancestor this is a line-based three-way merge: a region
+/// changed on only one side takes that side's version. Without one, every
+/// differing region is a conflict. Conflicting regions are wrapped in
+/// git-style `<<<<<<< original` / `=======` / `>>>>>>> restore` markers.
+pub fn merge_texts(base: Option<&str>, ours: &str, theirs: &str) -> Merged {
+    // Two-way merge runs the same walk against the lines both sides share,
+    // but never guesses which side changed.
+    let two_way = base.is_none();
+    let common;
+    let base = if let Some(base) = base {
+        base
+    } else {
+        common = common_lines(ours, theirs);
+        &common
+    };
+
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let to_ours = matches(&base, &ours);
+    let to_theirs = matches(&base, &theirs);
+
+    let mut out = Merged {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        // Next base line that survives unchanged on both sides
+        let sync = (b..base.len()).find_map(|i| Some((i, to_ours[i]?, to_theirs[i]?)));
+        let (bi, oi, ti) = sync.unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        if (bi, oi, ti) == (b, o, t) {
+            if bi == base.len() {
+                break;
+            }
+            out.text.push_str(base[bi]);
+            (b, o, t) = (bi + 1, oi + 1, ti + 1);
+            continue;
+        }
+
+        resolve_chunk(
+            &mut out,
+            two_way,
+            &base[b..bi],
+            &ours[o..oi],
+            &theirs[t..ti],
+        );
+        (b, o, t) = (bi, oi, ti);
+    }
+    out
+}
+
+fn resolve_chunk(out: &mut Merged, two_way: bool, base: &[&str], ours: &[&str], theirs: &[&str]) {
+    let chosen = if ours == theirs {
+        Some(ours)
+    } else if two_way {
+        None
+    } else if ours == base {
+        Some(theirs)
+    } else if theirs == base {
+        Some(ours)
+    } else {
+        None
+    };
+
+    if let Some(lines) = chosen {
+        out.text.extend(lines.iter().copied());
+    } else {
+        out.conflicts += 1;
+        out.text.push_str("<<<<<<< original\n");
+        push_lines(&mut out.text, ours);
+        out.text.push_str("=======\n");
+        push_lines(&mut out.text, theirs);
+        out.text.push_str(">>>>>>> restore\n");
+    }
+}
+
+/// Append lines, making sure the last one ends in a newline so the next
+/// marker starts on its own line.
+fn push_lines(text: &mut String, lines: &[&str]) {
+    for line in lines {
+        text.push_str(line);
+    }
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// For each line of `from`, the index of the matching line in `to`, if any.
+fn matches(from: &[&str], to: &[&str]) -> Vec<Option<usize>> {
+    let mut map = vec![None; from.len()];
+    for op in capture_diff_slices(Algorithm::Myers, from, to) {
+        if let DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for k in 0..len {
+                map[old_index + k] = Some(new_index + k);
+            }
+        }
+    }
+    map
+}
+
+/// The lines both texts share, in order (their longest common subsequence).
+fn common_lines(a: &str, b: &str) -> String {
+    let a: Vec<&str> = a.split_inclusive('\n').collect();
+    let b: Vec<&str> = b.split_inclusive('\n').collect();
+    let mut out = String::new();
+    for op in capture_diff_slices(Algorithm::Myers, &a, &b) {
+        if let DiffOp::Equal { old_index, len, .. } = op {
+            for line in &a[old_index..old_index + len] {
+                out.push_str(line);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_sides_merge_cleanly() {
+        let m = merge_texts(None, "a\nb\n", "a\nb\n");
+        assert_eq!(m.text, "a\nb\n");
+        assert_eq!(m.conflicts, 0);
+    }
+
+    #[test]
+    fn two_way_marks_every_difference() {
+        let m = merge_texts(None, "milk\nbread\neggs\n", "milk\nbutter\neggs\n");
+        assert_eq!(
+            m.text,
+            "milk\n<<<<<<< original\nbread\n=======\nbutter\n>>>>>>> restore\neggs\n"
+        );
+        assert_eq!(m.conflicts, 1);
+    }
+
+    #[test]
+    fn two_way_additions_on_one_side_are_conflicts() {
+        // Without an ancestor we cannot tell an addition from a deletion
+        let m = merge_texts(None, "a\nc\n", "a\nb\nc\n");
+        assert_eq!(m.conflicts, 1);
+        assert!(m.text.contains("=======\nb\n>>>>>>> restore\n"));
+    }
+
+    #[test]
+    fn three_way_takes_one_sided_changes() {
+        let base = "title\none\ntwo\nthree\n";
+        let ours = "title\nONE\ntwo\nthree\n";
+        let theirs = "title\none\ntwo\nthree\nfour\n";
+
+        let m = merge_texts(Some(base), ours, theirs);
+
+        assert_eq!(m.text, "title\nONE\ntwo\nthree\nfour\n");
+        assert_eq!(m.conflicts, 0);
+    }
+
+    #[test]
+    fn three_way_accepts_identical_changes() {
+        let m = merge_texts(Some("a\nb\n"), "a\nB\n", "a\nB\n");
+        assert_eq!(m.text, "a\nB\n");
+        assert_eq!(m.conflicts, 0);
+    }
+
+    #[test]
+    fn three_way_marks_divergent_changes() {
+        let m = merge_texts(Some("a\nb\nc\n"), "a\nX\nc\n", "a\nY\nc\n");
+        assert_eq!(
+            m.text,
+            "a\n<<<<<<< original\nX\n=======\nY\n>>>>>>> restore\nc\n"
+        );
+        assert_eq!(m.conflicts, 1);
+    }
+
+    #[test]
+    fn handles_missing_trailing_newline() {
+        let m = merge_texts(Some("a\nb"), "a\nX", "a\nY");
+        assert_eq!(
+            m.text,
+            "a\n<<<<<<< original\nX\n=======\nY\n>>>>>>> restore\n"
+        );
+    }
+}
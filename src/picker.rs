@@ -0,0 +1,467 @@
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::report::format_bytes;
+use crate::types::CopyPlan;
+
+const HELP: &str = "space check  → expand  ← collapse  a all/none  c continue  q abort";
+
+/// Let the user check and uncheck folders and files of a plan in a tree
+/// view. Everything starts checked.
+///
+/// Returns the smallest set of paths (relative to `home_dir`) covering the
+/// checked files, suitable for [`crate::plan::filter_plan`] or `--only`, or
+/// `None` if the user aborted.
+pub fn pick(plan: &CopyPlan, home_dir: &Path) -> io::Result<Option<Vec<PathBuf>>> {
+    let mut picker = Picker::new(plan, home_dir);
+    let mut terminal = ratatui::try_init().map_err(|e| {
+        ratatui::restore();
+        io::Error::new(
+            e.kind(),
+            format!("the full-screen view needs a terminal: {e}"),
+        )
+    })?;
+    let done = run(&mut terminal, &mut picker);
+    ratatui::restore();
+
+    Ok(done?.then(|| picker.selected_paths()))
+}
+
+fn run(terminal: &mut DefaultTerminal, picker: &mut Picker) -> io::Result<bool> {
+    loop {
+        terminal.draw(|frame| picker.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Release {
+                if let Some(done) = picker.handle_key(key) {
+                    return Ok(done);
+                }
+            }
+        }
+    }
+}
+
+/// A folder or file in the tree.
+#[derive(Debug)]
+struct Node {
+    /// Path relative to the home directory.
+    path: PathBuf,
+    depth: usize,
+    /// The files under this node, as a range of the sorted file list.
+    files: Range<usize>,
+    is_dir: bool,
+    expanded: bool,
+}
+
+struct Picker {
+    /// Nodes in depth-first order, so a node's subtree follows it.
+    nodes: Vec<Node>,
+    /// Running total of file sizes, for per-node byte counts.
+    size_prefix: Vec<u64>,
+    checked: Vec<bool>,
+    /// Indices of the nodes whose ancestors are all expanded.
+    visible: Vec<usize>,
+    cursor: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    All,
+    Some,
+    None,
+}
+
+impl Picker {
+    fn new(plan: &CopyPlan, home_dir: &Path) -> Picker {
+        let mut files: Vec<(PathBuf, u64)> = plan
+            .files
+            .iter()
+            .map(|f| {
+                let relative = f.dest.strip_prefix(home_dir).unwrap_or(&f.dest);
+                (relative.to_path_buf(), f.size)
+            })
+            .collect();
+        // Component-wise order keeps every folder's files contiguous
+        files.sort();
+
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for (i, (path, _)) in files.iter().enumerate() {
+            let parent = path.parent().unwrap_or(Path::new(""));
+            while let Some(&top) = open.last() {
+                if parent.starts_with(&nodes[top].path) {
+                    break;
+                }
+                nodes[top].files.end = i;
+                open.pop();
+            }
+            let components: Vec<_> = parent.components().collect();
+            for depth in open.len()..components.len() {
+                open.push(nodes.len());
+                nodes.push(Node {
+                    path: components[..=depth].iter().collect(),
+                    depth,
+                    files: i..i,
+                    is_dir: true,
+                    expanded: false,
+                });
+            }
+            nodes.push(Node {
+                path: path.clone(),
+                depth: components.len(),
+                files: i..i + 1,
+                is_dir: false,
+                expanded: false,
+            });
+        }
+        for top in open {
+            nodes[top].files.end = files.len();
+        }
+
+        let mut size_prefix = vec![0];
+        for (_, size) in &files {
+            size_prefix.push(size_prefix.last().unwrap() + size);
+        }
+
+        let mut picker = Picker {
+            nodes,
+            size_prefix,
+            checked: vec![true; files.len()],
+            visible: Vec::new(),
+            cursor: 0,
+        };
+        picker.rebuild_visible();
+        picker
+    }
+
+    fn rebuild_visible(&mut self) {
+        let current = self.visible.get(self.cursor).copied();
+        self.visible.clear();
+        let mut collapsed_at = None;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if let Some(depth) = collapsed_at {
+                if node.depth > depth {
+                    continue;
+                }
+            }
+            collapsed_at = (!node.expanded).then_some(node.depth);
+            self.visible.push(i);
+        }
+        self.cursor = current
+            .and_then(|n| self.visible.iter().position(|&v| v == n))
+            .unwrap_or(0);
+    }
+
+    fn check_state(&self, node: &Node) -> Check {
+        let checked = self.checked[node.files.clone()]
+            .iter()
+            .filter(|&&c| c)
+            .count();
+        match checked {
+            0 => Check::None,
+            n if n == node.files.len() => Check::All,
+            _ => Check::Some,
+        }
+    }
+
+    fn selected_paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        let mut skip_deeper_than = None;
+        for node in &self.nodes {
+            if let Some(depth) = skip_deeper_than {
+                if node.depth > depth {
+                    continue;
+                }
+            }
+            skip_deeper_than = match self.check_state(node) {
+                Check::All => {
+                    paths.push(node.path.clone());
+                    Some(node.depth)
+                }
+                Check::None => Some(node.depth),
+                Check::Some => None,
+            };
+        }
+        paths
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.visible.get(self.cursor).copied()
+    }
+
+    fn toggle(&mut self) {
+        let Some(n) = self.current() else { return };
+        let range = self.nodes[n].files.clone();
+        let check = self.check_state(&self.nodes[n]) != Check::All;
+        self.checked[range].fill(check);
+    }
+
+    fn toggle_all(&mut self) {
+        let check = !self.checked.iter().all(|&c| c);
+        self.checked.fill(check);
+    }
+
+    fn set_expanded(&mut self, expanded: bool) {
+        let Some(n) = self.current() else { return };
+        let node = &mut self.nodes[n];
+        if node.is_dir && node.expanded != expanded {
+            node.expanded = expanded;
+            self.rebuild_visible();
+        } else if !expanded && node.depth > 0 {
+            // Already collapsed: jump to the parent folder
+            let depth = node.depth;
+            if let Some(pos) = self.visible[..self.cursor]
+                .iter()
+                .rposition(|&v| self.nodes[v].depth < depth)
+            {
+                self.cursor = pos;
+            }
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let last = self.visible.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    /// `Some(true)` to continue with the selection, `Some(false)` to abort.
+    fn handle_key(&mut self, key: KeyEvent) -> Option<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(false);
+        }
+        match key.code {
+            KeyCode::Up => self.move_cursor(-1),
+            KeyCode::Down => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-10),
+            KeyCode::PageDown => self.move_cursor(10),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.move_cursor(isize::MAX),
+            KeyCode::Right => self.set_expanded(true),
+            KeyCode::Left => self.set_expanded(false),
+            KeyCode::Enter => {
+                if let Some(n) = self.current() {
+                    let expanded = self.nodes[n].expanded;
+                    self.set_expanded(!expanded);
+                }
+            }
+            KeyCode::Char(' ') => self.toggle(),
+            KeyCode::Char('a') => self.toggle_all(),
+            KeyCode::Char('c') => return Some(true),
+            KeyCode::Char('q') | KeyCode::Esc => return Some(false),
+            _ => {}
+        }
+        None
+    }
+
+    fn bytes(&self, files: &Range<usize>, checked_only: bool) -> u64 {
+        if !checked_only {
+            return self.size_prefix[files.end] - self.size_prefix[files.start];
+        }
+        files
+            .clone()
+            .filter(|&i| self.checked[i])
+            .map(|i| self.size_prefix[i + 1] - self.size_prefix[i])
+            .sum()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tree, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let all = 0..self.checked.len();
+        let checked = self.checked.iter().filter(|&&c| c).count();
+        let title = format!(
+            " Restore {checked}/{} files ({} of {}) ",
+            self.checked.len(),
+            format_bytes(self.bytes(&all, true)),
+            format_bytes(self.bytes(&all, false))
+        );
+
+        let items: Vec<ListItem> = self.visible.iter().map(|&n| self.row(n)).collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.cursor));
+        frame.render_stateful_widget(list, tree, &mut state);
+        frame.render_widget(Line::from(HELP).dim(), status);
+    }
+
+    fn row(&self, n: usize) -> ListItem<'static> {
+        let node = &self.nodes[n];
+        let indent = "  ".repeat(node.depth);
+        let arrow = match (node.is_dir, node.expanded) {
+            (false, _) => " ",
+            (true, false) => "▸",
+            (true, true) => "▾",
+        };
+        let check = match self.check_state(node) {
+            Check::All => "[x]",
+            Check::Some => "[-]",
+            Check::None => "[ ]",
+        };
+        let name = node
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut spans = vec![Span::from(format!("{indent}{arrow} {check} "))];
+        if node.is_dir {
+            spans.push(Span::from(format!("{name}/")).bold());
+            let total = node.files.len();
+            let selected = self.checked[node.files.clone()]
+                .iter()
+                .filter(|&&c| c)
+                .count();
+            let files = if selected == total {
+                format!("{total} files")
+            } else {
+                format!("{selected}/{total} files")
+            };
+            spans.push(
+                Span::from(format!(
+                    "  {files}, {}",
+                    format_bytes(self.bytes(&node.files, false))
+                ))
+                .dim(),
+            );
+        } else {
+            spans.push(Span::from(name));
+            spans.push(
+                Span::from(format!(
+                    "  {}",
+                    format_bytes(self.bytes(&node.files, false))
+                ))
+                .dim(),
+            );
+        }
+        ListItem::new(Line::from(spans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CopyOp, XdgDir};
+
+    fn plan(home: &Path, files: &[(&str, u64)]) -> CopyPlan {
+        CopyPlan {
+            dirs: Vec::new(),
+            files: files
+                .iter()
+                .map(|&(path, size)| CopyOp {
+                    source: PathBuf::from("/backup").join(path),
+                    dest: home.join(path),
+                    size,
+                    mtime: None,
+                    xdg_dir: XdgDir::Pictures,
+                    snapshot: None,
+                })
+                .collect(),
+            total_bytes: files.iter().map(|f| f.1).sum(),
+        }
+    }
+
+    fn sample() -> Picker {
+        let home = Path::new("/home/u");
+        Picker::new(
+            &plan(
+                home,
+                &[
+                    ("Pictures/2019/b.jpg", 2),
+                    ("Documents/taxes/2020.pdf", 5),
+                    ("Pictures/2019/a.jpg", 1),
+                    ("Pictures/2020/c.jpg", 3),
+                    ("Documents/cv.odt", 7),
+                ],
+            ),
+            home,
+        )
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::from(code)
+    }
+
+    #[test]
+    fn builds_tree_with_per_folder_totals() {
+        let picker = sample();
+
+        let outline: Vec<(String, usize, usize, u64)> = picker
+            .nodes
+            .iter()
+            .map(|n| {
+                (
+                    n.path.display().to_string(),
+                    n.depth,
+                    n.files.len(),
+                    picker.bytes(&n.files, false),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outline,
+            [
+                ("Documents", 0, 2, 12),
+                ("Documents/cv.odt", 1, 1, 7),
+                ("Documents/taxes", 1, 1, 5),
+                ("Documents/taxes/2020.pdf", 2, 1, 5),
+                ("Pictures", 0, 3, 6),
+                ("Pictures/2019", 1, 2, 3),
+                ("Pictures/2019/a.jpg", 2, 1, 1),
+                ("Pictures/2019/b.jpg", 2, 1, 2),
+                ("Pictures/2020", 1, 1, 3),
+                ("Pictures/2020/c.jpg", 2, 1, 3),
+            ]
+            .map(|(p, d, f, b)| (p.to_string(), d, f, b))
+        );
+        // Only the top level shows until something is expanded
+        assert_eq!(picker.visible, vec![0, 4]);
+    }
+
+    #[test]
+    fn selecting_subtrees_yields_minimal_paths() {
+        let mut picker = sample();
+
+        picker.handle_key(key(KeyCode::Char('a'))); // uncheck everything
+        picker.handle_key(key(KeyCode::Right)); // expand Documents
+        picker.handle_key(key(KeyCode::Down));
+        picker.handle_key(key(KeyCode::Down)); // Documents/taxes
+        picker.handle_key(key(KeyCode::Char(' ')));
+        picker.handle_key(key(KeyCode::End)); // Pictures
+        picker.handle_key(key(KeyCode::Right));
+        picker.handle_key(key(KeyCode::Down)); // Pictures/2019
+        picker.handle_key(key(KeyCode::Char(' ')));
+
+        assert_eq!(
+            picker.selected_paths(),
+            [
+                PathBuf::from("Documents/taxes"),
+                PathBuf::from("Pictures/2019")
+            ]
+        );
+        assert_eq!(picker.check_state(&picker.nodes[4]), Check::Some);
+        assert_eq!(picker.handle_key(key(KeyCode::Char('c'))), Some(true));
+    }
+
+    #[test]
+    fn left_collapses_then_jumps_to_parent() {
+        let mut picker = sample();
+        picker.handle_key(key(KeyCode::Enter)); // expand Documents
+        picker.handle_key(key(KeyCode::Down)); // cv.odt
+
+        picker.handle_key(key(KeyCode::Left));
+        assert_eq!(picker.cursor, 0);
+
+        picker.handle_key(key(KeyCode::Left));
+        assert_eq!(picker.visible, vec![0, 4]);
+        assert_eq!(picker.handle_key(key(KeyCode::Char('q'))), Some(false));
+    }
+}
@@ -1,52 +1,248 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
 use walkdir::WalkDir;
 
+use crate::preview::contents_identical;
 use crate::types::{CopyOp, CopyPlan, DetectedMapping, DirOp};
 
 /// Build a copy plan from confirmed mappings.
 ///
 /// Enumerates all files and directories within each mapping's source,
-/// producing `CopyOps` for files and `DirOps` for directories.
-pub fn build_plan(mappings: &[DetectedMapping]) -> std::io::Result<CopyPlan> {
+/// producing `CopyOps` for files and `DirOps` for directories. Mappings
+/// that share a destination are merged; see [`build_merged_plan`].
+pub fn build_plan(mappings: &[DetectedMapping]) -> io::Result<CopyPlan> {
+    build_merged_plan(mappings).map(|(plan, _)| plan)
+}
+
+/// A file or directory found under a backup folder.
+#[derive(Debug, Clone)]
+pub struct SourceEntry {
+    /// Path relative to the folder; empty for the folder itself.
+    pub relative: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+}
+
+/// List everything under `root`, following symlinks. The root itself comes
+/// first, as a directory with an empty relative path.
+pub fn walk_source(root: &Path) -> io::Result<Vec<SourceEntry>> {
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(root).follow_links(true) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(root).unwrap().to_path_buf();
+        if entry.file_type().is_dir() {
+            entries.push(SourceEntry {
+                relative,
+                is_dir: true,
+                size: 0,
+                mtime: None,
+            });
+        } else {
+            let metadata = entry.metadata()?;
+            entries.push(SourceEntry {
+                relative,
+                is_dir: false,
+                size: metadata.len(),
+                mtime: metadata.modified().ok(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// How a file present in several merged folders was planned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// All copies were byte-identical; one is copied.
+    Identical { copies: usize },
+    /// The copies differ; the most recently modified one wins.
+    Newest { older: usize },
+    /// The copies differ and none is newer. All are copied: the first to
+    /// the destination, the rest as `.restore` conflicts.
+    Divergent { versions: usize },
+}
+
+/// A destination that more than one merged folder supplies.
+#[derive(Debug, Clone)]
+pub struct MergeNote {
+    pub dest: PathBuf,
+    /// Sources that will be copied, the one taking the destination first.
+    pub kept: Vec<PathBuf>,
+    pub outcome: MergeOutcome,
+}
+
+/// Build a copy plan, merging mappings that share a destination (several
+/// copies of the same XDG folder).
+///
+/// Identical copies of a file are copied once and, when copies differ, the
+/// newest wins. Copies that differ without one being newer are all
+/// planned, so the extras go through the conflict flow. Returns a note for
+/// every destination supplied by more than one folder.
+pub fn build_merged_plan(mappings: &[DetectedMapping]) -> io::Result<(CopyPlan, Vec<MergeNote>)> {
     let mut files = Vec::new();
     let mut dirs = Vec::new();
-    let mut total_bytes: u64 = 0;
 
     for mapping in mappings {
-        for entry in WalkDir::new(&mapping.source_path).follow_links(true) {
-            let entry = entry?;
-            let relative = entry.path().strip_prefix(&mapping.source_path).unwrap();
-
-            if relative.as_os_str().is_empty() {
+        for entry in walk_source(&mapping.source_path)? {
+            if entry.relative.as_os_str().is_empty() {
                 // The root of the mapping itself — ensure the dest dir exists
                 dirs.push(DirOp {
                     dest: mapping.dest_path.clone(),
+                    source: Some(mapping.source_path.clone()),
                 });
                 continue;
             }
 
-            let dest = mapping.dest_path.join(relative);
-
-            if entry.file_type().is_dir() {
-                dirs.push(DirOp { dest });
+            let dest = mapping.dest_path.join(&entry.relative);
+            if entry.is_dir {
+                dirs.push(DirOp {
+                    dest,
+                    source: Some(mapping.source_path.join(&entry.relative)),
+                });
             } else {
-                let metadata = entry.metadata()?;
-                let size = metadata.len();
-                total_bytes += size;
                 files.push(CopyOp {
-                    source: entry.path().to_path_buf(),
+                    source: mapping.source_path.join(&entry.relative),
                     dest,
-                    size,
+                    size: entry.size,
+                    mtime: entry.mtime,
                     xdg_dir: mapping.xdg_dir,
+                    snapshot: None,
                 });
             }
         }
     }
 
-    Ok(CopyPlan {
+    let mut dest_roots = HashSet::new();
+    let mut notes = Vec::new();
+    if !mappings.iter().all(|m| dest_roots.insert(&m.dest_path)) {
+        let mut seen = HashSet::new();
+        dirs.retain(|d| seen.insert(d.dest.clone()));
+        (files, notes) = merge_files(files)?;
+    }
+
+    let total_bytes = files.iter().map(|f| f.size).sum();
+    Ok((
+        CopyPlan {
+            dirs,
+            files,
+            total_bytes,
+        },
+        notes,
+    ))
+}
+
+fn merge_files(files: Vec<CopyOp>) -> io::Result<(Vec<CopyOp>, Vec<MergeNote>)> {
+    let mut order = Vec::new();
+    let mut by_dest: HashMap<PathBuf, Vec<CopyOp>> = HashMap::new();
+    for op in files {
+        let versions = by_dest.entry(op.dest.clone()).or_default();
+        if versions.is_empty() {
+            order.push(op.dest.clone());
+        }
+        versions.push(op);
+    }
+
+    let mut merged = Vec::new();
+    let mut notes = Vec::new();
+    for dest in order {
+        let versions = by_dest.remove(&dest).unwrap();
+        if versions.len() == 1 {
+            merged.extend(versions);
+            continue;
+        }
+        let (kept, outcome) = pick_versions(versions)?;
+        notes.push(MergeNote {
+            dest,
+            kept: kept.iter().map(|op| op.source.clone()).collect(),
+            outcome,
+        });
+        merged.extend(kept);
+    }
+    Ok((merged, notes))
+}
+
+/// Decide which copies of one destination to plan.
+fn pick_versions(versions: Vec<CopyOp>) -> io::Result<(Vec<CopyOp>, MergeOutcome)> {
+    let copies = versions.len();
+
+    // One representative per distinct content, preferring the newest copy
+    let mut distinct: Vec<CopyOp> = Vec::new();
+    for op in versions {
+        let mut same = None;
+        for (i, d) in distinct.iter().enumerate() {
+            if d.size == op.size && contents_identical(&d.source, &op.source)? {
+                same = Some(i);
+                break;
+            }
+        }
+        match same {
+            Some(i) if op.mtime > distinct[i].mtime => distinct[i] = op,
+            Some(_) => {}
+            None => distinct.push(op),
+        }
+    }
+    if distinct.len() == 1 {
+        return Ok((distinct, MergeOutcome::Identical { copies }));
+    }
+
+    // Newest first; unknown mtimes sort last
+    distinct.sort_by_key(|op| std::cmp::Reverse(op.mtime));
+    let newest = distinct[0].mtime;
+    let tied = distinct.iter().filter(|op| op.mtime == newest).count();
+    if newest.is_some() && tied == 1 {
+        let older = distinct.len() - 1;
+        distinct.truncate(1);
+        return Ok((distinct, MergeOutcome::Newest { older }));
+    }
+    distinct.truncate(tied);
+    Ok((distinct, MergeOutcome::Divergent { versions: tied }))
+}
+
+/// Keep only the parts of a plan at or under `only`, given as paths
+/// relative to `home_dir` (absolute paths inside it work too).
+///
+/// Directories above a selected path are kept so it can be created.
+pub fn filter_plan(plan: &CopyPlan, home_dir: &Path, only: &[PathBuf]) -> CopyPlan {
+    let only: Vec<PathBuf> = only.iter().map(|p| home_dir.join(p)).collect();
+    let selected = |dest: &Path| only.iter().any(|o| dest.starts_with(o));
+
+    let files: Vec<CopyOp> = plan
+        .files
+        .iter()
+        .filter(|f| selected(&f.dest))
+        .cloned()
+        .collect();
+    let dirs = plan
+        .dirs
+        .iter()
+        .filter(|d| selected(&d.dest) || only.iter().any(|o| o.starts_with(&d.dest)))
+        .cloned()
+        .collect();
+
+    CopyPlan {
         dirs,
+        total_bytes: files.iter().map(|f| f.size).sum(),
         files,
-        total_bytes,
-    })
+    }
+}
+
+/// The `only` paths that select nothing in the plan.
+pub fn unmatched_paths<'a>(
+    plan: &CopyPlan,
+    home_dir: &Path,
+    only: &'a [PathBuf],
+) -> Vec<&'a PathBuf> {
+    only.iter()
+        .filter(|o| {
+            let o = home_dir.join(o);
+            !plan.files.iter().any(|f| f.dest.starts_with(&o))
+                && !plan.dirs.iter().any(|d| d.dest.starts_with(&o))
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -54,7 +250,6 @@ mod tests {
     use super::*;
     use crate::types::XdgDir;
     use std::fs;
-    use std::path::PathBuf;
     use tempfile::tempdir;
 
     fn mapping(xdg_dir: XdgDir, source: PathBuf, dest: PathBuf) -> DetectedMapping {
@@ -167,4 +362,131 @@ mod tests {
 
         assert_eq!(plan.total_bytes, 10);
     }
+
+    #[test]
+    fn filters_plan_to_selected_paths() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let pics = backup.path().join("Pictures");
+        fs::create_dir_all(pics.join("2019/summer")).unwrap();
+        fs::create_dir_all(pics.join("2020")).unwrap();
+        fs::write(pics.join("2019/summer/a.jpg"), "aa").unwrap();
+        fs::write(pics.join("2019/b.jpg"), "bbb").unwrap();
+        fs::write(pics.join("2020/c.jpg"), "c").unwrap();
+        fs::write(pics.join("top.jpg"), "t").unwrap();
+
+        let m = mapping(XdgDir::Pictures, pics, home.path().join("Pictures"));
+        let plan = build_plan(&[m]).unwrap();
+        let only = [
+            PathBuf::from("Pictures/2019"),
+            home.path().join("Pictures/top.jpg"),
+        ];
+        let filtered = filter_plan(&plan, home.path(), &only);
+
+        let mut files: Vec<_> = filtered
+            .files
+            .iter()
+            .map(|f| f.dest.strip_prefix(home.path()).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "Pictures/2019/b.jpg",
+                "Pictures/2019/summer/a.jpg",
+                "Pictures/top.jpg"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(filtered.total_bytes, 6);
+        // Parents are kept so the selection can be created; 2020 is not
+        let dirs: Vec<_> = filtered.dirs.iter().map(|d| d.dest.clone()).collect();
+        assert!(dirs.contains(&home.path().join("Pictures")));
+        assert!(dirs.contains(&home.path().join("Pictures/2019/summer")));
+        assert!(!dirs.contains(&home.path().join("Pictures/2020")));
+
+        let typo = [PathBuf::from("Pictures/2091")];
+        assert_eq!(unmatched_paths(&plan, home.path(), &typo), vec![&typo[0]]);
+        assert!(unmatched_paths(&plan, home.path(), &only).is_empty());
+    }
+
+    #[test]
+    fn merges_duplicate_folders_into_one_destination() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let a = backup.path().join("Documents");
+        let b = backup.path().join("laptop/home/Documents");
+        let set_mtime = |path: &Path, secs: u64| {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        for dir in [&a, &b] {
+            fs::create_dir_all(dir.join("sub")).unwrap();
+            fs::write(dir.join("same.txt"), "same").unwrap();
+        }
+        fs::write(a.join("only-a.txt"), "a").unwrap();
+        fs::write(b.join("sub/only-b.txt"), "b").unwrap();
+        fs::write(a.join("edited.txt"), "old").unwrap();
+        set_mtime(&a.join("edited.txt"), 1_000);
+        fs::write(b.join("edited.txt"), "newer").unwrap();
+        set_mtime(&b.join("edited.txt"), 2_000);
+        fs::write(a.join("split.txt"), "left").unwrap();
+        fs::write(b.join("split.txt"), "right").unwrap();
+        set_mtime(&a.join("split.txt"), 3_000);
+        set_mtime(&b.join("split.txt"), 3_000);
+
+        let dest = home.path().join("Documents");
+        let mappings = [
+            mapping(XdgDir::Documents, a.clone(), dest.clone()),
+            mapping(XdgDir::Documents, b.clone(), dest.clone()),
+        ];
+        let (plan, notes) = build_merged_plan(&mappings).unwrap();
+
+        let sources: Vec<&Path> = plan.files.iter().map(|f| f.source.as_path()).collect();
+        assert_eq!(plan.files.len(), 6);
+        assert!(sources.contains(&a.join("only-a.txt").as_path()));
+        assert!(sources.contains(&b.join("sub/only-b.txt").as_path()));
+        assert!(sources.contains(&b.join("edited.txt").as_path()));
+        assert!(!sources.contains(&a.join("edited.txt").as_path()));
+        assert_eq!(
+            plan.files
+                .iter()
+                .filter(|f| f.dest == dest.join("same.txt"))
+                .count(),
+            1
+        );
+        assert_eq!(
+            plan.files
+                .iter()
+                .filter(|f| f.dest == dest.join("split.txt"))
+                .count(),
+            2
+        );
+        assert_eq!(plan.total_bytes, 4 + 1 + 1 + 5 + 4 + 5);
+        assert_eq!(plan.dirs.iter().filter(|d| d.dest == dest).count(), 1);
+
+        let outcome = |name: &str| {
+            notes
+                .iter()
+                .find(|n| n.dest == dest.join(name))
+                .map(|n| n.outcome.clone())
+        };
+        assert_eq!(
+            outcome("same.txt"),
+            Some(MergeOutcome::Identical { copies: 2 })
+        );
+        assert_eq!(
+            outcome("edited.txt"),
+            Some(MergeOutcome::Newest { older: 1 })
+        );
+        assert_eq!(
+            outcome("split.txt"),
+            Some(MergeOutcome::Divergent { versions: 2 })
+        );
+        assert_eq!(outcome("only-a.txt"), None);
+    }
 }
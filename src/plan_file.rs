@@ -0,0 +1,228 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{CopyOp, CopyPlan, DirOp};
+
+/// Bumped whenever the on-disk layout changes incompatibly.
+const FORMAT_VERSION: u32 = 1;
+
+/// On-disk representation of a copy plan.
+///
+/// `total_bytes` is deliberately not stored: reviewers may trim entries,
+/// so it is recomputed from `files` on load.
+#[derive(Serialize, Deserialize)]
+struct PlanFile {
+    version: u32,
+    /// The home directory the plan restores into, which rule globs and
+    /// `--only` paths are relative to.
+    home_dir: PathBuf,
+    dirs: Vec<DirOp>,
+    files: Vec<CopyOp>,
+}
+
+/// Write a copy plan into `home_dir` to `path` as pretty-printed JSON.
+pub fn save_plan(plan: &CopyPlan, home_dir: &Path, path: &Path) -> io::Result<()> {
+    let file = PlanFile {
+        version: FORMAT_VERSION,
+        home_dir: std::path::absolute(home_dir)?,
+        dirs: plan.dirs.clone(),
+        files: plan.files.clone(),
+    };
+    let mut json = serde_json::to_string_pretty(&file)?;
+    json.push('\n');
+    fs::write(path, json)
+}
+
+/// Read a copy plan previously written by [`save_plan`], with the home
+/// directory it restores into.
+pub fn load_plan(path: &Path) -> io::Result<(CopyPlan, PathBuf)> {
+    let json = fs::read_to_string(path)?;
+    let file: PlanFile = serde_json::from_str(&json)?;
+    if file.version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported plan version {} (expected {FORMAT_VERSION})",
+                file.version
+            ),
+        ));
+    }
+    let total_bytes = file.files.iter().map(|f| f.size).sum();
+    let plan = CopyPlan {
+        dirs: file.dirs,
+        files: file.files,
+        total_bytes,
+    };
+    Ok((plan, file.home_dir))
+}
+
+/// How a source file differs from what the plan recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    Missing,
+    SizeChanged { planned: u64, actual: u64 },
+    MtimeChanged,
+}
+
+/// A planned source file that no longer matches the plan.
+#[derive(Debug, Clone)]
+pub struct StaleSource {
+    pub source: PathBuf,
+    pub drift: Drift,
+}
+
+impl fmt::Display for StaleSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.drift {
+            Drift::Missing => write!(f, "{}: missing", self.source.display()),
+            Drift::SizeChanged { planned, actual } => write!(
+                f,
+                "{}: size changed ({planned} → {actual} bytes)",
+                self.source.display()
+            ),
+            Drift::MtimeChanged => write!(f, "{}: modified since planning", self.source.display()),
+        }
+    }
+}
+
+/// Check that every source file still has the size and mtime recorded in the plan.
+///
+/// Files without a recorded mtime are checked by size only.
+pub fn verify_sources(plan: &CopyPlan) -> Vec<StaleSource> {
+    let mut stale = Vec::new();
+    for op in &plan.files {
+        let drift = match fs::metadata(&op.source) {
+            Err(_) => Some(Drift::Missing),
+            Ok(meta) if meta.len() != op.size => Some(Drift::SizeChanged {
+                planned: op.size,
+                actual: meta.len(),
+            }),
+            Ok(meta) => match (op.mtime, meta.modified().ok()) {
+                (Some(planned), Some(actual)) if planned != actual => Some(Drift::MtimeChanged),
+                _ => None,
+            },
+        };
+        if let Some(drift) = drift {
+            stale.push(StaleSource {
+                source: op.source.clone(),
+                drift,
+            });
+        }
+    }
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::build_plan;
+    use crate::types::{DetectedMapping, XdgDir};
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    fn plan_for(backup: &Path, home: &Path) -> CopyPlan {
+        let docs = backup.join("Documents");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("a.txt"), "aaaa").unwrap();
+        fs::write(docs.join("b.txt"), "bb").unwrap();
+        build_plan(&[DetectedMapping {
+            xdg_dir: XdgDir::Documents,
+            source_path: docs,
+            dest_path: home.join("Documents"),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_plan_through_file() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let plan = plan_for(backup.path(), home.path());
+        let path = backup.path().join("restore.plan");
+
+        save_plan(&plan, home.path(), &path).unwrap();
+        let (loaded, home_dir) = load_plan(&path).unwrap();
+
+        assert_eq!(home_dir, home.path());
+        assert_eq!(loaded.files.len(), 2);
+        assert_eq!(loaded.dirs.len(), 1);
+        assert_eq!(loaded.total_bytes, 6);
+        assert_eq!(loaded.files[0].source, plan.files[0].source);
+        assert_eq!(loaded.files[0].mtime, plan.files[0].mtime);
+        assert!(verify_sources(&loaded).is_empty());
+    }
+
+    #[test]
+    fn recomputes_total_after_trimming() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let mut plan = plan_for(backup.path(), home.path());
+        plan.files.retain(|f| f.source.ends_with("a.txt"));
+        let path = backup.path().join("restore.plan");
+
+        save_plan(&plan, home.path(), &path).unwrap();
+        let (loaded, _) = load_plan(&path).unwrap();
+
+        assert_eq!(loaded.files.len(), 1);
+        assert_eq!(loaded.total_bytes, 4);
+    }
+
+    #[test]
+    fn rejects_plans_without_a_home() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("restore.plan");
+        fs::write(&path, r#"{"version": 1, "dirs": [], "files": []}"#).unwrap();
+
+        let err = load_plan(&path).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("home_dir"));
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("restore.plan");
+        fs::write(
+            &path,
+            r#"{"version": 99, "home_dir": "/home/joe", "dirs": [], "files": []}"#,
+        )
+        .unwrap();
+
+        let err = load_plan(&path).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn detects_missing_resized_and_touched_sources() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let mut plan = plan_for(backup.path(), home.path());
+        plan.files.sort_by(|a, b| a.source.cmp(&b.source));
+
+        fs::write(backup.path().join("Documents/a.txt"), "longer now").unwrap();
+        fs::remove_file(backup.path().join("Documents/b.txt")).unwrap();
+        let stale = verify_sources(&plan);
+        let drifts: Vec<_> = stale.iter().map(|s| s.drift.clone()).collect();
+        assert_eq!(
+            drifts,
+            vec![
+                Drift::SizeChanged {
+                    planned: 4,
+                    actual: 10
+                },
+                Drift::Missing
+            ]
+        );
+
+        fs::write(backup.path().join("Documents/a.txt"), "aaaa").unwrap();
+        plan.files[0].mtime = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        let stale = verify_sources(&plan);
+        assert_eq!(stale[0].drift, Drift::MtimeChanged);
+    }
+}
@@ -0,0 +1,581 @@
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use similar::TextDiff;
+
+use crate::report::format_bytes;
+use crate::types::Conflict;
+
+/// How much of a file to sniff when deciding whether it is text.
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// Files larger than this are never diffed.
+const MAX_DIFF_BYTES: u64 = 1024 * 1024;
+
+/// Diff output is cut off after this many lines.
+const MAX_DIFF_LINES: usize = 40;
+
+/// What kind of content a file holds, as far as can be cheaply determined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileKind {
+    Text,
+    Image {
+        format: &'static str,
+        width: usize,
+        height: usize,
+    },
+    Media {
+        format: &'static str,
+        duration: Option<Duration>,
+    },
+    Binary,
+}
+
+impl std::fmt::Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileKind::Text => f.write_str("text"),
+            FileKind::Image {
+                format,
+                width,
+                height,
+            } => write!(f, "{format} image, {width}×{height}"),
+            FileKind::Media {
+                format,
+                duration: Some(d),
+            } => {
+                let secs = d.as_secs();
+                write!(f, "{format}, {}:{:02}", secs / 60, secs % 60)
+            }
+            FileKind::Media {
+                format,
+                duration: None,
+            } => f.write_str(format),
+            FileKind::Binary => f.write_str("binary"),
+        }
+    }
+}
+
+/// Size, mtime and content kind of one side of a conflict.
+#[derive(Debug, Clone)]
+pub struct FileSummary {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub kind: FileKind,
+}
+
+/// Gather a summary of the file at `path`.
+pub fn summarize(path: &Path) -> io::Result<FileSummary> {
+    let metadata = fs::metadata(path)?;
+    Ok(FileSummary {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        kind: detect_kind(path)?,
+    })
+}
+
+/// Compare two files byte for byte. Hard links to the same file (as in
+/// snapshot backups) are identical without being read.
+pub fn contents_identical(a: &Path, b: &Path) -> io::Result<bool> {
+    let (meta_a, meta_b) = (fs::metadata(a)?, fs::metadata(b)?);
+    if meta_a.dev() == meta_b.dev() && meta_a.ino() == meta_b.ino() {
+        return Ok(true);
+    }
+    if meta_a.len() != meta_b.len() {
+        return Ok(false);
+    }
+
+    let mut fa = File::open(a)?;
+    let mut fb = File::open(b)?;
+    let mut buf_a = vec![0u8; 64 * 1024];
+    let mut buf_b = vec![0u8; 64 * 1024];
+    loop {
+        let n = read_full(&mut fa, &mut buf_a)?;
+        let m = read_full(&mut fb, &mut buf_b)?;
+        if n != m || buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Whether a conflict's two files have the same contents.
+pub fn is_identical(conflict: &Conflict) -> bool {
+    contents_identical(&conflict.original_path, &conflict.restore_path).unwrap_or(false)
+}
+
+/// Unified diff of two text files, or `None` if either is binary or too large.
+pub fn unified_diff(original: &Path, restore: &Path) -> io::Result<Option<String>> {
+    for path in [original, restore] {
+        if fs::metadata(path)?.len() > MAX_DIFF_BYTES || detect_kind(path)? != FileKind::Text {
+            return Ok(None);
+        }
+    }
+
+    let old = fs::read_to_string(original)?;
+    let new = fs::read_to_string(restore)?;
+    let diff = TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .context_radius(3)
+        .header("original", "restore")
+        .to_string();
+
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut out = String::new();
+    for line in lines.iter().take(MAX_DIFF_LINES) {
+        writeln!(out, "{line}").unwrap();
+    }
+    if lines.len() > MAX_DIFF_LINES {
+        writeln!(out, "... ({} more lines)", lines.len() - MAX_DIFF_LINES).unwrap();
+    }
+    Ok(Some(out))
+}
+
+/// Describe both sides of a conflict for the interactive resolver.
+pub fn format_conflict_preview(conflict: &Conflict) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", conflict.original_path.display()).unwrap();
+
+    let original = summarize(&conflict.original_path);
+    let restore = summarize(&conflict.restore_path);
+    for (label, summary) in [("original", &original), ("restore", &restore)] {
+        match summary {
+            Ok(s) => writeln!(
+                out,
+                "  {label:<9} {:>10}  {}  {}",
+                format_bytes(s.size),
+                s.modified
+                    .map_or_else(|| "unknown".to_string(), format_mtime),
+                s.kind
+            )
+            .unwrap(),
+            Err(e) => writeln!(out, "  {label:<9} unreadable ({e})").unwrap(),
+        }
+    }
+
+    let mut notes = Vec::new();
+    if let (Ok(o), Ok(r)) = (&original, &restore) {
+        if let (Some(om), Some(rm)) = (o.modified, r.modified) {
+            notes.push(match rm.cmp(&om) {
+                std::cmp::Ordering::Greater => "restore is newer",
+                std::cmp::Ordering::Less => "original is newer",
+                std::cmp::Ordering::Equal => "same mtime",
+            });
+        }
+    }
+    notes.push(if is_identical(conflict) {
+        "contents identical"
+    } else {
+        "contents differ"
+    });
+    writeln!(out, "  {}", notes.join(", ")).unwrap();
+
+    if let Ok(Some(diff)) = unified_diff(&conflict.original_path, &conflict.restore_path) {
+        for line in diff.lines() {
+            writeln!(out, "    {line}").unwrap();
+        }
+    }
+
+    out
+}
+
+/// Format a timestamp as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_mtime(time: SystemTime) -> String {
+    let rfc = humantime::format_rfc3339_seconds(time).to_string();
+    rfc.trim_end_matches('Z').replacen('T', " ", 1)
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn detect_kind(path: &Path) -> io::Result<FileKind> {
+    let mut file = File::open(path)?;
+    let mut head = vec![0u8; SNIFF_LEN];
+    let n = read_full(&mut file, &mut head)?;
+    head.truncate(n);
+
+    if looks_like_text(&head) {
+        return Ok(FileKind::Text);
+    }
+    if let Some(kind) = image_kind(path, &head) {
+        return Ok(kind);
+    }
+    if let Some(kind) = media_kind(&mut file, &head) {
+        return Ok(kind);
+    }
+    Ok(FileKind::Binary)
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // A multi-byte character cut off by the sniff window is still text
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+fn image_kind(path: &Path, head: &[u8]) -> Option<FileKind> {
+    use imagesize::ImageType;
+
+    let format = match imagesize::image_type(head).ok()? {
+        ImageType::Png => "PNG",
+        ImageType::Jpeg => "JPEG",
+        ImageType::Gif => "GIF",
+        ImageType::Webp => "WebP",
+        ImageType::Bmp => "BMP",
+        ImageType::Tiff => "TIFF",
+        ImageType::Heif(_) => "HEIF",
+        ImageType::Jxl => "JPEG XL",
+        _ => return None,
+    };
+    let size = imagesize::size(path).ok()?;
+    Some(FileKind::Image {
+        format,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+fn media_kind(file: &mut File, head: &[u8]) -> Option<FileKind> {
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WAVE" {
+        return Some(FileKind::Media {
+            format: "WAV audio",
+            duration: wav_duration(head),
+        });
+    }
+    if head.starts_with(b"fLaC") {
+        return Some(FileKind::Media {
+            format: "FLAC audio",
+            duration: flac_duration(head),
+        });
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some(FileKind::Media {
+            format: "MP4/QuickTime",
+            duration: mp4_duration(file).ok().flatten(),
+        });
+    }
+    let format = if head.starts_with(b"ID3") || head.starts_with(&[0xFF, 0xFB]) {
+        "MP3 audio"
+    } else if head.starts_with(b"OggS") {
+        "Ogg media"
+    } else if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        "Matroska/WebM"
+    } else {
+        return None;
+    };
+    Some(FileKind::Media {
+        format,
+        duration: None,
+    })
+}
+
+fn u32_le(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn u32_be(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn u64_be(b: &[u8]) -> u64 {
+    u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+}
+
+/// Walk RIFF chunks for the `fmt ` byte rate and the `data` length.
+fn wav_duration(head: &[u8]) -> Option<Duration> {
+    let mut pos = 12;
+    let mut byte_rate = None;
+    while pos + 8 <= head.len() {
+        let id = &head[pos..pos + 4];
+        let len = u32_le(&head[pos + 4..]) as usize;
+        let body = pos + 8;
+        if id == b"fmt " && body + 12 <= head.len() {
+            byte_rate = Some(u32_le(&head[body + 8..]));
+        } else if id == b"data" {
+            let rate = byte_rate.filter(|&r| r > 0)?;
+            return Some(Duration::from_secs_f64(len as f64 / f64::from(rate)));
+        }
+        pos = body + len + (len & 1);
+    }
+    None
+}
+
+/// Read sample rate and total samples from the `STREAMINFO` block.
+fn flac_duration(head: &[u8]) -> Option<Duration> {
+    // "fLaC", 4-byte block header, then STREAMINFO; the fields we need
+    // live in bytes 10..18 of the block.
+    let info = head.get(8 + 10..8 + 18)?;
+    let sample_rate =
+        (u32::from(info[0]) << 12) | (u32::from(info[1]) << 4) | (u32::from(info[2]) >> 4);
+    let total_samples = (u64::from(info[3] & 0x0F) << 32) | u64::from(u32_be(&info[4..]));
+    if sample_rate == 0 || total_samples == 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        total_samples as f64 / f64::from(sample_rate),
+    ))
+}
+
+/// Find `moov/mvhd` by skipping over top-level boxes.
+fn mp4_duration(file: &mut File) -> io::Result<Option<Duration>> {
+    let len = file.metadata()?.len();
+    let mut pos = 0;
+    while len.saturating_sub(pos) >= 8 {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+        let mut size = u64::from(u32_be(&header));
+        let mut header_len = 8;
+        if size == 1 {
+            file.read_exact(&mut header[8..])?;
+            size = u64_be(&header[8..]);
+            header_len = 16;
+        } else if size == 0 {
+            size = len - pos;
+        }
+        if size < header_len {
+            return Ok(None);
+        }
+
+        match &header[4..8] {
+            b"moov" => {
+                let mut moov =
+                    vec![0u8; usize::try_from(size - header_len).unwrap_or(0).min(1 << 20)];
+                let n = read_full(file, &mut moov)?;
+                return Ok(mvhd_duration(&moov[..n]));
+            }
+            _ => match pos.checked_add(size) {
+                Some(next) => pos = next,
+                None => return Ok(None),
+            },
+        }
+    }
+    Ok(None)
+}
+
+fn mvhd_duration(moov: &[u8]) -> Option<Duration> {
+    let mut pos = 0;
+    while moov.len().saturating_sub(pos) >= 8 {
+        let size = u32_be(&moov[pos..]) as usize;
+        if &moov[pos + 4..pos + 8] == b"mvhd" {
+            let body = moov.get(pos + 8..)?;
+            let (timescale, duration) = if *body.first()? == 1 {
+                (u32_be(body.get(20..24)?), u64_be(body.get(24..32)?))
+            } else {
+                (
+                    u32_be(body.get(12..16)?),
+                    u64::from(u32_be(body.get(16..20)?)),
+                )
+            };
+            if timescale == 0 {
+                return None;
+            }
+            return Duration::try_from_secs_f64(duration as f64 / f64::from(timescale)).ok();
+        }
+        if size < 8 {
+            return None;
+        }
+        pos = pos.checked_add(size)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::XdgDir;
+    use tempfile::tempdir;
+
+    fn conflict(dir: &Path, name: &str, original: &[u8], restore: &[u8]) -> Conflict {
+        let original_path = dir.join(name);
+        let restore_path = dir.join(format!("{name}.restore"));
+        fs::write(&original_path, original).unwrap();
+        fs::write(&restore_path, restore).unwrap();
+        Conflict {
+            restore_path,
+            original_path,
+            size: restore.len() as u64,
+            original_mtime: None,
+            xdg_dir: XdgDir::Documents,
+        }
+    }
+
+    fn wav(seconds: u32) -> Vec<u8> {
+        let byte_rate: u32 = 8000;
+        let data_len = byte_rate * seconds;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        out.extend_from_slice(&1u16.to_le_bytes()); // mono
+        out.extend_from_slice(&8000u32.to_le_bytes()); // sample rate
+        out.extend_from_slice(&byte_rate.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // block align
+        out.extend_from_slice(&8u16.to_le_bytes()); // bits per sample
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        out.resize(out.len() + data_len as usize, 0x80);
+        out
+    }
+
+    #[test]
+    fn detects_identical_and_differing_contents() {
+        let dir = tempdir().unwrap();
+        let same = conflict(dir.path(), "same.txt", b"hello", b"hello");
+        let differ = conflict(dir.path(), "differ.txt", b"hello", b"world");
+        let longer = conflict(dir.path(), "longer.txt", b"hello", b"hello!");
+
+        assert!(is_identical(&same));
+        assert!(!is_identical(&differ));
+        assert!(!is_identical(&longer));
+    }
+
+    #[test]
+    fn hard_links_are_identical() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("daily.1"), "snapshot").unwrap();
+        fs::hard_link(dir.path().join("daily.1"), dir.path().join("daily.0")).unwrap();
+
+        assert!(
+            contents_identical(&dir.path().join("daily.0"), &dir.path().join("daily.1")).unwrap()
+        );
+    }
+
+    #[test]
+    fn classifies_text_binary_and_images() {
+        let dir = tempdir().unwrap();
+        let text = dir.path().join("notes.txt");
+        let binary = dir.path().join("blob.bin");
+        let png = dir.path().join("pixel.png");
+        fs::write(&text, "plain text, ünïcode").unwrap();
+        fs::write(&binary, [0u8, 1, 2, 3, 0xFF]).unwrap();
+        // Minimal PNG header with a 3×2 IHDR
+        let mut png_bytes = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png_bytes.extend_from_slice(&3u32.to_be_bytes());
+        png_bytes.extend_from_slice(&2u32.to_be_bytes());
+        png_bytes.extend_from_slice(&[8, 2, 0, 0, 0, 0, 0, 0, 0]);
+        fs::write(&png, png_bytes).unwrap();
+
+        assert_eq!(summarize(&text).unwrap().kind, FileKind::Text);
+        assert_eq!(summarize(&binary).unwrap().kind, FileKind::Binary);
+        assert_eq!(
+            summarize(&png).unwrap().kind,
+            FileKind::Image {
+                format: "PNG",
+                width: 3,
+                height: 2
+            }
+        );
+    }
+
+    #[test]
+    fn reads_wav_duration() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("clip.wav");
+        fs::write(&path, wav(3)).unwrap();
+
+        assert_eq!(
+            summarize(&path).unwrap().kind,
+            FileKind::Media {
+                format: "WAV audio",
+                duration: Some(Duration::from_secs(3))
+            }
+        );
+    }
+
+    #[test]
+    fn reads_mp4_duration_from_mvhd() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&16u32.to_be_bytes());
+        bytes.extend_from_slice(b"ftypisom\0\0\0\0");
+        bytes.extend_from_slice(&8u32.to_be_bytes());
+        bytes.extend_from_slice(b"free");
+        let mut mvhd = Vec::new();
+        mvhd.extend_from_slice(&[0, 0, 0, 0]); // version 0, flags
+        mvhd.extend_from_slice(&[0; 8]); // creation + modification
+        mvhd.extend_from_slice(&1000u32.to_be_bytes()); // timescale
+        mvhd.extend_from_slice(&90_000u32.to_be_bytes()); // 90 s
+        bytes.extend_from_slice(&(16 + u32::try_from(mvhd.len()).unwrap()).to_be_bytes());
+        bytes.extend_from_slice(b"moov");
+        bytes.extend_from_slice(&(8 + u32::try_from(mvhd.len()).unwrap()).to_be_bytes());
+        bytes.extend_from_slice(b"mvhd");
+        bytes.extend_from_slice(&mvhd);
+        fs::write(&path, bytes).unwrap();
+
+        let kind = summarize(&path).unwrap().kind;
+
+        assert_eq!(kind.to_string(), "MP4/QuickTime, 1:30");
+
+        // Truncated and oversized boxes give no duration rather than a panic
+        assert_eq!(mvhd_duration(b"\0\0\0\x08mvhd"), None);
+        assert_eq!(mvhd_duration(b"\0\0\0\x0cmvhd\0\0\0\0"), None);
+        let mut huge = u32::MAX.to_be_bytes().to_vec();
+        huge.extend_from_slice(b"trak\0\0\0\0");
+        assert_eq!(mvhd_duration(&huge), None);
+        let mut file = Vec::new();
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"free");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+        fs::write(&path, file).unwrap();
+        assert_eq!(mp4_duration(&mut File::open(&path).unwrap()).unwrap(), None);
+    }
+
+    #[test]
+    fn diffs_text_files_only() {
+        let dir = tempdir().unwrap();
+        let text = conflict(dir.path(), "todo.txt", b"a\nb\nc\n", b"a\nB\nc\n");
+        let bin = conflict(dir.path(), "data.bin", b"\0\x01", b"\0\x02");
+
+        let diff = unified_diff(&text.original_path, &text.restore_path)
+            .unwrap()
+            .unwrap();
+        assert!(diff.contains("-b"));
+        assert!(diff.contains("+B"));
+        assert!(unified_diff(&bin.original_path, &bin.restore_path)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn preview_shows_both_sides_and_diff() {
+        let dir = tempdir().unwrap();
+        let c = conflict(dir.path(), "todo.txt", b"milk\n", b"milk\neggs\n");
+
+        let preview = format_conflict_preview(&c);
+
+        assert!(preview.contains("original"));
+        assert!(preview.contains("restore"));
+        assert!(preview.contains("5 B"));
+        assert!(preview.contains("10 B"));
+        assert!(preview.contains("contents differ"));
+        assert!(preview.contains("+eggs"));
+    }
+
+    #[test]
+    fn formats_mtime_in_utc() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(format_mtime(t), "2020-09-13 12:26:40");
+    }
+}
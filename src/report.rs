@@ -1,8 +1,16 @@
 use std::collections::HashMap;
 use std::fmt::Write;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use crate::types::{CopyPlan, CopyResult, XdgDir};
+use crate::candidates::{Candidate, Comparison};
+use crate::copy::Owner;
+use crate::plan::{MergeNote, MergeOutcome};
+use crate::preview::format_mtime;
+use crate::rules::{PlannedDecision, RuleSet};
+use crate::snapshot::Snapshot;
+use crate::types::{CopyOp, CopyPlan, CopyResult, DetectedMapping, XdgDir};
+use crate::users::IdMap;
 
 /// Format a summary report of the copy operation.
 pub fn format_report(result: &CopyResult, elapsed: Duration) -> String {
@@ -144,6 +152,298 @@ pub fn format_dry_run_report(plan: &CopyPlan) -> String {
     out
 }
 
+/// Format what the conflict rules would decide for a plan's predicted conflicts.
+pub fn format_rule_preview(rules: &RuleSet, decisions: &[PlannedDecision]) -> String {
+    let mut out = String::new();
+    if decisions.is_empty() {
+        return out;
+    }
+
+    writeln!(out, "\nConflict rules:").unwrap();
+    let mut per_rule = vec![0usize; rules.rules().len()];
+    let mut unmatched = 0;
+    for d in decisions {
+        match d.decision {
+            Some(decision) => per_rule[decision.rule] += 1,
+            None => unmatched += 1,
+        }
+    }
+    for (i, (rule, count)) in rules.rules().iter().zip(&per_rule).enumerate() {
+        writeln!(
+            out,
+            "  {}. {:<30} {count} conflicts",
+            i + 1,
+            rule.to_string()
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "  {:<33} {unmatched} conflicts (will ask)",
+        "unmatched"
+    )
+    .unwrap();
+
+    writeln!(out, "\nRule decisions:").unwrap();
+    let shown = if decisions.len() <= 10 {
+        decisions.len()
+    } else {
+        5
+    };
+    for d in &decisions[..shown] {
+        match d.decision {
+            Some(decision) => writeln!(
+                out,
+                "  {} → {} (rule {})",
+                d.dest.display(),
+                decision.resolution,
+                decision.rule + 1
+            ),
+            None => writeln!(out, "  {} → ask", d.dest.display()),
+        }
+        .unwrap();
+    }
+    if shown < decisions.len() {
+        writeln!(out, "  ... and {} more", decisions.len() - shown).unwrap();
+    }
+
+    out
+}
+
+/// Describe merged folders for a dry run: which copy supplies each file and
+/// how files present in several copies were handled.
+pub fn format_merge_report(
+    plan: &CopyPlan,
+    mappings: &[DetectedMapping],
+    notes: &[MergeNote],
+) -> String {
+    let mut out = String::new();
+    let mut dests: Vec<&PathBuf> = Vec::new();
+    for m in mappings {
+        if !dests.contains(&&m.dest_path) {
+            dests.push(&m.dest_path);
+        }
+    }
+
+    for dest in dests {
+        let sources: Vec<&Path> = mappings
+            .iter()
+            .filter(|m| &m.dest_path == dest)
+            .map(|m| m.source_path.as_path())
+            .collect();
+        if sources.len() < 2 {
+            continue;
+        }
+
+        let from: Vec<(&Path, &Path)> = plan
+            .files
+            .iter()
+            .filter_map(|f| {
+                let source = sources.iter().find(|s| f.source.starts_with(s))?;
+                Some((f.dest.strip_prefix(dest).unwrap_or(&f.dest), *source))
+            })
+            .collect();
+
+        writeln!(
+            out,
+            "\nMerging {} copies into {}:",
+            sources.len(),
+            dest.display()
+        )
+        .unwrap();
+        for source in &sources {
+            let count = from.iter().filter(|(_, s)| s == source).count();
+            writeln!(out, "  {}: {count} files", source.display()).unwrap();
+        }
+
+        let (mut identical, mut older, mut divergent) = (0, 0, 0);
+        for note in notes.iter().filter(|n| n.dest.starts_with(dest)) {
+            match note.outcome {
+                MergeOutcome::Identical { copies } => identical += copies - 1,
+                MergeOutcome::Newest { older: n } => older += n,
+                MergeOutcome::Divergent { .. } => divergent += 1,
+            }
+        }
+        let mut parts = Vec::new();
+        if identical > 0 {
+            parts.push(format!("{identical} identical copies skipped"));
+        }
+        if older > 0 {
+            parts.push(format!("{older} older versions skipped"));
+        }
+        if divergent > 0 {
+            parts.push(format!(
+                "{divergent} diverging files (extra versions become .restore conflicts)"
+            ));
+        }
+        if !parts.is_empty() {
+            writeln!(out, "  {}", parts.join(", ")).unwrap();
+        }
+
+        let shown = if from.len() <= 10 { from.len() } else { 5 };
+        for (relative, source) in &from[..shown] {
+            writeln!(out, "    {} ← {}", relative.display(), source.display()).unwrap();
+        }
+        if shown < from.len() {
+            writeln!(out, "    ... and {} more", from.len() - shown).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Describe a point-in-time restore for a dry run: how many files each
+/// snapshot supplies, newest snapshot first. Files from older snapshots are
+/// ones missing from the newer snapshots.
+pub fn format_snapshot_report(plan: &CopyPlan, snapshots: &[Snapshot]) -> String {
+    let mut out = String::new();
+    let Some(newest) = snapshots.last() else {
+        return out;
+    };
+    writeln!(
+        out,
+        "\nPoint-in-time restore from {} snapshots up to {} ({}):",
+        snapshots.len(),
+        newest.name,
+        format_mtime(newest.time)
+    )
+    .unwrap();
+
+    for snapshot in snapshots.iter().rev() {
+        let files: Vec<&CopyOp> = plan
+            .files
+            .iter()
+            .filter(|f| f.snapshot.as_deref() == Some(snapshot.name.as_str()))
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        let bytes = files.iter().map(|f| f.size).sum();
+        write!(
+            out,
+            "  {} ({}): {} files, {}",
+            snapshot.name,
+            format_mtime(snapshot.time),
+            files.len(),
+            format_bytes(bytes)
+        )
+        .unwrap();
+        if snapshot.name == newest.name {
+            writeln!(out).unwrap();
+        } else {
+            writeln!(out, ", not in later snapshots").unwrap();
+        }
+    }
+
+    out
+}
+
+/// Describe who restored files will belong to for a dry run, from
+/// [`crate::users::count_owners`]: `uid:gid` in the backup and, where it
+/// differs, on this system. Owners `map` has no entry for are marked.
+pub fn format_ownership_report(counts: &[(Owner, Owner, usize)], map: &IdMap) -> String {
+    let mut out = String::new();
+    if counts.is_empty() {
+        return out;
+    }
+    writeln!(out, "\nOwnership (uid:gid):").unwrap();
+    for &(owner, restored, files) in counts {
+        let old = format!("{}:{}", owner.uid, owner.gid);
+        let new = format!("{}:{}", restored.uid, restored.gid);
+        let note = if map.is_mapped(owner) {
+            ""
+        } else {
+            " (unmapped)"
+        };
+        if old == new {
+            writeln!(out, "  {old:<12} {:<14} {files} files{note}", "kept").unwrap();
+        } else {
+            writeln!(out, "  {old:<12} → {new:<12} {files} files{note}").unwrap();
+        }
+    }
+    if counts.iter().any(|&(owner, _, _)| !map.is_mapped(owner)) {
+        writeln!(
+            out,
+            "  Unmapped ids are in neither the backup's accounts nor --map-owner/--map-group."
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// One-line summary of a duplicate candidate, e.g.
+/// `120 files, 4.2 MiB, 2019-03-01 to 2024-06-30, 85% overlap`.
+pub fn format_candidate(candidate: &Candidate) -> String {
+    let stats = &candidate.stats;
+    if stats.file_count == 0 {
+        return "empty".to_string();
+    }
+    let mut out = format!(
+        "{} file{}, {}",
+        stats.file_count,
+        if stats.file_count == 1 { "" } else { "s" },
+        format_bytes(stats.total_bytes)
+    );
+    let day = |t| format_mtime(t)[..10].to_string();
+    match (stats.oldest, stats.newest) {
+        (Some(oldest), Some(newest)) if day(oldest) != day(newest) => {
+            write!(out, ", {} to {}", day(oldest), day(newest)).unwrap();
+        }
+        (_, Some(newest)) => write!(out, ", {}", day(newest)).unwrap(),
+        _ => {}
+    }
+    write!(out, ", {:.0}% overlap", stats.overlap() * 100.0).unwrap();
+    out
+}
+
+/// Summarize how two folders differ. Lists are abbreviated unless
+/// `detailed` is set.
+pub fn format_comparison(cmp: &Comparison, a: &Path, b: &Path, detailed: bool) -> String {
+    let mut out = String::new();
+    writeln!(out, "A: {}", a.display()).unwrap();
+    writeln!(out, "B: {}", b.display()).unwrap();
+    writeln!(
+        out,
+        "  only in A: {} files ({})",
+        cmp.only_a.len(),
+        format_bytes(cmp.only_a_bytes)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "  only in B: {} files ({})",
+        cmp.only_b.len(),
+        format_bytes(cmp.only_b_bytes)
+    )
+    .unwrap();
+    writeln!(out, "  differ:    {} files", cmp.differing.len()).unwrap();
+    writeln!(out, "  identical: {} files", cmp.identical).unwrap();
+
+    for (title, paths) in [
+        ("Only in A", &cmp.only_a),
+        ("Only in B", &cmp.only_b),
+        ("Differ", &cmp.differing),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+        writeln!(out, "\n{title}:").unwrap();
+        let shown = if detailed || paths.len() <= 10 {
+            paths.len()
+        } else {
+            5
+        };
+        for p in &paths[..shown] {
+            writeln!(out, "  {}", p.display()).unwrap();
+        }
+        if shown < paths.len() {
+            writeln!(out, "  ... and {} more", paths.len() - shown).unwrap();
+        }
+    }
+
+    out
+}
+
 pub fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = 1024 * KB;
@@ -205,6 +505,7 @@ mod tests {
                 restore_path: PathBuf::from("/home/joe/Documents/a.restore.txt"),
                 original_path: PathBuf::from("/home/joe/Documents/a.txt"),
                 size: 50,
+                original_mtime: None,
                 xdg_dir: XdgDir::Documents,
             }],
             errors: vec![CopyError {
@@ -260,6 +561,7 @@ mod tests {
                 restore_path: PathBuf::from(format!("/home/joe/Documents/file{i}.restore.txt")),
                 original_path: PathBuf::from(format!("/home/joe/Documents/file{i}.txt")),
                 size: 10,
+                original_mtime: None,
                 xdg_dir: XdgDir::Documents,
             })
             .collect();
@@ -293,12 +595,15 @@ mod tests {
             dirs: vec![
                 DirOp {
                     dest: docs_dir.clone(),
+                    source: None,
                 },
                 DirOp {
                     dest: docs_dir.join("subdir"),
+                    source: None,
                 },
                 DirOp {
                     dest: music_dir.clone(),
+                    source: None,
                 },
             ],
             files: vec![
@@ -306,19 +611,25 @@ mod tests {
                     source: PathBuf::from("/backup/Documents/existing.txt"),
                     dest: docs_dir.join("existing.txt"),
                     size: 100,
+                    mtime: None,
                     xdg_dir: XdgDir::Documents,
+                    snapshot: None,
                 },
                 CopyOp {
                     source: PathBuf::from("/backup/Documents/new.txt"),
                     dest: docs_dir.join("new.txt"),
                     size: 250,
+                    mtime: None,
                     xdg_dir: XdgDir::Documents,
+                    snapshot: None,
                 },
                 CopyOp {
                     source: PathBuf::from("/backup/Music/song.mp3"),
                     dest: music_dir.join("song.mp3"),
                     size: 5000,
+                    mtime: None,
                     xdg_dir: XdgDir::Music,
+                    snapshot: None,
                 },
             ],
             total_bytes: 5350,
@@ -351,6 +662,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rule_preview_counts_decisions_per_rule() {
+        use crate::conflict::Resolution;
+        use crate::rules::{Decision, Rule};
+
+        let rules = RuleSet::new(vec![
+            "Downloads/**=keep-original".parse::<Rule>().unwrap(),
+            "newer".parse::<Rule>().unwrap(),
+        ]);
+        let decisions = vec![
+            PlannedDecision {
+                dest: PathBuf::from("/home/joe/Downloads/a.iso"),
+                decision: Some(Decision {
+                    rule: 0,
+                    resolution: Resolution::KeepOriginal,
+                }),
+            },
+            PlannedDecision {
+                dest: PathBuf::from("/home/joe/Documents/b.txt"),
+                decision: None,
+            },
+        ];
+
+        let report = format_rule_preview(&rules, &decisions);
+
+        assert!(report.contains("1. Downloads/**=keep-original"));
+        assert!(report.contains("1 conflicts (will ask)"));
+        assert!(report.contains("a.iso → keep original (rule 1)"));
+        assert!(report.contains("b.txt → ask"));
+    }
+
+    #[test]
+    fn ownership_report_shows_mapped_and_kept_owners() {
+        let owner = |uid, gid| Owner { uid, gid };
+        let mut map = IdMap::default();
+        map.users.extend([(1000, 1001), (0, 0)]);
+        map.groups.extend([(1000, 1001), (0, 0)]);
+        let report = format_ownership_report(
+            &[
+                (owner(1000, 1000), owner(1001, 1001), 42),
+                (owner(0, 0), owner(0, 0), 3),
+                (owner(1002, 1002), owner(1001, 1001), 2),
+            ],
+            &map,
+        );
+        assert!(report.contains("1000:1000    → 1001:1001    42 files\n"));
+        assert!(report.contains("0:0          kept           3 files\n"));
+        assert!(report.contains("1002:1002    → 1001:1001    2 files (unmapped)"));
+        assert_eq!(format_ownership_report(&[], &map), "");
+    }
+
     #[test]
     fn format_bytes_uses_correct_units() {
         assert_eq!(format_bytes(500), "500 B");
@@ -358,4 +720,143 @@ mod tests {
         assert_eq!(format_bytes(1024 * 1024), "1.0 MiB");
         assert_eq!(format_bytes(1024 * 1024 * 1024), "1.0 GiB");
     }
+
+    #[test]
+    fn candidate_summary_shows_counts_dates_and_overlap() {
+        use crate::candidates::CandidateStats;
+        use crate::types::DetectedMapping;
+        use std::time::SystemTime;
+
+        let day = |d: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(d * 86_400);
+        let mut candidate = Candidate {
+            mapping: DetectedMapping {
+                xdg_dir: XdgDir::Documents,
+                source_path: PathBuf::from("/backup/Documents"),
+                dest_path: PathBuf::from("/home/joe/Documents"),
+            },
+            stats: CandidateStats {
+                file_count: 4,
+                total_bytes: 2048,
+                newest: Some(day(19_000)),
+                oldest: Some(day(18_000)),
+                shared: 3,
+            },
+            score: 0.9,
+        };
+
+        assert_eq!(
+            format_candidate(&candidate),
+            "4 files, 2.0 KiB, 2019-04-14 to 2022-01-08, 75% overlap"
+        );
+
+        candidate.stats = CandidateStats::default();
+        assert_eq!(format_candidate(&candidate), "empty");
+    }
+
+    #[test]
+    fn merge_report_shows_source_of_each_file() {
+        use crate::types::CopyOp;
+
+        let mapping = |source: &str| DetectedMapping {
+            xdg_dir: XdgDir::Documents,
+            source_path: PathBuf::from(source),
+            dest_path: PathBuf::from("/home/joe/Documents"),
+        };
+        let op = |source: &str, dest: &str| CopyOp {
+            source: PathBuf::from(source),
+            dest: PathBuf::from(dest),
+            size: 1,
+            mtime: None,
+            xdg_dir: XdgDir::Documents,
+            snapshot: None,
+        };
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![
+                op("/b/Documents/a.txt", "/home/joe/Documents/a.txt"),
+                op("/b/old/Documents/b.txt", "/home/joe/Documents/b.txt"),
+            ],
+            total_bytes: 2,
+        };
+        let notes = vec![MergeNote {
+            dest: PathBuf::from("/home/joe/Documents/b.txt"),
+            kept: vec![PathBuf::from("/b/old/Documents/b.txt")],
+            outcome: MergeOutcome::Newest { older: 1 },
+        }];
+
+        let report = format_merge_report(
+            &plan,
+            &[mapping("/b/Documents"), mapping("/b/old/Documents")],
+            &notes,
+        );
+
+        assert!(report.contains("Merging 2 copies into /home/joe/Documents:"));
+        assert!(report.contains("  /b/Documents: 1 files\n"));
+        assert!(report.contains("1 older versions skipped"));
+        assert!(report.contains("    b.txt ← /b/old/Documents\n"));
+    }
+
+    #[test]
+    fn snapshot_report_counts_files_per_snapshot() {
+        use crate::types::CopyOp;
+
+        let snapshot = |name: &str, time: &str| Snapshot {
+            name: name.to_string(),
+            root: PathBuf::from("/b").join(name),
+            time: humantime::parse_rfc3339(time).unwrap(),
+        };
+        let op = |snapshot: &str, size: u64| CopyOp {
+            source: PathBuf::from("/b").join(snapshot).join("Documents/x"),
+            dest: PathBuf::from("/home/joe/Documents/x"),
+            size,
+            mtime: None,
+            xdg_dir: XdgDir::Documents,
+            snapshot: Some(snapshot.to_string()),
+        };
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![op("daily.0", 10), op("daily.0", 5), op("weekly.0", 1)],
+            total_bytes: 16,
+        };
+        let snapshots = [
+            snapshot("weekly.0", "2024-03-03T04:00:00Z"),
+            snapshot("daily.1", "2024-03-09T03:00:00Z"),
+            snapshot("daily.0", "2024-03-10T03:00:00Z"),
+        ];
+
+        let report = format_snapshot_report(&plan, &snapshots);
+
+        assert!(report.contains("from 3 snapshots up to daily.0 (2024-03-10 03:00:00):"));
+        assert!(report.contains("  daily.0 (2024-03-10 03:00:00): 2 files, 15 B\n"));
+        assert!(report
+            .contains("  weekly.0 (2024-03-03 04:00:00): 1 files, 1 B, not in later snapshots\n"));
+        assert!(!report.contains("daily.1 ("));
+        assert!(format_snapshot_report(&plan, &[]).is_empty());
+    }
+
+    #[test]
+    fn comparison_abbreviates_unless_detailed() {
+        let cmp = Comparison {
+            only_a: (0..12)
+                .map(|i| PathBuf::from(format!("a{i}.txt")))
+                .collect(),
+            only_b: vec![PathBuf::from("b.txt")],
+            differing: vec![],
+            identical: 3,
+            only_a_bytes: 2048,
+            only_b_bytes: 5,
+        };
+        let (a, b) = (Path::new("/b/Documents"), Path::new("/b/old/Documents"));
+
+        let summary = format_comparison(&cmp, a, b, false);
+        assert!(summary.contains("  only in A: 12 files (2.0 KiB)\n"));
+        assert!(summary.contains("  identical: 3 files\n"));
+        assert!(summary.contains("  ... and 7 more\n"));
+        assert!(summary.contains("Only in B:\n  b.txt\n"));
+        assert!(!summary.contains("Differ:"));
+
+        let detailed = format_comparison(&cmp, a, b, true);
+        assert!(detailed.contains("  a11.txt\n"));
+        assert!(!detailed.contains("more"));
+    }
 }
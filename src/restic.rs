@@ -0,0 +1,837 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use aes::cipher::{BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use poly1305::Poly1305;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::archive::{self, Files, Listing, Member};
+use crate::copy::Owner;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+type BlobId = [u8; 32];
+
+/// Largest scrypt work factor accepted in a key file; restic itself writes
+/// 15 to 18. As in age, each step up doubles the memory a password check
+/// needs (1 GiB at 20, with restic's `r` of 8).
+const MAX_SCRYPT_LOG_N: u8 = 20;
+/// Largest scrypt block size and parallelism accepted alongside it.
+const MAX_SCRYPT_R: u32 = 16;
+const MAX_SCRYPT_P: u32 = 4;
+
+#[derive(Debug)]
+pub enum ResticError {
+    /// None of the repository's keys opens with this password.
+    WrongPassword,
+    /// A file failed its authentication check or doesn't parse.
+    Corrupt(String),
+    /// A tree or file refers to a blob missing from every index.
+    MissingBlob(String),
+    /// A tree entry would land outside the restore directory.
+    UnsafePath(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for ResticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResticError::WrongPassword => f.write_str("wrong password for this repository"),
+            ResticError::Corrupt(what) => write!(f, "repository data is damaged: {what}"),
+            ResticError::MissingBlob(id) => write!(f, "blob {id} is missing from the repository"),
+            ResticError::UnsafePath(p) => {
+                write!(f, "refusing to restore {} outside the target", p.display())
+            }
+            ResticError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ResticError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResticError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ResticError {
+    fn from(e: io::Error) -> Self {
+        ResticError::Io(e)
+    }
+}
+
+fn corrupt(what: impl fmt::Display) -> ResticError {
+    ResticError::Corrupt(what.to_string())
+}
+
+/// Whether `dir` looks like a restic repository.
+pub fn is_restic_repo(dir: &Path) -> bool {
+    dir.join("config").is_file()
+        && ["keys", "data", "index", "snapshots"]
+            .iter()
+            .all(|d| dir.join(d).is_dir())
+}
+
+/// A backup run stored in the repository.
+#[derive(Debug, Clone)]
+pub struct RepoSnapshot {
+    /// Full hex ID; restic shows the first 8 characters.
+    pub id: String,
+    pub time: SystemTime,
+    pub hostname: String,
+    /// The paths that were backed up.
+    pub paths: Vec<String>,
+    tree: BlobId,
+}
+
+impl RepoSnapshot {
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(8)]
+    }
+}
+
+/// Where a blob lives: a range of a pack file.
+#[derive(Debug, Clone)]
+struct BlobLocation {
+    pack: String,
+    offset: u64,
+    length: u64,
+    /// Set when the blob is zstd-compressed (repository version 2).
+    compressed: bool,
+}
+
+/// An open local restic repository.
+pub struct Repository {
+    root: PathBuf,
+    key: CryptoKey,
+    index: HashMap<BlobId, BlobLocation>,
+}
+
+impl Repository {
+    /// Unlock the repository with `password` and load its index.
+    pub fn open(root: &Path, password: &[u8]) -> Result<Repository, ResticError> {
+        let key = unlock(root, password)?;
+        let config = decode_unpacked(&key, &fs::read(root.join("config"))?)?;
+        let config: Config = serde_json::from_slice(&config).map_err(corrupt)?;
+        if !(1..=2).contains(&config.version) {
+            return Err(corrupt(format!(
+                "unsupported repository version {}",
+                config.version
+            )));
+        }
+
+        let mut index = HashMap::new();
+        for path in list_files(&root.join("index"))? {
+            let data = decode_unpacked(&key, &fs::read(&path)?)?;
+            let file: IndexFile = serde_json::from_slice(&data).map_err(corrupt)?;
+            for pack in file.packs {
+                for blob in pack.blobs {
+                    index.insert(
+                        parse_id(&blob.id)?,
+                        BlobLocation {
+                            pack: pack.id.clone(),
+                            offset: blob.offset,
+                            length: blob.length,
+                            compressed: blob.uncompressed_length.is_some(),
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(Repository {
+            root: root.to_path_buf(),
+            key,
+            index,
+        })
+    }
+
+    /// All snapshots, oldest first.
+    pub fn snapshots(&self) -> Result<Vec<RepoSnapshot>, ResticError> {
+        let mut snapshots = Vec::new();
+        for path in list_files(&self.root.join("snapshots"))? {
+            let data = decode_unpacked(&self.key, &fs::read(&path)?)?;
+            let file: SnapshotFile = serde_json::from_slice(&data).map_err(corrupt)?;
+            snapshots.push(RepoSnapshot {
+                id: path.file_name().unwrap().to_string_lossy().into_owned(),
+                time: parse_time(&file.time)
+                    .ok_or_else(|| corrupt(format!("bad snapshot time {}", file.time)))?,
+                hostname: file.hostname,
+                paths: file.paths,
+                tree: parse_id(&file.tree)?,
+            });
+        }
+        snapshots.sort_by_key(|s| s.time);
+        Ok(snapshots)
+    }
+
+    /// List the files and folders of `snapshot` at the paths they had when
+    /// backed up: `/home/joe/Documents` is listed as `home/joe/Documents`.
+    /// Only trees are read, plus any `etc/passwd` and `etc/group`; the
+    /// returned [`SnapshotFiles`] reads the rest as they are restored.
+    pub fn list_snapshot(
+        self,
+        snapshot: &RepoSnapshot,
+    ) -> Result<(Listing, SnapshotFiles), ResticError> {
+        let mut files = SnapshotFiles {
+            repo: self,
+            contents: Vec::new(),
+        };
+        let mut listing = Listing::default();
+        files.list_tree(&snapshot.tree, Path::new(""), &mut listing)?;
+        Ok((listing, files))
+    }
+
+    /// Fetch, authenticate, decrypt and (if needed) decompress a blob.
+    fn read_blob(&self, id: &BlobId) -> Result<Vec<u8>, ResticError> {
+        let hex_id = to_hex(id);
+        let location = self
+            .index
+            .get(id)
+            .ok_or_else(|| ResticError::MissingBlob(hex_id.clone()))?;
+        let pack = self
+            .root
+            .join("data")
+            .join(location.pack.get(..2).unwrap_or_default())
+            .join(&location.pack);
+        let mut file = File::open(&pack)?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut sealed = vec![0; usize::try_from(location.length).map_err(corrupt)?];
+        file.read_exact(&mut sealed)?;
+
+        let mut plain = self.key.open(&sealed)?;
+        if location.compressed {
+            plain = decompress(&plain)?;
+        }
+        if Sha256::digest(&plain).as_slice() != id {
+            return Err(corrupt(format!("blob {hex_id} does not match its ID")));
+        }
+        Ok(plain)
+    }
+}
+
+/// The files of a snapshot, read blob by blob straight from the packs.
+pub struct SnapshotFiles {
+    repo: Repository,
+    /// The blobs of each listed file, by [`Member::index`].
+    contents: Vec<Vec<BlobId>>,
+}
+
+impl SnapshotFiles {
+    fn list_tree(
+        &mut self,
+        tree: &BlobId,
+        dir: &Path,
+        listing: &mut Listing,
+    ) -> Result<(), ResticError> {
+        let tree: Tree = serde_json::from_slice(&self.repo.read_blob(tree)?).map_err(corrupt)?;
+        for node in tree.nodes {
+            let mut name = Path::new(&node.name).components();
+            if !matches!(
+                (name.next(), name.next()),
+                (Some(Component::Normal(_)), None)
+            ) {
+                return Err(ResticError::UnsafePath(dir.join(&node.name)));
+            }
+            let path = dir.join(&node.name);
+            let mut member = Member {
+                index: self.contents.len(),
+                path: path.clone(),
+                is_dir: true,
+                size: node.size.unwrap_or_default(),
+                mode: node.mode.map_or(0o755, unix_mode),
+                mtime: node
+                    .mtime
+                    .as_deref()
+                    .and_then(parse_time)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                owner: node.uid.zip(node.gid).map(|(uid, gid)| Owner { uid, gid }),
+            };
+            match node.kind.as_str() {
+                "dir" => {
+                    let Some(subtree) = &node.subtree else {
+                        continue;
+                    };
+                    listing.members.push(member);
+                    self.list_tree(&parse_id(subtree)?, &path, listing)?;
+                }
+                "file" => {
+                    let blobs = node
+                        .content
+                        .iter()
+                        .map(|id| parse_id(id))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    member.is_dir = false;
+                    member.mode = node.mode.map_or(0o644, unix_mode);
+                    self.contents.push(blobs);
+                    if archive::is_account_file(&path, member.size) {
+                        let mut text = Vec::new();
+                        self.write_member(&member, &mut text)?;
+                        if let Ok(text) = String::from_utf8(text) {
+                            listing.account_files.insert(path, text);
+                        }
+                    }
+                    listing.members.push(member);
+                }
+                _ => listing.skipped += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Files for SnapshotFiles {
+    fn write_member(&self, member: &Member, out: &mut dyn Write) -> io::Result<u64> {
+        let blobs = self
+            .contents
+            .get(member.index)
+            .map_or(&[][..], Vec::as_slice);
+        let mut written = 0;
+        for id in blobs {
+            let blob = self.repo.read_blob(id).map_err(|e| match e {
+                ResticError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?;
+            out.write_all(&blob)?;
+            written += blob.len() as u64;
+        }
+        Ok(written)
+    }
+}
+
+/// Unix permission bits from a node's Go `os.FileMode`, which keeps the
+/// setuid, setgid and sticky bits elsewhere.
+fn unix_mode(mode: u32) -> u32 {
+    let mut unix = mode & 0o777;
+    for (go, bit) in [(1 << 23, 0o4000), (1 << 22, 0o2000), (1 << 20, 0o1000)] {
+        if mode & go != 0 {
+            unix |= bit;
+        }
+    }
+    unix
+}
+
+/// Encryption and MAC keys; restic seals data as
+/// `IV ‖ AES-256-CTR(plaintext) ‖ Poly1305-AES(ciphertext)`.
+#[derive(Clone)]
+struct CryptoKey {
+    encrypt: [u8; 32],
+    mac_k: [u8; 16],
+    mac_r: [u8; 16],
+}
+
+impl CryptoKey {
+    fn from_bytes(bytes: &[u8]) -> CryptoKey {
+        CryptoKey {
+            encrypt: bytes[..32].try_into().unwrap(),
+            mac_k: bytes[32..48].try_into().unwrap(),
+            mac_r: bytes[48..64].try_into().unwrap(),
+        }
+    }
+
+    fn mac(&self, iv: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+        let mut s = aes::cipher::generic_array::GenericArray::clone_from_slice(iv);
+        aes::Aes128::new(&self.mac_k.into()).encrypt_block(&mut s);
+        let mut key = [0u8; 32];
+        key[..16].copy_from_slice(&self.mac_r);
+        key[16..].copy_from_slice(&s);
+        Poly1305::new(&key.into())
+            .compute_unpadded(ciphertext)
+            .into()
+    }
+
+    /// Check and decrypt sealed data.
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, ResticError> {
+        if sealed.len() < 32 {
+            return Err(corrupt("encrypted data is truncated"));
+        }
+        let (iv, rest) = sealed.split_at(16);
+        let (ciphertext, tag) = rest.split_at(rest.len() - 16);
+        let expected = self.mac(iv, ciphertext);
+        // Constant-time comparison
+        if expected
+            .iter()
+            .zip(tag)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            != 0
+        {
+            return Err(corrupt("authentication failed"));
+        }
+        let mut plain = ciphertext.to_vec();
+        Aes256Ctr::new(&self.encrypt.into(), iv.into()).apply_keystream(&mut plain);
+        Ok(plain)
+    }
+
+    #[cfg(test)]
+    fn seal(&self, plain: &[u8], iv: [u8; 16]) -> Vec<u8> {
+        let mut ciphertext = plain.to_vec();
+        Aes256Ctr::new(&self.encrypt.into(), &iv.into()).apply_keystream(&mut ciphertext);
+        let tag = self.mac(&iv, &ciphertext);
+        [&iv[..], &ciphertext, &tag].concat()
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    kdf: String,
+    #[serde(rename = "N")]
+    n: u64,
+    r: u32,
+    p: u32,
+    salt: String,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct MasterKey {
+    mac: MacKey,
+    encrypt: String,
+}
+
+#[derive(Deserialize)]
+struct MacKey {
+    k: String,
+    r: String,
+}
+
+/// Find the key file `password` opens and decrypt the master key from it.
+fn unlock(root: &Path, password: &[u8]) -> Result<CryptoKey, ResticError> {
+    let b64 = |s: &str| BASE64.decode(s).map_err(corrupt);
+    for path in list_files(&root.join("keys"))? {
+        let file: KeyFile = serde_json::from_slice(&fs::read(&path)?).map_err(corrupt)?;
+        if file.kdf != "scrypt" || !file.n.is_power_of_two() {
+            continue;
+        }
+        let log_n = u8::try_from(file.n.trailing_zeros()).map_err(corrupt)?;
+        if log_n > MAX_SCRYPT_LOG_N || file.r > MAX_SCRYPT_R || file.p > MAX_SCRYPT_P {
+            return Err(corrupt(format!(
+                "scrypt parameters N={} r={} p={} in {} are beyond any restic writes",
+                file.n,
+                file.r,
+                file.p,
+                path.display()
+            )));
+        }
+        let params = scrypt::Params::new(log_n, file.r, file.p, 64).map_err(corrupt)?;
+        let mut derived = [0u8; 64];
+        scrypt::scrypt(password, &b64(&file.salt)?, &params, &mut derived).map_err(corrupt)?;
+
+        let Ok(plain) = CryptoKey::from_bytes(&derived).open(&b64(&file.data)?) else {
+            continue;
+        };
+        let master: MasterKey = serde_json::from_slice(&plain).map_err(corrupt)?;
+        let bytes = [
+            b64(&master.encrypt)?,
+            b64(&master.mac.k)?,
+            b64(&master.mac.r)?,
+        ]
+        .concat();
+        if bytes.len() != 64 {
+            return Err(corrupt("master key has the wrong length"));
+        }
+        return Ok(CryptoKey::from_bytes(&bytes));
+    }
+    Err(ResticError::WrongPassword)
+}
+
+/// Decrypt a config, index or snapshot file. Since repository version 2
+/// these may be compressed, flagged by a leading version byte of 2.
+fn decode_unpacked(key: &CryptoKey, sealed: &[u8]) -> Result<Vec<u8>, ResticError> {
+    let plain = key.open(sealed)?;
+    match plain.first() {
+        Some(2) => decompress(&plain[1..]),
+        _ => Ok(plain),
+    }
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, ResticError> {
+    let mut decoder = ruzstd::decoding::StreamingDecoder::new(data).map_err(corrupt)?;
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[derive(Deserialize)]
+struct Config {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct IndexFile {
+    packs: Vec<IndexPack>,
+}
+
+#[derive(Deserialize)]
+struct IndexPack {
+    id: String,
+    blobs: Vec<IndexBlob>,
+}
+
+#[derive(Deserialize)]
+struct IndexBlob {
+    id: String,
+    offset: u64,
+    length: u64,
+    uncompressed_length: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotFile {
+    time: String,
+    tree: String,
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    hostname: String,
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    mtime: Option<String>,
+    mode: Option<u32>,
+    size: Option<u64>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    #[serde(default)]
+    content: Vec<String>,
+    subtree: Option<String>,
+}
+
+fn list_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn parse_id(hex: &str) -> Result<BlobId, ResticError> {
+    let bad = || corrupt(format!("bad ID {hex}"));
+    if hex.len() != 64 {
+        return Err(bad());
+    }
+    let mut id = [0u8; 32];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2).ok_or_else(bad)?, 16)
+            .map_err(|_| bad())?;
+    }
+    Ok(id)
+}
+
+fn to_hex(id: &[u8]) -> String {
+    use std::fmt::Write as _;
+    id.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// Parse restic's RFC 3339 times, which carry nanoseconds and a UTC
+/// offset: `2021-03-04T10:22:31.123456789+01:00`.
+fn parse_time(s: &str) -> Option<SystemTime> {
+    let base = humantime::parse_rfc3339(&format!("{}Z", s.get(..19)?)).ok()?;
+    let rest = &s[19..];
+    let (fraction, zone) = match rest.strip_prefix('.') {
+        Some(r) => {
+            let digits = r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len());
+            (&r[..digits], &r[digits..])
+        }
+        None => ("", rest),
+    };
+    let nanos: u32 = format!("{fraction:0<9}").get(..9)?.parse().ok()?;
+    let time = base + Duration::from_nanos(u64::from(nanos));
+
+    if zone == "Z" {
+        return Some(time);
+    }
+    let (sign, hm) = (zone.get(..1)?, zone.get(1..)?);
+    let (h, m) = hm.split_once(':')?;
+    let offset = Duration::from_secs(h.parse::<u64>().ok()? * 3600 + m.parse::<u64>().ok()? * 60);
+    match sign {
+        "+" => time.checked_sub(offset),
+        "-" => time.checked_add(offset),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::restore_files;
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    const PASSWORD: &[u8] = b"correct horse";
+
+    /// Builds a small repository the way restic lays one out, with
+    /// deliberately cheap scrypt parameters.
+    struct Fixture {
+        root: PathBuf,
+        key: CryptoKey,
+        pack: Vec<u8>,
+        blobs: Vec<serde_json::Value>,
+        next_iv: u8,
+    }
+
+    impl Fixture {
+        fn new(root: &Path) -> Fixture {
+            for dir in ["keys", "data", "index", "snapshots"] {
+                fs::create_dir_all(root.join(dir)).unwrap();
+            }
+            let master: Vec<u8> = (0..64).collect();
+            let mut fixture = Fixture {
+                root: root.to_path_buf(),
+                key: CryptoKey::from_bytes(&master),
+                pack: Vec::new(),
+                blobs: Vec::new(),
+                next_iv: 0,
+            };
+
+            let salt = b"0123456789abcdef";
+            let mut derived = [0u8; 64];
+            let params = scrypt::Params::new(4, 8, 1, 64).unwrap();
+            scrypt::scrypt(PASSWORD, salt, &params, &mut derived).unwrap();
+            let master_json = json!({
+                "mac": {"k": BASE64.encode(&master[32..48]), "r": BASE64.encode(&master[48..])},
+                "encrypt": BASE64.encode(&master[..32]),
+            });
+            let iv = fixture.iv();
+            let data = CryptoKey::from_bytes(&derived).seal(master_json.to_string().as_bytes(), iv);
+            let key_file = json!({
+                "created": "2024-01-01T00:00:00Z", "username": "joe", "hostname": "laptop",
+                "kdf": "scrypt", "N": 16, "r": 8, "p": 1,
+                "salt": BASE64.encode(salt), "data": BASE64.encode(data),
+            });
+            fs::write(root.join("keys/aa11"), key_file.to_string()).unwrap();
+
+            // Version 2 config, compressed
+            let config = json!({"version": 2, "id": "x", "chunker_polynomial": "3abc"});
+            fixture.write_unpacked("config", &config, true);
+            fixture
+        }
+
+        fn iv(&mut self) -> [u8; 16] {
+            self.next_iv += 1;
+            [self.next_iv; 16]
+        }
+
+        fn write_unpacked(&mut self, name: &str, value: &serde_json::Value, compress: bool) {
+            let mut plain = value.to_string().into_bytes();
+            if compress {
+                let packed = ruzstd::encoding::compress_to_vec(
+                    plain.as_slice(),
+                    ruzstd::encoding::CompressionLevel::Fastest,
+                );
+                plain = [&[2u8][..], &packed].concat();
+            }
+            let iv = self.iv();
+            fs::write(self.root.join(name), self.key.seal(&plain, iv)).unwrap();
+        }
+
+        /// Add a blob to the pack being built and return its ID.
+        fn blob(&mut self, kind: &str, plain: &[u8], compress: bool) -> String {
+            let id = to_hex(&Sha256::digest(plain));
+            let stored = if compress {
+                ruzstd::encoding::compress_to_vec(
+                    plain,
+                    ruzstd::encoding::CompressionLevel::Fastest,
+                )
+            } else {
+                plain.to_vec()
+            };
+            let iv = self.iv();
+            let sealed = self.key.seal(&stored, iv);
+            let mut entry = json!({
+                "id": id, "type": kind, "offset": self.pack.len(), "length": sealed.len(),
+            });
+            if compress {
+                entry["uncompressed_length"] = json!(plain.len());
+            }
+            self.blobs.push(entry);
+            self.pack.extend(sealed);
+            id
+        }
+
+        fn tree(&mut self, nodes: &serde_json::Value) -> String {
+            let tree = json!({ "nodes": nodes }).to_string();
+            self.blob("tree", tree.as_bytes(), false)
+        }
+
+        fn finish(&mut self, root_tree: &str) {
+            let pack_id = to_hex(&Sha256::digest(&self.pack));
+            fs::create_dir_all(self.root.join("data").join(&pack_id[..2])).unwrap();
+            fs::write(
+                self.root.join("data").join(&pack_id[..2]).join(&pack_id),
+                &self.pack,
+            )
+            .unwrap();
+            let index = json!({"packs": [{"id": pack_id, "blobs": self.blobs}]});
+            self.write_unpacked("index/0011", &index, true);
+            let snapshot = json!({
+                "time": "2024-03-05T14:30:00.5+01:00", "tree": root_tree,
+                "paths": ["/home/joe"], "hostname": "laptop", "username": "joe",
+            });
+            self.write_unpacked(
+                "snapshots/3f2a9c1b00000000000000000000000000000000000000000000000000000000",
+                &snapshot,
+                false,
+            );
+        }
+    }
+
+    fn build_repo(root: &Path) {
+        let mut f = Fixture::new(root);
+        let part1 = f.blob("data", b"hello ", true);
+        let part2 = f.blob("data", b"world", false);
+        let cache = f.blob("data", b"cache", false);
+        let docs = f.tree(&json!([
+            {"name": "notes.txt", "type": "file", "mtime": "2024-03-01T08:00:00Z",
+             "mode": 0o600, "size": 11, "uid": 1000, "gid": 100, "content": [part1, part2]},
+            {"name": "link", "type": "symlink", "linktarget": "notes.txt"},
+        ]));
+        let cache_tree = f.tree(&json!([
+            {"name": "blob.bin", "type": "file", "content": [cache]},
+        ]));
+        let joe = f.tree(&json!([
+            {"name": "Documents", "type": "dir", "subtree": docs},
+            {"name": ".cache", "type": "dir", "subtree": cache_tree},
+            {"name": ".bashrc", "type": "file", "content": [cache]},
+        ]));
+        let home = f.tree(&json!([{"name": "joe", "type": "dir", "subtree": joe}]));
+        let root_tree = f.tree(&json!([{"name": "home", "type": "dir", "subtree": home}]));
+        f.finish(&root_tree);
+    }
+
+    #[test]
+    fn restores_xdg_folders_straight_from_the_packs() {
+        let repo_dir = tempdir().unwrap();
+        build_repo(repo_dir.path());
+        assert!(is_restic_repo(repo_dir.path()));
+
+        let repo = Repository::open(repo_dir.path(), PASSWORD).unwrap();
+        let snapshots = repo.snapshots().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].short_id(), "3f2a9c1b");
+        assert_eq!(snapshots[0].paths, ["/home/joe"]);
+        assert_eq!(
+            snapshots[0].time,
+            humantime::parse_rfc3339("2024-03-05T13:30:00.5Z").unwrap()
+        );
+
+        let (listing, files) = repo.list_snapshot(&snapshots[0]).unwrap();
+        let out = tempdir().unwrap();
+        let result = restore_files(&files, &listing, out.path());
+
+        let docs = out.path().join("Documents");
+        assert_eq!(result.copied.len(), 1);
+        assert!(result.errors.is_empty());
+        assert_eq!(listing.skipped, 1);
+        assert_eq!(
+            fs::read_to_string(docs.join("notes.txt")).unwrap(),
+            "hello world"
+        );
+        assert_eq!(
+            fs::metadata(docs.join("notes.txt"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            humantime::parse_rfc3339("2024-03-01T08:00:00Z").unwrap()
+        );
+        let meta = fs::metadata(docs.join("notes.txt")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o600);
+        let notes = listing
+            .members
+            .iter()
+            .find(|m| m.path.ends_with("notes.txt"))
+            .unwrap();
+        assert_eq!(
+            notes.owner,
+            Some(Owner {
+                uid: 1000,
+                gid: 100
+            })
+        );
+        // Nothing is written outside the XDG folders
+        assert_eq!(fs::read_dir(out.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn wrong_password_is_reported() {
+        let repo_dir = tempdir().unwrap();
+        build_repo(repo_dir.path());
+
+        let err = Repository::open(repo_dir.path(), b"hunter2").err().unwrap();
+
+        assert!(matches!(err, ResticError::WrongPassword));
+    }
+
+    #[test]
+    fn refuses_costly_scrypt_parameters() {
+        let repo_dir = tempdir().unwrap();
+        build_repo(repo_dir.path());
+        let key_path = repo_dir.path().join("keys/aa11");
+        let key: serde_json::Value = serde_json::from_slice(&fs::read(&key_path).unwrap()).unwrap();
+        for (field, value) in [
+            ("N", json!(1u64 << 30)),
+            ("r", json!(1024)),
+            ("p", json!(64)),
+        ] {
+            let mut costly = key.clone();
+            costly[field] = value;
+            fs::write(&key_path, costly.to_string()).unwrap();
+
+            let err = Repository::open(repo_dir.path(), PASSWORD).err().unwrap();
+
+            assert!(matches!(err, ResticError::Corrupt(_)), "{field}");
+        }
+    }
+
+    #[test]
+    fn tampered_data_fails_authentication() {
+        let key = CryptoKey::from_bytes(&[7; 64]);
+        let mut sealed = key.seal(b"secret", [1; 16]);
+        assert_eq!(key.open(&sealed).unwrap(), b"secret");
+
+        sealed[20] ^= 1;
+        assert!(matches!(key.open(&sealed), Err(ResticError::Corrupt(_))));
+    }
+
+    #[test]
+    fn parses_restic_times() {
+        let utc = |s| humantime::parse_rfc3339(s).unwrap();
+        assert_eq!(
+            parse_time("2021-03-04T10:22:31.123456789+01:00"),
+            Some(utc("2021-03-04T09:22:31.123456789Z"))
+        );
+        assert_eq!(
+            parse_time("2021-03-04T10:22:31-05:30"),
+            Some(utc("2021-03-04T15:52:31Z"))
+        );
+        assert_eq!(
+            parse_time("2021-03-04T10:22:31Z"),
+            Some(utc("2021-03-04T10:22:31Z"))
+        );
+        assert_eq!(parse_time("yesterday"), None);
+    }
+}
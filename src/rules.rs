@@ -0,0 +1,487 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+
+use crate::conflict::Resolution;
+use crate::types::{Conflict, CopyPlan};
+
+/// What a rule does with a conflict it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Overwrite,
+    KeepOriginal,
+    KeepBoth,
+    /// The file with the later mtime wins; equal mtimes fall through.
+    NewerWins,
+    /// The larger file wins; equal sizes fall through.
+    LargerWins,
+    /// Merge text files; leaves conflict markers where they diverge.
+    Merge,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Overwrite => "overwrite",
+            Action::KeepOriginal => "keep-original",
+            Action::KeepBoth => "keep-both",
+            Action::NewerWins => "newer",
+            Action::LargerWins => "larger",
+            Action::Merge => "merge",
+        }
+    }
+
+    /// What this action does with a conflict, or `None` when it can't tell
+    /// the two files apart.
+    pub fn resolve(self, original: FileFacts, restore: FileFacts) -> Option<Resolution> {
+        match self {
+            Action::Overwrite => Some(Resolution::Overwrite),
+            Action::KeepOriginal => Some(Resolution::KeepOriginal),
+            Action::KeepBoth => Some(Resolution::LeaveAsIs),
+            Action::NewerWins => winner(restore.modified?.cmp(&original.modified?)),
+            Action::LargerWins => winner(restore.size.cmp(&original.size)),
+            Action::Merge => Some(Resolution::Merge),
+        }
+    }
+}
+
+/// A conflict resolution rule: an optional path glob and an action.
+///
+/// Written as `ACTION` or `GLOB=ACTION`, e.g. `newer` or
+/// `Pictures/**/*.jpg=keep-both`. Globs are matched against the path
+/// relative to the home directory; `*` does not cross `/`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pattern: Option<GlobMatcher>,
+    action: Action,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.pattern {
+            Some(p) => write!(f, "{}={}", p.glob(), self.action.name()),
+            None => f.write_str(self.action.name()),
+        }
+    }
+}
+
+/// A rule that could not be parsed.
+#[derive(Debug)]
+pub struct ParseRuleError {
+    pub rule: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rule '{}': {}", self.rule, self.reason)
+    }
+}
+
+impl std::error::Error for ParseRuleError {}
+
+impl FromStr for Rule {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |reason: String| ParseRuleError {
+            rule: s.to_string(),
+            reason,
+        };
+
+        let (glob, action) = match s.rsplit_once('=') {
+            Some((glob, action)) => (Some(glob.trim()), action.trim()),
+            None => (None, s.trim()),
+        };
+
+        let action = match action {
+            "overwrite" => Action::Overwrite,
+            "keep-original" => Action::KeepOriginal,
+            "keep-both" => Action::KeepBoth,
+            "newer" => Action::NewerWins,
+            "larger" => Action::LargerWins,
+            "merge" => Action::Merge,
+            other => {
+                return Err(err(format!(
+                    "unknown action '{other}' (expected overwrite, keep-original, keep-both, newer, larger or merge)"
+                )))
+            }
+        };
+
+        let pattern = match glob {
+            Some("") => return Err(err("empty pattern".to_string())),
+            Some(glob) => Some(
+                GlobBuilder::new(glob)
+                    .literal_separator(true)
+                    .build()
+                    .map(|g: Glob| g.compile_matcher())
+                    .map_err(|e| err(e.kind().to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(Rule { pattern, action })
+    }
+}
+
+/// Size and mtime of one side of a conflict, as the rules see it.
+#[derive(Debug, Clone, Copy)]
+pub struct FileFacts {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileFacts {
+    pub fn of(path: &Path) -> io::Result<FileFacts> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileFacts {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// The outcome of running the rules against one conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    /// Index into the rule set of the rule that decided.
+    pub rule: usize,
+    pub resolution: Resolution,
+}
+
+/// An ordered list of rules; the first rule that decides wins.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> RuleSet {
+        RuleSet { rules }
+    }
+
+    /// Parse rules from a file: one per line. A `#` at the start of a line
+    /// or after whitespace starts a comment; elsewhere it is part of the
+    /// glob, as in `Music/#archive/**`.
+    pub fn from_file(path: &Path) -> io::Result<RuleSet> {
+        let text = fs::read_to_string(path)?;
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            rules.push(
+                line.parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            );
+        }
+        Ok(RuleSet { rules })
+    }
+
+    pub fn extend(&mut self, other: RuleSet) {
+        self.rules.extend(other.rules);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Decide a conflict at `relative` (path under the home directory).
+    pub fn decide(
+        &self,
+        relative: &Path,
+        original: FileFacts,
+        restore: FileFacts,
+    ) -> Option<Decision> {
+        self.rules.iter().enumerate().find_map(|(i, rule)| {
+            if let Some(pattern) = &rule.pattern {
+                if !pattern.is_match(relative) {
+                    return None;
+                }
+            }
+            Some(Decision {
+                rule: i,
+                resolution: rule.action.resolve(original, restore)?,
+            })
+        })
+    }
+
+    /// Decide a conflict produced by a copy.
+    pub fn decide_conflict(&self, conflict: &Conflict, home_dir: &Path) -> Option<Decision> {
+        let original = FileFacts::of(&conflict.original_path).ok()?;
+        let restore = FileFacts::of(&conflict.restore_path).ok()?;
+        self.decide(
+            relative_to(&conflict.original_path, home_dir),
+            original,
+            restore,
+        )
+    }
+
+    /// Predict what the rules would do with each conflict a plan will cause.
+    ///
+    /// A planned file conflicts when its destination already exists; the
+    /// size and mtime the plan records for the source stand in for the
+    /// `.restore` copy, since archives and remote backups have no source
+    /// on disk to look at.
+    pub fn preview_plan(&self, plan: &CopyPlan, home_dir: &Path) -> Vec<PlannedDecision> {
+        plan.files
+            .iter()
+            .filter_map(|op| {
+                let original = FileFacts::of(&op.dest).ok()?;
+                let restore = FileFacts {
+                    size: op.size,
+                    modified: op.mtime,
+                };
+                Some(PlannedDecision {
+                    dest: op.dest.clone(),
+                    decision: self.decide(relative_to(&op.dest, home_dir), original, restore),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A predicted conflict and what the rules would do with it.
+#[derive(Debug, Clone)]
+pub struct PlannedDecision {
+    pub dest: PathBuf,
+    pub decision: Option<Decision>,
+}
+
+fn strip_comment(line: &str) -> &str {
+    let comment = line.char_indices().find(|&(i, c)| {
+        c == '#'
+            && line[..i]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+    });
+    match comment {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+/// Map "restore compared to original" onto a resolution.
+fn winner(ordering: Ordering) -> Option<Resolution> {
+    match ordering {
+        Ordering::Greater => Some(Resolution::Overwrite),
+        Ordering::Less => Some(Resolution::KeepOriginal),
+        Ordering::Equal => None,
+    }
+}
+
+fn relative_to<'a>(path: &'a Path, home_dir: &Path) -> &'a Path {
+    path.strip_prefix(home_dir).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn facts(size: u64, secs: u64) -> FileFacts {
+        FileFacts {
+            size,
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    fn rules(specs: &[&str]) -> RuleSet {
+        RuleSet::new(specs.iter().map(|s| s.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parses_and_displays_rules() {
+        let rule: Rule = "Pictures/**/*.jpg = keep-both".parse().unwrap();
+        assert_eq!(rule.to_string(), "Pictures/**/*.jpg=keep-both");
+        assert_eq!("newer".parse::<Rule>().unwrap().to_string(), "newer");
+        assert_eq!(
+            "Documents/**/*.md=merge"
+                .parse::<Rule>()
+                .unwrap()
+                .to_string(),
+            "Documents/**/*.md=merge"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!("Downloads/**=shred".parse::<Rule>().is_err());
+        assert!("=overwrite".parse::<Rule>().is_err());
+        assert!("[=overwrite".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let set = rules(&["Downloads/**=keep-original", "overwrite"]);
+
+        let d = set
+            .decide(Path::new("Downloads/a.iso"), facts(1, 1), facts(2, 2))
+            .unwrap();
+        assert_eq!(d.rule, 0);
+        assert_eq!(d.resolution, Resolution::KeepOriginal);
+
+        let d = set
+            .decide(Path::new("Documents/a.txt"), facts(1, 1), facts(2, 2))
+            .unwrap();
+        assert_eq!(d.rule, 1);
+        assert_eq!(d.resolution, Resolution::Overwrite);
+    }
+
+    #[test]
+    fn star_does_not_cross_directories() {
+        let set = rules(&["Pictures/*.jpg=keep-both"]);
+
+        assert!(set
+            .decide(Path::new("Pictures/a.jpg"), facts(1, 1), facts(1, 1))
+            .is_some());
+        assert!(set
+            .decide(Path::new("Pictures/2019/a.jpg"), facts(1, 1), facts(1, 1))
+            .is_none());
+    }
+
+    #[test]
+    fn newer_and_larger_fall_through_on_ties() {
+        let set = rules(&["newer", "larger"]);
+
+        // Restore newer → overwrite
+        let d = set
+            .decide(Path::new("a"), facts(5, 10), facts(5, 20))
+            .unwrap();
+        assert_eq!((d.rule, d.resolution), (0, Resolution::Overwrite));
+
+        // Original newer → keep original
+        let d = set
+            .decide(Path::new("a"), facts(5, 20), facts(5, 10))
+            .unwrap();
+        assert_eq!((d.rule, d.resolution), (0, Resolution::KeepOriginal));
+
+        // Same mtime → larger decides
+        let d = set
+            .decide(Path::new("a"), facts(9, 10), facts(5, 10))
+            .unwrap();
+        assert_eq!((d.rule, d.resolution), (1, Resolution::KeepOriginal));
+
+        // Same mtime and size → undecided
+        assert!(set
+            .decide(Path::new("a"), facts(5, 10), facts(5, 10))
+            .is_none());
+    }
+
+    #[test]
+    fn reads_rules_file_with_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules");
+        fs::write(
+            &path,
+            "# downloads are disposable\nDownloads/**=keep-original\n\
+             Music/#archive/**=keep-both # tagged by hand\n\nnewer  # otherwise\n",
+        )
+        .unwrap();
+
+        let set = RuleSet::from_file(&path).unwrap();
+
+        let shown: Vec<String> = set.rules().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "Downloads/**=keep-original",
+                "Music/#archive/**=keep-both",
+                "newer"
+            ]
+        );
+    }
+
+    #[test]
+    fn previews_decisions_for_existing_destinations() {
+        use crate::types::{CopyOp, XdgDir};
+
+        let backup = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        fs::create_dir(home.path().join("Downloads")).unwrap();
+        fs::write(home.path().join("Downloads/a.iso"), "old").unwrap();
+        fs::write(backup.path().join("a.iso"), "new").unwrap();
+        fs::write(backup.path().join("b.iso"), "new").unwrap();
+
+        let op = |name: &str| CopyOp {
+            source: backup.path().join(name),
+            dest: home.path().join("Downloads").join(name),
+            size: 3,
+            mtime: None,
+            xdg_dir: XdgDir::Downloads,
+            snapshot: None,
+        };
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![op("a.iso"), op("b.iso")],
+            total_bytes: 6,
+        };
+
+        let set = rules(&["Downloads/**=keep-original"]);
+        let preview = set.preview_plan(&plan, home.path());
+
+        assert_eq!(preview.len(), 1);
+        assert_eq!(preview[0].dest, home.path().join("Downloads/a.iso"));
+        assert_eq!(
+            preview[0].decision,
+            Some(Decision {
+                rule: 0,
+                resolution: Resolution::KeepOriginal
+            })
+        );
+    }
+
+    #[test]
+    fn previews_decisions_for_sources_not_on_disk() {
+        use crate::types::{CopyOp, XdgDir};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let home = tempfile::tempdir().unwrap();
+        let original = home.path().join("notes.txt");
+        fs::write(&original, "old").unwrap();
+        let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&original)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // As listed in an archive: the source exists only inside it
+        let plan = CopyPlan {
+            dirs: vec![],
+            files: vec![CopyOp {
+                source: PathBuf::from("/backup.tar.gpg/home/joe/notes.txt"),
+                dest: original,
+                size: 10,
+                mtime: Some(old + Duration::from_mins(1)),
+                xdg_dir: XdgDir::Documents,
+                snapshot: None,
+            }],
+            total_bytes: 10,
+        };
+
+        let preview = rules(&["newer"]).preview_plan(&plan, home.path());
+
+        assert_eq!(preview.len(), 1);
+        assert_eq!(
+            preview[0].decision,
+            Some(Decision {
+                rule: 0,
+                resolution: Resolution::Overwrite
+            })
+        );
+    }
+}
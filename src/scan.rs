@@ -59,6 +59,7 @@ pub fn scan_backup(backup_root: &Path, home_dir: &Path) -> ScanResult {
 mod tests {
     use super::*;
     use std::fs;
+    use std::path::PathBuf;
     use tempfile::tempdir;
 
     #[test]
@@ -164,6 +165,37 @@ mod tests {
         assert_eq!(result.mappings[0].dest_path, home.path().join("Documents"));
     }
 
+    #[test]
+    fn maps_windows_and_macos_folder_names() {
+        let backup = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let profile = backup.path().join("Users/joe");
+        for name in ["My Pictures", "My Videos", "Documents", "AppData"] {
+            fs::create_dir_all(profile.join(name)).unwrap();
+        }
+        fs::create_dir_all(backup.path().join("mac/Movies")).unwrap();
+
+        let mut result = scan_backup(backup.path(), home.path());
+        result
+            .mappings
+            .sort_by(|a, b| a.source_path.cmp(&b.source_path));
+
+        let found: Vec<(XdgDir, PathBuf)> = result
+            .mappings
+            .iter()
+            .map(|m| (m.xdg_dir, m.dest_path.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (XdgDir::Documents, home.path().join("Documents")),
+                (XdgDir::Pictures, home.path().join("Pictures")),
+                (XdgDir::Videos, home.path().join("Videos")),
+                (XdgDir::Videos, home.path().join("Videos")),
+            ]
+        );
+    }
+
     #[test]
     fn collects_warnings_for_unreadable_dirs() {
         use std::os::unix::fs::PermissionsExt;
@@ -0,0 +1,1151 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::dotfiles::{self, Category};
+use crate::types::XdgDir;
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_READLINK: u8 = 19;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FXF_READ: u32 = 1;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+/// Bytes asked for per read; OpenSSH serves up to 255 KiB, but 32 KiB is
+/// the size every server must support.
+const READ_SIZE: u32 = 32 * 1024;
+/// Reads kept in flight per file, so a download isn't one round trip per
+/// 32 KiB.
+const READ_WINDOW: usize = 16;
+/// Largest packet accepted from the server.
+const MAX_PACKET: u32 = 1 << 20;
+
+#[derive(Debug)]
+pub enum SftpError {
+    /// `ssh` could not be started or closed the connection, e.g. because
+    /// the host key or the login was refused.
+    Connect(String),
+    /// The server refused a request.
+    Status {
+        path: String,
+        code: u32,
+        message: String,
+    },
+    /// The server sent something that doesn't follow the protocol.
+    Protocol(String),
+    /// A remote name would land outside the staging directory.
+    UnsafePath(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for SftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftpError::Connect(why) => write!(f, "SSH connection failed: {why}"),
+            SftpError::Status { path, message, .. } => write!(f, "{path}: {message}"),
+            SftpError::Protocol(what) => write!(f, "SFTP protocol error: {what}"),
+            SftpError::UnsafePath(name) => {
+                write!(f, "refusing to download {name} outside the target")
+            }
+            SftpError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SftpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SftpError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SftpError {
+    fn from(e: io::Error) -> Self {
+        SftpError::Io(e)
+    }
+}
+
+fn protocol(what: impl fmt::Display) -> SftpError {
+    SftpError::Protocol(what.to_string())
+}
+
+/// A backup location on an SSH server: `sftp://[user@]host[:port]/path`,
+/// or scp-style `[user@]host:path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Path on the server; relative paths start at the login directory.
+    pub path: String,
+}
+
+impl Remote {
+    pub fn parse(spec: &str) -> Option<Remote> {
+        let (login, path) = if let Some(rest) = spec.strip_prefix("sftp://") {
+            let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+            // `sftp://host/~/x` and `sftp://host/x` are both home-relative
+            // in OpenSSH's sftp; a leading `//` is absolute
+            let path = path.strip_prefix("~/").unwrap_or(path);
+            (
+                authority,
+                if path.is_empty() { "." } else { path }.to_string(),
+            )
+        } else {
+            let (login, path) = spec.split_once(':')?;
+            if login.is_empty() || login.contains('/') {
+                return None;
+            }
+            (login, if path.is_empty() { "." } else { path }.to_string())
+        };
+
+        let (user, host_port) = match login.rsplit_once('@') {
+            Some((user, rest)) => (Some(user.to_string()), rest),
+            None => (None, login),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if spec.starts_with("sftp://") => (host, Some(port.parse().ok()?)),
+            _ => (host_port, None),
+        };
+        // ssh would take either for an option
+        if host.is_empty()
+            || host.starts_with('-')
+            || user.as_ref().is_some_and(|u| u.starts_with('-'))
+        {
+            return None;
+        }
+        Some(Remote {
+            user,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// The `ssh` command that starts the server's SFTP subsystem. Host keys
+    /// must already be known and only key-based logins are tried, so a
+    /// connection never stops to ask anything.
+    pub fn ssh_command(&self, identity: Option<&Path>) -> Command {
+        let mut cmd = Command::new("ssh");
+        for option in [
+            "BatchMode=yes",
+            "StrictHostKeyChecking=yes",
+            "PreferredAuthentications=publickey",
+        ] {
+            cmd.arg("-o").arg(option);
+        }
+        cmd.args(["-x", "-a", "-e", "none"]);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if let Some(key) = identity {
+            cmd.arg("-i").arg(key).args(["-o", "IdentitiesOnly=yes"]);
+        }
+        let login = match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        };
+        cmd.arg("-s").arg("--").arg(login).arg("sftp");
+        cmd
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{user}@")?;
+        }
+        write!(f, "{}:{}", self.host, self.path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteKind {
+    Dir,
+    File,
+    Symlink,
+    Other,
+}
+
+/// A directory entry with the attributes the listing returned.
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub kind: RemoteKind,
+    pub size: u64,
+    /// Permission bits.
+    pub mode: u32,
+    pub mtime: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn kind(self) -> RemoteKind {
+        match self.permissions.map(|p| p & 0o170_000) {
+            Some(0o040_000) => RemoteKind::Dir,
+            Some(0o100_000) => RemoteKind::File,
+            Some(0o120_000) => RemoteKind::Symlink,
+            _ => RemoteKind::Other,
+        }
+    }
+}
+
+/// One SFTP (version 3) conversation with a server.
+pub struct Session {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Child>,
+    next_id: u32,
+}
+
+impl Session {
+    /// Start `ssh` and open an SFTP session over it.
+    pub fn connect(remote: &Remote, identity: Option<&Path>) -> Result<Session, SftpError> {
+        let mut child = remote
+            .ssh_command(identity)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| SftpError::Connect(format!("could not run ssh: {e}")))?;
+        let reader = Box::new(child.stdout.take().unwrap());
+        let writer = Box::new(child.stdin.take().unwrap());
+        match Session::start(reader, writer) {
+            Ok(mut session) => {
+                session.child = Some(child);
+                Ok(session)
+            }
+            // ssh exits before the handshake when the host key or login
+            // is refused, after printing why
+            Err(SftpError::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe
+                ) =>
+            {
+                let status = child.wait()?;
+                Err(SftpError::Connect(format!("ssh exited with {status}")))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open an SFTP session over an already connected byte stream.
+    pub fn start(
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+    ) -> Result<Session, SftpError> {
+        let mut session = Session {
+            reader: BufReader::with_capacity(64 * 1024, reader),
+            writer,
+            child: None,
+            next_id: 0,
+        };
+        let mut init = Packet::new(SSH_FXP_INIT);
+        init.u32(3);
+        session.send(&init)?;
+        let (kind, body) = session.recv()?;
+        let mut r = Reader::new(&body);
+        if kind != SSH_FXP_VERSION || r.u32()? < 3 {
+            return Err(protocol("server does not speak SFTP version 3"));
+        }
+        Ok(session)
+    }
+
+    fn request(&mut self, kind: u8) -> (u32, Packet) {
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut packet = Packet::new(kind);
+        packet.u32(self.next_id);
+        (self.next_id, packet)
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<(), SftpError> {
+        let len = u32::try_from(packet.0.len()).map_err(protocol)?;
+        self.writer.write_all(&len.to_be_bytes())?;
+        self.writer.write_all(&packet.0)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<(u8, Vec<u8>), SftpError> {
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len);
+        if len == 0 || len > MAX_PACKET {
+            return Err(protocol(format!("bad packet length {len}")));
+        }
+        let mut body = vec![0; len as usize];
+        self.reader.read_exact(&mut body)?;
+        let kind = body.remove(0);
+        Ok((kind, body))
+    }
+
+    /// Receive the reply to request `id`, which must be the next one. The
+    /// body still starts with the request id.
+    fn reply(&mut self, id: u32) -> Result<(u8, Vec<u8>), SftpError> {
+        let (kind, body) = self.recv()?;
+        if Reader::new(&body).u32()? != id {
+            return Err(protocol("reply out of order"));
+        }
+        Ok((kind, body))
+    }
+
+    fn expect(&mut self, id: u32, want: u8, path: &str) -> Result<Vec<u8>, SftpError> {
+        let (kind, body) = self.reply(id)?;
+        if kind == want {
+            return Ok(body[4..].to_vec());
+        }
+        Err(status_error(kind, &body, path))
+    }
+
+    fn open_handle(&mut self, kind: u8, path: &str) -> Result<Vec<u8>, SftpError> {
+        let (id, mut packet) = self.request(kind);
+        packet.string(path.as_bytes());
+        if kind == SSH_FXP_OPEN {
+            packet.u32(SSH_FXF_READ);
+            packet.u32(0);
+        }
+        self.send(&packet)?;
+        let body = self.expect(id, SSH_FXP_HANDLE, path)?;
+        Ok(Reader::new(&body).string()?.to_vec())
+    }
+
+    fn close(&mut self, handle: &[u8], path: &str) -> Result<(), SftpError> {
+        let (id, mut packet) = self.request(SSH_FXP_CLOSE);
+        packet.string(handle);
+        self.send(&packet)?;
+        let (kind, body) = self.reply(id)?;
+        match status_code(kind, &body) {
+            Some(SSH_FX_OK) => Ok(()),
+            _ => Err(status_error(kind, &body, path)),
+        }
+    }
+
+    /// List a directory. Each reply carries a batch of names with their
+    /// attributes, so no entry needs a stat of its own.
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<RemoteEntry>, SftpError> {
+        let handle = self.open_handle(SSH_FXP_OPENDIR, path)?;
+        let mut entries = Vec::new();
+        loop {
+            let (id, mut packet) = self.request(SSH_FXP_READDIR);
+            packet.string(&handle);
+            self.send(&packet)?;
+            let (kind, body) = self.reply(id)?;
+            if status_code(kind, &body) == Some(SSH_FX_EOF) {
+                break;
+            }
+            if kind != SSH_FXP_NAME {
+                return Err(status_error(kind, &body, path));
+            }
+            let mut r = Reader::new(&body[4..]);
+            for _ in 0..r.u32()? {
+                let name = String::from_utf8_lossy(r.string()?).into_owned();
+                r.string()?; // long name, for display only
+                let attrs = r.attrs()?;
+                if name == "." || name == ".." {
+                    continue;
+                }
+                entries.push(RemoteEntry {
+                    name,
+                    kind: attrs.kind(),
+                    size: attrs.size.unwrap_or(0),
+                    mode: attrs.permissions.unwrap_or(0o644) & 0o7777,
+                    mtime: attrs
+                        .mtime
+                        .map(|t| SystemTime::UNIX_EPOCH + Duration::from_secs(u64::from(t))),
+                });
+            }
+        }
+        self.close(&handle, path)?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Whether `path` exists and is a directory, following symlinks.
+    pub fn is_dir(&mut self, path: &str) -> Result<bool, SftpError> {
+        let (id, mut packet) = self.request(SSH_FXP_STAT);
+        packet.string(path.as_bytes());
+        self.send(&packet)?;
+        let (kind, body) = self.reply(id)?;
+        if kind == SSH_FXP_ATTRS {
+            return Ok(Reader::new(&body[4..]).attrs()?.kind() == RemoteKind::Dir);
+        }
+        match status_code(kind, &body) {
+            Some(2) => Ok(false),
+            _ => Err(status_error(kind, &body, path)),
+        }
+    }
+
+    pub fn read_link(&mut self, path: &str) -> Result<String, SftpError> {
+        let (id, mut packet) = self.request(SSH_FXP_READLINK);
+        packet.string(path.as_bytes());
+        self.send(&packet)?;
+        let body = self.expect(id, SSH_FXP_NAME, path)?;
+        let mut r = Reader::new(&body);
+        if r.u32()? == 0 {
+            return Err(protocol("empty readlink reply"));
+        }
+        Ok(String::from_utf8_lossy(r.string()?).into_owned())
+    }
+
+    /// Copy a remote file's contents to `out`, keeping several reads in
+    /// flight. Returns the number of bytes written.
+    pub fn download(
+        &mut self,
+        path: &str,
+        size: u64,
+        out: &mut dyn Write,
+    ) -> Result<u64, SftpError> {
+        let handle = self.open_handle(SSH_FXP_OPEN, path)?;
+        let result = self.read_pipelined(&handle, path, size, out);
+        let closed = self.close(&handle, path);
+        let written = result?;
+        closed?;
+        Ok(written)
+    }
+
+    fn send_read(&mut self, handle: &[u8], offset: u64, len: u32) -> Result<u32, SftpError> {
+        let (id, mut packet) = self.request(SSH_FXP_READ);
+        packet.string(handle);
+        packet.u64(offset);
+        packet.u32(len);
+        self.send(&packet)?;
+        Ok(id)
+    }
+
+    fn read_pipelined(
+        &mut self,
+        handle: &[u8],
+        path: &str,
+        size: u64,
+        out: &mut dyn Write,
+    ) -> Result<u64, SftpError> {
+        let mut in_flight: Vec<(u32, u64, u32)> = Vec::new();
+        // Replies that arrived ahead of a gap left by a short read
+        let mut ahead: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+        let mut next_offset = 0;
+        let mut written = 0;
+        let mut eof = false;
+        loop {
+            // The size may have changed since the listing; reading on until
+            // EOF picks up whatever the file holds now
+            while !eof
+                && in_flight.len() < READ_WINDOW
+                && (next_offset < size || in_flight.is_empty())
+            {
+                let id = self.send_read(handle, next_offset, READ_SIZE)?;
+                in_flight.push((id, next_offset, READ_SIZE));
+                next_offset += u64::from(READ_SIZE);
+            }
+            if in_flight.is_empty() {
+                return Ok(written);
+            }
+
+            let (kind, body) = self.recv()?;
+            let id = Reader::new(&body).u32()?;
+            let Some(index) = in_flight.iter().position(|r| r.0 == id) else {
+                return Err(protocol("reply to an unknown request"));
+            };
+            let (_, offset, len) = in_flight.remove(index);
+            if kind == SSH_FXP_DATA {
+                let data = Reader::new(&body[4..]).string()?.to_vec();
+                let got = u32::try_from(data.len()).map_err(protocol)?;
+                if got == 0 {
+                    eof = true;
+                } else if got < len {
+                    // Servers may return less than asked; ask for the rest
+                    let rest = offset + u64::from(got);
+                    let id = self.send_read(handle, rest, len - got)?;
+                    in_flight.push((id, rest, len - got));
+                }
+                ahead.insert(offset, data);
+            } else if status_code(kind, &body) == Some(SSH_FX_EOF) {
+                eof = true;
+            } else {
+                for _ in 0..in_flight.len() {
+                    self.recv()?;
+                }
+                return Err(status_error(kind, &body, path));
+            }
+
+            while let Some(data) = ahead.remove(&written) {
+                out.write_all(&data)?;
+                written += data.len() as u64;
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            // Closing stdin ends the session; ssh then exits on its own
+            self.writer = Box::new(io::sink());
+            let _ = child.wait();
+        }
+    }
+}
+
+fn status_code(kind: u8, body: &[u8]) -> Option<u32> {
+    if kind != SSH_FXP_STATUS {
+        return None;
+    }
+    Reader::new(body.get(4..)?).u32().ok()
+}
+
+fn status_error(kind: u8, body: &[u8], path: &str) -> SftpError {
+    if kind != SSH_FXP_STATUS {
+        return protocol(format!("unexpected reply type {kind} for {path}"));
+    }
+    let mut r = Reader::new(body.get(4..).unwrap_or_default());
+    let code = r.u32().unwrap_or(u32::MAX);
+    let message = r
+        .string()
+        .map(|m| String::from_utf8_lossy(m).into_owned())
+        .unwrap_or_default();
+    SftpError::Status {
+        path: path.to_string(),
+        code,
+        message: if message.is_empty() {
+            format!("request failed (status {code})")
+        } else {
+            message
+        },
+    }
+}
+
+struct Packet(Vec<u8>);
+
+impl Packet {
+    fn new(kind: u8) -> Packet {
+        Packet(vec![kind])
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend(v.to_be_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend(v.to_be_bytes());
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        self.u32(u32::try_from(s.len()).unwrap());
+        self.0.extend(s);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SftpError> {
+        if self.data.len() < n {
+            return Err(protocol("truncated reply"));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, SftpError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SftpError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], SftpError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn attrs(&mut self) -> Result<Attrs, SftpError> {
+        let flags = self.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(self.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(self.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            self.u32()?;
+            attrs.mtime = Some(self.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn check_name(name: &str) -> Result<(), SftpError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(SftpError::UnsafePath(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Find the XDG folders under `root` on the server, the way
+/// [`scan_backup`](crate::scan::scan_backup) does locally: matching folders
+/// are recorded and not descended into. Returns their paths relative to
+/// `root`, plus folders that could not be listed.
+pub fn find_xdg_folders(
+    session: &mut Session,
+    root: &str,
+) -> Result<(Vec<PathBuf>, Vec<SftpError>), SftpError> {
+    let mut found = Vec::new();
+    let mut warnings = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let path = remote_path(root, &relative);
+        let entries = match session.read_dir(&path) {
+            Ok(entries) => entries,
+            // The root itself must be readable
+            Err(e @ SftpError::Status { .. }) if !relative.as_os_str().is_empty() => {
+                warnings.push(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for entry in entries.iter().rev() {
+            if entry.kind != RemoteKind::Dir {
+                continue;
+            }
+            check_name(&entry.name)?;
+            let child = relative.join(&entry.name);
+            if XdgDir::from_dir_name(&entry.name).is_some() {
+                found.push(child);
+            } else {
+                pending.push(child);
+            }
+        }
+    }
+    found.sort();
+    Ok((found, warnings))
+}
+
+/// The folders of the `include` categories next to the XDG `folders` found
+/// under `root`, relative to it like them. A folder that can't be checked
+/// counts as missing.
+pub fn find_included_folders(
+    session: &mut Session,
+    root: &str,
+    folders: &[PathBuf],
+    include: &[Category],
+) -> Vec<PathBuf> {
+    dotfiles::included_folders(folders, include, |path| {
+        session.is_dir(&remote_path(root, path)).unwrap_or(false)
+    })
+}
+
+/// The server's path for `relative` under `root`.
+pub fn remote_path(root: &str, relative: &Path) -> String {
+    relative.iter().fold(root.to_string(), |path, part| {
+        join(&path, &part.to_string_lossy())
+    })
+}
+
+/// A file, folder or symlink to download, relative to the remote root.
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    pub relative: PathBuf,
+    pub entry: RemoteEntry,
+    /// Where a symlink points.
+    pub target: Option<String>,
+}
+
+/// List everything under the folders at `relatives`, with the attributes
+/// needed to plan the download.
+pub fn list_folders(
+    session: &mut Session,
+    root: &str,
+    relatives: &[PathBuf],
+) -> Result<Vec<RemoteFile>, SftpError> {
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = relatives.iter().rev().cloned().collect();
+    while let Some(relative) = pending.pop() {
+        let path = remote_path(root, &relative);
+        let mut subdirs = Vec::new();
+        for entry in session.read_dir(&path)? {
+            check_name(&entry.name)?;
+            let child = relative.join(&entry.name);
+            let target = match entry.kind {
+                RemoteKind::Symlink => Some(session.read_link(&join(&path, &entry.name))?),
+                RemoteKind::Dir => {
+                    subdirs.push(child.clone());
+                    None
+                }
+                RemoteKind::File => None,
+                RemoteKind::Other => continue,
+            };
+            files.push(RemoteFile {
+                relative: child,
+                entry,
+                target,
+            });
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    Ok(files)
+}
+
+/// Download `files` under `dest`, one worker per session, keeping the
+/// remote modes and mtimes. Returns the number of files written.
+pub fn mirror(
+    sessions: Vec<Session>,
+    root: &str,
+    relatives: &[PathBuf],
+    files: &[RemoteFile],
+    dest: &Path,
+) -> Result<usize, SftpError> {
+    for relative in relatives {
+        fs::create_dir_all(dest.join(relative))?;
+    }
+    for file in files {
+        if file.entry.kind == RemoteKind::Dir {
+            fs::create_dir_all(dest.join(&file.relative))?;
+        }
+    }
+
+    let downloads: Vec<&RemoteFile> = files
+        .iter()
+        .filter(|f| f.entry.kind == RemoteKind::File)
+        .collect();
+    let progress = ProgressBar::new(downloads.iter().map(|f| f.entry.size).sum());
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40} {bytes}/{total_bytes} ({eta})")
+            .unwrap(),
+    );
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for mut session in sessions {
+            let (next, failed, first_error) = (&next, &failed, &first_error);
+            let (downloads, progress) = (&downloads, &progress);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(file) = downloads.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    match download_one(&mut session, root, file, dest) {
+                        Ok(bytes) => progress.inc(bytes),
+                        Err(e) => {
+                            failed.store(true, Ordering::Relaxed);
+                            first_error.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    progress.finish_and_clear();
+    if let Some(e) = first_error.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    // Links go in last, so no download can be led through one
+    for file in files {
+        if let (RemoteKind::Symlink, Some(target)) = (file.entry.kind, &file.target) {
+            std::os::unix::fs::symlink(target, dest.join(&file.relative))?;
+        }
+    }
+
+    // Deepest first, so writing a folder's contents doesn't reset its mtime
+    for file in files.iter().rev() {
+        if file.entry.kind == RemoteKind::Dir {
+            set_times(&dest.join(&file.relative), &file.entry, 0o700)?;
+        }
+    }
+    Ok(downloads.len())
+}
+
+fn download_one(
+    session: &mut Session,
+    root: &str,
+    file: &RemoteFile,
+    dest: &Path,
+) -> Result<u64, SftpError> {
+    let path = dest.join(&file.relative);
+    // Private until written, and never through anything already there
+    let out = File::options()
+        .write(true)
+        .create_new(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .mode(0o600)
+        .open(&path)?;
+    let mut out = BufWriter::new(out);
+    let bytes = session.download(
+        &remote_path(root, &file.relative),
+        file.entry.size,
+        &mut out,
+    )?;
+    let out = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+    out.set_permissions(fs::Permissions::from_mode(file.entry.mode))?;
+    if let Some(mtime) = file.entry.mtime {
+        out.set_modified(mtime)?;
+    }
+    Ok(bytes)
+}
+
+/// Apply the remote mode and mtime to a folder. `keep` bits stay set, so
+/// the owner can still clean up staged folders.
+fn set_times(path: &Path, entry: &RemoteEntry, keep: u32) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(entry.mode | keep))?;
+    if let Some(mtime) = entry.mtime {
+        File::open(path)?.set_modified(mtime)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+// Fixture builders write lengths and times into the protocol's u32 fields
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::os::unix::fs::MetadataExt;
+    use std::time::UNIX_EPOCH;
+    use tempfile::tempdir;
+
+    /// Largest read the fake server answers, so downloads see short reads.
+    const SERVED: usize = 10_000;
+
+    /// Serves a folder the way `sftp-server` would, with directory listings
+    /// sent two names at a time.
+    struct FakeServer {
+        root: PathBuf,
+        handles: HashMap<u32, (PathBuf, Vec<String>)>,
+        next_handle: u32,
+    }
+
+    /// Start a fake server for `root` on a pair of pipes and connect to it.
+    fn fake_server(root: &Path) -> Session {
+        let (client_in, mut server_out) = io::pipe().unwrap();
+        let (mut server_in, client_out) = io::pipe().unwrap();
+        let mut server = FakeServer {
+            root: root.to_path_buf(),
+            handles: HashMap::new(),
+            next_handle: 0,
+        };
+        std::thread::spawn(move || loop {
+            let mut len = [0u8; 4];
+            if server_in.read_exact(&mut len).is_err() {
+                return;
+            }
+            let mut body = vec![0; u32::from_be_bytes(len) as usize];
+            server_in.read_exact(&mut body).unwrap();
+            let answer = server.answer(body[0], &body[1..]);
+            server_out
+                .write_all(&(answer.0.len() as u32).to_be_bytes())
+                .unwrap();
+            server_out.write_all(&answer.0).unwrap();
+        });
+        Session::start(Box::new(client_in), Box::new(client_out)).unwrap()
+    }
+
+    fn status(id: u32, code: u32) -> Packet {
+        let mut p = Packet::new(SSH_FXP_STATUS);
+        p.u32(id);
+        p.u32(code);
+        p.string(b"");
+        p.string(b"");
+        p
+    }
+
+    fn attrs(p: &mut Packet, path: &Path) {
+        let meta = fs::symlink_metadata(path).unwrap();
+        p.u32(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME);
+        p.u64(meta.len());
+        p.u32(meta.mode());
+        p.u32(meta.atime() as u32);
+        p.u32(meta.mtime() as u32);
+    }
+
+    impl FakeServer {
+        fn answer(&mut self, kind: u8, body: &[u8]) -> Packet {
+            let mut r = Reader::new(body);
+            if kind == SSH_FXP_INIT {
+                let mut p = Packet::new(SSH_FXP_VERSION);
+                p.u32(3);
+                return p;
+            }
+            let id = r.u32().unwrap();
+            let arg = r.string().unwrap();
+            let path = self
+                .root
+                .join(std::str::from_utf8(arg).unwrap().trim_start_matches('/'));
+            let handle = || u32::from_be_bytes(arg[..4].try_into().unwrap());
+            let mut p = Packet::new(match kind {
+                SSH_FXP_OPENDIR | SSH_FXP_OPEN => SSH_FXP_HANDLE,
+                SSH_FXP_READDIR | SSH_FXP_READLINK => SSH_FXP_NAME,
+                SSH_FXP_READ => SSH_FXP_DATA,
+                SSH_FXP_STAT => SSH_FXP_ATTRS,
+                _ => SSH_FXP_STATUS,
+            });
+            p.u32(id);
+            match kind {
+                SSH_FXP_OPENDIR | SSH_FXP_OPEN => {
+                    let names = match fs::read_dir(&path) {
+                        Ok(dir) => dir
+                            .map(|e| e.unwrap().file_name().into_string().unwrap())
+                            .chain([".".to_string(), "..".to_string()])
+                            .collect(),
+                        Err(_) if path.is_file() => Vec::new(),
+                        Err(_) => return status(id, 2),
+                    };
+                    self.next_handle += 1;
+                    self.handles.insert(self.next_handle, (path, names));
+                    p.string(&self.next_handle.to_be_bytes());
+                }
+                SSH_FXP_READDIR => {
+                    let (dir, names) = self.handles.get_mut(&handle()).unwrap();
+                    if names.is_empty() {
+                        return status(id, SSH_FX_EOF);
+                    }
+                    let batch: Vec<String> = names.drain(..names.len().min(2)).collect();
+                    p.u32(batch.len() as u32);
+                    for name in batch {
+                        p.string(name.as_bytes());
+                        p.string(b"");
+                        attrs(&mut p, &dir.join(name));
+                    }
+                }
+                SSH_FXP_READ => {
+                    let offset = r.u64().unwrap() as usize;
+                    let len = r.u32().unwrap() as usize;
+                    let data = fs::read(&self.handles[&handle()].0).unwrap();
+                    if offset >= data.len() {
+                        return status(id, SSH_FX_EOF);
+                    }
+                    p.string(&data[offset..data.len().min(offset + len.min(SERVED))]);
+                }
+                SSH_FXP_CLOSE => {
+                    self.handles.remove(&handle());
+                    return status(id, SSH_FX_OK);
+                }
+                SSH_FXP_STAT => match fs::canonicalize(&path) {
+                    Ok(path) => attrs(&mut p, &path),
+                    Err(_) => return status(id, 2),
+                },
+                SSH_FXP_READLINK => {
+                    let target = fs::read_link(&path).unwrap();
+                    p.u32(1);
+                    p.string(target.to_str().unwrap().as_bytes());
+                    p.string(b"");
+                    p.u32(0);
+                }
+                other => panic!("unexpected request {other}"),
+            }
+            p
+        }
+    }
+
+    #[test]
+    fn parses_remote_specs() {
+        assert_eq!(
+            Remote::parse("sftp://joe@nas:2222/backups/laptop"),
+            Some(Remote {
+                user: Some("joe".into()),
+                host: "nas".into(),
+                port: Some(2222),
+                path: "backups/laptop".into(),
+            })
+        );
+        assert_eq!(
+            Remote::parse("sftp://nas//srv/backup").unwrap().path,
+            "/srv/backup"
+        );
+        let scp = Remote::parse("nas:/srv/backup").unwrap();
+        assert_eq!((scp.user, scp.host, scp.port), (None, "nas".into(), None));
+        assert_eq!(Remote::parse("joe@nas:").unwrap().path, ".");
+        assert_eq!(Remote::parse("./odd:name"), None);
+        assert_eq!(Remote::parse("/mnt/backup"), None);
+        assert_eq!(Remote::parse("-oProxyCommand=evil:x"), None);
+        assert_eq!(Remote::parse("sftp://-oProxyCommand=evil@nas/x"), None);
+
+        let args: Vec<_> = Remote::parse("joe@nas:x")
+            .unwrap()
+            .ssh_command(None)
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(args[args.len() - 3..], ["--", "joe@nas", "sftp"]);
+    }
+
+    #[test]
+    fn finds_xdg_folders_remotely_and_mirrors_them() {
+        let server = tempdir().unwrap();
+        let docs = server.path().join("backup/home/joe/Documents");
+        fs::create_dir_all(docs.join("sub")).unwrap();
+        fs::create_dir_all(server.path().join("backup/home/joe/src")).unwrap();
+        fs::write(server.path().join("backup/home/joe/.bashrc"), "x").unwrap();
+        // Several read windows' worth, with a length that isn't a multiple
+        // of any read size
+        let big: Vec<u8> = (0..1_234_567u32).map(|i| (i % 251) as u8).collect();
+        fs::write(docs.join("big.bin"), &big).unwrap();
+        fs::write(docs.join("sub/note.txt"), "hello").unwrap();
+        fs::write(docs.join("empty"), "").unwrap();
+        fs::set_permissions(docs.join("sub/note.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("big.bin", docs.join("link")).unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::open(docs.join("sub/note.txt"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let mut session = fake_server(server.path());
+
+        let (folders, warnings) = find_xdg_folders(&mut session, "/backup").unwrap();
+        let files = list_folders(&mut session, "/backup", &folders).unwrap();
+        let out = tempdir().unwrap();
+        let sessions = vec![session, fake_server(server.path())];
+        let count = mirror(sessions, "/backup", &folders, &files, out.path()).unwrap();
+
+        assert_eq!(folders, vec![PathBuf::from("home/joe/Documents")]);
+        assert!(warnings.is_empty());
+        assert_eq!(count, 3);
+        let local = out.path().join("home/joe/Documents");
+        assert_eq!(fs::read(local.join("big.bin")).unwrap(), big);
+        assert_eq!(fs::read(local.join("empty")).unwrap(), b"");
+        assert_eq!(
+            fs::read_link(local.join("link")).unwrap(),
+            Path::new("big.bin")
+        );
+        let note = fs::metadata(local.join("sub/note.txt")).unwrap();
+        assert_eq!(note.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(note.modified().unwrap(), mtime);
+        assert!(!out.path().join("home/joe/.bashrc").exists());
+        assert!(!out.path().join("home/joe/src").exists());
+    }
+
+    #[test]
+    fn downloads_the_included_hidden_folders_too() {
+        let server = tempdir().unwrap();
+        let home = server.path().join("backup/home/joe");
+        for dir in ["Documents", ".config/app", ".ssh", ".cache/app"] {
+            fs::create_dir_all(home.join(dir)).unwrap();
+            fs::write(home.join(dir).join("file"), dir).unwrap();
+        }
+        // Only the home holding the XDG folders is looked in
+        fs::create_dir_all(server.path().join("backup/.config")).unwrap();
+        fs::write(server.path().join("backup/.config/file"), "x").unwrap();
+        let mut session = fake_server(server.path());
+
+        let (mut folders, _) = find_xdg_folders(&mut session, "/backup").unwrap();
+        let hidden = find_included_folders(
+            &mut session,
+            "/backup",
+            &folders,
+            &[Category::Config, Category::BrowserProfiles],
+        );
+        folders.extend(hidden);
+        let files = list_folders(&mut session, "/backup", &folders).unwrap();
+        let out = tempdir().unwrap();
+        let count = mirror(vec![session], "/backup", &folders, &files, out.path()).unwrap();
+
+        assert_eq!(
+            folders,
+            ["home/joe/Documents", "home/joe/.config"].map(PathBuf::from)
+        );
+        assert_eq!(count, 2);
+        let local = out.path().join("home/joe");
+        assert_eq!(
+            fs::read_to_string(local.join(".config/app/file")).unwrap(),
+            ".config/app"
+        );
+        assert!(!local.join(".ssh").exists());
+        assert!(!local.join(".cache").exists());
+        assert!(!out.path().join(".config").exists());
+    }
+
+    #[test]
+    fn never_downloads_through_links() {
+        let server = tempdir().unwrap();
+        let docs = server.path().join("backup/Documents");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("a.txt"), "from the server").unwrap();
+        let elsewhere = tempdir().unwrap();
+        let victim = elsewhere.path().join("victim");
+        fs::write(&victim, "untouched").unwrap();
+        let out = tempdir().unwrap();
+        fs::create_dir_all(out.path().join("Documents")).unwrap();
+        std::os::unix::fs::symlink(&victim, out.path().join("Documents/a.txt")).unwrap();
+        let mut session = fake_server(server.path());
+
+        let (folders, _) = find_xdg_folders(&mut session, "/backup").unwrap();
+        let files = list_folders(&mut session, "/backup", &folders).unwrap();
+        let result = mirror(vec![session], "/backup", &folders, &files, out.path());
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+    }
+
+    #[test]
+    fn reports_missing_remote_folders() {
+        let server = tempdir().unwrap();
+        let mut session = fake_server(server.path());
+
+        let err = find_xdg_folders(&mut session, "/nowhere").unwrap_err();
+
+        assert!(matches!(err, SftpError::Status { code: 2, .. }), "{err}");
+    }
+}
@@ -0,0 +1,676 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::plan::walk_source;
+use crate::types::{CopyOp, CopyPlan, DetectedMapping, DirOp};
+
+/// How a set of snapshots is laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotLayout {
+    /// rsnapshot-style rotation: `daily.0`, `daily.1`, `weekly.0`, ...
+    Rsnapshot,
+    /// Folders named by the time they were taken, e.g. `2024-01-05`.
+    Dated,
+    /// `timeshift/snapshots/<YYYY-MM-DD_HH-MM-SS>/localhost`, each with an
+    /// `info.json`.
+    Timeshift,
+    /// `backintime/<host>/<user>/<profile>/<YYYYMMDD-HHMMSS-NNN>/backup`,
+    /// each with `info` and `fileinfo.bz2` files.
+    BackInTime,
+}
+
+impl fmt::Display for SnapshotLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SnapshotLayout::Rsnapshot => "rsnapshot",
+            SnapshotLayout::Dated => "dated",
+            SnapshotLayout::Timeshift => "Timeshift",
+            SnapshotLayout::BackInTime => "Back In Time",
+        })
+    }
+}
+
+/// One complete copy of the backed-up tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    /// Directory to scan for XDG folders: the snapshot itself, or the home
+    /// directory inside it for tools that snapshot a whole system.
+    pub root: PathBuf,
+    pub time: SystemTime,
+}
+
+/// Snapshots of the same tree, oldest first. Unchanged files are usually
+/// hard links shared between snapshots.
+#[derive(Debug, Clone)]
+pub struct SnapshotSet {
+    pub layout: SnapshotLayout,
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotSet {
+    fn new(layout: SnapshotLayout, mut snapshots: Vec<Snapshot>) -> SnapshotSet {
+        // On equal times, rsnapshot's lower numbers are newer; dated names
+        // sort in time order anyway
+        snapshots.sort_by(|a, b| {
+            let by_name = if layout == SnapshotLayout::Rsnapshot {
+                b.name.cmp(&a.name)
+            } else {
+                a.name.cmp(&b.name)
+            };
+            a.time.cmp(&b.time).then(by_name)
+        });
+        SnapshotSet { layout, snapshots }
+    }
+
+    pub fn latest(&self) -> &Snapshot {
+        self.snapshots
+            .last()
+            .expect("snapshot sets are never empty")
+    }
+
+    /// Find a snapshot by exact name, or the latest one taken at or before
+    /// a time such as `2024-01-05` (end of that day) or `2024-01-05 14:30`.
+    pub fn find(&self, spec: &str) -> Option<&Snapshot> {
+        if let Some(s) = self.snapshots.iter().find(|s| s.name == spec) {
+            return Some(s);
+        }
+        let until = parse_time_spec(spec)?;
+        self.snapshots.iter().rev().find(|s| s.time <= until)
+    }
+
+    /// The snapshots taken at or before `until`, oldest first.
+    pub fn until(&self, until: SystemTime) -> &[Snapshot] {
+        let end = self.snapshots.partition_point(|s| s.time <= until);
+        &self.snapshots[..end]
+    }
+
+    /// The snapshot holding `path`, if any.
+    pub fn containing(&self, path: &Path) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|s| path.starts_with(&s.root))
+    }
+}
+
+/// Plan a point-in-time restore: overlay each mapping's folder from every
+/// snapshot in `snapshots` (oldest first), so each file comes from the
+/// newest snapshot that has it.
+///
+/// Files deleted before the newest snapshot are restored from the last one
+/// that held them. Each mapping's source must lie inside one of the
+/// snapshots; its path relative to that snapshot is looked up in the
+/// others. Every `CopyOp` records the snapshot that supplied it.
+pub fn build_overlay_plan(
+    snapshots: &[Snapshot],
+    mappings: &[DetectedMapping],
+) -> io::Result<CopyPlan> {
+    let mut order = Vec::new();
+    let mut files: HashMap<PathBuf, CopyOp> = HashMap::new();
+    let mut seen_dirs = HashSet::new();
+    let mut dirs = Vec::new();
+
+    for mapping in mappings {
+        let Some(relative) = snapshots
+            .iter()
+            .find_map(|s| mapping.source_path.strip_prefix(&s.root).ok())
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not in any snapshot", mapping.source_path.display()),
+            ));
+        };
+
+        for snapshot in snapshots {
+            let source_root = snapshot.root.join(relative);
+            if !source_root.is_dir() {
+                continue;
+            }
+            for entry in walk_source(&source_root)? {
+                let dest = mapping.dest_path.join(&entry.relative);
+                if entry.is_dir {
+                    if seen_dirs.insert(dest.clone()) {
+                        dirs.push(DirOp {
+                            dest,
+                            source: Some(source_root.join(&entry.relative)),
+                        });
+                    }
+                    continue;
+                }
+                let op = CopyOp {
+                    source: source_root.join(&entry.relative),
+                    dest: dest.clone(),
+                    size: entry.size,
+                    mtime: entry.mtime,
+                    xdg_dir: mapping.xdg_dir,
+                    snapshot: Some(snapshot.name.clone()),
+                };
+                if files.insert(dest.clone(), op).is_none() {
+                    order.push(dest);
+                }
+            }
+        }
+    }
+
+    let files: Vec<CopyOp> = order
+        .into_iter()
+        .map(|dest| files.remove(&dest).unwrap())
+        .collect();
+    Ok(CopyPlan {
+        dirs,
+        total_bytes: files.iter().map(|f| f.size).sum(),
+        files,
+    })
+}
+
+/// Recognize a backup holding snapshots of the same tree.
+///
+/// Timeshift and Back In Time layouts are found from the backup drive's
+/// root or any folder down to a single snapshot series. Otherwise the
+/// backup's children must include at least two snapshot-like folders:
+/// rsnapshot interval folders (`<interval>.<n>`, timed by their mtime,
+/// which rsnapshot sets when the snapshot completes) or folders whose
+/// names start with a date.
+pub fn detect_snapshots(backup_root: &Path) -> Option<SnapshotSet> {
+    detect_timeshift(backup_root)
+        .or_else(|| detect_back_in_time(backup_root))
+        .or_else(|| detect_rotation(backup_root))
+}
+
+fn detect_rotation(backup_root: &Path) -> Option<SnapshotSet> {
+    let mut rotated = Vec::new();
+    let mut dated = Vec::new();
+    for entry in fs::read_dir(backup_root).ok()?.flatten() {
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if is_rotation_name(&name) {
+            if let Ok(time) = entry.metadata().and_then(|m| m.modified()) {
+                rotated.push(Snapshot {
+                    name,
+                    root: entry.path(),
+                    time,
+                });
+            }
+        } else if let Some((time, _)) = parse_stamp(&name) {
+            dated.push(Snapshot {
+                name,
+                root: entry.path(),
+                time,
+            });
+        }
+    }
+
+    if rotated.len() >= 2 {
+        Some(SnapshotSet::new(SnapshotLayout::Rsnapshot, rotated))
+    } else if dated.len() >= 2 {
+        Some(SnapshotSet::new(SnapshotLayout::Dated, dated))
+    } else {
+        None
+    }
+}
+
+/// Timeshift rsync snapshots copy the whole system under `localhost`,
+/// timed by the `created` field of their `info.json`.
+fn detect_timeshift(backup_root: &Path) -> Option<SnapshotSet> {
+    let dir = [
+        backup_root.join("timeshift/snapshots"),
+        backup_root.join("snapshots"),
+        backup_root.to_path_buf(),
+    ]
+    .into_iter()
+    .find(|d| d.is_dir())?;
+
+    let snapshots: Vec<Snapshot> = dated_children(&dir)
+        .into_iter()
+        .filter_map(|(name, path, stamp)| {
+            let info = fs::read_to_string(path.join("info.json")).ok()?;
+            let root = path.join("localhost");
+            if !root.is_dir() {
+                return None;
+            }
+            let time = timeshift_created(&info).unwrap_or(stamp);
+            Some(Snapshot { name, root, time })
+        })
+        .collect();
+    if snapshots.is_empty() {
+        return None;
+    }
+    Some(SnapshotSet::new(
+        SnapshotLayout::Timeshift,
+        narrow_to_home(snapshots),
+    ))
+}
+
+/// `"created" : "1614853351"` in a Timeshift `info.json`.
+fn timeshift_created(info: &str) -> Option<SystemTime> {
+    let info: serde_json::Value = serde_json::from_str(info).ok()?;
+    let secs = match &info["created"] {
+        serde_json::Value::String(s) => s.parse().ok()?,
+        v => v.as_u64()?,
+    };
+    Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+}
+
+/// Back In Time keeps `<host>/<user>/<profile>/<snapshot>` under a
+/// `backintime` folder; each snapshot holds the files under `backup`.
+fn detect_back_in_time(backup_root: &Path) -> Option<SnapshotSet> {
+    let in_tree = backup_root
+        .components()
+        .any(|c| c.as_os_str() == "backintime");
+    let start = backup_root.join("backintime");
+    let (start, depth) = if start.is_dir() {
+        (start, 3)
+    } else if in_tree {
+        (backup_root.to_path_buf(), 3)
+    } else {
+        (backup_root.to_path_buf(), 0)
+    };
+
+    let mut snapshots = Vec::new();
+    collect_back_in_time(&start, depth, &mut snapshots);
+    if snapshots.is_empty() {
+        return None;
+    }
+    Some(SnapshotSet::new(
+        SnapshotLayout::BackInTime,
+        narrow_to_home(snapshots),
+    ))
+}
+
+fn collect_back_in_time(dir: &Path, depth: usize, snapshots: &mut Vec<Snapshot>) {
+    let before = snapshots.len();
+    for (name, path, stamp) in dated_children(dir) {
+        let root = path.join("backup");
+        let has_metadata = path.join("info").is_file() || path.join("fileinfo.bz2").is_file();
+        if root.is_dir() && has_metadata {
+            let time = fs::read_to_string(path.join("info"))
+                .ok()
+                .and_then(|info| back_in_time_date(&info))
+                .unwrap_or(stamp);
+            snapshots.push(Snapshot { name, root, time });
+        }
+    }
+    if snapshots.len() > before || depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            collect_back_in_time(&entry.path(), depth - 1, snapshots);
+        }
+    }
+}
+
+/// `snapshot_date=20210304-102231` in a Back In Time `info` file.
+fn back_in_time_date(info: &str) -> Option<SystemTime> {
+    let date = info
+        .lines()
+        .find_map(|line| line.strip_prefix("snapshot_date="))?;
+    parse_stamp(date.trim()).map(|(time, _)| time)
+}
+
+/// Child directories (not symlinks) whose names start with a date.
+fn dated_children(dir: &Path) -> Vec<(String, PathBuf, SystemTime)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let (time, _) = parse_stamp(&name)?;
+            Some((name, e.path(), time))
+        })
+        .collect()
+}
+
+/// Point whole-system snapshots at the home directory inside them: the
+/// user's own when every snapshot holds the same single user, otherwise
+/// `home` itself. Applied to the whole set so folders line up across
+/// snapshots.
+fn narrow_to_home(mut snapshots: Vec<Snapshot>) -> Vec<Snapshot> {
+    let mut users = HashSet::new();
+    for s in &snapshots {
+        if let Ok(entries) = fs::read_dir(s.root.join("home")) {
+            users.extend(
+                entries
+                    .flatten()
+                    .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                    .map(|e| e.file_name()),
+            );
+        }
+    }
+    if users.is_empty() {
+        return snapshots;
+    }
+    let user = if users.len() == 1 {
+        users.into_iter().next()
+    } else {
+        None
+    };
+    for s in &mut snapshots {
+        s.root = s.root.join("home");
+        if let Some(user) = &user {
+            s.root.push(user);
+        }
+    }
+    snapshots
+}
+
+/// `daily.0`, `weekly.12`, `alpha.3`: a lowercase interval name and a number.
+fn is_rotation_name(name: &str) -> bool {
+    name.split_once('.').is_some_and(|(interval, n)| {
+        !interval.is_empty()
+            && interval.bytes().all(|b| b.is_ascii_lowercase())
+            && !n.is_empty()
+            && n.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// Parse a time like `2024-01-05`, `2024-01-05 14:30` or
+/// `2024-01-05T14:30:00`. A bare date means the end of that day.
+pub fn parse_time_spec(spec: &str) -> Option<SystemTime> {
+    let (time, has_time) = parse_stamp(spec)?;
+    Some(if has_time {
+        time
+    } else {
+        time + std::time::Duration::from_secs(86_399)
+    })
+}
+
+/// Read a date and optional time from the start of a name, in any of the
+/// usual separators: `2024-01-05_10-15-00`, `20240105-101500-123`,
+/// `2024-01-05T10:15`. Times are taken as UTC.
+pub(crate) fn parse_stamp(name: &str) -> Option<(SystemTime, bool)> {
+    let mut digits = String::new();
+    for c in name.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            if digits.len() == 14 {
+                break;
+            }
+        } else if !matches!(c, '-' | '_' | ':' | 'T' | ' ' | '.') {
+            break;
+        }
+    }
+    if !name.starts_with(|c: char| c.is_ascii_digit()) || digits.len() < 8 {
+        return None;
+    }
+
+    let d = digits.as_bytes();
+    let field = |range: std::ops::Range<usize>| std::str::from_utf8(&d[range]).unwrap();
+    let (hh, mm, ss, has_time) = match digits.len() {
+        12..=13 => (field(8..10), field(10..12), "00", true),
+        14 => (field(8..10), field(10..12), field(12..14), true),
+        _ => ("00", "00", "00", false),
+    };
+    let stamp = format!(
+        "{}-{}-{}T{hh}:{mm}:{ss}Z",
+        field(0..4),
+        field(4..6),
+        field(6..8)
+    );
+    humantime::parse_rfc3339(&stamp)
+        .ok()
+        .map(|time| (time, has_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::XdgDir;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn at(rfc: &str) -> SystemTime {
+        humantime::parse_rfc3339(rfc).unwrap()
+    }
+
+    fn set_mtime(path: &Path, time: SystemTime) {
+        fs::File::open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn detects_rsnapshot_rotation_ordered_by_mtime() {
+        let backup = tempdir().unwrap();
+        for (name, time) in [
+            ("daily.0", "2024-03-10T03:00:00Z"),
+            ("daily.1", "2024-03-09T03:00:00Z"),
+            ("weekly.0", "2024-03-03T04:00:00Z"),
+        ] {
+            let dir = backup.path().join(name);
+            fs::create_dir_all(dir.join("localhost/home/joe/Documents")).unwrap();
+            set_mtime(&dir, at(time));
+        }
+        fs::write(backup.path().join("rsnapshot.conf"), "").unwrap();
+
+        let set = detect_snapshots(backup.path()).unwrap();
+
+        assert_eq!(set.layout, SnapshotLayout::Rsnapshot);
+        let names: Vec<&str> = set.snapshots.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["weekly.0", "daily.1", "daily.0"]);
+        assert_eq!(set.latest().root, backup.path().join("daily.0"));
+    }
+
+    #[test]
+    fn detects_dated_folders() {
+        let backup = tempdir().unwrap();
+        for name in ["2024-01-05", "2024-02-01_10-15-00", "notes"] {
+            fs::create_dir(backup.path().join(name)).unwrap();
+        }
+
+        let set = detect_snapshots(backup.path()).unwrap();
+
+        assert_eq!(set.layout, SnapshotLayout::Dated);
+        assert_eq!(set.snapshots.len(), 2);
+        assert_eq!(set.latest().time, at("2024-02-01T10:15:00Z"));
+    }
+
+    #[test]
+    fn detects_timeshift_snapshots_from_drive_root() {
+        let drive = tempdir().unwrap();
+        let snapshots = drive.path().join("timeshift/snapshots");
+        for (name, created) in [
+            ("2021-03-04_10-22-31", "1614853351"),
+            ("2021-03-11_10-00-02", "1615456802"),
+        ] {
+            let snapshot = snapshots.join(name);
+            fs::create_dir_all(snapshot.join("localhost/home/joe/Documents")).unwrap();
+            fs::create_dir_all(snapshot.join("localhost/etc")).unwrap();
+            fs::write(
+                snapshot.join("info.json"),
+                format!("{{\n  \"name\" : \"{name}\",\n  \"created\" : \"{created}\"\n}}\n"),
+            )
+            .unwrap();
+        }
+        // Timeshift's own bookkeeping, not a snapshot
+        fs::create_dir_all(snapshots.join("2021-03-12_10-00-00")).unwrap();
+
+        let set = detect_snapshots(drive.path()).unwrap();
+
+        assert_eq!(set.layout, SnapshotLayout::Timeshift);
+        assert_eq!(set.snapshots.len(), 2);
+        let latest = set.latest();
+        assert_eq!(latest.name, "2021-03-11_10-00-02");
+        assert_eq!(
+            latest.root,
+            snapshots.join("2021-03-11_10-00-02/localhost/home/joe")
+        );
+        assert_eq!(
+            latest.time,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_615_456_802)
+        );
+    }
+
+    #[test]
+    fn detects_back_in_time_snapshots() {
+        let drive = tempdir().unwrap();
+        let profile = drive.path().join("backintime/laptop/joe/1");
+        for (name, user) in [
+            ("20210304-102231-123", "joe"),
+            ("20210311-090000-456", "ann"),
+        ] {
+            let snapshot = profile.join(name);
+            fs::create_dir_all(snapshot.join("backup/home").join(user).join("Music")).unwrap();
+            fs::write(snapshot.join("fileinfo.bz2"), "").unwrap();
+        }
+        fs::write(
+            profile.join("20210311-090000-456/info"),
+            "snapshot_version=1\nsnapshot_date=20210311-091500\n",
+        )
+        .unwrap();
+        // An unfinished snapshot has no metadata yet
+        fs::create_dir_all(profile.join("new_snapshot/backup")).unwrap();
+
+        for start in [drive.path().to_path_buf(), profile.clone()] {
+            let set = detect_snapshots(&start).unwrap();
+
+            assert_eq!(set.layout, SnapshotLayout::BackInTime);
+            assert_eq!(set.snapshots.len(), 2);
+            // Two different users: keep the whole of /home
+            assert_eq!(
+                set.snapshots[0].root,
+                profile.join("20210304-102231-123/backup/home")
+            );
+            assert_eq!(set.latest().time, at("2021-03-11T09:15:00Z"));
+        }
+    }
+
+    #[test]
+    fn ordinary_backups_are_not_snapshot_sets() {
+        let backup = tempdir().unwrap();
+        fs::create_dir(backup.path().join("Documents")).unwrap();
+        fs::create_dir(backup.path().join("daily.0")).unwrap();
+
+        assert!(detect_snapshots(backup.path()).is_none());
+    }
+
+    #[test]
+    fn finds_snapshots_by_name_or_time() {
+        let snapshot = |name: &str, time: &str| Snapshot {
+            name: name.to_string(),
+            root: PathBuf::from(name),
+            time: at(time),
+        };
+        let set = SnapshotSet {
+            layout: SnapshotLayout::Rsnapshot,
+            snapshots: vec![
+                snapshot("daily.2", "2024-03-08T03:00:00Z"),
+                snapshot("daily.1", "2024-03-09T03:00:00Z"),
+                snapshot("daily.0", "2024-03-10T03:00:00Z"),
+            ],
+        };
+
+        assert_eq!(set.find("daily.1").unwrap().name, "daily.1");
+        // A bare date covers the whole day
+        assert_eq!(set.find("2024-03-09").unwrap().name, "daily.1");
+        assert_eq!(set.find("2024-03-09 02:00").unwrap().name, "daily.2");
+        assert!(set.find("2024-03-01").is_none());
+        assert!(set.find("yesterday").is_none());
+    }
+
+    #[test]
+    fn parses_common_stamp_formats() {
+        for (name, expected) in [
+            ("2024-01-05_10-15-00", "2024-01-05T10:15:00Z"),
+            ("20240105-101500-123", "2024-01-05T10:15:00Z"),
+            ("2024-01-05T10:15", "2024-01-05T10:15:00Z"),
+        ] {
+            assert_eq!(parse_stamp(name), Some((at(expected), true)), "{name}");
+        }
+        assert_eq!(
+            parse_stamp("2024-01-05"),
+            Some((at("2024-01-05T00:00:00Z"), false))
+        );
+        assert_eq!(
+            parse_time_spec("2024-01-05"),
+            Some(at("2024-01-05T00:00:00Z") + Duration::from_secs(86_399))
+        );
+        assert_eq!(parse_stamp("2024-13-45"), None);
+        assert_eq!(parse_stamp("backup-2024-01-05"), None);
+    }
+
+    #[test]
+    fn overlay_takes_newest_version_and_keeps_deleted_files() {
+        let backup = tempdir().unwrap();
+        let snapshots: Vec<Snapshot> = [
+            ("weekly.0", "2024-03-03T04:00:00Z"),
+            ("daily.1", "2024-03-09T03:00:00Z"),
+            ("daily.0", "2024-03-10T03:00:00Z"),
+        ]
+        .iter()
+        .map(|(name, time)| Snapshot {
+            name: name.to_string(),
+            root: backup.path().join(name),
+            time: at(time),
+        })
+        .collect();
+        let docs = |s: &Snapshot| s.root.join("home/joe/Documents");
+        let write = |path: PathBuf, text: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write(docs(&snapshots[0]).join("gone/old.txt"), "old");
+        write(docs(&snapshots[0]).join("notes.txt"), "v1");
+        write(docs(&snapshots[1]).join("notes.txt"), "v2");
+        write(docs(&snapshots[2]).join("notes.txt"), "v3");
+        let mapping = DetectedMapping {
+            xdg_dir: XdgDir::Documents,
+            source_path: docs(&snapshots[1]),
+            dest_path: PathBuf::from("/home/joe/Documents"),
+        };
+
+        // As of daily.1, daily.0 is left out
+        let plan = build_overlay_plan(&snapshots[..2], &[mapping]).unwrap();
+
+        let source_of = |name: &str| {
+            let op = plan.files.iter().find(|f| f.dest.ends_with(name)).unwrap();
+            (
+                op.snapshot.as_deref().unwrap(),
+                fs::read_to_string(&op.source).unwrap(),
+            )
+        };
+        assert_eq!(plan.files.len(), 2);
+        assert_eq!(source_of("notes.txt"), ("daily.1", "v2".to_string()));
+        assert_eq!(source_of("gone/old.txt"), ("weekly.0", "old".to_string()));
+        assert!(plan
+            .dirs
+            .iter()
+            .any(|d| d.dest == Path::new("/home/joe/Documents/gone")));
+        assert_eq!(plan.total_bytes, 5);
+    }
+
+    #[test]
+    fn selects_snapshots_up_to_a_time() {
+        let snapshot = |name: &str, time: &str| Snapshot {
+            name: name.to_string(),
+            root: PathBuf::from("/backup").join(name),
+            time: at(time),
+        };
+        let set = SnapshotSet {
+            layout: SnapshotLayout::Dated,
+            snapshots: vec![
+                snapshot("2024-03-01", "2024-03-01T00:00:00Z"),
+                snapshot("2024-03-08", "2024-03-08T00:00:00Z"),
+            ],
+        };
+
+        assert_eq!(set.until(at("2024-03-05T00:00:00Z")).len(), 1);
+        assert!(set.until(at("2024-02-01T00:00:00Z")).is_empty());
+        assert_eq!(
+            set.containing(Path::new("/backup/2024-03-08/home/Music"))
+                .unwrap()
+                .name,
+            "2024-03-08"
+        );
+    }
+}
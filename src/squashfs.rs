@@ -0,0 +1,680 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, SystemTime};
+
+use crate::image::{corrupt, Entry, EntryKind, Image, ImageError};
+
+const MAGIC: &[u8; 4] = b"hsqs";
+const METADATA_SIZE: usize = 8192;
+/// Set in a metadata block header when the block is stored uncompressed.
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+/// Set in a data block or fragment size when it is stored uncompressed.
+const DATA_UNCOMPRESSED: u32 = 0x0100_0000;
+const NO_FRAGMENT: u32 = 0xffff_ffff;
+const FRAGMENT_ENTRY_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, Copy)]
+enum Compression {
+    Gzip,
+    Xz,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    fn from_id(id: u16) -> Result<Compression, ImageError> {
+        match id {
+            1 => Ok(Compression::Gzip),
+            4 => Ok(Compression::Xz),
+            5 => Ok(Compression::Lz4),
+            6 => Ok(Compression::Zstd),
+            2 => Err(ImageError::Unsupported("lzma compression".into())),
+            3 => Err(ImageError::Unsupported("lzo compression".into())),
+            n => Err(corrupt(format!("unknown compression {n}"))),
+        }
+    }
+
+    /// Decompress a block, reading no more than one byte past `max`, so a
+    /// damaged or hostile block can't expand without bound.
+    fn decompress(self, data: &[u8], max: usize) -> Result<Vec<u8>, ImageError> {
+        let limit = max as u64 + 1;
+        let mut out = Vec::with_capacity(max);
+        match self {
+            Compression::Gzip => {
+                flate2::read::ZlibDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut out)?;
+            }
+            Compression::Xz => {
+                let mut capped = Capped { out, max: max + 1 };
+                lzma_rs::xz_decompress(&mut &data[..], &mut capped).map_err(corrupt)?;
+                out = capped.out;
+            }
+            Compression::Lz4 => {
+                out = lz4_flex::block::decompress(data, max).map_err(corrupt)?;
+            }
+            Compression::Zstd => {
+                ruzstd::decoding::StreamingDecoder::new(data)
+                    .map_err(corrupt)?
+                    .take(limit)
+                    .read_to_end(&mut out)?;
+            }
+        }
+        if out.len() > max {
+            return Err(corrupt("block decompresses past its size"));
+        }
+        Ok(out)
+    }
+}
+
+/// The [`Read::take`] of a decompressor that writes: keeps the first `max`
+/// bytes written and refuses the rest.
+struct Capped {
+    out: Vec<u8>,
+    max: usize,
+}
+
+impl Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.max - self.out.len();
+        if room == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "block decompresses past its size",
+            ));
+        }
+        let n = buf.len().min(room);
+        self.out.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Superblock {
+    block_size: u32,
+    compression: Compression,
+    root_inode: u64,
+    inode_table: u64,
+    directory_table: u64,
+    fragment_table: u64,
+}
+
+/// Where a regular file's data lives.
+struct FileData {
+    size: u64,
+    blocks_start: u64,
+    /// On-disk block sizes, with [`DATA_UNCOMPRESSED`] as a flag.
+    blocks: Vec<u32>,
+    /// Fragment index and offset of the file's tail, if it has one.
+    fragment: Option<(u32, u32)>,
+}
+
+/// A `SquashFS` 4.0 image, as written by `mksquashfs`.
+pub struct SquashFs {
+    file: File,
+    /// The image's length, where the last metadata table ends.
+    len: u64,
+    sb: Superblock,
+    root: Vec<Entry>,
+    files: Vec<FileData>,
+}
+
+impl SquashFs {
+    /// Read the superblock and the whole directory tree.
+    pub fn open(file: File) -> Result<SquashFs, ImageError> {
+        let mut raw = [0u8; 96];
+        file.read_exact_at(&mut raw, 0)?;
+        if &raw[..4] != MAGIC {
+            return Err(corrupt("not a SquashFS image"));
+        }
+        let (major, minor) = (le16(&raw, 28), le16(&raw, 30));
+        if (major, minor) != (4, 0) {
+            return Err(ImageError::Unsupported(format!(
+                "SquashFS version {major}.{minor}"
+            )));
+        }
+        let block_size = le32(&raw, 12);
+        if !(4096..=1 << 20).contains(&block_size) || !block_size.is_power_of_two() {
+            return Err(corrupt(format!("bad block size {block_size}")));
+        }
+        let sb = Superblock {
+            block_size,
+            compression: Compression::from_id(le16(&raw, 20))?,
+            root_inode: le64(&raw, 32),
+            inode_table: le64(&raw, 64),
+            directory_table: le64(&raw, 72),
+            fragment_table: le64(&raw, 80),
+        };
+
+        let mut image = SquashFs {
+            len: file.metadata()?.len(),
+            file,
+            sb,
+            root: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut files = Vec::new();
+        let root = image.read_inode(image.sb.root_inode, String::new(), 0, &mut files)?;
+        image.files = files;
+        image.root = match root.kind {
+            EntryKind::Dir(children) => children,
+            _ => return Err(corrupt("root is not a directory")),
+        };
+        Ok(image)
+    }
+
+    /// Read from the metadata table at `table`, which ends before `end`.
+    fn metadata(&self, table: u64, end: u64, reference: u64) -> MetadataReader<'_> {
+        MetadataReader {
+            image: self,
+            next_block: table.saturating_add(reference >> 16),
+            end,
+            buf: Vec::new(),
+            pos: usize::from(u16::try_from(reference & 0xffff).unwrap()),
+            skip_first: true,
+        }
+    }
+
+    fn read_inode(
+        &self,
+        reference: u64,
+        name: String,
+        depth: usize,
+        files: &mut Vec<FileData>,
+    ) -> Result<Entry, ImageError> {
+        // Guards against directory loops in a damaged image
+        if depth > 256 {
+            return Err(corrupt("directories nested too deeply"));
+        }
+        let mut r = self.metadata(self.sb.inode_table, self.sb.directory_table, reference);
+        let header = r.read(16)?;
+        let kind = le16(&header, 0);
+        let mode = u32::from(le16(&header, 2) & 0o7777);
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(u64::from(le32(&header, 8)));
+
+        let kind = match kind {
+            1 => {
+                let d = r.read(16)?;
+                let (block, size, offset) = (le32(&d, 0), le16(&d, 8), le16(&d, 10));
+                EntryKind::Dir(self.read_dir(block, u32::from(size), offset, depth, files)?)
+            }
+            8 => {
+                let d = r.read(24)?;
+                let (size, block, offset) = (le32(&d, 4), le32(&d, 8), le16(&d, 18));
+                EntryKind::Dir(self.read_dir(block, size, offset, depth, files)?)
+            }
+            2 => {
+                let d = r.read(16)?;
+                let size = u64::from(le32(&d, 12));
+                let (start, frag, frag_offset) = (u64::from(le32(&d, 0)), le32(&d, 4), le32(&d, 8));
+                Self::file_entry(
+                    &mut r,
+                    self.sb.block_size,
+                    start,
+                    size,
+                    frag,
+                    frag_offset,
+                    files,
+                )?
+            }
+            9 => {
+                let d = r.read(40)?;
+                let (start, size) = (le64(&d, 0), le64(&d, 8));
+                let (frag, frag_offset) = (le32(&d, 28), le32(&d, 32));
+                Self::file_entry(
+                    &mut r,
+                    self.sb.block_size,
+                    start,
+                    size,
+                    frag,
+                    frag_offset,
+                    files,
+                )?
+            }
+            3 | 10 => {
+                let d = r.read(8)?;
+                let target = r.read(le32(&d, 4) as usize)?;
+                EntryKind::Symlink(String::from_utf8_lossy(&target).into_owned())
+            }
+            4..=7 | 11..=14 => EntryKind::Other,
+            n => return Err(corrupt(format!("unknown inode type {n}"))),
+        };
+        Ok(Entry {
+            name,
+            mode,
+            mtime,
+            kind,
+        })
+    }
+
+    fn file_entry(
+        r: &mut MetadataReader,
+        block_size: u32,
+        blocks_start: u64,
+        size: u64,
+        fragment: u32,
+        fragment_offset: u32,
+        files: &mut Vec<FileData>,
+    ) -> Result<EntryKind, ImageError> {
+        let block_size = u64::from(block_size);
+        let count = if fragment == NO_FRAGMENT {
+            size.div_ceil(block_size)
+        } else {
+            size / block_size
+        };
+        // Read through the table, so a damaged size can't ask for more
+        // than the image holds
+        let raw = r.read(usize::try_from(count.saturating_mul(4)).map_err(corrupt)?)?;
+        let blocks = raw.chunks_exact(4).map(|c| le32(c, 0)).collect();
+
+        let id = files.len();
+        files.push(FileData {
+            size,
+            blocks_start,
+            blocks,
+            fragment: (fragment != NO_FRAGMENT).then_some((fragment, fragment_offset)),
+        });
+        Ok(EntryKind::File { size, id })
+    }
+
+    fn read_dir(
+        &self,
+        block: u32,
+        size: u32,
+        offset: u16,
+        depth: usize,
+        files: &mut Vec<FileData>,
+    ) -> Result<Vec<Entry>, ImageError> {
+        // The stored size counts the implicit `.` and `..` entries
+        let Some(mut remaining) = (size as usize).checked_sub(3) else {
+            return Ok(Vec::new());
+        };
+        let reference = (u64::from(block) << 16) | u64::from(offset);
+        let mut r = self.metadata(self.sb.directory_table, self.len, reference);
+        let mut entries = Vec::new();
+        while remaining >= 12 {
+            let header = r.read(12)?;
+            remaining -= 12;
+            let count = le32(&header, 0)
+                .checked_add(1)
+                .ok_or_else(|| corrupt("bad directory header"))?;
+            let start = le32(&header, 4);
+            for _ in 0..count {
+                let e = r.read(8)?;
+                let inode_offset = le16(&e, 0);
+                let name_len = usize::from(le16(&e, 6)) + 1;
+                let name = r.read(name_len)?;
+                remaining = remaining.saturating_sub(8 + name_len);
+                let name = String::from_utf8_lossy(&name).into_owned();
+                let reference = (u64::from(start) << 16) | u64::from(inode_offset);
+                entries.push(self.read_inode(reference, name, depth + 1, files)?);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_block(&self, start: u64, stored: u32, max: usize) -> Result<Vec<u8>, ImageError> {
+        let len = (stored & !DATA_UNCOMPRESSED) as usize;
+        let mut raw = vec![0; len];
+        self.file.read_exact_at(&mut raw, start)?;
+        if stored & DATA_UNCOMPRESSED != 0 {
+            Ok(raw)
+        } else {
+            self.sb.compression.decompress(&raw, max)
+        }
+    }
+
+    fn fragment_location(&self, index: u32) -> Result<(u64, u32), ImageError> {
+        let entry = u64::from(index) * FRAGMENT_ENTRY_SIZE;
+        let mut pointer = [0u8; 8];
+        let slot = entry / METADATA_SIZE as u64;
+        let at = self
+            .sb
+            .fragment_table
+            .checked_add(slot * 8)
+            .ok_or_else(|| corrupt("fragment table past the end of the image"))?;
+        self.file.read_exact_at(&mut pointer, at)?;
+        let block = u64::from_le_bytes(pointer);
+        let within = entry % METADATA_SIZE as u64;
+        let mut r = self.metadata(block, self.sb.fragment_table, within);
+        let e = r.read(16)?;
+        Ok((le64(&e, 0), le32(&e, 8)))
+    }
+}
+
+impl Image for SquashFs {
+    fn root(&self) -> &[Entry] {
+        &self.root
+    }
+
+    fn read_file(&self, id: usize, out: &mut dyn Write) -> Result<(), ImageError> {
+        let data = &self.files[id];
+        let block_size = self.sb.block_size as usize;
+        let mut left = data.size;
+        let mut pos = data.blocks_start;
+        for &stored in &data.blocks {
+            let want = block_size.min(usize::try_from(left).unwrap_or(usize::MAX));
+            if stored == 0 {
+                // Sparse block
+                out.write_all(&vec![0; want])?;
+            } else {
+                let block = self.read_block(pos, stored, block_size)?;
+                out.write_all(
+                    block
+                        .get(..want)
+                        .ok_or_else(|| corrupt("short data block"))?,
+                )?;
+                pos += u64::from(stored & !DATA_UNCOMPRESSED);
+            }
+            left -= want as u64;
+        }
+        if let Some((index, offset)) = data.fragment {
+            let (start, stored) = self.fragment_location(index)?;
+            let block = self.read_block(start, stored, block_size)?;
+            let offset = offset as usize;
+            let tail = usize::try_from(left).map_err(corrupt)?;
+            out.write_all(
+                block
+                    .get(offset..offset + tail)
+                    .ok_or_else(|| corrupt("short fragment"))?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a run of bytes that may span consecutive metadata blocks.
+struct MetadataReader<'a> {
+    image: &'a SquashFs,
+    next_block: u64,
+    /// Where the table ends; reads never go past it.
+    end: u64,
+    buf: Vec<u8>,
+    pos: usize,
+    /// The first block's offset applies to the first block only.
+    skip_first: bool,
+}
+
+impl MetadataReader<'_> {
+    fn load(&mut self) -> Result<(), ImageError> {
+        if self.next_block >= self.end {
+            return Err(corrupt("metadata runs past the end of its table"));
+        }
+        let mut header = [0u8; 2];
+        self.image
+            .file
+            .read_exact_at(&mut header, self.next_block)?;
+        let header = u16::from_le_bytes(header);
+        let len = usize::from(header & !METADATA_UNCOMPRESSED);
+        let mut raw = vec![0; len];
+        self.image
+            .file
+            .read_exact_at(&mut raw, self.next_block + 2)?;
+        self.next_block += 2 + len as u64;
+        self.buf = if header & METADATA_UNCOMPRESSED != 0 {
+            raw
+        } else {
+            self.image.sb.compression.decompress(&raw, METADATA_SIZE)?
+        };
+        if self.skip_first {
+            self.skip_first = false;
+        } else {
+            self.pos = 0;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, len: usize) -> Result<Vec<u8>, ImageError> {
+        let mut out = Vec::with_capacity(len.min(METADATA_SIZE));
+        while out.len() < len {
+            if self.skip_first || self.pos >= self.buf.len() {
+                let first = self.skip_first;
+                self.load()?;
+                if first && self.pos > self.buf.len() {
+                    return Err(corrupt("metadata offset past the end of its block"));
+                }
+                continue;
+            }
+            let take = (len - out.len()).min(self.buf.len() - self.pos);
+            out.extend_from_slice(&self.buf[self.pos..self.pos + take]);
+            self.pos += take;
+        }
+        Ok(out)
+    }
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+// Fixture builders write lengths into the format's narrow fields
+#[allow(clippy::cast_possible_truncation)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::restore_files;
+    use crate::image::{list_image, open_image, ImageKind};
+    use std::fs;
+    use std::io::Seek;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    const BLOCK: usize = 4096;
+
+    fn le16b(v: u16) -> [u8; 2] {
+        v.to_le_bytes()
+    }
+
+    /// Lays out a small image the way `mksquashfs` does: data blocks and
+    /// fragments, then the inode table, directory table and fragment table.
+    /// Metadata is stored uncompressed, data blocks with zlib.
+    fn build_image() -> Vec<u8> {
+        let notes: Vec<u8> = (0..BLOCK + 5).map(|i| b"notes"[i % 5]).collect();
+        let mut img = vec![0u8; 96];
+
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&notes[..BLOCK]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let blocks_start = img.len() as u32;
+        img.extend(&compressed);
+
+        // Fragment block, stored uncompressed: the tail of notes.txt, then tiny.txt
+        let fragment_start = img.len() as u64;
+        let fragment = [&notes[BLOCK..], b"hi"].concat();
+        img.extend(&fragment);
+
+        let mut inodes = Vec::new();
+        let mut inode = |kind: u16, mode: u16, mtime: u32, body: &[u8]| -> u64 {
+            let at = inodes.len() as u64;
+            inodes.extend(le16b(kind));
+            inodes.extend(le16b(mode));
+            inodes.extend([0; 4]);
+            inodes.extend(mtime.to_le_bytes());
+            inodes.extend(((at as u32) + 1).to_le_bytes());
+            inodes.extend(body);
+            at
+        };
+        let file = |start: u32, offset: u32, size: u32, blocks: &[u32]| {
+            let mut body = Vec::new();
+            for v in [start, 0, offset, size].iter().chain(blocks) {
+                body.extend(v.to_le_bytes());
+            }
+            body
+        };
+        let notes_ref = inode(
+            2,
+            0o640,
+            1_600_000_000,
+            &file(
+                blocks_start,
+                0,
+                notes.len() as u32,
+                &[compressed.len() as u32],
+            ),
+        );
+        let tiny_ref = inode(2, 0o755, 1_600_000_001, &file(0, 5, 2, &[]));
+        let link_ref = inode(
+            3,
+            0o777,
+            1_600_000_002,
+            &[&1u32.to_le_bytes()[..], &9u32.to_le_bytes(), b"notes.txt"].concat(),
+        );
+
+        let mut dirs = Vec::new();
+        let mut listing = |entries: &[(u64, u16, &str)]| -> (u16, u16) {
+            let at = dirs.len();
+            dirs.extend((entries.len() as u32 - 1).to_le_bytes());
+            dirs.extend(0u32.to_le_bytes());
+            dirs.extend(1u32.to_le_bytes());
+            for (reference, kind, name) in entries {
+                dirs.extend(le16b(*reference as u16));
+                dirs.extend(0i16.to_le_bytes());
+                dirs.extend(le16b(*kind));
+                dirs.extend(le16b(name.len() as u16 - 1));
+                dirs.extend(name.as_bytes());
+            }
+            (at as u16, (dirs.len() - at) as u16 + 3)
+        };
+        let (docs_at, docs_size) = listing(&[
+            (link_ref, 3, "link"),
+            (notes_ref, 2, "notes.txt"),
+            (tiny_ref, 2, "tiny.txt"),
+        ]);
+        let dir_body = |offset: u16, size: u16| {
+            [
+                &0u32.to_le_bytes()[..],
+                &2u32.to_le_bytes(),
+                &le16b(size),
+                &le16b(offset),
+                &0u32.to_le_bytes(),
+            ]
+            .concat()
+        };
+        let docs_ref = inode(1, 0o755, 1_600_000_003, &dir_body(docs_at, docs_size));
+        let (root_at, root_size) = listing(&[(docs_ref, 1, "Documents"), (tiny_ref, 2, "x")]);
+        let root_ref = inode(1, 0o755, 1_600_000_004, &dir_body(root_at, root_size));
+
+        let fragment_entry = [
+            &fragment_start.to_le_bytes()[..],
+            &(fragment.len() as u32 | DATA_UNCOMPRESSED).to_le_bytes(),
+            &[0; 4],
+        ]
+        .concat();
+        write_tables(&mut img, root_ref, &inodes, &dirs, &fragment_entry);
+        img
+    }
+
+    /// Append the metadata tables, stored uncompressed, and fill in the
+    /// superblock to point at them.
+    fn write_tables(img: &mut Vec<u8>, root_ref: u64, inodes: &[u8], dirs: &[u8], fragment: &[u8]) {
+        let metadata = |data: &[u8]| [&le16b(0x8000 | data.len() as u16)[..], data].concat();
+        let inode_table = img.len() as u64;
+        img.extend(metadata(inodes));
+        let directory_table = img.len() as u64;
+        img.extend(metadata(dirs));
+        let fragment_entries = img.len() as u64;
+        img.extend(metadata(fragment));
+        let fragment_table = img.len() as u64;
+        img.extend(fragment_entries.to_le_bytes());
+        let tables = [inode_table, directory_table, fragment_table];
+
+        let sb = &mut img[..96];
+        sb[..4].copy_from_slice(MAGIC);
+        sb[12..16].copy_from_slice(&(BLOCK as u32).to_le_bytes());
+        sb[16..20].copy_from_slice(&1u32.to_le_bytes());
+        sb[20..22].copy_from_slice(&le16b(1));
+        sb[22..24].copy_from_slice(&le16b(12));
+        sb[28..30].copy_from_slice(&le16b(4));
+        sb[32..40].copy_from_slice(&root_ref.to_le_bytes());
+        for (i, table) in tables.iter().enumerate() {
+            sb[64 + i * 8..72 + i * 8].copy_from_slice(&table.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn reads_tree_and_file_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("home.sqfs");
+        fs::write(&path, build_image()).unwrap();
+
+        let image = open_image(&path, ImageKind::SquashFs).unwrap();
+        let (listing, files) = list_image(image).unwrap();
+        let out = tempdir().unwrap();
+        let result = restore_files(&files, &listing, out.path());
+
+        let docs = out.path().join("Documents");
+        assert_eq!(result.copied.len(), 2);
+        assert_eq!(listing.skipped, 1);
+        let notes = fs::read(docs.join("notes.txt")).unwrap();
+        assert_eq!(notes.len(), BLOCK + 5);
+        assert!(notes.starts_with(b"notesnotes"));
+        assert_eq!(fs::read_to_string(docs.join("tiny.txt")).unwrap(), "hi");
+        let meta = fs::metadata(docs.join("notes.txt")).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(
+            meta.modified().unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        assert!(!out.path().join("x").exists());
+    }
+
+    #[test]
+    fn rejects_unsupported_compression() {
+        let mut img = build_image();
+        img[20..22].copy_from_slice(&le16b(3));
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&img).unwrap();
+        file.rewind().unwrap();
+
+        let err = SquashFs::open(file).err().unwrap();
+
+        assert!(matches!(err, ImageError::Unsupported(_)));
+    }
+
+    #[test]
+    fn refuses_blocks_and_tables_that_overrun() {
+        let zeros = vec![0u8; 64 * 1024];
+        let mut gzip = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(&zeros).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = ruzstd::encoding::compress_to_vec(
+            &zeros[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &zeros[..], &mut xz).unwrap();
+        for (compression, data) in [
+            (Compression::Gzip, gzip),
+            (Compression::Zstd, zstd),
+            (Compression::Xz, xz),
+        ] {
+            assert!(compression.decompress(&data, METADATA_SIZE).is_err());
+            assert_eq!(compression.decompress(&data, zeros.len()).unwrap(), zeros);
+        }
+
+        // An inode table that ends before the root inode is read
+        let mut img = build_image();
+        let inode_table = img[64..72].to_vec();
+        img[72..80].copy_from_slice(&inode_table);
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&img).unwrap();
+        file.rewind().unwrap();
+
+        let err = SquashFs::open(file).err().unwrap();
+
+        assert!(matches!(err, ImageError::Corrupt(_)));
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A scratch directory that backups stored in archive formats are unpacked
+/// into, so the usual scan → plan → copy pipeline can read them as a plain
+/// file tree. Removed on drop unless [`keep`](StagingDir::keep) is called.
+#[derive(Debug)]
+pub struct StagingDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl StagingDir {
+    /// Create a fresh staging directory under `parent`, named after `label`
+    /// and this process so concurrent restores don't collide.
+    pub fn create(parent: &Path, label: &str) -> io::Result<StagingDir> {
+        let path = parent.join(format!("{label}-{}", std::process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(StagingDir { path, keep: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place, e.g. because a saved plan refers to it.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Where staging directories go by default: the user's cache directory,
+/// which is usually on the same disk as the home being restored into.
+pub fn default_parent(home_dir: &Path) -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map_or_else(|| home_dir.join(".cache"), PathBuf::from)
+        .join("backup-restore")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn removed_on_drop_unless_kept() {
+        let parent = tempdir().unwrap();
+
+        let staging = StagingDir::create(parent.path(), "duplicity").unwrap();
+        let path = staging.path().to_path_buf();
+        fs::write(path.join("a.txt"), "a").unwrap();
+        drop(staging);
+        assert!(!path.exists());
+
+        let mut staging = StagingDir::create(parent.path(), "duplicity").unwrap();
+        staging.keep();
+        let path = staging.path().to_path_buf();
+        drop(staging);
+        assert!(path.is_dir());
+    }
+}
@@ -0,0 +1,306 @@
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// A freedesktop.org trash can.
+///
+/// Files on the same filesystem as the home trash go there; files on other
+/// filesystems go to that filesystem's `$topdir/.Trash/$uid` or
+/// `$topdir/.Trash-$uid`, as the Trash spec requires, so nothing is copied
+/// across devices.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    home_trash: PathBuf,
+    /// Whose trash it is, naming its folders on other filesystems.
+    uid: u32,
+}
+
+impl Trash {
+    /// The user's home trash, `$XDG_DATA_HOME/Trash`.
+    pub fn from_env() -> Trash {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .unwrap_or_else(|| {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/share")
+            });
+        Trash::new(data_home.join("Trash"))
+    }
+
+    /// A trash rooted at an explicit directory.
+    pub fn new(home_trash: PathBuf) -> Trash {
+        // SAFETY: getuid has no preconditions and cannot fail.
+        let uid = unsafe { libc::getuid() };
+        Trash { home_trash, uid }
+    }
+
+    /// Another user's home trash, `~/.local/share/Trash` in `home`, for
+    /// root to use on their behalf. What it creates belongs to whoever
+    /// writes it, so it should be used with that user's file access.
+    pub fn for_user(home: &Path, uid: u32) -> Trash {
+        Trash {
+            home_trash: home.join(".local/share/Trash"),
+            uid,
+        }
+    }
+
+    /// Move `path` (a file or directory) into the trash, writing its
+    /// `.trashinfo` entry. Returns where the item now lives.
+    pub fn trash(&self, path: &Path) -> io::Result<PathBuf> {
+        self.trash_as(path, path)
+    }
+
+    /// Like [`Trash::trash`], but record the item as having come from
+    /// `original_location`, so "Restore" in a file manager puts it there.
+    ///
+    /// Used when a file has already been moved aside, e.g. an original
+    /// swapped onto its `.restore` path.
+    pub fn trash_as(&self, path: &Path, original_location: &Path) -> io::Result<PathBuf> {
+        let path = absolute_parent(path)?;
+        let original_location = absolute_parent(original_location)?;
+        let file_dev = fs::symlink_metadata(&path)?.dev();
+
+        ensure_trash_dir(&self.home_trash)?;
+        let (trash_dir, info_path) = if fs::metadata(&self.home_trash)?.dev() == file_dev {
+            (self.home_trash.clone(), original_location.clone())
+        } else {
+            let topdir = mount_point(&path, file_dev)?;
+            let trash_dir = topdir_trash(&topdir, self.uid)?;
+            // Paths in a topdir trash are relative to the topdir
+            let relative = original_location
+                .strip_prefix(&topdir)
+                .unwrap_or(&original_location)
+                .to_path_buf();
+            (trash_dir, relative)
+        };
+
+        let (name, info_file) = reserve_name(&trash_dir, &original_location, &info_path)?;
+        let dest = trash_dir.join("files").join(&name);
+        if let Err(e) = fs::rename(&path, &dest) {
+            let _ = fs::remove_file(&info_file);
+            return Err(e);
+        }
+        Ok(dest)
+    }
+}
+
+/// Make `path` absolute without resolving a symlink in its final component.
+fn absolute_parent(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot trash a root path"))?;
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    Ok(parent.join(name))
+}
+
+fn ensure_trash_dir(trash_dir: &Path) -> io::Result<()> {
+    for sub in ["files", "info"] {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(trash_dir.join(sub))?;
+    }
+    Ok(())
+}
+
+/// Walk up from `path` to the last ancestor on the same device.
+fn mount_point(path: &Path, dev: u64) -> io::Result<PathBuf> {
+    let mut top = path.parent().unwrap_or(path).to_path_buf();
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        top = parent.to_path_buf();
+    }
+    Ok(top)
+}
+
+/// Pick `uid`'s trash directory on a mounted filesystem.
+fn topdir_trash(topdir: &Path, uid: u32) -> io::Result<PathBuf> {
+    // $topdir/.Trash is only usable if an admin created it with the sticky bit
+    let shared = topdir.join(".Trash");
+    if let Ok(meta) = fs::symlink_metadata(&shared) {
+        if meta.is_dir() && meta.mode() & 0o1000 != 0 {
+            let dir = shared.join(uid.to_string());
+            if ensure_trash_dir(&dir).is_ok() {
+                return Ok(dir);
+            }
+        }
+    }
+
+    let dir = topdir.join(format!(".Trash-{uid}"));
+    ensure_trash_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Claim a free name in the trash by atomically creating its `.trashinfo`.
+fn reserve_name(
+    trash_dir: &Path,
+    original: &Path,
+    info_path: &Path,
+) -> io::Result<(OsString, PathBuf)> {
+    let base = original.file_name().unwrap_or_default();
+    let contents = trash_info(info_path, &local_timestamp());
+
+    for n in 1u32.. {
+        let mut name = base.to_os_string();
+        if n > 1 {
+            name.push(format!(".{n}"));
+        }
+        if trash_dir.join("files").join(&name).exists() {
+            continue;
+        }
+        let mut info_name = name.clone();
+        info_name.push(".trashinfo");
+        let info_file = trash_dir.join("info").join(info_name);
+        match File::create_new(&info_file) {
+            Ok(mut f) => {
+                f.write_all(contents.as_bytes())?;
+                return Ok((name, info_file));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+fn trash_info(original: &Path, deletion_date: &str) -> String {
+    format!(
+        "[Trash Info]\nPath={}\nDeletionDate={deletion_date}\n",
+        percent_encode(original)
+    )
+}
+
+/// Escape a path the way the spec requires: as in a URL path, keeping `/`.
+fn percent_encode(path: &Path) -> String {
+    let mut out = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            write!(out, "%{b:02X}").unwrap();
+        }
+    }
+    out
+}
+
+/// Current local time as `YYYY-MM-DDThh:mm:ss`.
+fn local_timestamp() -> String {
+    // SAFETY: time(NULL) only returns the clock; localtime_r writes into the
+    // zeroed tm we own and returns null on failure, which we check.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&raw const now, &raw mut tm).is_null() {
+            return "1970-01-01T00:00:00".to_string();
+        }
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn moves_file_and_writes_trashinfo() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let victim = dir.path().join("my notes.txt");
+        fs::write(&victim, "old").unwrap();
+
+        let dest = trash.trash(&victim).unwrap();
+
+        assert!(!victim.exists());
+        assert_eq!(dest, dir.path().join("Trash/files/my notes.txt"));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        let info =
+            fs::read_to_string(dir.path().join("Trash/info/my notes.txt.trashinfo")).unwrap();
+        let canonical = dir.path().canonicalize().unwrap();
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!(
+            "Path={}/my%20notes.txt\n",
+            percent_encode(&canonical)
+        )));
+        assert!(info.contains("DeletionDate="));
+    }
+
+    #[test]
+    fn numbers_names_that_are_already_taken() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let victim = dir.path().join("a.txt");
+
+        fs::write(&victim, "first").unwrap();
+        trash.trash(&victim).unwrap();
+        fs::write(&victim, "second").unwrap();
+        let dest = trash.trash(&victim).unwrap();
+
+        assert_eq!(dest, dir.path().join("Trash/files/a.txt.2"));
+        assert!(dir.path().join("Trash/info/a.txt.2.trashinfo").exists());
+        assert_eq!(fs::read_to_string(dest).unwrap(), "second");
+    }
+
+    #[test]
+    fn trashes_whole_directories() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let victim = dir.path().join("Documents");
+        fs::create_dir_all(victim.join("sub")).unwrap();
+        fs::write(victim.join("sub/x"), "x").unwrap();
+
+        let dest = trash.trash(&victim).unwrap();
+
+        assert!(!victim.exists());
+        assert_eq!(fs::read_to_string(dest.join("sub/x")).unwrap(), "x");
+    }
+
+    #[test]
+    fn trash_as_records_the_given_location() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let moved_aside = dir.path().join("photo.restore.jpg");
+        fs::write(&moved_aside, "original").unwrap();
+
+        let dest = trash
+            .trash_as(&moved_aside, &dir.path().join("photo.jpg"))
+            .unwrap();
+
+        assert_eq!(dest, dir.path().join("Trash/files/photo.jpg"));
+        let info = fs::read_to_string(dir.path().join("Trash/info/photo.jpg.trashinfo")).unwrap();
+        assert!(info.contains("/photo.jpg\n"));
+    }
+
+    #[test]
+    fn percent_encodes_reserved_bytes() {
+        assert_eq!(
+            percent_encode(Path::new("/home/joe/a b%c/ü.txt")),
+            "/home/joe/a%20b%25c/%C3%BC.txt"
+        );
+    }
+
+    #[test]
+    fn timestamp_has_spec_format() {
+        let ts = local_timestamp();
+        assert_eq!(ts.len(), 19);
+        assert_eq!(&ts[10..11], "T");
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::conflict::Resolution;
+use crate::preview;
+use crate::types::{Conflict, XdgDir};
+
+const HELP: &str =
+    "o overwrite  k keep  b both  m merge  space mark  a mark all  / filter  u undo  c commit  q quit";
+
+/// Browse conflicts in a full-screen view and decide them.
+///
+/// Nothing is touched on disk here. Returns the decisions as indices into
+/// `conflicts` when the user commits, or `None` when they quit. Conflicts
+/// left undecided are not included.
+pub fn review(
+    conflicts: &[Conflict],
+    home_dir: &Path,
+) -> io::Result<Option<Vec<(usize, Resolution)>>> {
+    let mut review = Review::new(conflicts, home_dir);
+    let mut terminal = ratatui::try_init().map_err(|e| {
+        ratatui::restore();
+        io::Error::new(
+            e.kind(),
+            format!("the full-screen view needs a terminal: {e}"),
+        )
+    })?;
+    let exit = run(&mut terminal, &mut review);
+    ratatui::restore();
+
+    Ok(match exit? {
+        Exit::Commit => Some(review.decisions()),
+        Exit::Cancel => None,
+    })
+}
+
+fn run(terminal: &mut DefaultTerminal, review: &mut Review) -> io::Result<Exit> {
+    loop {
+        terminal.draw(|frame| review.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Release {
+                if let Some(exit) = review.handle_key(key) {
+                    return Ok(exit);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Commit,
+    Cancel,
+}
+
+/// A line in the conflict tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Row {
+    Dir(XdgDir),
+    /// A subfolder, as a path relative to the home directory.
+    Folder(XdgDir, PathBuf),
+    File(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    Filter,
+}
+
+/// Session state: pending decisions, marks, filter and undo history.
+struct Review<'a> {
+    conflicts: &'a [Conflict],
+    /// Each conflict's original path relative to the home directory.
+    relative: Vec<PathBuf>,
+    /// Conflict indices in tree order.
+    order: Vec<usize>,
+    pending: Vec<Option<Resolution>>,
+    marked: Vec<bool>,
+    filter: String,
+    mode: Mode,
+    rows: Vec<Row>,
+    cursor: usize,
+    /// Each entry restores the previous decisions of one action.
+    undo: Vec<Vec<(usize, Option<Resolution>)>>,
+    previews: HashMap<usize, String>,
+}
+
+impl<'a> Review<'a> {
+    fn new(conflicts: &'a [Conflict], home_dir: &Path) -> Review<'a> {
+        let relative: Vec<PathBuf> = conflicts
+            .iter()
+            .map(|c| {
+                c.original_path
+                    .strip_prefix(home_dir)
+                    .unwrap_or(&c.original_path)
+                    .to_path_buf()
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..conflicts.len()).collect();
+        order.sort_by(|&a, &b| {
+            (conflicts[a].xdg_dir, relative[a].parent(), &relative[a]).cmp(&(
+                conflicts[b].xdg_dir,
+                relative[b].parent(),
+                &relative[b],
+            ))
+        });
+
+        let mut review = Review {
+            conflicts,
+            relative,
+            order,
+            pending: vec![None; conflicts.len()],
+            marked: vec![false; conflicts.len()],
+            filter: String::new(),
+            mode: Mode::Browse,
+            rows: Vec::new(),
+            cursor: 0,
+            undo: Vec::new(),
+            previews: HashMap::new(),
+        };
+        review.rebuild_rows();
+        review
+    }
+
+    /// The committed decisions, in conflict order.
+    fn decisions(&self) -> Vec<(usize, Resolution)> {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| r.map(|r| (i, r)))
+            .collect()
+    }
+
+    fn matches_filter(&self, i: usize) -> bool {
+        let filter = self.filter.trim().to_lowercase();
+        if filter.is_empty() {
+            return true;
+        }
+        let path = self.relative[i].to_string_lossy().to_lowercase();
+        // `.jpg` means the extension; anything else is a path substring
+        if filter.starts_with('.') && !filter.contains('/') {
+            path.ends_with(&filter)
+        } else {
+            path.contains(&filter)
+        }
+    }
+
+    fn rebuild_rows(&mut self) {
+        let current = self.rows.get(self.cursor).cloned();
+        self.rows.clear();
+
+        let mut last_dir = None;
+        let mut last_folder = None;
+        for &i in &self.order {
+            if !self.matches_filter(i) {
+                continue;
+            }
+            let dir = self.conflicts[i].xdg_dir;
+            if last_dir != Some(dir) {
+                self.rows.push(Row::Dir(dir));
+                last_dir = Some(dir);
+                last_folder = None;
+            }
+            let folder = self.relative[i].parent().map(Path::to_path_buf);
+            if folder != last_folder {
+                if let Some(f) = &folder {
+                    if f != Path::new(dir.dir_name()) {
+                        self.rows.push(Row::Folder(dir, f.clone()));
+                    }
+                }
+                last_folder = folder;
+            }
+            self.rows.push(Row::File(i));
+        }
+
+        // Stay on the same row if it is still visible
+        self.cursor = current
+            .and_then(|row| self.rows.iter().position(|r| *r == row))
+            .unwrap_or(0);
+    }
+
+    /// Visible conflicts under a row: the file itself, or everything in a
+    /// folder or XDG directory.
+    fn files_under(&self, row: &Row) -> Vec<usize> {
+        self.visible_files()
+            .into_iter()
+            .filter(|&i| match row {
+                Row::File(f) => i == *f,
+                Row::Dir(d) => self.conflicts[i].xdg_dir == *d,
+                Row::Folder(_, folder) => self.relative[i].starts_with(folder),
+            })
+            .collect()
+    }
+
+    /// What an action applies to: the visible marked files, or else
+    /// everything under the cursor.
+    fn targets(&self) -> Vec<usize> {
+        let marked: Vec<usize> = self
+            .visible_files()
+            .into_iter()
+            .filter(|&i| self.marked[i])
+            .collect();
+        if !marked.is_empty() {
+            return marked;
+        }
+        self.rows
+            .get(self.cursor)
+            .map(|row| self.files_under(row))
+            .unwrap_or_default()
+    }
+
+    fn visible_files(&self) -> Vec<usize> {
+        self.rows
+            .iter()
+            .filter_map(|r| match r {
+                Row::File(i) => Some(*i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn decide(&mut self, resolution: Resolution) {
+        let targets = self.targets();
+        if targets.is_empty() {
+            return;
+        }
+        self.undo
+            .push(targets.iter().map(|&i| (i, self.pending[i])).collect());
+        for i in targets {
+            self.pending[i] = Some(resolution);
+            self.marked[i] = false;
+        }
+        // Move on so a run of files can be decided key by key
+        if matches!(self.rows.get(self.cursor), Some(Row::File(_))) {
+            self.move_cursor(1);
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(previous) = self.undo.pop() {
+            for (i, resolution) in previous {
+                self.pending[i] = resolution;
+            }
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        let Some(row) = self.rows.get(self.cursor).cloned() else {
+            return;
+        };
+        let files = self.files_under(&row);
+        let mark = !files.iter().all(|&i| self.marked[i]);
+        for i in files {
+            self.marked[i] = mark;
+        }
+        if matches!(row, Row::File(_)) {
+            self.move_cursor(1);
+        }
+    }
+
+    fn toggle_mark_all(&mut self) {
+        let files = self.visible_files();
+        let mark = !files.iter().all(|&i| self.marked[i]);
+        for i in files {
+            self.marked[i] = mark;
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Exit> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Exit::Cancel);
+        }
+
+        if self.mode == Mode::Filter {
+            match key.code {
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.mode = Mode::Browse;
+                    self.rebuild_rows();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.rebuild_rows();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.rebuild_rows();
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        match key.code {
+            KeyCode::Up => self.move_cursor(-1),
+            KeyCode::Down => self.move_cursor(1),
+            KeyCode::PageUp => self.move_cursor(-10),
+            KeyCode::PageDown => self.move_cursor(10),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.move_cursor(isize::MAX),
+            KeyCode::Char(' ') => self.toggle_mark(),
+            KeyCode::Char('a') => self.toggle_mark_all(),
+            KeyCode::Char('o') => self.decide(Resolution::Overwrite),
+            KeyCode::Char('k') => self.decide(Resolution::KeepOriginal),
+            KeyCode::Char('b') => self.decide(Resolution::LeaveAsIs),
+            KeyCode::Char('m') => self.decide(Resolution::Merge),
+            KeyCode::Char('u') => self.undo(),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.rebuild_rows();
+            }
+            KeyCode::Char('c') => return Some(Exit::Commit),
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Exit::Cancel),
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [tree, preview] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self.rows.iter().map(|row| self.row_line(row)).collect();
+        let decided = self.pending.iter().filter(|r| r.is_some()).count();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(
+                " Conflicts: {decided}/{} decided ",
+                self.conflicts.len()
+            )))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.cursor));
+        frame.render_stateful_widget(list, tree, &mut state);
+
+        let text = self.preview_text();
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(" Preview ")),
+            preview,
+        );
+
+        let status_line = match self.mode {
+            Mode::Filter => Line::from(format!("/{}▏", self.filter)),
+            Mode::Browse if !self.filter.is_empty() => {
+                Line::from(format!("filter: {}  (esc clears)   {HELP}", self.filter))
+            }
+            Mode::Browse => Line::from(HELP),
+        };
+        frame.render_widget(status_line.dim(), status);
+    }
+
+    fn row_line(&self, row: &Row) -> ListItem<'static> {
+        match row {
+            Row::Dir(dir) => {
+                let count = self.files_under(row).len();
+                ListItem::new(Line::from(vec![
+                    Span::from(dir.dir_name()).bold(),
+                    Span::from(format!("  ({count})")).dim(),
+                ]))
+            }
+            Row::Folder(dir, folder) => {
+                let name = folder.strip_prefix(dir.dir_name()).unwrap_or(folder);
+                ListItem::new(format!("  {}/", name.display()))
+            }
+            Row::File(i) => {
+                let indent = match self.relative[*i].parent() {
+                    Some(p) if p != Path::new(self.conflicts[*i].xdg_dir.dir_name()) => "    ",
+                    _ => "  ",
+                };
+                let mark = if self.marked[*i] { "[x]" } else { "[ ]" };
+                let tag = match self.pending[*i] {
+                    Some(Resolution::Overwrite) => "overwrite".red(),
+                    Some(Resolution::KeepOriginal) => "keep     ".green(),
+                    Some(Resolution::LeaveAsIs) => "both     ".yellow(),
+                    Some(Resolution::Merge) => "merge    ".cyan(),
+                    None => "         ".into(),
+                };
+                let name = self.relative[*i]
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                ListItem::new(Line::from(vec![
+                    Span::from(format!("{indent}{mark} ")),
+                    tag,
+                    Span::from(format!(" {name}")),
+                ]))
+            }
+        }
+    }
+
+    fn preview_text(&mut self) -> String {
+        match self.rows.get(self.cursor).cloned() {
+            Some(Row::File(i)) => self
+                .previews
+                .entry(i)
+                .or_insert_with(|| preview::format_conflict_preview(&self.conflicts[i]))
+                .clone(),
+            Some(row) => {
+                let files = self.files_under(&row);
+                let count =
+                    |r: Option<Resolution>| files.iter().filter(|&&i| self.pending[i] == r).count();
+                format!(
+                    "{} conflicts\n\n  overwrite  {}\n  keep       {}\n  both       {}\n  merge      {}\n  undecided  {}\n",
+                    files.len(),
+                    count(Some(Resolution::Overwrite)),
+                    count(Some(Resolution::KeepOriginal)),
+                    count(Some(Resolution::LeaveAsIs)),
+                    count(Some(Resolution::Merge)),
+                    count(None),
+                )
+            }
+            None => "No conflicts match the filter.".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(home: &Path, xdg_dir: XdgDir, relative: &str) -> Conflict {
+        Conflict {
+            restore_path: home.join(format!("{relative}.restore")),
+            original_path: home.join(relative),
+            size: 1,
+            original_mtime: None,
+            xdg_dir,
+        }
+    }
+
+    fn sample() -> Vec<Conflict> {
+        let home = Path::new("/home/u");
+        vec![
+            conflict(home, XdgDir::Pictures, "Pictures/2019/b.jpg"),
+            conflict(home, XdgDir::Documents, "Documents/notes.txt"),
+            conflict(home, XdgDir::Pictures, "Pictures/2019/a.png"),
+            conflict(home, XdgDir::Pictures, "Pictures/cover.jpg"),
+        ]
+    }
+
+    fn press(review: &mut Review, keys: &str) -> Option<Exit> {
+        let mut exit = None;
+        for c in keys.chars() {
+            exit = review.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+        exit
+    }
+
+    #[test]
+    fn groups_rows_by_xdg_dir_and_folder() {
+        let conflicts = sample();
+        let review = Review::new(&conflicts, Path::new("/home/u"));
+
+        assert_eq!(
+            review.rows,
+            vec![
+                Row::Dir(XdgDir::Documents),
+                Row::File(1),
+                Row::Dir(XdgDir::Pictures),
+                Row::File(3),
+                Row::Folder(XdgDir::Pictures, PathBuf::from("Pictures/2019")),
+                Row::File(2),
+                Row::File(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn deciding_a_folder_applies_to_its_files_and_undoes() {
+        let conflicts = sample();
+        let mut review = Review::new(&conflicts, Path::new("/home/u"));
+        review.cursor = 4; // Pictures/2019
+
+        press(&mut review, "o");
+        assert_eq!(
+            review.decisions(),
+            vec![(0, Resolution::Overwrite), (2, Resolution::Overwrite)]
+        );
+
+        press(&mut review, "u");
+        assert!(review.decisions().is_empty());
+    }
+
+    #[test]
+    fn marked_files_filtered_by_extension() {
+        let conflicts = sample();
+        let mut review = Review::new(&conflicts, Path::new("/home/u"));
+
+        press(&mut review, "/.jpg");
+        review.handle_key(KeyEvent::from(KeyCode::Enter));
+        assert_eq!(review.visible_files(), vec![3, 0]);
+
+        press(&mut review, "ak");
+        review.handle_key(KeyEvent::from(KeyCode::Esc));
+        assert_eq!(review.rows.len(), 7);
+
+        assert_eq!(press(&mut review, "c"), Some(Exit::Commit));
+        assert_eq!(
+            review.decisions(),
+            vec![(0, Resolution::KeepOriginal), (3, Resolution::KeepOriginal)]
+        );
+    }
+
+    #[test]
+    fn file_decisions_advance_the_cursor() {
+        let conflicts = sample();
+        let mut review = Review::new(&conflicts, Path::new("/home/u"));
+        review.cursor = 5; // a.png
+
+        press(&mut review, "mb");
+
+        assert_eq!(
+            review.decisions(),
+            vec![(0, Resolution::LeaveAsIs), (2, Resolution::Merge)]
+        );
+        assert_eq!(press(&mut review, "q"), Some(Exit::Cancel));
+    }
+}
@@ -1,8 +1,15 @@
 use std::fmt;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-/// The 8 user-facing XDG directories we care about.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+use serde::{Deserialize, Serialize};
+
+use crate::dotfiles::HiddenDir;
+
+/// The 8 user-facing XDG directories we care about, or one of the hidden
+/// folders restored when its [`Category`](crate::dotfiles::Category) is
+/// opted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum XdgDir {
     Desktop,
     Documents,
@@ -12,6 +19,9 @@ pub enum XdgDir {
     Public,
     Templates,
     Videos,
+    /// A hidden folder such as `~/.ssh`, which isn't an XDG folder and is
+    /// never found by a scan.
+    Hidden(HiddenDir),
 }
 
 impl XdgDir {
@@ -26,7 +36,8 @@ impl XdgDir {
         XdgDir::Videos,
     ];
 
-    /// Returns the directory name as it appears on disk.
+    /// Returns the directory name as it appears on disk, relative to the
+    /// home directory.
     pub fn dir_name(&self) -> &'static str {
         match self {
             XdgDir::Desktop => "Desktop",
@@ -37,12 +48,23 @@ impl XdgDir {
             XdgDir::Public => "Public",
             XdgDir::Templates => "Templates",
             XdgDir::Videos => "Videos",
+            XdgDir::Hidden(dir) => dir.dir_name(),
         }
     }
 
-    /// Try to parse a directory name into an `XdgDir`.
+    /// Try to parse a directory name into an `XdgDir`, including the names
+    /// older Windows profiles and macOS use (`My Pictures`, `Movies`).
     pub fn from_dir_name(name: &str) -> Option<XdgDir> {
-        XdgDir::ALL.iter().find(|d| d.dir_name() == name).copied()
+        if let Some(dir) = XdgDir::ALL.iter().find(|d| d.dir_name() == name) {
+            return Some(*dir);
+        }
+        match name {
+            "My Documents" => Some(XdgDir::Documents),
+            "My Music" => Some(XdgDir::Music),
+            "My Pictures" => Some(XdgDir::Pictures),
+            "My Videos" | "Movies" => Some(XdgDir::Videos),
+            _ => None,
+        }
     }
 }
 
@@ -61,18 +83,27 @@ pub struct DetectedMapping {
 }
 
 /// A single file copy operation.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CopyOp {
     pub source: PathBuf,
     pub dest: PathBuf,
     pub size: u64,
+    /// Source modification time when the plan was built, if known.
+    pub mtime: Option<SystemTime>,
     pub xdg_dir: XdgDir,
+    /// Snapshot the file was taken from, for point-in-time restores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
 }
 
 /// A directory that needs to be created.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirOp {
     pub dest: PathBuf,
+    /// The folder in the backup it mirrors, whose owner it can keep; `None`
+    /// for folders the backup only implies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
 }
 
 /// The full copy plan.
@@ -98,6 +129,9 @@ pub struct Conflict {
     pub restore_path: PathBuf,
     pub original_path: PathBuf,
     pub size: u64,
+    /// The original's mtime when the conflict was recorded, used to refuse
+    /// overwriting a file that changed in the meantime.
+    pub original_mtime: Option<SystemTime>,
     pub xdg_dir: XdgDir,
 }
 
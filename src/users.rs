@@ -0,0 +1,633 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::copy::Owner;
+use crate::types::DetectedMapping;
+
+/// A local user account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+/// Look up a local account by name, through NSS like `id` does, so LDAP and
+/// other directory accounts are found too.
+pub fn lookup_account(name: &str) -> Option<Account> {
+    let c_name = CString::new(name).ok()?;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        // SAFETY: passwd is plain data; getpwnam_r fills it with pointers
+        // into buf, which outlives every use below.
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &raw mut pwd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                &raw mut found,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || found.is_null() {
+            return None;
+        }
+        // SAFETY: on success pw_dir points to a NUL-terminated string in buf
+        let home = unsafe { CStr::from_ptr(pwd.pw_dir) };
+        return Some(Account {
+            name: name.to_string(),
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+            home: PathBuf::from(OsStr::from_bytes(home.to_bytes())),
+        });
+    }
+}
+
+/// Look up a local group's id by name.
+pub fn lookup_group(name: &str) -> Option<u32> {
+    let c_name = CString::new(name).ok()?;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        // SAFETY: as in lookup_account; only the gid is read back.
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &raw mut grp,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                &raw mut found,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        return (rc == 0 && !found.is_null()).then_some(grp.gr_gid);
+    }
+}
+
+/// Whether this process can give files to other users.
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// One user's home folder in a backup holding several, such as a copy of
+/// `/home`.
+#[derive(Debug)]
+pub struct UserHome {
+    /// The home folder's name in the backup, normally the user name.
+    pub name: String,
+    /// Every folder of that name holding XDG folders; more than one when
+    /// the backup has several copies of a home.
+    pub roots: Vec<PathBuf>,
+    pub mappings: Vec<DetectedMapping>,
+}
+
+/// Group scanned XDG folders by the home folder holding them: the folder
+/// they are in, named after its user (`home/alice/Documents` belongs to
+/// `alice`). Only folders in a `home` folder, or directly in `backup_root`
+/// when that is a copy of `/home`, count as homes; the XDG folders found
+/// anywhere else are returned apart. Homes come out sorted by name.
+pub fn group_by_home(
+    backup_root: &Path,
+    mappings: Vec<DetectedMapping>,
+) -> (Vec<UserHome>, Vec<DetectedMapping>) {
+    let mut homes: BTreeMap<String, UserHome> = BTreeMap::new();
+    let mut elsewhere = Vec::new();
+    for mapping in mappings {
+        let root = mapping
+            .source_path
+            .parent()
+            .unwrap_or(Path::new("/"))
+            .to_path_buf();
+        let in_home = root
+            .parent()
+            .is_some_and(|p| p == backup_root || p.file_name() == Some(OsStr::new("home")));
+        let name = match root.file_name() {
+            Some(name) if in_home => name.to_string_lossy().into_owned(),
+            _ => {
+                elsewhere.push(mapping);
+                continue;
+            }
+        };
+        let home = homes.entry(name.clone()).or_insert_with(|| UserHome {
+            name,
+            roots: Vec::new(),
+            mappings: Vec::new(),
+        });
+        if !home.roots.contains(&root) {
+            home.roots.push(root);
+        }
+        home.mappings.push(mapping);
+    }
+    (homes.into_values().collect(), elsewhere)
+}
+
+/// The lowest uid of a regular user: `UID_MIN` from `/etc/login.defs`, or
+/// 1000 as most distributions have it. Lower ones are system accounts.
+pub fn uid_min() -> u32 {
+    fs::read_to_string("/etc/login.defs")
+        .ok()
+        .and_then(|text| parse_uid_min(&text))
+        .unwrap_or(1000)
+}
+
+fn parse_uid_min(login_defs: &str) -> Option<u32> {
+    login_defs.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        (fields.next()? == "UID_MIN").then(|| fields.next()?.parse().ok())?
+    })
+}
+
+/// Why a folder found in the backup isn't restored into any account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Skipped {
+    /// An XDG folder that isn't in a home folder.
+    NotInHome(PathBuf),
+    /// A home with no local account of its name, or of the name `--user`
+    /// maps it to.
+    NoAccount {
+        root: PathBuf,
+        name: String,
+        local: String,
+    },
+    /// A home named after a system account, which is more likely a
+    /// service's folder than a person's, so it needs mapping explicitly.
+    SystemAccount {
+        root: PathBuf,
+        name: String,
+        account: Account,
+    },
+}
+
+/// The homes in a backup paired with local accounts, by account name.
+#[derive(Debug)]
+pub struct AccountMatch {
+    pub accounts: BTreeMap<String, (Account, Vec<DetectedMapping>)>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// Pair the homes found in the backup at `root` with local accounts, by
+/// name or as `user_map` renames them, pointing their folders at the
+/// account's home. Folders outside a home, homes without an account and,
+/// unless renamed, homes of system accounts are skipped.
+pub fn match_accounts(
+    root: &Path,
+    mappings: Vec<DetectedMapping>,
+    user_map: &[(String, String)],
+) -> AccountMatch {
+    match_accounts_with(root, mappings, user_map, lookup_account, uid_min())
+}
+
+fn match_accounts_with(
+    root: &Path,
+    mappings: Vec<DetectedMapping>,
+    user_map: &[(String, String)],
+    lookup: impl Fn(&str) -> Option<Account>,
+    uid_min: u32,
+) -> AccountMatch {
+    let renamed: HashMap<&str, &str> = user_map
+        .iter()
+        .map(|(name, user)| (name.as_str(), user.as_str()))
+        .collect();
+    let (user_homes, elsewhere) = group_by_home(root, mappings);
+    let mut skipped: Vec<Skipped> = elsewhere
+        .into_iter()
+        .map(|m| Skipped::NotInHome(m.source_path))
+        .collect();
+    // Several backup homes can go to one account; their folders are then
+    // duplicates of each other
+    let mut accounts: BTreeMap<String, (Account, Vec<DetectedMapping>)> = BTreeMap::new();
+    for home in user_homes {
+        let mapped = renamed.get(home.name.as_str()).copied();
+        let local = mapped.unwrap_or(&home.name);
+        let root = home.roots[0].clone();
+        let Some(account) = lookup(local) else {
+            skipped.push(Skipped::NoAccount {
+                root,
+                local: local.to_string(),
+                name: home.name,
+            });
+            continue;
+        };
+        if account.uid < uid_min && mapped.is_none() {
+            skipped.push(Skipped::SystemAccount {
+                root,
+                name: home.name,
+                account,
+            });
+            continue;
+        }
+        let (account, mappings) = accounts
+            .entry(account.name.clone())
+            .or_insert_with(|| (account, Vec::new()));
+        mappings.extend(home.mappings.into_iter().map(|m| DetectedMapping {
+            dest_path: account.home.join(m.xdg_dir.dir_name()),
+            ..m
+        }));
+    }
+    AccountMatch { accounts, skipped }
+}
+
+/// Translates the uids and gids stored in a backup into this system's.
+/// Ids without an entry go to `unmapped`, or are kept as they are without
+/// one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap {
+    pub users: BTreeMap<u32, u32>,
+    pub groups: BTreeMap<u32, u32>,
+    /// Who gets what the backup's accounts and the mappings given don't
+    /// name, normally the owner of the home restored into.
+    pub unmapped: Option<Owner>,
+}
+
+impl IdMap {
+    /// Map the backup's accounts to local ones of the same name, given the
+    /// contents of the backup's `/etc/passwd` and `/etc/group`.
+    pub fn by_name(passwd: &str, group: &str) -> IdMap {
+        IdMap::match_names(
+            passwd,
+            group,
+            |name| lookup_account(name).map(|a| a.uid),
+            lookup_group,
+        )
+    }
+
+    fn match_names(
+        passwd: &str,
+        group: &str,
+        local_user: impl Fn(&str) -> Option<u32>,
+        local_group: impl Fn(&str) -> Option<u32>,
+    ) -> IdMap {
+        let matched = |text: &str, local: &dyn Fn(&str) -> Option<u32>| {
+            parse_id_file(text)
+                .into_iter()
+                .filter_map(|(name, old)| Some((old, local(name)?)))
+                .collect()
+        };
+        IdMap {
+            users: matched(passwd, &local_user),
+            groups: matched(group, &local_group),
+            unmapped: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    /// Send the ids nothing maps to the owner of `home_dir`, the home
+    /// being restored into, and return that owner. A home that doesn't
+    /// exist yet will be root's, as it is created.
+    pub fn fall_back_to_home(&mut self, home_dir: &Path) -> Owner {
+        let owner = Owner::of(home_dir).unwrap_or(Owner { uid: 0, gid: 0 });
+        self.unmapped = Some(owner);
+        owner
+    }
+
+    /// Whether both of `owner`'s ids have an entry.
+    pub fn is_mapped(&self, owner: Owner) -> bool {
+        self.users.contains_key(&owner.uid) && self.groups.contains_key(&owner.gid)
+    }
+
+    /// The local owner for files owned by `owner` in the backup.
+    pub fn map(&self, owner: Owner) -> Owner {
+        let fallback = self.unmapped.unwrap_or(owner);
+        Owner {
+            uid: *self.users.get(&owner.uid).unwrap_or(&fallback.uid),
+            gid: *self.groups.get(&owner.gid).unwrap_or(&fallback.gid),
+        }
+    }
+}
+
+/// The names and ids in a passwd or group file, whose first and third
+/// fields they are. Malformed lines are skipped.
+fn parse_id_file(text: &str) -> Vec<(&str, u32)> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next().filter(|n| !n.is_empty())?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((name, id))
+        })
+        .collect()
+}
+
+/// Find the `etc/passwd` and `etc/group` of the system a backup was taken
+/// from: in the folders holding `sources`, up to `backup_root`, nearest
+/// first. Returns the `etc` folder and the two files' contents; a missing
+/// group file reads as empty.
+pub fn find_account_files(
+    backup_root: &Path,
+    sources: &[&Path],
+) -> Option<(PathBuf, String, String)> {
+    sources.iter().find_map(|source| {
+        source
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(backup_root))
+            .find_map(|dir| {
+                let etc = dir.join("etc");
+                let passwd = fs::read_to_string(etc.join("passwd")).ok()?;
+                let group = fs::read_to_string(etc.join("group")).unwrap_or_default();
+                Some((etc, passwd, group))
+            })
+    })
+}
+
+/// How many files end up with each owner: `(in the backup, restored as,
+/// files)`, most common first.
+pub fn count_owners(
+    owners: impl IntoIterator<Item = Owner>,
+    map: &IdMap,
+) -> Vec<(Owner, Owner, usize)> {
+    let mut counts: HashMap<Owner, usize> = HashMap::new();
+    for owner in owners {
+        *counts.entry(owner).or_default() += 1;
+    }
+    let mut counts: Vec<(Owner, Owner, usize)> = counts
+        .into_iter()
+        .map(|(owner, n)| (owner, map.map(owner), n))
+        .collect();
+    counts.sort_by_key(|&(owner, _, n)| (std::cmp::Reverse(n), owner.uid, owner.gid));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::XdgDir;
+
+    fn mapping(source: &str, xdg_dir: XdgDir) -> DetectedMapping {
+        DetectedMapping {
+            xdg_dir,
+            source_path: PathBuf::from(source),
+            dest_path: PathBuf::from("/unused").join(xdg_dir.dir_name()),
+        }
+    }
+
+    #[test]
+    fn groups_folders_by_the_home_holding_them() {
+        let (homes, elsewhere) = group_by_home(
+            Path::new("/b"),
+            vec![
+                mapping("/b/home/bob/Music", XdgDir::Music),
+                mapping("/b/home/alice/Documents", XdgDir::Documents),
+                mapping("/b/home/alice/Pictures", XdgDir::Pictures),
+                mapping("/b/old/home/alice/Documents", XdgDir::Documents),
+                mapping("/b/carol/Videos", XdgDir::Videos),
+                mapping("/b/home/alice/work/etc/Music", XdgDir::Music),
+                mapping("/b/Pictures", XdgDir::Pictures),
+            ],
+        );
+        let summary: Vec<(&str, usize, usize)> = homes
+            .iter()
+            .map(|h| (h.name.as_str(), h.roots.len(), h.mappings.len()))
+            .collect();
+        assert_eq!(summary, [("alice", 2, 3), ("bob", 1, 1), ("carol", 1, 1)]);
+        assert_eq!(homes[0].roots[0], Path::new("/b/home/alice"));
+        let elsewhere: Vec<&Path> = elsewhere.iter().map(|m| m.source_path.as_path()).collect();
+        assert_eq!(elsewhere, ["/b/home/alice/work/etc/Music", "/b/Pictures"]);
+    }
+
+    #[test]
+    fn matches_homes_to_local_accounts() {
+        let account = |name: &str, uid: u32| Account {
+            name: name.to_string(),
+            uid,
+            gid: uid,
+            home: PathBuf::from("/home").join(name),
+        };
+        let local = [
+            account("alice", 1000),
+            account("john", 1001),
+            account("daemon", 2),
+            account("backup", 34),
+        ];
+        let lookup = |name: &str| local.iter().find(|a| a.name == name).cloned();
+        let user_map = [
+            ("jsmith".to_string(), "john".to_string()),
+            ("old-alice".to_string(), "alice".to_string()),
+            ("svc".to_string(), "backup".to_string()),
+        ];
+
+        let matched = match_accounts_with(
+            Path::new("/b"),
+            vec![
+                mapping("/b/home/alice/Documents", XdgDir::Documents),
+                mapping("/b/home/old-alice/Documents", XdgDir::Documents),
+                mapping("/b/home/jsmith/Music", XdgDir::Music),
+                mapping("/b/home/daemon/Music", XdgDir::Music),
+                mapping("/b/home/svc/Music", XdgDir::Music),
+                mapping("/b/home/dave/Pictures", XdgDir::Pictures),
+                mapping("/b/Pictures", XdgDir::Pictures),
+            ],
+            &user_map,
+            lookup,
+            1000,
+        );
+
+        let restored: Vec<(&str, Vec<(&Path, &Path)>)> = matched
+            .accounts
+            .values()
+            .map(|(account, mappings)| {
+                let paths = mappings
+                    .iter()
+                    .map(|m| (m.source_path.as_path(), m.dest_path.as_path()))
+                    .collect();
+                (account.name.as_str(), paths)
+            })
+            .collect();
+        let path = Path::new;
+        assert_eq!(
+            restored,
+            [
+                (
+                    "alice",
+                    vec![
+                        (
+                            path("/b/home/alice/Documents"),
+                            path("/home/alice/Documents")
+                        ),
+                        (
+                            path("/b/home/old-alice/Documents"),
+                            path("/home/alice/Documents")
+                        ),
+                    ]
+                ),
+                // A system account is restored only when asked for by name
+                (
+                    "backup",
+                    vec![(path("/b/home/svc/Music"), path("/home/backup/Music"))]
+                ),
+                (
+                    "john",
+                    vec![(path("/b/home/jsmith/Music"), path("/home/john/Music"))]
+                ),
+            ]
+        );
+        assert_eq!(
+            matched.skipped,
+            [
+                Skipped::NotInHome(PathBuf::from("/b/Pictures")),
+                Skipped::SystemAccount {
+                    root: PathBuf::from("/b/home/daemon"),
+                    name: "daemon".to_string(),
+                    account: local[2].clone(),
+                },
+                Skipped::NoAccount {
+                    root: PathBuf::from("/b/home/dave"),
+                    name: "dave".to_string(),
+                    local: "dave".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_unmapped_ids_to_the_homes_owner() {
+        let home = tempfile::tempdir().unwrap();
+        if is_root() {
+            std::os::unix::fs::chown(home.path(), Some(4321), Some(4322)).unwrap();
+        }
+        let owner = Owner::of(home.path()).unwrap();
+        let mut map = IdMap::default();
+        map.users.insert(1000, 1001);
+        map.groups.insert(1000, 1001);
+
+        assert_eq!(map.fall_back_to_home(home.path()), owner);
+
+        let mapped = Owner {
+            uid: 1000,
+            gid: 1000,
+        };
+        let stranger = Owner {
+            uid: 5000,
+            gid: 1000,
+        };
+        assert_eq!(
+            map.map(mapped),
+            Owner {
+                uid: 1001,
+                gid: 1001
+            }
+        );
+        assert_eq!(
+            map.map(stranger),
+            Owner {
+                uid: owner.uid,
+                gid: 1001
+            }
+        );
+        assert!(!map.is_mapped(stranger));
+        // A home about to be created will be root's
+        let missing = home.path().join("new");
+        assert_eq!(map.fall_back_to_home(&missing), Owner { uid: 0, gid: 0 });
+        assert_eq!(map.map(stranger).uid, 0);
+    }
+
+    #[test]
+    fn reads_uid_min_from_login_defs() {
+        let defs = "# UID_MIN 10\nMAIL_DIR /var/mail\nUID_MIN\t\t  500\nUID_MAX 60000\n";
+        assert_eq!(parse_uid_min(defs), Some(500));
+        assert_eq!(parse_uid_min("UID_MAX 60000\n"), None);
+    }
+
+    #[test]
+    fn looks_up_local_accounts() {
+        let root = lookup_account("root").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert!(root.home.is_absolute());
+        assert_eq!(lookup_account("no-such-user-here"), None);
+        assert_eq!(lookup_account("nul\0byte"), None);
+    }
+
+    #[test]
+    fn maps_ids_by_name_from_the_backups_account_files() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+            alice:x:1000:1000:Alice:/home/alice:/bin/bash\n\
+            bob:x:1001:1001::/home/bob:/bin/sh\n\
+            gone:x:1002:1002::/home/gone:/bin/sh\n\
+            broken line\n";
+        let group = "root:x:0:\nalice:x:1000:\nbob:x:1001:\nusers:x:100:alice,bob\n";
+        let local_users = |name: &str| match name {
+            "root" => Some(0),
+            "alice" => Some(1001),
+            "bob" => Some(1000),
+            _ => None,
+        };
+        let local_groups = |name: &str| match name {
+            "root" => Some(0),
+            "alice" => Some(1001),
+            "bob" => Some(1000),
+            "users" => Some(100),
+            _ => None,
+        };
+        let mut map = IdMap::match_names(passwd, group, local_users, local_groups);
+        assert_eq!(
+            map.users,
+            BTreeMap::from([(0, 0), (1000, 1001), (1001, 1000)])
+        );
+        assert_eq!(
+            map.groups,
+            BTreeMap::from([(0, 0), (100, 100), (1000, 1001), (1001, 1000)])
+        );
+
+        let owner = |uid, gid| Owner { uid, gid };
+        assert_eq!(map.map(owner(1000, 100)), owner(1001, 100));
+        assert_eq!(map.map(owner(1002, 1002)), owner(1002, 1002));
+        assert!(map.is_mapped(owner(0, 0)));
+        assert!(!map.is_mapped(owner(1000, 1002)));
+        // Ids nobody named go to the home's owner instead
+        map.unmapped = Some(owner(1001, 1001));
+        assert_eq!(map.map(owner(1002, 1002)), owner(1001, 1001));
+        assert_eq!(map.map(owner(1000, 1002)), owner(1001, 1001));
+
+        let counts = count_owners(
+            [owner(1000, 1000), owner(1001, 1001), owner(1000, 1000)],
+            &map,
+        );
+        assert_eq!(
+            counts,
+            [
+                (owner(1000, 1000), owner(1001, 1001), 2),
+                (owner(1001, 1001), owner(1000, 1000), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_the_nearest_account_files() {
+        let backup = tempfile::tempdir().unwrap();
+        let system = backup.path().join("laptop");
+        fs::create_dir_all(system.join("etc")).unwrap();
+        fs::create_dir_all(system.join("home/alice/Documents")).unwrap();
+        fs::write(
+            system.join("etc/passwd"),
+            "alice:x:1000:1000::/home/alice:/bin/sh\n",
+        )
+        .unwrap();
+        let docs = system.join("home/alice/Documents");
+
+        let (etc, passwd, group) = find_account_files(backup.path(), &[&docs]).unwrap();
+        assert_eq!(etc, system.join("etc"));
+        assert!(passwd.starts_with("alice:"));
+        assert_eq!(group, "");
+        // Nothing above the backup root is looked at
+        assert_eq!(find_account_files(&docs, &[&docs]), None);
+    }
+}
@@ -2,7 +2,7 @@ use std::fs;
 
 use tempfile::tempdir;
 
-use backup_restore::conflict::{apply_resolution, Resolution};
+use backup_restore::conflict::{apply_resolution, find_leftover_conflicts, Resolution};
 use backup_restore::copy::execute_plan;
 use backup_restore::plan::build_plan;
 use backup_restore::report::{format_dry_run_report, format_report};
@@ -179,3 +179,106 @@ fn empty_directories_created() {
     assert!(home.path().join("Pictures/Vacation").is_dir());
     assert!(home.path().join("Pictures/Vacation/Empty Album").is_dir());
 }
+
+/// Conflicts left as-is can be found and resolved in a later session
+#[test]
+fn leftover_conflicts_resolved_later() {
+    let backup_root = tempdir().unwrap();
+    let home = tempdir().unwrap();
+
+    let docs = backup_root.path().join("Documents");
+    fs::create_dir(&docs).unwrap();
+    fs::write(docs.join("todo.txt"), "new todo").unwrap();
+    fs::create_dir(home.path().join("Documents")).unwrap();
+    fs::write(home.path().join("Documents/todo.txt"), "old todo").unwrap();
+
+    let scan_result = scan_backup(backup_root.path(), home.path());
+    let plan = build_plan(&scan_result.mappings).unwrap();
+    let result = execute_plan(&plan, 1).unwrap();
+    assert_eq!(result.conflicts.len(), 1);
+
+    // Later session: rediscover the conflict from disk alone
+    let leftovers = find_leftover_conflicts(home.path(), &[]);
+    assert_eq!(leftovers.conflicts.len(), 1);
+    assert_eq!(
+        leftovers.conflicts[0].restore_path,
+        result.conflicts[0].restore_path
+    );
+    assert_eq!(
+        leftovers.conflicts[0].original_path,
+        result.conflicts[0].original_path
+    );
+
+    apply_resolution(&leftovers.conflicts[0], Resolution::Overwrite).unwrap();
+    assert_eq!(
+        fs::read_to_string(home.path().join("Documents/todo.txt")).unwrap(),
+        "new todo"
+    );
+}
+
+/// Download from a real SSH server. The backup is written locally and read
+/// back over SFTP, so this needs an sshd on this machine that accepts a key
+/// for the login in `BACKUP_RESTORE_SFTP_TEST`, e.g. `$USER@localhost`:
+///
+///     BACKUP_RESTORE_SFTP_TEST=$USER@localhost cargo test -- --ignored sftp
+#[test]
+#[ignore = "needs a local sshd; set BACKUP_RESTORE_SFTP_TEST=user@localhost"]
+fn sftp_mirrors_from_local_sshd() {
+    use backup_restore::sftp::{self, Remote, Session};
+
+    let login = std::env::var("BACKUP_RESTORE_SFTP_TEST").unwrap();
+    let backup_root = tempdir().unwrap();
+    let docs = backup_root.path().join("laptop/Documents");
+    fs::create_dir_all(docs.join("subdir")).unwrap();
+    fs::write(docs.join("notes.txt"), "my notes").unwrap();
+    let big: Vec<u8> = (0..3_000_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(docs.join("subdir/big.bin"), &big).unwrap();
+
+    let remote = Remote::parse(&format!("{login}:{}", backup_root.path().display())).unwrap();
+    let mut session = Session::connect(&remote, None).unwrap();
+    let (folders, _) = sftp::find_xdg_folders(&mut session, &remote.path).unwrap();
+    let files = sftp::list_folders(&mut session, &remote.path, &folders).unwrap();
+    let sessions = vec![session, Session::connect(&remote, None).unwrap()];
+    let out = tempdir().unwrap();
+    let count = sftp::mirror(sessions, &remote.path, &folders, &files, out.path()).unwrap();
+
+    assert_eq!(count, 2);
+    assert_eq!(
+        fs::read(out.path().join("laptop/Documents/subdir/big.bin")).unwrap(),
+        big
+    );
+}
+
+/// Download from a real S3-compatible server, such as a local `MinIO`. The
+/// prefix in `BACKUP_RESTORE_S3_TEST` must hold at least one XDG folder;
+/// the endpoint and credentials come from the usual `AWS_*` variables.
+#[test]
+#[ignore = "needs an S3 server; set BACKUP_RESTORE_S3_TEST=s3://bucket/prefix"]
+fn s3_downloads_from_server() {
+    use backup_restore::s3::{self, Client, Credentials, Location};
+
+    let spec = std::env::var("BACKUP_RESTORE_S3_TEST").unwrap();
+    let location = Location::parse(&spec).unwrap();
+    let endpoint = std::env::var("AWS_ENDPOINT_URL").unwrap();
+    let home = tempdir().unwrap();
+    let credentials = Credentials::from_env(home.path()).unwrap();
+    let client = Client::new(&endpoint, "us-east-1", credentials).unwrap();
+
+    let objects = client.list(&location).unwrap();
+    let folders = s3::find_xdg_prefixes(&objects, &location.prefix);
+    let out = tempdir().unwrap();
+    let count = s3::download(&client, &location, &objects, &folders, out.path(), 4).unwrap();
+
+    assert!(!folders.is_empty());
+    let expected = objects
+        .iter()
+        .filter(|o| !o.key.ends_with('/'))
+        .filter(|o| {
+            let relative = &o.key[location.prefix.len()..];
+            folders
+                .iter()
+                .any(|f| relative.starts_with(&format!("{f}/")))
+        })
+        .count();
+    assert_eq!(count, expected);
+}